@@ -158,9 +158,13 @@ export default function ProfilePage() {
       return;
     }
 
-    if (confirm('Are you sure you want to delete your account? This action cannot be undone.')) {
+    if (confirm('Are you sure you want to delete your account? It will be permanently deleted after 30 days unless you sign in and cancel.')) {
+      const password = prompt('Enter your password (or a two-factor code) to confirm');
+      if (password === null) {
+        return;
+      }
       try {
-        await deleteAccount();
+        await deleteAccount(/^\d{6}$/.test(password) ? { code: password } : { password });
         router.push('/');
       } catch {
         alert('Failed to delete account. Please try again.');
@@ -20,9 +20,12 @@ export default function SignupPage() {
   const [showPassword, setShowPassword] = useState(false);
   const [showConfirmPassword, setShowConfirmPassword] = useState(false);
   const [mounted, setMounted] = useState(false);
+  // Invite links look like /auth/signup?invite=<token>
+  const [inviteToken, setInviteToken] = useState<string | undefined>(undefined);
 
   useEffect(() => {
     setMounted(true);
+    setInviteToken(new URLSearchParams(window.location.search).get('invite') ?? undefined);
   }, []);
 
   // Redirect if already authenticated
@@ -76,7 +79,7 @@ export default function SignupPage() {
     }
 
     try {
-      const response = await signup(formData.email, formData.password, formData.name.trim());
+      const response = await signup(formData.email, formData.password, formData.name.trim(), inviteToken);
       if (response.success) {
         // Redirect to email verification page or dashboard
         router.push('/auth/verify-email');
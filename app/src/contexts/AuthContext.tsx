@@ -1,7 +1,7 @@
 'use client';
 
 import React, { createContext, useContext, useReducer, useEffect, ReactNode } from 'react';
-import { AuthAPI, User, AuthResponse, isAuthenticated, clearAuth } from '../lib/auth';
+import { AuthAPI, User, AuthResponse, SocialProvider, isAuthenticated, clearAuth } from '../lib/auth';
 
 interface AuthState {
   user: User | null;
@@ -74,15 +74,15 @@ function authReducer(state: AuthState, action: AuthAction): AuthState {
 
 interface AuthContextType extends AuthState {
   login: (email: string, password: string) => Promise<AuthResponse>;
-  signup: (email: string, password: string, name: string) => Promise<AuthResponse>;
+  signup: (email: string, password: string, name: string, inviteToken?: string) => Promise<AuthResponse>;
   logout: () => Promise<void>;
   forgotPassword: (email: string) => Promise<void>;
   resetPassword: (token: string, newPassword: string) => Promise<void>;
   changePassword: (currentPassword: string, newPassword: string) => Promise<void>;
   updateProfile: (data: { name?: string; email?: string }) => Promise<void>;
-  deleteAccount: () => Promise<void>;
+  deleteAccount: (confirmation: { password?: string; code?: string }) => Promise<void>;
   verifyEmail: (token: string) => Promise<void>;
-  socialLogin: (provider: string, accessToken: string, profile: { id: string; email: string; name: string; avatar?: string }) => Promise<AuthResponse>;
+  socialLogin: (provider: SocialProvider, redirectTo?: string) => Promise<void>;
   clearError: () => void;
   refreshUser: () => Promise<void>;
 }
@@ -136,10 +136,10 @@ export function AuthProvider({ children }: AuthProviderProps) {
     }
   };
 
-  const signup = async (email: string, password: string, name: string): Promise<AuthResponse> => {
+  const signup = async (email: string, password: string, name: string, inviteToken?: string): Promise<AuthResponse> => {
     try {
       dispatch({ type: 'AUTH_START' });
-      const response = await AuthAPI.signup({ email, password, name });
+      const response = await AuthAPI.signup({ email, password, name, invite_token: inviteToken });
       
       if (response.success && response.user) {
         dispatch({ type: 'AUTH_SUCCESS', payload: response.user });
@@ -198,9 +198,9 @@ export function AuthProvider({ children }: AuthProviderProps) {
     }
   };
 
-  const deleteAccount = async (): Promise<void> => {
+  const deleteAccount = async (confirmation: { password?: string; code?: string }): Promise<void> => {
     try {
-      await AuthAPI.deleteAccount();
+      await AuthAPI.deleteAccount(confirmation);
       dispatch({ type: 'AUTH_LOGOUT' });
     } catch (error) {
       throw error;
@@ -217,26 +217,14 @@ export function AuthProvider({ children }: AuthProviderProps) {
     }
   };
 
-  const socialLogin = async (provider: string, accessToken: string, profile: { id: string; email: string; name: string; avatar?: string }): Promise<AuthResponse> => {
+  const socialLogin = async (provider: SocialProvider, redirectTo?: string): Promise<void> => {
     try {
       dispatch({ type: 'AUTH_START' });
-      const response = await AuthAPI.socialLogin({
-        provider: provider as 'google' | 'github' | 'microsoft' | 'discord',
-        accessToken,
-        profile,
-      });
-      
-      if (response.success && response.user) {
-        dispatch({ type: 'AUTH_SUCCESS', payload: response.user });
-      } else {
-        dispatch({ type: 'AUTH_FAILURE', payload: response.error || 'Social login failed' });
-      }
-      
-      return response;
+      await AuthAPI.socialLogin(provider, redirectTo);
     } catch (error) {
       const message = error instanceof Error ? error.message : 'Social login failed';
       dispatch({ type: 'AUTH_FAILURE', payload: message });
-      return { success: false, error: message };
+      throw error;
     }
   };
 
@@ -31,14 +31,32 @@ export interface AuthResponse {
   success: boolean;
   user?: User;
   token?: string;
+  refreshToken?: string;
   expiresAt?: string;
   error?: string;
+  mfaRequired?: boolean;
+  mfaToken?: string;
+  // Set while the account is waiting to be erased; POST /api/auth/account/restore cancels it
+  deletion_scheduled_for?: string | null;
+}
+
+export interface MfaVerifyRequest {
+  // Omitted after social sign-in, where the challenge travels in an httpOnly cookie
+  mfaToken?: string;
+  code?: string;
+  recoveryCode?: string;
 }
 
 export interface SignupRequest {
   email: string;
   password: string;
   name: string;
+  invite_token?: string;
+}
+
+export interface SignupPolicy {
+  mode: 'open' | 'domain' | 'invite';
+  allowed_domains?: string[];
 }
 
 export interface LoginRequest {
@@ -63,18 +81,10 @@ export interface ChangePasswordRequest {
 export interface UpdateProfileRequest {
   name?: string;
   email?: string;
+  avatar?: string;
 }
 
-export interface SocialLoginRequest {
-  provider: 'google' | 'github' | 'microsoft' | 'discord';
-  accessToken: string;
-  profile: {
-    id: string;
-    email: string;
-    name: string;
-    avatar?: string;
-  };
-}
+export type SocialProvider = 'google' | 'github' | 'microsoft' | 'discord';
 
 // Authentication API functions
 const API_BASE = process.env.NEXT_PUBLIC_ENVIRONMENT === 'production'
@@ -125,7 +135,20 @@ function clearCsrfToken(): void {
 }
 
 export class AuthAPI {
-  private static async request(endpoint: string, options?: RequestInit, requiresCsrf: boolean = false): Promise<unknown> {
+  // Exchanges the refresh_token cookie for a new short-lived access token
+  private static async refreshSession(): Promise<boolean> {
+    try {
+      const response = await fetch(`${API_BASE}/api/auth/refresh`, {
+        method: 'POST',
+        credentials: 'include',
+      });
+      return response.ok;
+    } catch {
+      return false;
+    }
+  }
+
+  private static async request(endpoint: string, options?: RequestInit, requiresCsrf: boolean = false, isRetry: boolean = false): Promise<unknown> {
     const url = `${API_BASE}${endpoint}`;
     
     const defaultOptions: RequestInit = {
@@ -153,6 +176,11 @@ export class AuthAPI {
 
     try {
       const response = await fetch(url, mergedOptions);
+
+      // Access tokens are short-lived: refresh once and replay the request
+      if (response.status === 401 && !isRetry && !endpoint.startsWith('/api/auth/login') && !endpoint.startsWith('/api/auth/mfa/verify') && await this.refreshSession()) {
+        return this.request(endpoint, options, requiresCsrf, true);
+      }
       
       // Try to parse as JSON, but handle text responses too
       let data;
@@ -197,6 +225,17 @@ export class AuthAPI {
     return response;
   }
 
+  static async verifyMfa(request: MfaVerifyRequest): Promise<AuthResponse> {
+    return this.request('/api/auth/mfa/verify', {
+      method: 'POST',
+      body: JSON.stringify({
+        mfa_token: request.mfaToken,
+        code: request.code,
+        recovery_code: request.recoveryCode,
+      }),
+    }) as Promise<AuthResponse>;
+  }
+
   static async logout(): Promise<void> {
     await this.request('/api/auth/logout', { method: 'POST' });
     // Clear CSRF token after logout
@@ -210,6 +249,20 @@ export class AuthAPI {
     }) as Promise<{ message: string }>;
   }
 
+  static async requestMagicLink(email: string): Promise<{ message: string }> {
+    return this.request('/api/auth/magic-link', {
+      method: 'POST',
+      body: JSON.stringify({ email }),
+    }) as Promise<{ message: string }>;
+  }
+
+  static async consumeMagicLink(token: string): Promise<AuthResponse> {
+    return this.request('/api/auth/magic-link/verify', {
+      method: 'POST',
+      body: JSON.stringify({ token }),
+    }) as Promise<AuthResponse>;
+  }
+
   static async resetPassword(request: ResetPasswordRequest): Promise<{ message: string }> {
     return this.request('/api/auth/reset-password', {
       method: 'POST',
@@ -247,24 +300,57 @@ export class AuthAPI {
     return response.data;
   }
 
-  static async deleteAccount(): Promise<{ message: string }> {
+  // Schedules deletion after the grace period; confirm with the password or,
+  // for accounts with two-factor, a current authentication code
+  static async deleteAccount(confirmation: { password?: string; code?: string }): Promise<{ scheduled_for: string }> {
     const response = await this.request('/api/auth/account', {
       method: 'DELETE',
-    }, true) as { message: string };
+      body: JSON.stringify(confirmation),
+    }, true) as { data: { scheduled_for: string } };
     
     // Clear CSRF token after account deletion
     clearCsrfToken();
-    return response;
+    return response.data;
   }
 
-  static async socialLogin(request: SocialLoginRequest): Promise<AuthResponse> {
-    const response = await this.request('/api/auth/social', {
+  static async cancelAccountDeletion(): Promise<{ message: string }> {
+    return this.request('/api/auth/account/restore', {
       method: 'POST',
-      body: JSON.stringify(request),
-    }) as AuthResponse;
-    
-    // Token is now handled via httpOnly cookies automatically
-    return response;
+    }, true) as Promise<{ message: string }>;
+  }
+
+  static async exportAccountData(): Promise<Blob> {
+    const response = await fetch(`${API_BASE}/api/auth/export`, {
+      credentials: 'include',
+    });
+    if (!response.ok) {
+      throw new Error('Failed to export account data');
+    }
+    return response.blob();
+  }
+
+  // Sends the browser to the provider; the backend callback sets the session
+  // cookies and redirects back to `redirectTo`
+  static async socialLogin(provider: SocialProvider, redirectTo?: string): Promise<void> {
+    const response = await this.request(`/api/auth/oauth/${provider}/authorize`, {
+      method: 'POST',
+      body: JSON.stringify({ redirect_to: redirectTo }),
+    }) as { data: { authorization_url: string } };
+    window.location.assign(response.data.authorization_url);
+  }
+
+  static async linkSocialAccount(provider: SocialProvider, redirectTo?: string): Promise<void> {
+    const response = await this.request(`/api/auth/oauth/${provider}/link`, {
+      method: 'POST',
+      body: JSON.stringify({ redirect_to: redirectTo }),
+    }, true) as { data: { authorization_url: string } };
+    window.location.assign(response.data.authorization_url);
+  }
+
+  static async unlinkSocialAccount(provider: SocialProvider): Promise<{ message: string }> {
+    return this.request(`/api/auth/oauth/${provider}`, {
+      method: 'DELETE',
+    }, true) as Promise<{ message: string }>;
   }
 
   static async getSessions(): Promise<unknown[]> {
@@ -281,6 +367,66 @@ export class AuthAPI {
     clearCsrfToken();
     return response;
   }
+
+  static async getAuthEvents(before?: string): Promise<unknown[]> {
+    const query = before ? `?before=${encodeURIComponent(before)}` : '';
+    const response = await this.request(`/api/auth/events${query}`) as { data: unknown[] };
+    return response.data;
+  }
+
+  static async getPasskeys(): Promise<unknown[]> {
+    const response = await this.request('/api/auth/passkeys') as { data: unknown[] };
+    return response.data;
+  }
+
+  static async removePasskey(id: string): Promise<{ message: string }> {
+    return this.request(`/api/auth/passkeys/${id}`, {
+      method: 'DELETE',
+    }, true) as Promise<{ message: string }>;
+  }
+
+  static async getAccessTokens(): Promise<unknown[]> {
+    const response = await this.request('/api/auth/tokens') as { data: unknown[] };
+    return response.data;
+  }
+
+  static async createAccessToken(name: string, scopes: string[], expiresInDays?: number): Promise<{ token: string; access_token: unknown }> {
+    const response = await this.request('/api/auth/tokens', {
+      method: 'POST',
+      body: JSON.stringify({ name, scopes, expires_in_days: expiresInDays }),
+    }, true) as { data: { token: string; access_token: unknown } };
+    return response.data;
+  }
+
+  static async revokeAccessToken(id: string): Promise<{ message: string }> {
+    return this.request(`/api/auth/tokens/${id}`, {
+      method: 'DELETE',
+    }, true) as Promise<{ message: string }>;
+  }
+
+  static async getSignupPolicy(): Promise<SignupPolicy> {
+    const response = await this.request('/api/auth/signup-policy') as { data: SignupPolicy };
+    return response.data;
+  }
+
+  static async getPlatformInvites(): Promise<unknown[]> {
+    const response = await this.request('/api/admin/invites') as { data: unknown[] };
+    return response.data;
+  }
+
+  static async createPlatformInvite(email?: string, expiresInDays?: number): Promise<{ token: string; link: string; invite: unknown }> {
+    const response = await this.request('/api/admin/invites', {
+      method: 'POST',
+      body: JSON.stringify({ email, expires_in_days: expiresInDays }),
+    }, true) as { data: { token: string; link: string; invite: unknown } };
+    return response.data;
+  }
+
+  static async revokePlatformInvite(id: string): Promise<{ message: string }> {
+    return this.request(`/api/admin/invites/${id}`, {
+      method: 'DELETE',
+    }, true) as Promise<{ message: string }>;
+  }
 }
 
 // Utility functions
@@ -0,0 +1,3 @@
+-- Password reset tokens now hold the SHA-256 hash, never the raw token.
+-- Resets are throttled per user by creation time.
+CREATE INDEX IF NOT EXISTS idx_password_resets_created_at ON password_resets(created_at);
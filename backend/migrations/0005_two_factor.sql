@@ -0,0 +1,39 @@
+-- TOTP enrollment (one row per user; enabled once the first code is confirmed,
+-- last_used_step blocks replaying a code within its window)
+CREATE TABLE IF NOT EXISTS user_mfa (
+    user_id TEXT PRIMARY KEY,
+    secret TEXT NOT NULL,
+    enabled INTEGER DEFAULT 0,
+    created_at TEXT NOT NULL,
+    confirmed_at TEXT,
+    last_used_step INTEGER,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Two-factor recovery codes (code_hash holds the SHA-256 of the normalized code)
+CREATE TABLE IF NOT EXISTS mfa_recovery_codes (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    code_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Pending logins waiting for a second factor (token_hash holds the SHA-256 of the mfa_token)
+CREATE TABLE IF NOT EXISTS mfa_challenges (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    token_hash TEXT UNIQUE NOT NULL,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    attempts INTEGER DEFAULT 0,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+-- Clubs can require their admins to have two-factor enabled
+ALTER TABLE clubs ADD COLUMN require_admin_mfa INTEGER DEFAULT 0;
+
+CREATE INDEX IF NOT EXISTS idx_mfa_recovery_codes_user_id ON mfa_recovery_codes(user_id);
+CREATE INDEX IF NOT EXISTS idx_mfa_challenges_token_hash ON mfa_challenges(token_hash);
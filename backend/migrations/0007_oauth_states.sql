@@ -0,0 +1,17 @@
+-- In-flight OAuth sign-ins (state_hash holds the SHA-256 of the state parameter,
+-- rows are deleted when the callback arrives)
+CREATE TABLE IF NOT EXISTS oauth_states (
+    id TEXT PRIMARY KEY,
+    state_hash TEXT UNIQUE NOT NULL,
+    provider TEXT NOT NULL,
+    mode TEXT NOT NULL,
+    user_id TEXT,
+    code_verifier TEXT NOT NULL,
+    nonce TEXT NOT NULL,
+    redirect_to TEXT,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_oauth_states_expires_at ON oauth_states(expires_at);
@@ -0,0 +1,16 @@
+-- Magic sign-in links (token holds the SHA-256 hash; rows without a user_id
+-- record requests for unknown addresses so they count towards the rate limits)
+CREATE TABLE IF NOT EXISTS magic_links (
+    id TEXT PRIMARY KEY,
+    user_id TEXT,
+    email TEXT NOT NULL,
+    token TEXT UNIQUE NOT NULL,
+    ip_address TEXT,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    used_at TEXT,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_magic_links_email ON magic_links(email, created_at);
+CREATE INDEX IF NOT EXISTS idx_magic_links_ip_address ON magic_links(ip_address, created_at);
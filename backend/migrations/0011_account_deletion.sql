@@ -0,0 +1,6 @@
+-- Set while a requested deletion waits out its grace period
+ALTER TABLE users ADD COLUMN deletion_scheduled_for TEXT;
+-- Set once the account has been anonymized
+ALTER TABLE users ADD COLUMN deleted_at TEXT;
+
+CREATE INDEX IF NOT EXISTS idx_users_deletion_scheduled_for ON users(deletion_scheduled_for);
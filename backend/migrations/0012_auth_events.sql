@@ -0,0 +1,17 @@
+-- Authentication audit log (event_type is one of login, login_failed, logout,
+-- password_changed, password_reset, session_revoked, other_sessions_revoked;
+-- device_hash identifies browser + network for new-device alerts)
+CREATE TABLE IF NOT EXISTS auth_events (
+    id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL,
+    event_type TEXT NOT NULL,
+    detail TEXT,
+    ip_address TEXT,
+    user_agent TEXT,
+    device_hash TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_auth_events_user_id ON auth_events(user_id, created_at);
+CREATE INDEX IF NOT EXISTS idx_auth_events_created_at ON auth_events(created_at);
@@ -0,0 +1,5 @@
+-- CSRF tokens are now stateless HMACs (see CSRF_PROTECTION.md)
+DROP INDEX IF EXISTS idx_csrf_tokens_user_id;
+DROP INDEX IF EXISTS idx_csrf_tokens_token;
+DROP INDEX IF EXISTS idx_csrf_tokens_expires_at;
+DROP TABLE IF EXISTS csrf_tokens;
@@ -0,0 +1,16 @@
+-- Platform invites (required to sign up when SIGNUP_MODE is "invite"; token_hash is the
+-- SHA-256 of the token, email optionally restricts who can redeem it)
+CREATE TABLE IF NOT EXISTS platform_invites (
+    id TEXT PRIMARY KEY,
+    token_hash TEXT UNIQUE NOT NULL,
+    email TEXT,
+    created_by TEXT NOT NULL,
+    expires_at TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    used_at TEXT,
+    used_by TEXT,
+    FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS idx_platform_invites_created_at ON platform_invites(created_at);
+CREATE INDEX IF NOT EXISTS idx_platform_invites_used_by ON platform_invites(used_by);
@@ -0,0 +1,16 @@
+-- Rendered messages waiting to be sent after the request that queued them
+-- (see src/email/queue.rs); rows are deleted once sent, claimed_at leases a
+-- row to the run sending it
+CREATE TABLE IF NOT EXISTS email_queue (
+    id TEXT PRIMARY KEY,
+    recipient TEXT NOT NULL,
+    subject TEXT NOT NULL,
+    template TEXT NOT NULL,
+    html_body TEXT NOT NULL,
+    text_body TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    claimed_at TEXT,
+    attempts INTEGER NOT NULL DEFAULT 0
+);
+
+CREATE INDEX IF NOT EXISTS idx_email_queue_created_at ON email_queue(created_at);
@@ -0,0 +1,134 @@
+//! Deleting an account: confirming the owner is present, and anonymizing the
+//! account once its grace period has run out.
+//!
+//! The user row is kept but anonymized so the events, announcements and
+//! projects they created stay with their clubs, credited to "Deleted user";
+//! memberships and every credential and personal record are removed. Forum
+//! content isn't persisted yet, so there is nothing of it to clear.
+
+use crate::handlers::mfa;
+use crate::models::{AuthUser, DeleteAccountRequest, Session};
+use crate::password;
+use crate::store::{Statement, Store, StoreError, StoreResult};
+use chrono::{DateTime, Duration, Utc};
+
+/// Accounts with neither a password nor two-factor confirm deletion by having
+/// signed in this recently.
+pub const RECENT_SIGN_IN_MINUTES: i64 = 10;
+
+/// What `process_due_deletions` did with one account.
+#[derive(Debug, PartialEq)]
+pub enum DeletionOutcome {
+    Erased,
+    /// The user has come to own a club since scheduling; the deletion waits
+    /// until it is handed over.
+    WaitingOnClubs,
+    Failed(StoreError),
+}
+
+/// Whether the request proves the owner is present: their password, a current
+/// authentication code, or for accounts with neither, a fresh sign-in.
+pub async fn reauthenticated<S: Store>(store: &S, auth_user: &AuthUser, session_id: &str, request: &DeleteAccountRequest, now: DateTime<Utc>) -> bool {
+    if let Some(password) = request.password.as_deref().filter(|p| !p.is_empty()) {
+        return password::verify_password(password, &auth_user.password_hash).await;
+    }
+
+    let has_mfa = store.user_has_mfa(&auth_user.id).await.unwrap_or(false);
+    if let Some(code) = request.code.as_deref().filter(|c| !c.is_empty()) {
+        return has_mfa && mfa::verify_current_code(store, &auth_user.id, code, now).await;
+    }
+
+    if !auth_user.password_hash.is_empty() || has_mfa {
+        return false;
+    }
+
+    let session: Option<Session> = store
+        .query_first_as("SELECT * FROM sessions WHERE id = ?1 AND user_id = ?2", &[session_id.into(), auth_user.id.as_str().into()])
+        .await
+        .unwrap_or(None);
+    let created_at = session.and_then(|session| DateTime::parse_from_rfc3339(&session.created_at).ok());
+
+    matches!(created_at, Some(created_at) if now - created_at.with_timezone(&Utc) < Duration::minutes(RECENT_SIGN_IN_MINUTES))
+}
+
+/// Names of the clubs the user owns. A club can't be left without an owner,
+/// so these block deletion until ownership has moved.
+pub async fn owned_club_names<S: Store>(store: &S, user_id: &str) -> StoreResult<Vec<String>> {
+    let rows = store.query("SELECT name FROM clubs WHERE owner_id = ?1 ORDER BY name", &[user_id.into()]).await?;
+    Ok(rows.iter().filter_map(|row| row["name"].as_str().map(|s| s.to_string())).collect())
+}
+
+/// Carries out deletions whose grace period has ended by `now`.
+pub async fn process_due_deletions<S: Store>(store: &S, now: DateTime<Utc>) -> StoreResult<Vec<(String, DeletionOutcome)>> {
+    let now = now.to_rfc3339();
+    let rows = store.query("
+        SELECT id FROM users
+        WHERE deletion_scheduled_for IS NOT NULL AND deletion_scheduled_for <= ?1
+    ", &[now.as_str().into()]).await?;
+
+    let mut outcomes = Vec::new();
+    for row in rows {
+        let Some(user_id) = row["id"].as_str() else {
+            continue;
+        };
+
+        // Fail closed: an unknown answer must not let deletion strand a club
+        let outcome = match owned_club_names(store, user_id).await {
+            Ok(clubs) if clubs.is_empty() => match erase_user(store, user_id, &now).await {
+                Ok(()) => DeletionOutcome::Erased,
+                Err(e) => DeletionOutcome::Failed(e),
+            },
+            Ok(_) => DeletionOutcome::WaitingOnClubs,
+            Err(e) => DeletionOutcome::Failed(e),
+        };
+        outcomes.push((user_id.to_string(), outcome));
+    }
+    Ok(outcomes)
+}
+
+/// Anonymizes the user and removes everything personal to them.
+pub async fn erase_user<S: Store>(store: &S, user_id: &str, now: &str) -> StoreResult<()> {
+    let email = match store.user_by_id(user_id).await? {
+        Some(user) => user.email,
+        None => return Ok(()),
+    };
+
+    let by_user = |sql: &'static str| -> Statement { (sql, vec![user_id.into()]) };
+
+    // One batch, so a failure part way leaves the account as it was
+    store.batch(vec![
+        by_user("DELETE FROM members WHERE user_id = ?1"),
+        by_user("DELETE FROM refresh_tokens WHERE user_id = ?1"),
+        by_user("DELETE FROM sessions WHERE user_id = ?1"),
+        by_user("DELETE FROM email_verifications WHERE user_id = ?1"),
+        by_user("DELETE FROM password_resets WHERE user_id = ?1"),
+        ("DELETE FROM magic_links WHERE user_id = ?1 OR email = ?2", vec![user_id.into(), email.as_str().into()]),
+        by_user("DELETE FROM user_mfa WHERE user_id = ?1"),
+        by_user("DELETE FROM mfa_recovery_codes WHERE user_id = ?1"),
+        by_user("DELETE FROM mfa_challenges WHERE user_id = ?1"),
+        by_user("DELETE FROM passkeys WHERE user_id = ?1"),
+        by_user("DELETE FROM webauthn_challenges WHERE user_id = ?1"),
+        by_user("DELETE FROM oauth_states WHERE user_id = ?1"),
+        by_user("DELETE FROM social_accounts WHERE user_id = ?1"),
+        by_user("DELETE FROM personal_access_tokens WHERE user_id = ?1"),
+        by_user("DELETE FROM auth_events WHERE user_id = ?1"),
+        ("DELETE FROM email_outbox WHERE recipient = ?1", vec![email.as_str().into()]),
+        ("DELETE FROM email_queue WHERE recipient = ?1", vec![email.as_str().into()]),
+        ("
+            UPDATE users SET
+                email = 'deleted-' || id || '@deleted.invalid',
+                name = 'Deleted user',
+                avatar = NULL,
+                password_hash = '',
+                email_verified = 0,
+                is_active = 0,
+                last_login = NULL,
+                failed_login_attempts = 0,
+                locked_until = NULL,
+                deletion_scheduled_for = NULL,
+                deleted_at = ?1,
+                updated_at = ?1
+            WHERE id = ?2
+        ", vec![now.into(), user_id.into()]),
+    ]).await
+}
@@ -0,0 +1,110 @@
+//! Applies the database migrations in `src/migrations.rs`.
+//!
+//!     cargo run --bin migrate -- status --d1 DB
+//!     cargo run --bin migrate -- up --d1 DB --remote --env production
+//!     cargo run --bin migrate -- up --sqlite local.db
+//!
+//! `--d1` goes through wrangler, so run it from the directory holding
+//! `wrangler.toml`. It targets the local database unless `--remote` is given.
+
+use backend::migrations::{self, MigrationError, MigrationTarget};
+use backend::store::{block_on, SqliteStore, WranglerD1};
+use chrono::Utc;
+use std::process::ExitCode;
+
+const USAGE: &str = "usage: migrate <status|up> (--sqlite PATH | --d1 DATABASE [--remote] [--env NAME])";
+
+enum Command {
+    Status,
+    Up,
+}
+
+enum Target {
+    Sqlite(String),
+    D1(WranglerD1),
+}
+
+fn main() -> ExitCode {
+    let (command, target) = match parse_args(std::env::args().skip(1).collect()) {
+        Ok(args) => args,
+        Err(message) => {
+            eprintln!("{}\n{}", message, USAGE);
+            return ExitCode::from(2);
+        }
+    };
+
+    let result = match target {
+        Target::Sqlite(path) => match SqliteStore::open(&path) {
+            Ok(store) => run(&command, &store),
+            Err(error) => Err(error.into()),
+        },
+        Target::D1(d1) => run(&command, &d1),
+    };
+
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            eprintln!("error: {}", error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run<D: MigrationTarget>(command: &Command, db: &D) -> Result<(), MigrationError> {
+    let all = migrations::all();
+
+    match command {
+        Command::Status => {
+            for migration in block_on(migrations::status(db, &all))? {
+                let state = match migration.applied_at {
+                    Some(applied_at) => format!("applied {}", applied_at),
+                    None => "pending".to_string(),
+                };
+                println!("{:04} {:<40} {}", migration.version, migration.name, state);
+            }
+        }
+        Command::Up => {
+            let applied = block_on(migrations::migrate(db, &all, Utc::now()))?;
+            if applied.is_empty() {
+                println!("Already up to date");
+            }
+            for version in applied {
+                let migration = all.iter().find(|migration| migration.version == version);
+                println!("Applied {:04} {}", version, migration.map_or("", |migration| migration.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_args(args: Vec<String>) -> Result<(Command, Target), String> {
+    let mut args = args.into_iter();
+    let command = match args.next().as_deref() {
+        Some("status") => Command::Status,
+        Some("up") => Command::Up,
+        Some(other) => return Err(format!("unknown command `{}`", other)),
+        None => return Err("missing command".to_string()),
+    };
+
+    let mut sqlite = None;
+    let mut database = None;
+    let mut remote = false;
+    let mut env = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--sqlite" => sqlite = Some(args.next().ok_or("--sqlite needs a path")?),
+            "--d1" => database = Some(args.next().ok_or("--d1 needs a database name")?),
+            "--remote" => remote = true,
+            "--env" => env = Some(args.next().ok_or("--env needs a name")?),
+            other => return Err(format!("unknown option `{}`", other)),
+        }
+    }
+
+    let target = match (sqlite, database) {
+        (Some(path), None) if !remote && env.is_none() => Target::Sqlite(path),
+        (Some(_), None) => return Err("--remote and --env only apply to --d1".to_string()),
+        (None, Some(database)) => Target::D1(WranglerD1 { database, remote, env }),
+        _ => return Err("give exactly one of --sqlite and --d1".to_string()),
+    };
+    Ok((command, target))
+}
@@ -0,0 +1,129 @@
+//! Cross-origin access for the frontend.
+//!
+//! The allowed origins come from the `ALLOWED_ORIGINS` var, a comma-separated
+//! list set per environment in wrangler.toml. An entry is either an exact
+//! origin (`https://nivaro-frontend.pages.dev`) or a wildcard subdomain pattern
+//! (`https://*.nivaro-frontend.pages.dev`), which matches any subdomain at any
+//! depth but not the bare domain. Scheme and port must always match exactly.
+//!
+//! Responses to any other origin carry no CORS headers at all, so the browser
+//! withholds them from the calling page. Nothing is allowed when the var is
+//! missing; there is deliberately no fallback origin.
+
+use worker::Env;
+
+pub const ALLOWED_METHODS: &str = "GET, POST, PUT, DELETE, OPTIONS";
+pub const ALLOWED_HEADERS: &str = "Content-Type, Authorization, X-CSRF-Token";
+const PREFLIGHT_MAX_AGE_SECONDS: &str = "86400";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OriginPattern {
+    Exact(String),
+    /// `<scheme>://*.<domain>[:port]`, kept as the scheme prefix and the
+    /// `.<domain>[:port]` suffix a matching origin must end with.
+    Subdomains { scheme: String, suffix: String },
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CorsPolicy {
+    patterns: Vec<OriginPattern>,
+}
+
+impl CorsPolicy {
+    /// Parses a comma-separated allowlist. Entries that aren't a bare
+    /// `scheme://host[:port]` origin (paths, a `*` anywhere but the first
+    /// label, stray whitespace inside) are skipped.
+    pub fn parse(list: &str) -> Self {
+        let patterns = list
+            .split(',')
+            .map(|entry| entry.trim().trim_end_matches('/').to_ascii_lowercase())
+            .filter_map(|entry| parse_pattern(&entry))
+            .collect();
+
+        CorsPolicy { patterns }
+    }
+
+    /// Reads `ALLOWED_ORIGINS`; an unset var allows nothing.
+    pub fn from_env(env: &Env) -> Self {
+        env.var("ALLOWED_ORIGINS")
+            .map(|v| Self::parse(&v.to_string()))
+            .unwrap_or_default()
+    }
+
+    pub fn allows(&self, origin: &str) -> bool {
+        let origin = origin.to_ascii_lowercase();
+        let Some((scheme, authority)) = split_origin(&origin) else {
+            return false;
+        };
+
+        self.patterns.iter().any(|pattern| match pattern {
+            OriginPattern::Exact(allowed) => *allowed == origin,
+            OriginPattern::Subdomains { scheme: allowed_scheme, suffix } => {
+                scheme == allowed_scheme
+                    && authority.len() > suffix.len()
+                    && authority.ends_with(suffix.as_str())
+                    && is_valid_authority(authority)
+            }
+        })
+    }
+
+    /// Headers for a preflight response, or `None` if `origin` isn't allowed.
+    pub fn preflight_headers(&self, origin: Option<&str>) -> Option<Vec<(&'static str, String)>> {
+        let mut headers = self.response_headers(origin)?;
+        headers.push(("Access-Control-Max-Age", PREFLIGHT_MAX_AGE_SECONDS.to_string()));
+        Some(headers)
+    }
+
+    /// Headers for an ordinary (credentialed) response, or `None` if `origin`
+    /// isn't allowed.
+    pub fn response_headers(&self, origin: Option<&str>) -> Option<Vec<(&'static str, String)>> {
+        let origin = origin.filter(|origin| self.allows(origin))?;
+
+        Some(vec![
+            ("Access-Control-Allow-Origin", origin.to_string()),
+            ("Access-Control-Allow-Credentials", "true".to_string()),
+            ("Access-Control-Allow-Methods", ALLOWED_METHODS.to_string()),
+            ("Access-Control-Allow-Headers", ALLOWED_HEADERS.to_string()),
+        ])
+    }
+}
+
+fn parse_pattern(entry: &str) -> Option<OriginPattern> {
+    let (scheme, authority) = split_origin(entry)?;
+
+    match authority.strip_prefix("*.") {
+        Some(domain) if is_valid_authority(domain) => Some(OriginPattern::Subdomains {
+            scheme: scheme.to_string(),
+            suffix: format!(".{}", domain),
+        }),
+        Some(_) => None,
+        None if is_valid_authority(authority) => Some(OriginPattern::Exact(entry.to_string())),
+        None => None,
+    }
+}
+
+/// Splits `scheme://authority`, refusing anything with a path, query,
+/// credentials or an empty host.
+fn split_origin(origin: &str) -> Option<(&str, &str)> {
+    let (scheme, authority) = origin.split_once("://")?;
+    if !matches!(scheme, "http" | "https") || authority.is_empty() {
+        return None;
+    }
+    if authority.contains(|c: char| matches!(c, '/' | '?' | '#' | '@') || c.is_whitespace()) {
+        return None;
+    }
+    Some((scheme, authority))
+}
+
+fn is_valid_authority(authority: &str) -> bool {
+    let host = match authority.rsplit_once(':') {
+        Some((host, port)) if !port.is_empty() && port.bytes().all(|b| b.is_ascii_digit()) => host,
+        Some(_) => return false,
+        None => authority,
+    };
+
+    !host.is_empty()
+        && host.split('.').all(|label| {
+            !label.is_empty() && label.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'-')
+        })
+}
@@ -0,0 +1,92 @@
+//! Stateless CSRF tokens.
+//!
+//! A token is `<issued_at>.<mac>`, where `mac` is HMAC-SHA256 over the session
+//! id and `issued_at` under the `CSRF_SECRET` secret. Verifying one only needs
+//! the session id from the (already signed) access token, so no token is ever
+//! stored and checking a mutation costs no database round-trip. A token dies
+//! with its session or after `TOKEN_TTL_SECONDS`, whichever comes first.
+//!
+//! The browser's `Origin` and `Sec-Fetch-Site` headers are checked as well, so
+//! a cross-site request is refused even before its token is looked at.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use worker::Env;
+
+pub const TOKEN_TTL_SECONDS: i64 = 3600;
+// Shorter secrets are refused rather than quietly weakening every token
+const MIN_SECRET_LENGTH: usize = 32;
+// Leeway for clocks that disagree between isolates
+const MAX_CLOCK_SKEW_SECONDS: i64 = 60;
+
+pub struct CsrfKey {
+    secret: Vec<u8>,
+}
+
+impl CsrfKey {
+    pub fn new(secret: &[u8]) -> Option<Self> {
+        if secret.len() < MIN_SECRET_LENGTH {
+            return None;
+        }
+        Some(CsrfKey { secret: secret.to_vec() })
+    }
+
+    /// Reads the `CSRF_SECRET` secret; `None` if it is missing or too short.
+    pub fn from_env(env: &Env) -> Option<Self> {
+        let secret = env.secret("CSRF_SECRET").ok()?.to_string();
+        Self::new(secret.as_bytes())
+    }
+
+    /// A token for `session_id` issued at unix time `now`.
+    pub fn issue(&self, session_id: &str, now: i64) -> String {
+        let mac = self.mac(session_id, now).finalize().into_bytes();
+        format!("{}.{}", now, URL_SAFE_NO_PAD.encode(mac))
+    }
+
+    /// Whether `token` was issued by this key for `session_id` and is still
+    /// fresh at unix time `now`. The MAC comparison is constant time.
+    pub fn verify(&self, session_id: &str, token: &str, now: i64) -> bool {
+        let Some((issued_at, mac)) = token.split_once('.') else {
+            return false;
+        };
+        let Ok(issued_at) = issued_at.parse::<i64>() else {
+            return false;
+        };
+        let Ok(mac) = URL_SAFE_NO_PAD.decode(mac) else {
+            return false;
+        };
+
+        if issued_at > now + MAX_CLOCK_SKEW_SECONDS || now - issued_at >= TOKEN_TTL_SECONDS {
+            return false;
+        }
+
+        self.mac(session_id, issued_at).verify_slice(&mac).is_ok()
+    }
+
+    fn mac(&self, session_id: &str, issued_at: i64) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).expect("HMAC accepts keys of any length");
+        mac.update(session_id.as_bytes());
+        mac.update(b".");
+        mac.update(issued_at.to_string().as_bytes());
+        mac
+    }
+}
+
+/// Whether a state-changing request may have come from one of our own pages.
+///
+/// `Sec-Fetch-Site` and `Origin` can't be set by page scripts, so a browser
+/// reporting either one truthfully gives a forged request away. The frontend
+/// may live on another site than the API, so a cross-site request is fine as
+/// long as its `Origin` is allowed. Clients that send neither header (curl,
+/// server-to-server) are left to the token check.
+pub fn origin_allowed(sec_fetch_site: Option<&str>, origin: Option<&str>, is_allowed: impl Fn(&str) -> bool) -> bool {
+    if sec_fetch_site == Some("same-origin") {
+        return true;
+    }
+
+    match origin {
+        Some(origin) => is_allowed(origin),
+        None => sec_fetch_site != Some("cross-site"),
+    }
+}
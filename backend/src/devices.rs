@@ -0,0 +1,107 @@
+//! Describing the browser and network a request comes from, coarsely enough
+//! to show back to the user, and recognising a sign-in from one the account
+//! hasn't used before.
+
+use crate::store::{Database, DecodeError, FromRow, Row, StoreResult};
+use sha2::{Digest, Sha256};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// What the audit log says about an account's earlier sign-ins.
+#[derive(Debug, PartialEq)]
+pub struct LoginHistory {
+    pub has_logins: bool,
+    pub seen_device: bool,
+}
+
+impl LoginHistory {
+    /// Whether a sign-in is worth telling the owner about. The very first one
+    /// isn't; there is nothing to compare it with.
+    pub fn is_new_device(&self) -> bool {
+        self.has_logins && !self.seen_device
+    }
+}
+
+impl FromRow for LoginHistory {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(LoginHistory {
+            has_logins: row.get("has_logins")?,
+            seen_device: row.get("seen_device")?,
+        })
+    }
+}
+
+/// Reduces a User-Agent header to something like "Chrome on macOS".
+pub fn summarize_user_agent(user_agent: &str) -> String {
+    // Order matters: Edge and Opera also claim Chrome, Chrome also claims Safari
+    let browser = if user_agent.contains("Edg/") {
+        "Edge"
+    } else if user_agent.contains("OPR/") {
+        "Opera"
+    } else if user_agent.contains("Firefox/") {
+        "Firefox"
+    } else if user_agent.contains("Chrome/") {
+        "Chrome"
+    } else if user_agent.contains("Safari/") {
+        "Safari"
+    } else {
+        "Unknown browser"
+    };
+
+    // iOS claims to be "like Mac OS X" and Android claims Linux
+    let os = if user_agent.contains("iPhone") || user_agent.contains("iPad") {
+        "iOS"
+    } else if user_agent.contains("Android") {
+        "Android"
+    } else if user_agent.contains("Windows") {
+        "Windows"
+    } else if user_agent.contains("Mac OS X") {
+        "macOS"
+    } else if user_agent.contains("Linux") {
+        "Linux"
+    } else {
+        "unknown OS"
+    };
+
+    format!("{} on {}", browser, os)
+}
+
+/// Drops the host part of an address (the last IPv4 octet, everything after
+/// the first three IPv6 groups) so lists show roughly where, not exactly
+/// where. IPv6 is parsed rather than split on `:`, so compressed forms such as
+/// `2001:db8::1` keep the right groups.
+pub fn mask_ip_address(ip: &str) -> String {
+    match ip.trim().parse::<IpAddr>() {
+        Ok(IpAddr::V4(ip)) => mask_ipv4(ip),
+        // An IPv4 client seen through a dual-stack socket
+        Ok(IpAddr::V6(ip)) => match ip.to_ipv4_mapped() {
+            Some(ip) => mask_ipv4(ip),
+            None => {
+                let [a, b, c, ..] = ip.segments();
+                Ipv6Addr::new(a, b, c, 0, 0, 0, 0, 0).to_string()
+            }
+        },
+        Err(_) => ip.to_string(),
+    }
+}
+
+fn mask_ipv4(ip: Ipv4Addr) -> String {
+    let [a, b, c, _] = ip.octets();
+    format!("{}.{}.{}.x", a, b, c)
+}
+
+// Coarse on purpose: browser and OS rather than the full User-Agent, and the
+// network rather than the exact address, so updates and DHCP don't read as new devices
+pub fn device_hash(user_agent: Option<&str>, ip_address: Option<&str>) -> String {
+    let device = user_agent.map(summarize_user_agent).unwrap_or_default();
+    let network = ip_address.map(mask_ip_address).unwrap_or_default();
+    format!("{:x}", Sha256::digest(format!("{}|{}", device, network).as_bytes()))
+}
+
+pub async fn login_history<D: Database>(db: &D, user_id: &str, device_hash: &str) -> StoreResult<LoginHistory> {
+    let history = db.query_first_as("
+        SELECT
+            EXISTS(SELECT 1 FROM auth_events WHERE user_id = ?1 AND event_type = 'login') as has_logins,
+            EXISTS(SELECT 1 FROM auth_events WHERE user_id = ?1 AND event_type = 'login' AND device_hash = ?2) as seen_device
+    ", &[user_id.into(), device_hash.into()]).await?;
+    Ok(history.unwrap_or(LoginHistory { has_logins: false, seen_device: false }))
+}
@@ -0,0 +1,170 @@
+use chrono::Utc;
+use uuid::Uuid;
+use worker::*;
+
+mod queue;
+mod templates;
+
+pub use queue::{claim_queued, deliver_queued, enqueue, send_queued, QueuedEmail, DELIVERY_BATCH_SIZE};
+pub use templates::EmailTemplate;
+
+/// A fully rendered message ready to hand to a provider.
+#[derive(Debug, Clone)]
+pub struct EmailMessage {
+    pub to: String,
+    pub subject: String,
+    pub html_body: String,
+    pub text_body: String,
+    pub template: String,
+}
+
+impl EmailMessage {
+    pub fn new(to: &str, template: &EmailTemplate) -> Self {
+        Self {
+            to: to.to_string(),
+            subject: template.subject(),
+            html_body: template.html_body(),
+            text_body: template.text_body(),
+            template: template.name().to_string(),
+        }
+    }
+}
+
+/// Something that can deliver an `EmailMessage`.
+#[allow(async_fn_in_trait)] // Workers are single-threaded, so the futures never need to be Send
+pub trait EmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<()>;
+}
+
+/// Delivers mail through a JSON HTTP API (`POST {from, to, subject, html, text}`
+/// with a bearer key), which is the shape Resend, Postmark-style relays and
+/// most transactional providers accept.
+pub struct HttpEmailSender {
+    endpoint: String,
+    api_key: String,
+    from: String,
+}
+
+impl HttpEmailSender {
+    pub fn new(endpoint: String, api_key: String, from: String) -> Self {
+        Self { endpoint, api_key, from }
+    }
+}
+
+impl EmailSender for HttpEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<()> {
+        let body = serde_json::json!({
+            "from": self.from,
+            "to": [message.to],
+            "subject": message.subject,
+            "html": message.html_body,
+            "text": message.text_body,
+        });
+
+        let headers = Headers::new();
+        headers.set("Content-Type", "application/json")?;
+        headers.set("Authorization", &format!("Bearer {}", self.api_key))?;
+
+        let mut init = RequestInit::new();
+        init.with_method(Method::Post)
+            .with_headers(headers)
+            .with_body(Some(body.to_string().into()));
+
+        let request = Request::new_with_init(&self.endpoint, &init)?;
+        let response = Fetch::Request(request).send().await?;
+
+        match response.status_code() {
+            200..=299 => Ok(()),
+            status => Err(Error::RustError(format!("Email provider responded with status {}", status))),
+        }
+    }
+}
+
+/// Writes rendered messages to the `email_outbox` table instead of sending them,
+/// so local development and tests can read back exactly what would have gone out.
+pub struct OutboxEmailSender {
+    db: D1Database,
+}
+
+impl OutboxEmailSender {
+    pub fn new(db: D1Database) -> Self {
+        Self { db }
+    }
+}
+
+impl EmailSender for OutboxEmailSender {
+    async fn send(&self, message: &EmailMessage) -> Result<()> {
+        let stmt = self.db.prepare("
+            INSERT INTO email_outbox (id, recipient, subject, template, html_body, text_body, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ");
+        stmt.bind(&[
+            Uuid::new_v4().to_string().into(),
+            message.to.clone().into(),
+            message.subject.clone().into(),
+            message.template.clone().into(),
+            message.html_body.clone().into(),
+            message.text_body.clone().into(),
+            Utc::now().to_rfc3339().into(),
+        ])?.run().await?;
+
+        Ok(())
+    }
+}
+
+/// The sender selected by the `EMAIL_PROVIDER` variable: `"http"` for the
+/// HTTP API provider, anything else (including unset) for the D1 outbox.
+pub enum Mailer {
+    Http(HttpEmailSender),
+    Outbox(OutboxEmailSender),
+}
+
+impl Mailer {
+    pub fn from_env(env: &Env) -> Result<Self> {
+        let provider = env.var("EMAIL_PROVIDER").map(|v| v.to_string()).unwrap_or_default();
+
+        match provider.as_str() {
+            "http" => {
+                let endpoint = env.var("EMAIL_API_URL")?.to_string();
+                let api_key = env.secret("EMAIL_API_KEY")?.to_string();
+                let from = env.var("EMAIL_FROM")?.to_string();
+                Ok(Mailer::Http(HttpEmailSender::new(endpoint, api_key, from)))
+            }
+            _ => Ok(Mailer::Outbox(OutboxEmailSender::new(env.d1("DB")?))),
+        }
+    }
+}
+
+impl EmailSender for Mailer {
+    async fn send(&self, message: &EmailMessage) -> Result<()> {
+        match self {
+            Mailer::Http(sender) => sender.send(message).await,
+            Mailer::Outbox(sender) => sender.send(message).await,
+        }
+    }
+}
+
+/// Renders `template` and sends it to `to` with the configured provider.
+///
+/// Delivery failures are logged rather than returned: callers are request
+/// handlers that must not change their response based on whether mail went out.
+pub async fn send_email(env: &Env, to: &str, template: EmailTemplate) {
+    let message = EmailMessage::new(to, &template);
+
+    let result = match Mailer::from_env(env) {
+        Ok(mailer) => mailer.send(&message).await,
+        Err(e) => Err(e),
+    };
+
+    if let Err(e) = result {
+        console_log!("Failed to send {} email: {}", message.template, e);
+    }
+}
+
+/// Builds an absolute frontend URL from the `APP_URL` variable.
+pub fn app_link(env: &Env, path: &str) -> String {
+    let base = env.var("APP_URL")
+        .map(|v| v.to_string())
+        .unwrap_or_else(|_| "http://localhost:3000".to_string());
+    format!("{}{}", base.trim_end_matches('/'), path)
+}
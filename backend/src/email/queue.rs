@@ -0,0 +1,119 @@
+//! Mail sent after the response rather than inside the request.
+//!
+//! A fan-out such as an announcement to every club member would otherwise
+//! cost the request one provider call per recipient, and a Worker invocation
+//! only gets a limited number of subrequests. Messages are rendered into the
+//! `email_queue` table in one batch instead; `send_queued` then delivers them
+//! a batch at a time, first straight after the response and afterwards from
+//! the cron trigger until the queue is empty.
+
+use super::{EmailMessage, EmailSender, Mailer};
+use crate::store::{D1Store, Database, DecodeError, FromRow, Row, Statement, StoreResult};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+use worker::{console_log, Env};
+
+/// How many messages one invocation sends. The free plan allows 50
+/// subrequests per invocation; this leaves room for the D1 calls around them.
+pub const DELIVERY_BATCH_SIZE: i64 = 40;
+// A claimed message that is still queued this long after was never confirmed
+// sent, so its invocation died and another may pick it up
+const CLAIM_LEASE_MINUTES: i64 = 10;
+const MAX_DELIVERY_ATTEMPTS: i64 = 5;
+
+/// A row of `email_queue`.
+pub struct QueuedEmail {
+    pub id: String,
+    pub message: EmailMessage,
+    pub attempts: i64,
+}
+
+impl FromRow for QueuedEmail {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(QueuedEmail {
+            id: row.get("id")?,
+            message: EmailMessage {
+                to: row.get("recipient")?,
+                subject: row.get("subject")?,
+                html_body: row.get("html_body")?,
+                text_body: row.get("text_body")?,
+                template: row.get("template")?,
+            },
+            attempts: row.get("attempts")?,
+        })
+    }
+}
+
+/// Queues `messages` in a single batch.
+pub async fn enqueue<D: Database>(db: &D, messages: &[EmailMessage], now: DateTime<Utc>) -> StoreResult<()> {
+    let statements: Vec<Statement> = messages
+        .iter()
+        .map(|message| {
+            ("
+                INSERT INTO email_queue (id, recipient, subject, template, html_body, text_body, created_at)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+            ", vec![
+                Uuid::new_v4().to_string().into(),
+                message.to.as_str().into(),
+                message.subject.as_str().into(),
+                message.template.as_str().into(),
+                message.html_body.as_str().into(),
+                message.text_body.as_str().into(),
+                now.to_rfc3339().into(),
+            ])
+        })
+        .collect();
+
+    if statements.is_empty() {
+        return Ok(());
+    }
+    db.batch(statements).await
+}
+
+/// Takes up to `limit` messages off the queue, oldest first. A claimed message
+/// isn't handed out again until its lease runs out, so overlapping runs don't
+/// send it twice.
+pub async fn claim_queued<D: Database>(db: &D, limit: i64, now: DateTime<Utc>) -> StoreResult<Vec<QueuedEmail>> {
+    let lease_expired = (now - Duration::minutes(CLAIM_LEASE_MINUTES)).to_rfc3339();
+    db.query_as("
+        UPDATE email_queue SET claimed_at = ?1, attempts = attempts + 1
+        WHERE id IN (
+            SELECT id FROM email_queue
+            WHERE (claimed_at IS NULL OR claimed_at < ?2) AND attempts < ?3
+            ORDER BY created_at LIMIT ?4
+        )
+        RETURNING *
+    ", &[now.to_rfc3339().into(), lease_expired.into(), MAX_DELIVERY_ATTEMPTS.into(), limit.into()])
+        .await
+}
+
+/// Sends up to `limit` queued messages through `sender` and returns how many
+/// went out. A message that fails stays queued and is retried once its lease
+/// runs out, up to `MAX_DELIVERY_ATTEMPTS` times.
+pub async fn deliver_queued<D: Database, S: EmailSender>(db: &D, sender: &S, limit: i64, now: DateTime<Utc>) -> StoreResult<usize> {
+    let mut sent = 0;
+    for queued in claim_queued(db, limit, now).await? {
+        // A failure stays in the queue; after the last attempt it is never
+        // claimed again but is kept for someone to look into
+        if sender.send(&queued.message).await.is_ok() {
+            db.execute("DELETE FROM email_queue WHERE id = ?1", &[queued.id.as_str().into()]).await?;
+            sent += 1;
+        }
+    }
+    Ok(sent)
+}
+
+/// Sends one batch from the queue with the configured provider. Run after a
+/// response that queued mail, and from the cron trigger.
+pub async fn send_queued(env: &Env) {
+    let result = match (env.d1("DB"), Mailer::from_env(env)) {
+        (Ok(db), Ok(mailer)) => deliver_queued(&D1Store::new(&db), &mailer, DELIVERY_BATCH_SIZE, Utc::now())
+            .await
+            .map_err(worker::Error::from),
+        (Err(e), _) | (_, Err(e)) => Err(e),
+    };
+
+    if let Err(e) = result {
+        console_log!("Failed to send queued email: {}", e);
+    }
+}
@@ -0,0 +1,181 @@
+/// Every kind of email the platform sends. Each variant renders to a subject,
+/// an HTML body and a plain-text body from the same data.
+#[derive(Debug, Clone)]
+pub enum EmailTemplate {
+    Verification {
+        name: String,
+        link: String,
+    },
+    PasswordReset {
+        name: String,
+        link: String,
+    },
+    MagicLink {
+        name: String,
+        link: String,
+    },
+    NewDeviceLogin {
+        name: String,
+        device: String,
+        location: String,
+        time: String,
+        link: String,
+    },
+    ClubInvite {
+        club_name: String,
+        inviter_name: String,
+        invite_code: String,
+        link: String,
+    },
+    PlatformInvite {
+        inviter_name: String,
+        link: String,
+        expires_at: String,
+    },
+    Announcement {
+        club_name: String,
+        title: String,
+        content: String,
+        link: String,
+    },
+}
+
+impl EmailTemplate {
+    /// Stable identifier recorded alongside outbox messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmailTemplate::Verification { .. } => "verification",
+            EmailTemplate::PasswordReset { .. } => "password_reset",
+            EmailTemplate::MagicLink { .. } => "magic_link",
+            EmailTemplate::NewDeviceLogin { .. } => "new_device_login",
+            EmailTemplate::ClubInvite { .. } => "club_invite",
+            EmailTemplate::PlatformInvite { .. } => "platform_invite",
+            EmailTemplate::Announcement { .. } => "announcement",
+        }
+    }
+
+    pub fn subject(&self) -> String {
+        match self {
+            EmailTemplate::Verification { .. } => "Verify your Nivaro email address".to_string(),
+            EmailTemplate::PasswordReset { .. } => "Reset your Nivaro password".to_string(),
+            EmailTemplate::MagicLink { .. } => "Your Nivaro sign-in link".to_string(),
+            EmailTemplate::NewDeviceLogin { .. } => "New sign-in to your Nivaro account".to_string(),
+            EmailTemplate::ClubInvite { club_name, .. } => format!("You're invited to join {} on Nivaro", club_name),
+            EmailTemplate::PlatformInvite { .. } => "You're invited to Nivaro".to_string(),
+            EmailTemplate::Announcement { club_name, title, .. } => format!("[{}] {}", club_name, title),
+        }
+    }
+
+    pub fn text_body(&self) -> String {
+        match self {
+            EmailTemplate::Verification { name, link } => format!(
+                "Hi {},\n\nPlease confirm your email address by opening the link below:\n\n{}\n\n\
+                 The link expires in 24 hours. If you didn't create a Nivaro account, you can ignore this email.\n",
+                name, link
+            ),
+            EmailTemplate::PasswordReset { name, link } => format!(
+                "Hi {},\n\nWe received a request to reset your password. Open the link below to choose a new one:\n\n{}\n\n\
+                 The link expires in 1 hour and can only be used once. If you didn't ask for this, you can ignore this email.\n",
+                name, link
+            ),
+            EmailTemplate::MagicLink { name, link } => format!(
+                "Hi {},\n\nOpen the link below to sign in to Nivaro:\n\n{}\n\n\
+                 The link expires in 15 minutes and can only be used once. If you didn't ask for this, you can ignore this email.\n",
+                name, link
+            ),
+            EmailTemplate::NewDeviceLogin { name, device, location, time, link } => format!(
+                "Hi {},\n\nYour Nivaro account was just signed in to from a device we haven't seen before:\n\n\
+                 Device: {}\nNetwork: {}\nTime: {}\n\n\
+                 If this was you, there's nothing to do. If not, change your password and sign out the session here:\n\n{}\n",
+                name, device, location, time, link
+            ),
+            EmailTemplate::ClubInvite { club_name, inviter_name, invite_code, link } => format!(
+                "{} has invited you to join {} on Nivaro.\n\nJoin here: {}\n\nOr enter this invite code: {}\n",
+                inviter_name, club_name, link, invite_code
+            ),
+            EmailTemplate::PlatformInvite { inviter_name, link, expires_at } => format!(
+                "{} has invited you to create a Nivaro account.\n\nSign up here: {}\n\n\
+                 The invite can be used once and expires on {}.\n",
+                inviter_name, link, expires_at
+            ),
+            EmailTemplate::Announcement { club_name, title, content, link } => format!(
+                "New announcement in {}\n\n{}\n\n{}\n\nView it on Nivaro: {}\n",
+                club_name, title, content, link
+            ),
+        }
+    }
+
+    pub fn html_body(&self) -> String {
+        let body = match self {
+            EmailTemplate::Verification { name, link } => format!(
+                "<p>Hi {},</p>\
+                 <p>Please confirm your email address.</p>\
+                 <p><a href=\"{}\">Verify email address</a></p>\
+                 <p>The link expires in 24 hours. If you didn't create a Nivaro account, you can ignore this email.</p>",
+                escape_html(name), escape_html(link)
+            ),
+            EmailTemplate::PasswordReset { name, link } => format!(
+                "<p>Hi {},</p>\
+                 <p>We received a request to reset your password.</p>\
+                 <p><a href=\"{}\">Choose a new password</a></p>\
+                 <p>The link expires in 1 hour and can only be used once. If you didn't ask for this, you can ignore this email.</p>",
+                escape_html(name), escape_html(link)
+            ),
+            EmailTemplate::MagicLink { name, link } => format!(
+                "<p>Hi {},</p>\
+                 <p>Use the button below to sign in to Nivaro.</p>\
+                 <p><a href=\"{}\">Sign in</a></p>\
+                 <p>The link expires in 15 minutes and can only be used once. If you didn't ask for this, you can ignore this email.</p>",
+                escape_html(name), escape_html(link)
+            ),
+            EmailTemplate::NewDeviceLogin { name, device, location, time, link } => format!(
+                "<p>Hi {},</p>\
+                 <p>Your Nivaro account was just signed in to from a device we haven't seen before:</p>\
+                 <p>Device: {}<br>Network: {}<br>Time: {}</p>\
+                 <p>If this was you, there's nothing to do. If not, change your password and sign out the session.</p>\
+                 <p><a href=\"{}\">Review your sessions</a></p>",
+                escape_html(name), escape_html(device), escape_html(location), escape_html(time), escape_html(link)
+            ),
+            EmailTemplate::ClubInvite { club_name, inviter_name, invite_code, link } => format!(
+                "<p>{} has invited you to join <strong>{}</strong> on Nivaro.</p>\
+                 <p><a href=\"{}\">Join the club</a></p>\
+                 <p>Or enter this invite code: <code>{}</code></p>",
+                escape_html(inviter_name), escape_html(club_name), escape_html(link), escape_html(invite_code)
+            ),
+            EmailTemplate::PlatformInvite { inviter_name, link, expires_at } => format!(
+                "<p>{} has invited you to create a Nivaro account.</p>\
+                 <p><a href=\"{}\">Sign up</a></p>\
+                 <p>The invite can be used once and expires on {}.</p>",
+                escape_html(inviter_name), escape_html(link), escape_html(expires_at)
+            ),
+            EmailTemplate::Announcement { club_name, title, content, link } => format!(
+                "<p>New announcement in <strong>{}</strong></p>\
+                 <h2>{}</h2>\
+                 <p>{}</p>\
+                 <p><a href=\"{}\">View it on Nivaro</a></p>",
+                escape_html(club_name), escape_html(title),
+                escape_html(content).replace('\n', "<br>"), escape_html(link)
+            ),
+        };
+
+        format!(
+            "<!DOCTYPE html><html><body style=\"font-family: sans-serif; line-height: 1.5;\">{}</body></html>",
+            body
+        )
+    }
+}
+
+fn escape_html(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
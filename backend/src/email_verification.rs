@@ -0,0 +1,66 @@
+//! Redeeming email verification links.
+//!
+//! A link proves ownership of the address it was sent to, which becomes the
+//! account's address if it came from an email change. A link is only used up
+//! once the account has actually been updated, so one that can't be applied
+//! yet still works later.
+
+use crate::models::EmailVerification;
+use crate::store::{Database, StoreResult};
+use crate::token::hash_token;
+use chrono::{DateTime, Utc};
+
+#[derive(Debug, PartialEq)]
+pub enum Verification {
+    Verified,
+    /// Unknown, already used or expired.
+    InvalidToken,
+    /// Another account has taken the address since the link was sent.
+    AddressTaken,
+}
+
+pub async fn redeem<D: Database>(db: &D, token: &str, now: DateTime<Utc>) -> StoreResult<Verification> {
+    let verification: Option<EmailVerification> = db
+        .query_first_as("SELECT * FROM email_verifications WHERE token = ?1", &[hash_token(token).into()])
+        .await?;
+    let Some(verification) = verification else {
+        return Ok(Verification::InvalidToken);
+    };
+
+    let expired = DateTime::parse_from_rfc3339(&verification.expires_at)
+        .map(|expires_at| expires_at < now)
+        .unwrap_or(true);
+    if expired {
+        return Ok(Verification::InvalidToken);
+    }
+
+    let taken = db
+        .query_first("SELECT id FROM users WHERE email = ?1 AND id != ?2", &[verification.email.as_str().into(), verification.user_id.as_str().into()])
+        .await?;
+    if taken.is_some() {
+        return Ok(Verification::AddressTaken);
+    }
+
+    // Conditional on the link still being there, so one consumed in the
+    // meantime can't be applied
+    let updated = db.execute("
+        UPDATE users SET email = ?1, email_verified = 1, updated_at = ?2
+        WHERE id = ?3 AND EXISTS(SELECT 1 FROM email_verifications WHERE id = ?4)
+    ", &[
+        verification.email.as_str().into(),
+        now.to_rfc3339().into(),
+        verification.user_id.as_str().into(),
+        verification.id.as_str().into(),
+    ]).await;
+
+    match updated {
+        Ok(1) => {}
+        Ok(_) => return Ok(Verification::InvalidToken),
+        // Taken between the check above and the update
+        Err(e) if e.is_unique_violation() => return Ok(Verification::AddressTaken),
+        Err(e) => return Err(e),
+    }
+
+    db.execute("DELETE FROM email_verifications WHERE id = ?1", &[verification.id.as_str().into()]).await?;
+    Ok(Verification::Verified)
+}
@@ -0,0 +1,169 @@
+//! Errors handlers return to the client.
+//!
+//! A handler returns `ApiResult<Response>` and bails out with `?` or
+//! `Err(ApiError::..)`. The router turns the error into an `ApiResponse`
+//! carrying the message, a machine-readable code and, for validation
+//! failures, what was wrong with each field, under the matching HTTP status.
+//! Internal errors are logged and reach the client only as a generic message.
+
+use crate::membership::MembershipError;
+use crate::models::{ApiResponse, FieldError};
+use crate::store::StoreError;
+use serde::de::DeserializeOwned;
+use std::fmt;
+use worker::{console_log, Request, Response};
+
+pub type ApiResult<T> = std::result::Result<T, ApiError>;
+
+#[derive(Debug)]
+pub enum ApiError {
+    BadRequest(String),
+    /// The request parsed but some fields are invalid.
+    Validation(Vec<FieldError>),
+    Unauthorized(String),
+    Forbidden(String),
+    CsrfFailed,
+    NotFound(String),
+    MethodNotAllowed,
+    Conflict(String),
+    Locked(String),
+    TooManyRequests { message: String, retry_after: Option<i64> },
+    Unavailable(String),
+    /// Something broke on our side. The detail is logged, never sent.
+    Internal(String),
+}
+
+impl ApiError {
+    /// A validation failure on a single field.
+    pub fn invalid(field: &str, message: &str) -> Self {
+        ApiError::Validation(vec![FieldError {
+            field: field.to_string(),
+            message: message.to_string(),
+        }])
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            ApiError::BadRequest(_) => 400,
+            ApiError::Validation(_) => 422,
+            ApiError::Unauthorized(_) => 401,
+            ApiError::Forbidden(_) | ApiError::CsrfFailed => 403,
+            ApiError::NotFound(_) => 404,
+            ApiError::MethodNotAllowed => 405,
+            ApiError::Conflict(_) => 409,
+            ApiError::Locked(_) => 423,
+            ApiError::TooManyRequests { .. } => 429,
+            ApiError::Unavailable(_) => 503,
+            ApiError::Internal(_) => 500,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::BadRequest(_) => "bad_request",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::CsrfFailed => "csrf_failed",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            ApiError::Locked(_) => "locked",
+            ApiError::TooManyRequests { .. } => "rate_limited",
+            ApiError::Unavailable(_) => "unavailable",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// What the client is told.
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::BadRequest(message)
+            | ApiError::Unauthorized(message)
+            | ApiError::Forbidden(message)
+            | ApiError::NotFound(message)
+            | ApiError::Conflict(message)
+            | ApiError::Locked(message)
+            | ApiError::TooManyRequests { message, .. }
+            | ApiError::Unavailable(message) => message.clone(),
+            // Clients that only show `error` still get the first problem
+            ApiError::Validation(fields) => match fields.first() {
+                Some(field) => field.message.clone(),
+                None => "Validation failed".to_string(),
+            },
+            ApiError::CsrfFailed => "CSRF token validation failed".to_string(),
+            ApiError::MethodNotAllowed => "Method not allowed".to_string(),
+            ApiError::Internal(_) => "Internal server error".to_string(),
+        }
+    }
+
+    pub fn body(&self) -> ApiResponse<()> {
+        let details = match self {
+            ApiError::Validation(fields) => Some(fields.clone()),
+            _ => None,
+        };
+        ApiResponse::failure(self.message(), self.code(), details)
+    }
+
+    pub fn into_response(self) -> worker::Result<Response> {
+        if let ApiError::Internal(detail) = &self {
+            console_log!("Internal error: {}", detail);
+        }
+
+        let mut response = Response::from_json(&self.body())?.with_status(self.status());
+        if let ApiError::TooManyRequests { retry_after: Some(retry_after), .. } = self {
+            response.headers_mut().set("Retry-After", &retry_after.to_string())?;
+        }
+        Ok(response)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiError::Internal(detail) => write!(f, "{}: {}", self.code(), detail),
+            _ => write!(f, "{}: {}", self.code(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for ApiError {}
+
+impl From<worker::Error> for ApiError {
+    fn from(error: worker::Error) -> Self {
+        ApiError::Internal(error.to_string())
+    }
+}
+
+impl From<StoreError> for ApiError {
+    fn from(error: StoreError) -> Self {
+        ApiError::Internal(error.to_string())
+    }
+}
+
+/// The generic wording for each membership failure. Handlers match the
+/// variants they want to phrase differently first.
+impl From<MembershipError> for ApiError {
+    fn from(error: MembershipError) -> Self {
+        match error {
+            MembershipError::ClubNotFound => ApiError::NotFound("Club not found".to_string()),
+            MembershipError::NotMember => ApiError::Forbidden("User is not a member of this club".to_string()),
+            MembershipError::NotAdmin => ApiError::Forbidden("Only club admins can do this".to_string()),
+            MembershipError::NotOwner => ApiError::Forbidden("Only the club owner can do this".to_string()),
+            MembershipError::MfaRequired => {
+                ApiError::Forbidden("This club requires admins to enable two-factor authentication".to_string())
+            }
+            MembershipError::InvalidInviteCode => ApiError::BadRequest("Invalid invite code".to_string()),
+            MembershipError::InviteCodeUsed => ApiError::BadRequest("Invite code has already been used".to_string()),
+            MembershipError::InviteCodeExpired => ApiError::BadRequest("Invite code has expired".to_string()),
+            MembershipError::AlreadyMember => ApiError::Conflict("User is already a member of this club".to_string()),
+            MembershipError::AlreadyOwner => ApiError::BadRequest("You already own this club".to_string()),
+            MembershipError::Store(error) => error.into(),
+        }
+    }
+}
+
+/// The request's JSON body, or a 400 if it doesn't parse.
+pub async fn read_json<T: DeserializeOwned>(req: &mut Request) -> ApiResult<T> {
+    req.json().await.map_err(|_| ApiError::BadRequest("Invalid request body".to_string()))
+}
@@ -1,3 +1,4 @@
+use crate::error::{read_json, ApiError, ApiResult};
 use chrono::Utc;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
@@ -120,13 +121,13 @@ fn get_mock_tags() -> Vec<Tag> {
     ]
 }
 
-pub async fn get_questions() -> Result<Response> {
+pub async fn get_questions() -> ApiResult<Response> {
     let questions = get_mock_questions();
-    Response::from_json(&questions)
+    Ok(Response::from_json(&questions)?)
 }
 
-pub async fn create_question(mut req: Request) -> Result<Response> {
-    let create_req: CreateQuestionRequest = req.json().await?;
+pub async fn create_question(mut req: Request) -> ApiResult<Response> {
+    let create_req: CreateQuestionRequest = read_json(&mut req).await?;
 
     let new_question = Question {
         id: Uuid::new_v4().to_string(),
@@ -141,41 +142,41 @@ pub async fn create_question(mut req: Request) -> Result<Response> {
         resolved_at: None,
     };
 
-    Response::from_json(&new_question)
+    Ok(Response::from_json(&new_question)?)
 }
 
-pub async fn claim_question(id: &str, mut req: Request) -> Result<Response> {
-    let claim_req: ClaimQuestionRequest = req.json().await?;
+pub async fn claim_question(id: &str, mut req: Request) -> ApiResult<Response> {
+    let claim_req: ClaimQuestionRequest = read_json(&mut req).await?;
 
     // In a real app, this would update the database
     let mut question = get_mock_questions()
         .into_iter()
         .find(|q| q.id == id)
-        .ok_or_else(|| worker::Error::RustError("Question not found".to_string()))?;
+        .ok_or_else(|| ApiError::NotFound("Question not found".to_string()))?;
 
     question.status = "claimed".to_string();
     question.claimed_by = Some(claim_req.claimed_by);
     question.updated_at = js_sys::Date::new_0().to_iso_string().as_string().unwrap();
 
-    Response::from_json(&question)
+    Ok(Response::from_json(&question)?)
 }
 
-pub async fn resolve_question(id: &str) -> Result<Response> {
+pub async fn resolve_question(id: &str) -> ApiResult<Response> {
     // In a real app, this would update the database
     let mut question = get_mock_questions()
         .into_iter()
         .find(|q| q.id == id)
-        .ok_or_else(|| worker::Error::RustError("Question not found".to_string()))?;
+        .ok_or_else(|| ApiError::NotFound("Question not found".to_string()))?;
 
     question.status = "resolved".to_string();
     let now = js_sys::Date::new_0().to_iso_string().as_string().unwrap();
     question.updated_at = now.clone();
     question.resolved_at = Some(now);
 
-    Response::from_json(&question)
+    Ok(Response::from_json(&question)?)
 }
 
-pub async fn get_tags() -> Result<Response> {
+pub async fn get_tags() -> ApiResult<Response> {
     let tags = get_mock_tags();
-    Response::from_json(&tags)
+    Ok(Response::from_json(&tags)?)
 }
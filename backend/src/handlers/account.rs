@@ -0,0 +1,196 @@
+use crate::account::{self, DeletionOutcome};
+use crate::error::{read_json, ApiError, ApiResult};
+use crate::models::*;
+use crate::handlers::auth::get_user_by_id;
+use crate::middleware::RequestContext;
+use crate::store::D1Store;
+use chrono::Utc;
+use worker::*;
+
+// Deletion waits this long so a mistaken or hijacked request can be undone
+const DEFAULT_DELETION_GRACE_DAYS: i64 = 30;
+
+pub async fn handle_account(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Delete, "/api/auth/account") => request_account_deletion(req, ctx).await,
+        (Method::Post, "/api/auth/account/restore") => cancel_account_deletion(ctx).await,
+        (Method::Get, "/api/auth/export") => export_account_data(ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+async fn request_account_deletion(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let delete_request: DeleteAccountRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let auth_user = match get_user_by_id(&db, &session.user_id).await {
+        Some(user) => user,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    let store = D1Store::new(&db);
+    if !account::reauthenticated(&store, &auth_user, &session.session_id, &delete_request, Utc::now()).await {
+        return Err(ApiError::Forbidden("Confirm with your password or authentication code".to_string()));
+    }
+
+    // A club can't be left without an owner, so ownership has to move first
+    let owned_clubs = account::owned_club_names(&store, &auth_user.id).await?;
+    if !owned_clubs.is_empty() {
+        return Err(ApiError::Conflict(format!("Transfer ownership of your clubs before deleting your account: {}", owned_clubs.join(", "))));
+    }
+
+    let grace_days = ctx.env.var("ACCOUNT_DELETION_GRACE_DAYS")
+        .ok()
+        .and_then(|v| v.to_string().parse::<i64>().ok())
+        .unwrap_or(DEFAULT_DELETION_GRACE_DAYS);
+    let now = Utc::now();
+    let scheduled_for = (now + chrono::Duration::days(grace_days)).to_rfc3339();
+
+    let stmt = db.prepare("UPDATE users SET deletion_scheduled_for = ?1, updated_at = ?2 WHERE id = ?3");
+    let result = stmt.bind(&[
+        scheduled_for.clone().into(),
+        now.to_rfc3339().into(),
+        auth_user.id.clone().into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to schedule account deletion".to_string()));
+    }
+
+    // Sign out everywhere and stop any automation. To cancel during the grace
+    // period the owner signs back in, which reports the scheduled date, and
+    // calls `POST /api/auth/account/restore`
+    let stmt = db.prepare("UPDATE sessions SET is_active = 0 WHERE user_id = ?1 AND is_active = 1");
+    stmt.bind(&[auth_user.id.clone().into()])?.run().await?;
+    let stmt = db.prepare("DELETE FROM personal_access_tokens WHERE user_id = ?1");
+    stmt.bind(&[auth_user.id.into()])?.run().await?;
+
+    let response = ApiResponse::success(AccountDeletionResponse { scheduled_for });
+    Ok(Response::from_json(&response)?)
+}
+
+async fn cancel_account_deletion(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let stmt = db.prepare("
+        UPDATE users SET deletion_scheduled_for = NULL, updated_at = ?1
+        WHERE id = ?2 AND deletion_scheduled_for IS NOT NULL
+    ");
+    let result = stmt.bind(&[Utc::now().to_rfc3339().into(), session.user_id.into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) => {}
+        Ok(_) => return Err(ApiError::NotFound("No account deletion is scheduled".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to cancel account deletion".to_string())),
+    }
+
+    let response = ApiResponse::success("Account deletion cancelled");
+    Ok(Response::from_json(&response)?)
+}
+
+/// Everything stored about the user, as one JSON document. Secrets (password
+/// and token hashes, TOTP seeds, passkey public keys) are left out.
+async fn export_account_data(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+    let user_id = session.user_id.as_str();
+
+    let profile = match export_rows(&db, "
+        SELECT id, email, name, avatar, created_at, updated_at, email_verified, last_login, deletion_scheduled_for
+        FROM users WHERE id = ?1
+    ", user_id).await?.into_iter().next() {
+        Some(profile) => profile,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    let email = profile["email"].as_str().unwrap_or_default().to_string();
+
+    let export = serde_json::json!({
+        "exported_at": Utc::now().to_rfc3339(),
+        "profile": profile,
+        "sessions": export_rows(&db, "
+            SELECT id, created_at, last_accessed, expires_at, user_agent, ip_address, is_active
+            FROM sessions WHERE user_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "auth_events": export_rows(&db, "
+            SELECT event_type, detail, ip_address, user_agent, created_at
+            FROM auth_events WHERE user_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "two_factor": export_rows(&db, "SELECT enabled, created_at, confirmed_at FROM user_mfa WHERE user_id = ?1", user_id).await?,
+        "passkeys": export_rows(&db, "
+            SELECT id, name, transports, created_at, last_used_at
+            FROM passkeys WHERE user_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "social_accounts": export_rows(&db, "
+            SELECT provider, provider_id, email, name, avatar, created_at, updated_at
+            FROM social_accounts WHERE user_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "access_tokens": export_rows(&db, "
+            SELECT id, name, token_prefix, scopes, expires_at, created_at, last_used_at
+            FROM personal_access_tokens WHERE user_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "clubs_owned": export_rows(&db, "
+            SELECT id, name, description, avatar, created_at, updated_at
+            FROM clubs WHERE owner_id = ?1 ORDER BY created_at
+        ", user_id).await?,
+        "memberships": export_rows(&db, "
+            SELECT m.club_id, c.name as club_name, m.role, m.joined_at
+            FROM members m INNER JOIN clubs c ON m.club_id = c.id
+            WHERE m.user_id = ?1 ORDER BY m.joined_at
+        ", user_id).await?,
+        "events": export_rows(&db, "SELECT * FROM events WHERE created_by = ?1 ORDER BY created_at", user_id).await?,
+        "announcements": export_rows(&db, "SELECT * FROM announcements WHERE created_by = ?1 ORDER BY created_at", user_id).await?,
+        "projects": export_rows(&db, "SELECT * FROM projects WHERE created_by = ?1 ORDER BY created_at", user_id).await?,
+        "invite_codes": export_rows(&db, "
+            SELECT code, club_id, created_by = ?1 as created_by_you, expires_at, used_at
+            FROM invite_codes WHERE created_by = ?1 OR used_by = ?1
+        ", user_id).await?,
+        "emails": export_rows(&db, "
+            SELECT subject, template, text_body, created_at
+            FROM email_outbox WHERE recipient = ?1 ORDER BY created_at
+        ", &email).await?,
+    });
+
+    let mut response = Response::from_json(&export)?;
+    let headers = response.headers_mut();
+    headers.set(
+        "Content-Disposition",
+        &format!("attachment; filename=\"nivaro-export-{}.json\"", Utc::now().format("%Y-%m-%d")),
+    )?;
+    headers.set("Cache-Control", "no-store")?;
+    Ok(response)
+}
+
+/// Carries out deletions whose grace period has ended. Run from the cron trigger.
+pub async fn process_due_account_deletions(db: &D1Database) {
+    let outcomes = match account::process_due_deletions(&D1Store::new(db), Utc::now()).await {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            console_log!("Account deletions could not run: {}", e);
+            return;
+        }
+    };
+
+    for (user_id, outcome) in outcomes {
+        match outcome {
+            DeletionOutcome::Erased => {}
+            DeletionOutcome::WaitingOnClubs => console_log!("Account deletion for {} is waiting on club ownership transfers", user_id),
+            DeletionOutcome::Failed(e) => console_log!("Account deletion for {} failed: {}", user_id, e),
+        }
+    }
+}
+
+// Errors propagate: a partial export would look complete to the user
+async fn export_rows(db: &D1Database, sql: &str, user_id: &str) -> Result<Vec<serde_json::Value>> {
+    db.prepare(sql).bind(&[user_id.into()])?.all().await?.results::<serde_json::Value>()
+}
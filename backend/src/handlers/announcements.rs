@@ -1,172 +1,115 @@
+use crate::error::{read_json, ApiError, ApiResult};
 use crate::models::*;
-use crate::handlers::auth::{verify_csrf_token, get_user_id_from_token};
+use crate::email::{self, EmailMessage, EmailTemplate};
+use crate::middleware::RequestContext;
+use crate::membership::{self, MembershipError};
+use crate::store::{D1Store, Store};
 use chrono::Utc;
 use uuid::Uuid;
 use worker::*;
 
-pub async fn handle_announcements(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let method = req.method();
-    let url = req.url()?;
-    let path = url.path();
-
-    match method {
+pub async fn handle_announcements(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    match req.method() {
         Method::Get => {
-            // Require authentication for viewing club announcements
-            if get_user_id_from_token(&req, &ctx).is_none() {
-                return Response::error("Unauthorized", 401);
-            }
-            
-            // Extract club_id from path like /clubs/{club_id}/announcements
-            let segments: Vec<&str> = path.split('/').collect();
-            if let Some(pos) = segments.iter().position(|&x| x == "clubs") {
-                if let Some(club_id) = segments.get(pos + 1) {
-                    if !club_id.is_empty() {
-                        return get_club_announcements(club_id, ctx).await;
-                    }
-                }
-            }
-            Response::error("Club ID required", 400)
+            let club_id = ctx.data.club()?.club_id.clone();
+            get_club_announcements(&club_id, ctx).await
         }
         Method::Post => {
             // Create new announcement - requires CSRF protection
             create_announcement(req, ctx).await
         }
-        _ => Response::error("Method not allowed", 405),
+        _ => Err(ApiError::MethodNotAllowed),
     }
 }
 
-async fn get_club_announcements(club_id: &str, ctx: RouteContext<()>) -> Result<Response> {
+async fn get_club_announcements(club_id: &str, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
     let db = match ctx.env.d1("DB") {
         Ok(db) => db,
-        Err(_) => return Response::error("Database connection failed", 500),
-    };
-
-    // Query announcements for the club
-    let stmt = db.prepare("
-        SELECT id, club_id, title, content, created_by, created_at, pinned
-        FROM announcements 
-        WHERE club_id = ?1
-        ORDER BY pinned DESC, created_at DESC
-    ");
-
-    let stmt = match stmt.bind(&vec![club_id.into()]) {
-        Ok(stmt) => stmt,
-        Err(_) => return Response::error("Failed to prepare query", 500),
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
     };
 
-    let results = match stmt.all().await {
-        Ok(results) => results.results::<serde_json::Value>().ok().unwrap_or_default(),
-        Err(_) => return Response::error("Failed to fetch announcements", 500),
+    let announcements = match D1Store::new(&db).club_announcements(club_id).await {
+        Ok(announcements) => announcements,
+        Err(_) => return Err(ApiError::Internal("Failed to fetch announcements".to_string())),
     };
 
-    let announcements: Vec<Announcement> = results
-        .into_iter()
-        .filter_map(|row| {
-            Some(Announcement {
-                id: row["id"].as_str()?.to_string(),
-                club_id: row["club_id"].as_str()?.to_string(),
-                title: row["title"].as_str()?.to_string(),
-                content: row["content"].as_str()?.to_string(),
-                created_by: row["created_by"].as_str()?.to_string(),
-                created_at: row["created_at"].as_str()?.to_string(),
-                pinned: row["pinned"].as_i64().unwrap_or(0) == 1,
-            })
-        })
-        .collect();
-
-    let response = ApiResponse {
-        success: true,
-        data: Some(announcements),
-        error: None,
-    };
+    let response = ApiResponse::success(announcements);
 
-    Response::from_json(&response)
+    Ok(Response::from_json(&response)?)
 }
 
-async fn create_announcement(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
-    }
-
-    // Get user ID from token
-    let user_id = match get_user_id_from_token(&req, &ctx) {
-        Some(id) => id,
-        None => return Response::error("Unauthorized", 401),
-    };
+async fn create_announcement(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
 
-    let create_request: CreateAnnouncementRequest = match req.json().await {
-        Ok(req) => req,
-        Err(_) => return Response::error("Invalid request body", 400),
-    };
+    let create_request: CreateAnnouncementRequest = read_json(&mut req).await?;
 
     let db = match ctx.env.d1("DB") {
         Ok(db) => db,
-        Err(_) => return Response::error("Database connection failed", 500),
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
     };
 
-    // Verify user is an admin of the club (only admins can create announcements)
-    let member_check_stmt = db.prepare("SELECT role FROM members WHERE user_id = ?1 AND club_id = ?2");
-    let member_check_stmt = match member_check_stmt.bind(&vec![user_id.clone().into(), create_request.club_id.clone().into()]) {
-        Ok(stmt) => stmt,
-        Err(_) => return Response::error("Failed to check membership", 500),
-    };
+    let store = D1Store::new(&db);
 
-    match member_check_stmt.first::<serde_json::Value>(None).await {
-        Ok(Some(row)) => {
-            if row["role"].as_str().unwrap_or("") != "admin" {
-                return Response::error("Only club admins can create announcements", 403);
-            }
-        },
-        Ok(None) => return Response::error("User is not a member of this club", 403),
-        Err(_) => return Response::error("Failed to verify membership", 500),
+    // Only admins can create announcements, and clubs can require their
+    // admins to sign in with a second factor
+    match membership::require_admin(&store, &create_request.club_id, &user_id).await {
+        Ok(_) => {}
+        Err(MembershipError::NotAdmin) => return Err(ApiError::Forbidden("Only club admins can create announcements".to_string())),
+        Err(error) => return Err(error.into()),
     }
 
     // Create new announcement in database
-    let announcement_id = Uuid::new_v4().to_string();
-    let now = Utc::now().to_rfc3339();
-
-    let stmt = db.prepare("
-        INSERT INTO announcements (id, club_id, title, content, created_by, created_at, pinned)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
-    ");
-    
-    let stmt = match stmt.bind(&vec![
-        announcement_id.clone().into(),
-        create_request.club_id.clone().into(),
-        create_request.title.clone().into(),
-        create_request.content.clone().into(),
-        user_id.clone().into(),
-        now.clone().into(),
-        (if create_request.pinned { 1 } else { 0 }).into(),
-    ]) {
-        Ok(stmt) => stmt,
-        Err(_) => return Response::error("Failed to prepare announcement insert", 500),
-    };
-    
-    if stmt.run().await.is_err() {
-        return Response::error("Failed to create announcement", 500);
-    }
-
     let announcement = Announcement {
-        id: announcement_id,
+        id: Uuid::new_v4().to_string(),
         club_id: create_request.club_id,
         title: create_request.title,
         content: create_request.content,
         created_by: user_id,
-        created_at: now,
+        created_at: Utc::now().to_rfc3339(),
         pinned: create_request.pinned,
     };
 
-    let response = ApiResponse {
-        success: true,
-        data: Some(announcement),
-        error: None,
-    };
+    if store.create_announcement(&announcement).await.is_err() {
+        return Err(ApiError::Internal("Failed to create announcement".to_string()));
+    }
+
+    notify_club_members(&store, &ctx, &announcement).await;
+
+    let response = ApiResponse::success(announcement);
 
     Ok(Response::from_json(&response)?.with_status(201))
 }
 
+/// Queues the announcement for every other member and starts sending once
+/// the response is out; the cron trigger sends whatever one run can't.
+async fn notify_club_members(store: &D1Store<'_>, ctx: &RouteContext<RequestContext>, announcement: &Announcement) {
+    let recipients = match store.announcement_recipients(&announcement.club_id, &announcement.created_by).await {
+        Ok(recipients) => recipients,
+        Err(_) => return,
+    };
+
+    let link = email::app_link(&ctx.env, &format!("/club/{}", announcement.club_id));
+    let messages: Vec<EmailMessage> = recipients
+        .into_iter()
+        .map(|(to, club_name)| {
+            let template = EmailTemplate::Announcement {
+                club_name,
+                title: announcement.title.clone(),
+                content: announcement.content.clone(),
+                link: link.clone(),
+            };
+            EmailMessage::new(&to, &template)
+        })
+        .collect();
+
+    if messages.is_empty() || email::enqueue(store, &messages, Utc::now()).await.is_err() {
+        return;
+    }
+
+    let env = ctx.env.clone();
+    ctx.data.wait_until(async move { email::send_queued(&env).await });
+}
+
 // Request types
 #[derive(serde::Deserialize)]
 pub struct CreateAnnouncementRequest {
@@ -0,0 +1,154 @@
+use crate::error::{ApiError, ApiResult};
+use crate::models::*;
+use crate::devices::{self, device_hash, mask_ip_address, summarize_user_agent};
+use crate::email::{self, EmailTemplate};
+use crate::handlers::auth::{client_ip, user_agent};
+use crate::middleware::RequestContext;
+use crate::store::{D1Store, Database};
+use chrono::Utc;
+use uuid::Uuid;
+use worker::*;
+use worker::wasm_bindgen::JsValue;
+
+const DEFAULT_EVENT_PAGE_SIZE: usize = 50;
+const MAX_EVENT_PAGE_SIZE: usize = 200;
+// Long enough to cover a device that signs in a couple of times a year
+const AUTH_EVENT_RETENTION_DAYS: i64 = 180;
+
+impl AuthEventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            AuthEventType::Login => "login",
+            AuthEventType::LoginFailed => "login_failed",
+            AuthEventType::Logout => "logout",
+            AuthEventType::PasswordChanged => "password_changed",
+            AuthEventType::PasswordReset => "password_reset",
+            AuthEventType::SessionRevoked => "session_revoked",
+            AuthEventType::OtherSessionsRevoked => "other_sessions_revoked",
+        }
+    }
+}
+
+pub async fn handle_audit(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Get, "/api/auth/events") => list_auth_events(req, ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+/// The caller's own history, newest first. `before` (an RFC 3339 time taken
+/// from the last event of a page) fetches the next page.
+async fn list_auth_events(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let url = req.url()?;
+    let mut limit = DEFAULT_EVENT_PAGE_SIZE;
+    let mut before = Utc::now().to_rfc3339();
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "limit" => limit = value.parse().unwrap_or(DEFAULT_EVENT_PAGE_SIZE).clamp(1, MAX_EVENT_PAGE_SIZE),
+            "before" => before = value.into_owned(),
+            _ => {}
+        }
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    let events: Vec<AuthEvent> = D1Store::new(&db)
+        .query_as("
+            SELECT * FROM auth_events
+            WHERE user_id = ?1 AND created_at < ?2
+            ORDER BY created_at DESC LIMIT ?3
+        ", &[session.user_id.into(), before.into(), (limit as i64).into()])
+        .await?;
+
+    let events: Vec<AuthEventSummary> = events
+        .into_iter()
+        .map(|event| AuthEventSummary {
+            device: event.user_agent.as_deref().map(summarize_user_agent),
+            ip_address: event.ip_address.as_deref().map(mask_ip_address),
+            id: event.id,
+            event_type: event.event_type,
+            detail: event.detail,
+            created_at: event.created_at,
+        })
+        .collect();
+
+    let response = ApiResponse::success(events);
+    Ok(Response::from_json(&response)?)
+}
+
+/// Appends to the user's audit log. Best effort: a failed write never blocks
+/// the action being recorded.
+pub async fn record_auth_event(db: &D1Database, req: &Request, user_id: &str, event_type: AuthEventType, detail: Option<&str>) {
+    let user_agent = user_agent(req);
+    let ip_address = client_ip(req);
+    let device_hash = device_hash(user_agent.as_deref(), ip_address.as_deref());
+
+    let stmt = db.prepare("
+        INSERT INTO auth_events (id, user_id, event_type, detail, ip_address, user_agent, device_hash, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ");
+    let bound = stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
+        user_id.into(),
+        event_type.as_str().into(),
+        detail.map(JsValue::from).unwrap_or(JsValue::NULL),
+        ip_address.map(JsValue::from).unwrap_or(JsValue::NULL),
+        user_agent.map(JsValue::from).unwrap_or(JsValue::NULL),
+        device_hash.into(),
+        Utc::now().to_rfc3339().into(),
+    ]);
+
+    if let Ok(stmt) = bound {
+        let _ = stmt.run().await;
+    }
+}
+
+/// Records a successful sign-in and, if it comes from a browser and network
+/// this account has never signed in from before, emails the owner once the
+/// response has gone out.
+pub async fn record_login(ctx: &RouteContext<RequestContext>, db: &D1Database, req: &Request, auth_user: &AuthUser) {
+    let user_agent = user_agent(req);
+    let ip_address = client_ip(req);
+    let device_hash = device_hash(user_agent.as_deref(), ip_address.as_deref());
+
+    let history = devices::login_history(&D1Store::new(db), &auth_user.id, &device_hash).await;
+    if matches!(history, Ok(history) if history.is_new_device()) {
+        let template = EmailTemplate::NewDeviceLogin {
+            name: auth_user.name.clone(),
+            device: user_agent.as_deref().map(summarize_user_agent).unwrap_or_else(|| "Unknown device".to_string()),
+            location: ip_address.as_deref().map(mask_ip_address).unwrap_or_else(|| "an unknown network".to_string()),
+            time: Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+            link: email::app_link(&ctx.env, "/profile"),
+        };
+        let env = ctx.env.clone();
+        let to = auth_user.email.clone();
+        ctx.data.wait_until(async move { email::send_email(&env, &to, template).await });
+    }
+
+    record_auth_event(db, req, &auth_user.id, AuthEventType::Login, None).await;
+}
+
+/// Whether the account has signed in from this request's browser and network
+/// before.
+pub async fn is_known_device(db: &D1Database, req: &Request, user_id: &str) -> bool {
+    let device_hash = device_hash(user_agent(req).as_deref(), client_ip(req).as_deref());
+    matches!(
+        devices::login_history(&D1Store::new(db), user_id, &device_hash).await,
+        Ok(history) if history.seen_device
+    )
+}
+
+/// Drops events past the retention period. Run from the cron trigger.
+pub async fn prune_auth_events(db: &D1Database) {
+    let cutoff = (Utc::now() - chrono::Duration::days(AUTH_EVENT_RETENTION_DAYS)).to_rfc3339();
+    let stmt = db.prepare("DELETE FROM auth_events WHERE created_at < ?1");
+    if let Ok(stmt) = stmt.bind(&[cutoff.into()]) {
+        let _ = stmt.run().await;
+    }
+}
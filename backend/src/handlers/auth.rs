@@ -1,66 +1,133 @@
 use worker::*;
+use worker::wasm_bindgen::JsValue;
+use crate::error::{read_json, ApiError, ApiResult};
 use crate::models::*;
+use crate::email::{self, EmailTemplate};
+use crate::email_verification::{self, Verification};
+use crate::handlers::{audit, invites, mfa};
 use chrono::Utc;
 use uuid::Uuid;
-use jsonwebtoken::{encode, decode, Header, Validation, EncodingKey, DecodingKey};
+use crate::cors::CorsPolicy;
+use crate::csrf::{self, CsrfKey};
+use crate::devices::{mask_ip_address, summarize_user_agent};
+use crate::jwt::KeySet;
+use crate::middleware::RequestContext;
 use serde::{Deserialize, Serialize};
-use bcrypt::{hash, verify, DEFAULT_COST};
+use crate::password::{self, HashParams};
+use crate::password_reset;
+use crate::rate_limit::{self, D1CounterStore, Decision};
+use crate::signup::{SignupDecision, SignupMode, SignupPolicy};
+use crate::store::{D1Store, Database, Store};
+pub(crate) use crate::token::{generate_secure_token, hash_token};
+
+// Password reset tokens are valid for one hour and a single account may
+// request at most this many within that window.
+const MAX_PASSWORD_RESETS_PER_HOUR: i64 = 3;
+
+// Verification links last a day; resends are throttled per account by
+// `rate_limit::VERIFICATION_EMAIL_PER_ACCOUNT`.
+const EMAIL_VERIFICATION_TTL_HOURS: i64 = 24;
+const DEFAULT_UNVERIFIED_GRACE_HOURS: i64 = 72;
+
+// Sign-in links are short-lived and throttled both per address and per client
+// (`rate_limit::MAGIC_LINK_PER_ACCOUNT` and `MAGIC_LINK_PER_IP`), since the
+// request endpoint is unauthenticated.
+const MAGIC_LINK_TTL_MINUTES: i64 = 15;
+
+/// How `login` treats accounts whose email address has not been verified,
+/// configured through the `UNVERIFIED_LOGIN_POLICY` variable.
+enum UnverifiedLoginPolicy {
+    /// Unverified accounts can log in normally
+    Allow,
+    /// Unverified accounts can log in until this many hours after signup
+    Grace(i64),
+    /// Unverified accounts cannot log in at all
+    Block,
+}
 
 #[derive(Debug, Serialize, Deserialize)]
 struct Claims {
     sub: String, // user id
     email: String,
+    jti: String, // session id, checked against the sessions table
     exp: usize, // expiration timestamp
     iat: usize, // issued at timestamp
 }
 
-pub async fn handle_auth(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+/// A request whose token was valid and whose session is still active.
+pub struct AuthenticatedSession {
+    pub user_id: String,
+    pub session_id: String,
+}
+
+// How stale `sessions.last_accessed` may get before a request refreshes it,
+// so ordinary browsing doesn't cost a D1 write per request.
+const SESSION_TOUCH_INTERVAL_MINUTES: i64 = 5;
+
+// Access tokens are short-lived JWTs; the session itself lives as long as its
+// refresh token keeps being rotated, up to this many days of inactivity.
+const ACCESS_TOKEN_TTL_MINUTES: i64 = 15;
+const REFRESH_TOKEN_TTL_DAYS: i64 = 30;
+
+pub async fn handle_auth(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
     let method = req.method();
     let url = req.url()?;
     let path = url.path();
     
     match (method, path) {
         (Method::Post, "/api/auth/signup") => signup(req, ctx).await,
+        (Method::Get, "/api/auth/signup-policy") => get_signup_policy(ctx).await,
         (Method::Post, "/api/auth/login") => login(req, ctx).await,
         (Method::Post, "/api/auth/logout") => logout(req, ctx).await,
+        (Method::Post, "/api/auth/refresh") => refresh_session(req, ctx).await,
         (Method::Post, "/api/auth/forgot-password") => forgot_password(req, ctx).await,
         (Method::Post, "/api/auth/reset-password") => reset_password(req, ctx).await,
         (Method::Post, "/api/auth/change-password") => change_password(req, ctx).await,
         (Method::Post, "/api/auth/verify-email") => verify_email(req, ctx).await,
-        (Method::Get, "/api/auth/me") => get_current_user(req, ctx).await,
+        (Method::Post, "/api/auth/resend-verification") => resend_verification(req, ctx).await,
+        (Method::Post, "/api/auth/magic-link") => request_magic_link(req, ctx).await,
+        (Method::Post, "/api/auth/magic-link/verify") => consume_magic_link(req, ctx).await,
+        (Method::Get, "/api/auth/me") => get_current_user(ctx).await,
         (Method::Put, "/api/auth/profile") => update_profile(req, ctx).await,
-        (Method::Delete, "/api/auth/account") => delete_account(req, ctx).await,
-        (Method::Post, "/api/auth/social") => social_login(req, ctx).await,
-        (Method::Get, "/api/auth/sessions") => get_user_sessions(req, ctx).await,
+        (Method::Get, "/api/auth/sessions") => get_user_sessions(ctx).await,
         (Method::Delete, "/api/auth/sessions") => revoke_all_sessions(req, ctx).await,
-        _ => Response::error("Not found", 404)
+        (Method::Delete, path) if path.starts_with("/api/auth/sessions/") => revoke_session(req, ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
     }
 }
 
-async fn signup(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let signup_request: SignupRequest = match req.json().await {
-        Ok(req) => req,
-        Err(_) => return Response::error("Invalid request body", 400),
-    };
+async fn signup(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let signup_request: SignupRequest = read_json(&mut req).await?;
 
     // Basic validation
     if signup_request.email.is_empty() || signup_request.password.is_empty() || signup_request.name.is_empty() {
-        return Response::error("Email, password, and name are required", 400);
+        return Err(ApiError::BadRequest("Email, password, and name are required".to_string()));
     }
 
     if !is_valid_email(&signup_request.email) {
-        return Response::error("Invalid email format", 400);
+        return Err(ApiError::invalid("email", "Invalid email format"));
     }
 
     if !is_strong_password(&signup_request.password) {
-        return Response::error("Password must be at least 8 characters with uppercase, lowercase, number, and special character", 400);
+        return Err(ApiError::invalid("password", "Password must be at least 8 characters with uppercase, lowercase, number, and special character"));
+    }
+
+    let invite_token = signup_request.invite_token.as_deref().map(str::trim).filter(|token| !token.is_empty());
+    let policy = SignupPolicy::from_env(&ctx.env);
+    let decision = policy.decide(&signup_request.email, invite_token.is_some());
+    if decision == SignupDecision::Denied {
+        let message = match &policy.mode {
+            SignupMode::Domains(domains) => format!("Sign up with an address at {} or ask an admin for an invite", domains.join(", ")),
+            _ => "Signing up requires an invite".to_string(),
+        };
+        return Err(ApiError::Forbidden(message));
     }
 
     let db = ctx.env.d1("DB")?;
 
     // Check if user already exists
     if user_exists(&db, &signup_request.email).await {
-        return Response::error("User with this email already exists", 409);
+        return Err(ApiError::Conflict("User with this email already exists".to_string()));
     }
 
     // Create user
@@ -68,34 +135,43 @@ async fn signup(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
     let now = Utc::now().to_rfc3339();
     
     // Hash the password
-    let password_hash = match hash(&signup_request.password, DEFAULT_COST) {
-        Ok(hash) => hash,
-        Err(_) => return Response::error("Failed to process password", 500),
+    let password_hash = match password::hash_password(&signup_request.password, HashParams::from_env(&ctx.env)).await {
+        Some(hash) => hash,
+        None => return Err(ApiError::Internal("Failed to process password".to_string())),
     };
 
-    // Insert user into database with simpler approach
-    let stmt = db.prepare("
-        INSERT INTO users (id, email, password_hash, name, created_at, updated_at, email_verified, is_active, failed_login_attempts)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, 0, 1, 0)
-    ");
-    let result = stmt.bind(&vec![
-        user_id.clone().into(),
-        signup_request.email.clone().into(),
-        password_hash.into(),
-        signup_request.name.clone().into(),
-        now.clone().into(),
-        now.clone().into(),
-    ])?.run().await;
+    // Redeemed before the insert so two signups can't share one invite
+    if let (SignupDecision::NeedsInvite, Some(token)) = (decision, invite_token) {
+        if !invites::redeem_platform_invite(&db, token, &signup_request.email, &user_id).await {
+            return Err(ApiError::Forbidden("This invite is invalid, expired, already used or meant for another address".to_string()));
+        }
+    }
 
-    if result.is_err() {
-        return Response::error("Failed to create user", 500);
+    let new_user = AuthUser {
+        id: user_id.clone(),
+        email: signup_request.email.clone(),
+        password_hash,
+        name: signup_request.name.clone(),
+        avatar: None,
+        created_at: now.clone(),
+        updated_at: now.clone(),
+        email_verified: false,
+        is_active: true,
+        last_login: None,
+        failed_login_attempts: 0,
+        locked_until: None,
+        deletion_scheduled_for: None,
+    };
+
+    if D1Store::new(&db).create_user(&new_user).await.is_err() {
+        invites::release_platform_invite(&db, &user_id).await;
+        return Err(ApiError::Internal("Failed to create user".to_string()));
     }
 
     // Create email verification token
     let verification_token = create_email_verification_token(&db, &user_id, &signup_request.email).await;
 
-    // Send verification email (mock)
-    send_verification_email(&signup_request.email, &verification_token).await;
+    send_verification_email(&ctx.env, &signup_request.email, &signup_request.name, &verification_token).await;
 
     let user = User {
         id: user_id,
@@ -112,158 +188,184 @@ async fn signup(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
         success: true,
         user: Some(user),
         token: None, // Don't provide token until email is verified
+        refresh_token: None,
         expires_at: None,
         error: None,
+        deletion_scheduled_for: None,
     };
 
     Ok(Response::from_json(&response)?.with_status(201))
 }
 
-async fn login(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let login_request: LoginRequest = match req.json().await {
-        Ok(req) => req,
-        Err(_) => return Response::error("Invalid request body", 400),
-    };
+/// Lets the signup page show the right form before the user fills it in.
+async fn get_signup_policy(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let policy = SignupPolicy::from_env(&ctx.env);
+    let response = ApiResponse::success(policy.summary());
+    Ok(Response::from_json(&response)?)
+}
+
+async fn login(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let login_request: LoginRequest = read_json(&mut req).await?;
 
     // Basic validation
     if login_request.email.is_empty() || login_request.password.is_empty() {
-        return Response::error("Email and password are required", 400);
+        return Err(ApiError::BadRequest("Email and password are required".to_string()));
     }
 
     let db = ctx.env.d1("DB")?;
 
+    // Counted before the lookup so unknown addresses are throttled the same way
+    let email = login_request.email.to_lowercase();
+    let ip_address = client_ip(&req).unwrap_or_else(|| "unknown".to_string());
+    let counters = D1CounterStore::new(&db);
+    let now = Utc::now().timestamp();
+    let limit = (rate_limit::LOGIN_PER_ACCOUNT_AND_IP, format!("{} {}", email, ip_address));
+    if let Decision::Limited { retry_after } = rate_limit::check_all(&counters, &[limit], now).await {
+        return Err(rate_limit::too_many_requests(retry_after));
+    }
+
     // Get user from database
-    let user_result = get_user_by_email(&db, &login_request.email).await;
-    let auth_user = match user_result {
+    let auth_user = get_user_by_email(&db, &login_request.email).await;
+
+    // Past the account's failure budget only devices that have signed in to it
+    // before get through, so the owner isn't locked out by someone else's guesses
+    if let Decision::Limited { retry_after } = rate_limit::peek(&counters, &rate_limit::LOGIN_FAILURES_PER_ACCOUNT, &email, now).await {
+        let known_device = match &auth_user {
+            Some(user) => audit::is_known_device(&db, &req, &user.id).await,
+            None => false,
+        };
+        if !known_device {
+            return Err(rate_limit::too_many_requests(retry_after));
+        }
+    }
+
+    let auth_user = match auth_user {
         Some(user) => user,
         None => {
-            let error_response = AuthResponse {
-                success: false,
-                user: None,
-                token: None,
-                expires_at: None,
-                error: Some("Invalid credentials".to_string()),
-            };
-            return Ok(Response::from_json(&error_response)?.with_status(401));
+            rate_limit::record(&counters, &rate_limit::LOGIN_FAILURES_PER_ACCOUNT, &email, now).await;
+            return Err(ApiError::Unauthorized("Invalid credentials".to_string()));
         }
     };
 
-    // Check if account is locked
-    if let Some(locked_until) = &auth_user.locked_until {
-        if let Ok(locked_time) = chrono::DateTime::parse_from_rfc3339(locked_until) {
-            if locked_time > Utc::now() {
-                return Response::error("Account is locked due to too many failed login attempts", 423);
-            }
-        }
-    }
-
     // Verify password
-    let password_valid = match verify(&login_request.password, &auth_user.password_hash) {
-        Ok(valid) => valid,
-        Err(_) => false,
-    };
+    let password_valid = password::verify_password(&login_request.password, &auth_user.password_hash).await;
 
     if !password_valid {
+        rate_limit::record(&counters, &rate_limit::LOGIN_FAILURES_PER_ACCOUNT, &email, now).await;
         // Increment failed login attempts
         increment_failed_login_attempts(&db, &auth_user.id).await;
-        let error_response = AuthResponse {
-            success: false,
-            user: None,
-            token: None,
-            expires_at: None,
-            error: Some("Invalid credentials".to_string()),
-        };
-        return Ok(Response::from_json(&error_response)?.with_status(401));
+        audit::record_auth_event(&db, &req, &auth_user.id, AuthEventType::LoginFailed, None).await;
+        return Err(ApiError::Unauthorized("Invalid credentials".to_string()));
+    }
+
+    // Only checked after the password so the verification state doesn't leak
+    if !auth_user.email_verified && !unverified_login_allowed(&ctx, &auth_user.created_at) {
+        return Err(ApiError::Forbidden("Email address has not been verified".to_string()));
     }
 
     // Reset failed login attempts and update last login
     reset_failed_login_attempts(&db, &auth_user.id).await;
 
-    // Create JWT token
-    let claims = Claims {
-        sub: auth_user.id.clone(),
-        email: auth_user.email.clone(),
-        exp: (Utc::now() + chrono::Duration::hours(24)).timestamp() as usize,
-        iat: Utc::now().timestamp() as usize,
-    };
+    // Upgrade bcrypt or under-strength hashes while we have the plaintext;
+    // a failure here just means trying again next time
+    let hash_params = HashParams::from_env(&ctx.env);
+    if password::needs_rehash(&auth_user.password_hash, hash_params) {
+        if let Some(password_hash) = password::hash_password(&login_request.password, hash_params).await {
+            update_password_hash(&db, &auth_user.id, &auth_user.password_hash, &password_hash).await;
+        }
+    }
 
-    let secret = match get_jwt_secret(&ctx) {
-        Ok(secret) => secret,
-        Err(_) => return Response::error("Authentication configuration error", 500),
-    };
-    let token = match encode(&Header::default(), &claims, &EncodingKey::from_secret(secret.as_ref())) {
-        Ok(token) => token,
-        Err(_) => return Response::error("Failed to generate token", 500),
-    };
+    // With two-factor enabled the password only earns a challenge; the session
+    // is issued by /api/auth/mfa/verify once a code is posted
+    if mfa::user_has_mfa(&db, &auth_user.id).await {
+        return mfa::start_mfa_challenge(&db, &auth_user.id).await;
+    }
 
-    // Create session
-    let session_id = Uuid::new_v4().to_string();
-    let expires_at = (Utc::now() + chrono::Duration::hours(24)).to_rfc3339();
-    let now = Utc::now().to_rfc3339();
+    start_session(&req, &ctx, &db, auth_user).await
+}
 
-    create_session(&db, &session_id, &auth_user.id, &token, &expires_at, &now).await;
+async fn logout(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let db = ctx.env.d1("DB")?;
 
-    let user = User {
-        id: auth_user.id,
-        email: auth_user.email,
-        name: auth_user.name,
-        avatar: auth_user.avatar,
-        created_at: auth_user.created_at,
-        updated_at: auth_user.updated_at,
-        email_verified: auth_user.email_verified,
-        is_active: auth_user.is_active,
+    // The access token may already have expired, so fall back to the refresh
+    // token to find which session to end
+    if let Some(claims) = decode_claims(&req, &ctx.env) {
+        revoke_session_by_id(&db, &claims.jti, &claims.sub).await;
+        audit::record_auth_event(&db, &req, &claims.sub, AuthEventType::Logout, None).await;
+    } else if let Some(refresh_token) = extract_refresh_token(&req) {
+        if let Some(record) = get_refresh_token(&db, &refresh_token).await {
+            revoke_session_by_id(&db, &record.session_id, &record.user_id).await;
+            audit::record_auth_event(&db, &req, &record.user_id, AuthEventType::Logout, None).await;
+        }
+    }
+
+    let response = ApiResponse::success("Logged out successfully");
+    let mut response = Response::from_json(&response)?;
+    clear_auth_cookies(&mut response)?;
+    Ok(response)
+}
+
+async fn refresh_session(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    // Browsers send the cookie; other clients may post the token in the body
+    let refresh_token = match extract_refresh_token(&req) {
+        Some(token) => token,
+        None => match req.json::<RefreshTokenRequest>().await {
+            Ok(body) => body.refresh_token,
+            Err(_) => return Err(ApiError::Unauthorized("Refresh token required".to_string())),
+        },
     };
 
-    let response = AuthResponse {
-        success: true,
-        user: Some(user),
-        token: Some(token.clone()),
-        expires_at: Some(expires_at),
-        error: None,
+    let db = ctx.env.d1("DB")?;
+
+    let record = match get_refresh_token(&db, &refresh_token).await {
+        Some(record) => record,
+        None => return Err(ApiError::Unauthorized("Invalid refresh token".to_string())),
     };
 
-    let mut response = Response::from_json(&response)?;
-    
-    // Set secure httpOnly cookie
-    response = response.with_headers(
-        Headers::from_iter(vec![
-            ("Set-Cookie".to_string(), format!(
-                "auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=86400",
-                token
-            ))
-        ])
-    );
+    // A rotated token being presented again means it was copied: end the whole
+    // family (the session) so neither the thief nor the victim can keep using it
+    if record.used_at.is_some() || !mark_refresh_token_used(&db, &record.id).await {
+        console_log!("Refresh token reuse detected for session {}", record.session_id);
+        revoke_session_by_id(&db, &record.session_id, &record.user_id).await;
+        return Err(ApiError::Unauthorized("Invalid refresh token".to_string()));
+    }
 
-    Ok(response)
-}
+    let expired = chrono::DateTime::parse_from_rfc3339(&record.expires_at)
+        .map(|expires_at| expires_at < Utc::now())
+        .unwrap_or(true);
+    if expired || !is_session_active(&db, &record.session_id, &record.user_id).await {
+        return Err(ApiError::Unauthorized("Invalid refresh token".to_string()));
+    }
+
+    let auth_user = match get_user_by_id(&db, &record.user_id).await {
+        Some(user) if user.is_active => user,
+        _ => return Err(ApiError::Unauthorized("Invalid refresh token".to_string())),
+    };
+
+    let (access_token, access_expires_at) = match issue_access_token(&ctx, &auth_user, &record.session_id) {
+        Ok(issued) => issued,
+        Err(_) => return Err(ApiError::Internal("Failed to generate token".to_string())),
+    };
 
-async fn logout(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    if let Some(token) = extract_token(&req) {
-        let db = ctx.env.d1("DB")?;
-        // Revoke the session in the database
-        let stmt = db.prepare("UPDATE sessions SET is_active = 0 WHERE token = ?1");
-        let _ = stmt.bind(&vec![token.into()])?.run().await;
+    let now = Utc::now();
+    let session_expires_at = (now + chrono::Duration::days(REFRESH_TOKEN_TTL_DAYS)).to_rfc3339();
+    let new_refresh_token = generate_secure_token();
+    if !store_refresh_token(&db, &record.session_id, &record.user_id, &new_refresh_token, &session_expires_at).await {
+        return Err(ApiError::Internal("Failed to refresh session".to_string()));
     }
+    extend_session(&db, &record.session_id, &access_token, &session_expires_at, &now.to_rfc3339()).await;
 
-    let response = ApiResponse::success("Logged out successfully");
-    Ok(Response::from_json(&response)?
-        .with_headers(
-            Headers::from_iter(vec![
-                ("Set-Cookie".to_string(), "auth_token=; HttpOnly; Secure; SameSite=Strict; Max-Age=0".to_string())
-            ])
-        ))
+    auth_success_response(auth_user, access_token, access_expires_at, new_refresh_token)
 }
 
-async fn get_current_user(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    let user_id = match get_user_id_from_token(&req, &ctx) {
-        Some(id) => id,
-        None => return Response::error("Unauthorized", 401),
-    };
+async fn get_current_user(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
 
     let db = ctx.env.d1("DB")?;
 
     // Get user from database
     if let Some(auth_user) = get_user_by_id(&db, &user_id).await {
+        let deletion_scheduled_for = auth_user.deletion_scheduled_for.clone();
         let user = User {
             id: auth_user.id,
             email: auth_user.email,
@@ -279,174 +381,449 @@ async fn get_current_user(req: Request, ctx: RouteContext<()>) -> Result<Respons
             success: true,
             user: Some(user),
             token: None, // Don't send token back in /me endpoint
+            refresh_token: None,
             expires_at: None,
             error: None,
+            deletion_scheduled_for,
         };
-        return Response::from_json(&response);
+        return Ok(Response::from_json(&response)?);
+    }
+
+    Err(ApiError::NotFound("User not found".to_string()))
+}
+
+async fn forgot_password(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let forgot_request: ForgotPasswordRequest = read_json(&mut req).await?;
+
+    if !is_valid_email(&forgot_request.email) {
+        return Err(ApiError::invalid("email", "Invalid email format"));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    // Applies to every address alike, so a 429 says nothing about whether it has an account
+    let limit = rate_limit::FORGOT_PASSWORD_PER_ACCOUNT;
+    if let Decision::Limited { retry_after } = rate_limit::check(&D1CounterStore::new(&db), &limit, &forgot_request.email, Utc::now().timestamp()).await {
+        return Err(rate_limit::too_many_requests(retry_after));
+    }
+
+    // Only issue a token for active accounts that haven't hit the hourly limit,
+    // but never tell the caller which case applied
+    if let Some(auth_user) = get_user_by_email(&db, &forgot_request.email).await {
+        let store = D1Store::new(&db);
+        let now = Utc::now();
+        // Fail closed so a broken query can't be used to flood an inbox
+        let recent = password_reset::count_recent(&store, &auth_user.id, now).await.unwrap_or(i64::MAX);
+        if auth_user.is_active && recent < MAX_PASSWORD_RESETS_PER_HOUR {
+            if let Ok(reset_token) = password_reset::create(&store, &auth_user.id, now).await {
+                send_password_reset_email(&ctx.env, &auth_user.email, &auth_user.name, &reset_token).await;
+            }
+        }
+    }
+
+    // Always return the same response to prevent email enumeration
+    let response = ApiResponse::success("If an account with that email exists, a password reset link has been sent");
+    Ok(Response::from_json(&response)?)
+}
+
+async fn request_magic_link(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let link_request: MagicLinkRequest = read_json(&mut req).await?;
+
+    if !is_valid_email(&link_request.email) {
+        return Err(ApiError::invalid("email", "Invalid email format"));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    // Counted for every address alike, so a 429 says nothing about whether it
+    // has an account. The per-client limit runs with the other route limits
+    let email_address = link_request.email.trim().to_lowercase();
+    let limit = rate_limit::MAGIC_LINK_PER_ACCOUNT;
+    if let Decision::Limited { retry_after } = rate_limit::check(&D1CounterStore::new(&db), &limit, &email_address, Utc::now().timestamp()).await {
+        return Err(rate_limit::too_many_requests(retry_after));
+    }
+
+    if let Some(auth_user) = get_user_by_email(&db, &email_address).await.filter(|user| user.is_active) {
+        if let Some(token) = create_magic_link(&db, &auth_user.id, &email_address, client_ip(&req).as_deref()).await {
+            send_magic_link_email(&ctx.env, &auth_user.email, &auth_user.name, &token).await;
+        }
     }
 
-    Response::error("User not found", 404)
+    // Always return the same response to prevent email enumeration
+    let response = ApiResponse::success("If an account with that email exists, a sign-in link has been sent");
+    Ok(Response::from_json(&response)?)
 }
 
-// Simplified stubs for other endpoints to avoid compilation errors
-async fn forgot_password(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    let response = ApiResponse::success("Password reset email sent");
-    Response::from_json(&response)
+async fn consume_magic_link(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let consume_request: ConsumeMagicLinkRequest = read_json(&mut req).await?;
+
+    if consume_request.token.is_empty() {
+        return Err(ApiError::BadRequest("Sign-in token is required".to_string()));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    let magic_link = match get_magic_link_by_token(&db, &consume_request.token).await {
+        Some(link) => link,
+        None => return Err(ApiError::BadRequest("Invalid or expired sign-in link".to_string())),
+    };
+
+    let expired = chrono::DateTime::parse_from_rfc3339(&magic_link.expires_at)
+        .map(|expires_at| expires_at < Utc::now())
+        .unwrap_or(true);
+    if expired {
+        return Err(ApiError::BadRequest("Invalid or expired sign-in link".to_string()));
+    }
+
+    // Claim the link before issuing a session so a replayed link can't also succeed
+    if !mark_magic_link_used(&db, &magic_link.id).await {
+        return Err(ApiError::BadRequest("Invalid or expired sign-in link".to_string()));
+    }
+
+    let auth_user = match magic_link.user_id {
+        Some(user_id) => get_user_by_id(&db, &user_id).await,
+        None => None,
+    };
+    let mut auth_user = match auth_user {
+        Some(user) if user.is_active => user,
+        _ => return Err(ApiError::BadRequest("Invalid or expired sign-in link".to_string())),
+    };
+
+    // Following the link proves control of the address it was sent to
+    if !auth_user.email_verified && auth_user.email.to_lowercase() == magic_link.email {
+        let stmt = db.prepare("UPDATE users SET email_verified = 1, updated_at = ?1 WHERE id = ?2");
+        stmt.bind(&[Utc::now().to_rfc3339().into(), auth_user.id.clone().into()])?.run().await?;
+        auth_user.email_verified = true;
+    }
+
+    reset_failed_login_attempts(&db, &auth_user.id).await;
+
+    if mfa::user_has_mfa(&db, &auth_user.id).await {
+        return mfa::start_mfa_challenge(&db, &auth_user.id).await;
+    }
+
+    start_session(&req, &ctx, &db, auth_user).await
 }
 
-async fn reset_password(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+async fn reset_password(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let reset_request: ResetPasswordRequest = read_json(&mut req).await?;
+
+    if reset_request.token.is_empty() {
+        return Err(ApiError::BadRequest("Reset token is required".to_string()));
+    }
+
+    if !is_strong_password(&reset_request.new_password) {
+        return Err(ApiError::invalid("new_password", "Password must be at least 8 characters with uppercase, lowercase, number, and special character"));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    // Claim the token before touching the password so a concurrent request
+    // replaying the same token cannot also succeed
+    let user_id = match password_reset::redeem(&D1Store::new(&db), &reset_request.token, Utc::now()).await? {
+        Some(user_id) => user_id,
+        None => return Err(ApiError::BadRequest("Invalid or expired reset token".to_string())),
+    };
+
+    let password_hash = match password::hash_password(&reset_request.new_password, HashParams::from_env(&ctx.env)).await {
+        Some(hash) => hash,
+        None => return Err(ApiError::Internal("Failed to process password".to_string())),
+    };
+
+    let now = Utc::now();
+    let stmt = db.prepare("
+        UPDATE users SET password_hash = ?1, updated_at = ?2, failed_login_attempts = 0, locked_until = NULL
+        WHERE id = ?3
+    ");
+    let result = stmt.bind(&[
+        password_hash.into(),
+        now.to_rfc3339().into(),
+        user_id.clone().into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to reset password".to_string()));
+    }
+
+    // Any other outstanding reset links and every existing session die with the old password
+    let _ = password_reset::invalidate(&D1Store::new(&db), &user_id, now).await;
+    revoke_user_sessions(&db, &user_id).await;
+    audit::record_auth_event(&db, &req, &user_id, AuthEventType::PasswordReset, None).await;
+
     let response = ApiResponse::success("Password reset successfully");
-    Response::from_json(&response)
+    Ok(Response::from_json(&response)?)
 }
 
-async fn change_password(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
+async fn change_password(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
+
+    let change_request: ChangePasswordRequest = read_json(&mut req).await?;
+
+    if change_request.current_password.is_empty() || change_request.new_password.is_empty() {
+        return Err(ApiError::BadRequest("Current and new password are required".to_string()));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    let auth_user = match get_user_by_id(&db, &user_id).await {
+        Some(user) => user,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    if !password::verify_password(&change_request.current_password, &auth_user.password_hash).await {
+        return Err(ApiError::BadRequest("Current password is incorrect".to_string()));
+    }
+
+    if !is_strong_password(&change_request.new_password) {
+        return Err(ApiError::invalid("new_password", "Password must be at least 8 characters with uppercase, lowercase, number, and special character"));
+    }
+
+    let password_hash = match password::hash_password(&change_request.new_password, HashParams::from_env(&ctx.env)).await {
+        Some(hash) => hash,
+        None => return Err(ApiError::Internal("Failed to process password".to_string())),
+    };
+
+    let now = Utc::now().to_rfc3339();
+    let stmt = db.prepare("UPDATE users SET password_hash = ?1, updated_at = ?2 WHERE id = ?3");
+    let result = stmt.bind(&[
+        password_hash.into(),
+        now.clone().into(),
+        user_id.clone().into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to change password".to_string()));
+    }
+
+    // Keep the device that made the change signed in, sign out everything else
+    let _ = password_reset::invalidate(&D1Store::new(&db), &user_id, Utc::now()).await;
+    if let Some(session_id) = &ctx.data.auth()?.session_id {
+        revoke_other_sessions(&db, &user_id, session_id).await;
     }
+    audit::record_auth_event(&db, &req, &user_id, AuthEventType::PasswordChanged, None).await;
 
     let response = ApiResponse::success("Password changed successfully");
-    Response::from_json(&response)
+    Ok(Response::from_json(&response)?)
 }
 
-async fn verify_email(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
+async fn verify_email(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let verify_request: VerifyEmailRequest = read_json(&mut req).await?;
+
+    if verify_request.token.is_empty() {
+        return Err(ApiError::BadRequest("Verification token is required".to_string()));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    match email_verification::redeem(&D1Store::new(&db), &verify_request.token, Utc::now()).await? {
+        Verification::Verified => {}
+        Verification::InvalidToken => return Err(ApiError::BadRequest("Invalid or expired verification token".to_string())),
+        Verification::AddressTaken => return Err(ApiError::Conflict("User with this email already exists".to_string())),
+    }
+
     let response = ApiResponse::success("Email verified successfully");
-    Response::from_json(&response)
+    Ok(Response::from_json(&response)?)
 }
 
-async fn update_profile(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
+async fn resend_verification(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let resend_request: ResendVerificationRequest = read_json(&mut req).await?;
+
+    if !is_valid_email(&resend_request.email) {
+        return Err(ApiError::invalid("email", "Invalid email format"));
     }
 
-    let response = ApiResponse::success("Profile updated successfully");
-    Response::from_json(&response)
-}
+    let db = ctx.env.d1("DB")?;
 
-async fn delete_account(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
+    if let Some(auth_user) = get_user_by_email(&db, &resend_request.email).await {
+        // Counted in its own table: each resend replaces the previous token, so
+        // the tokens themselves can't tell how many were sent
+        let limit = rate_limit::VERIFICATION_EMAIL_PER_ACCOUNT;
+        if auth_user.is_active
+            && !auth_user.email_verified
+            && rate_limit::check(&D1CounterStore::new(&db), &limit, &auth_user.id, Utc::now().timestamp()).await == Decision::Allowed
+        {
+            let verification_token = create_email_verification_token(&db, &auth_user.id, &auth_user.email).await;
+            send_verification_email(&ctx.env, &auth_user.email, &auth_user.name, &verification_token).await;
+        }
     }
 
-    let response = ApiResponse::success("Account deleted successfully");
-    Response::from_json(&response)
+    // Same response whether or not anything was sent to prevent email enumeration
+    let response = ApiResponse::success("If that account exists and is unverified, a new verification email has been sent");
+    Ok(Response::from_json(&response)?)
 }
 
-async fn social_login(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    let response = ApiResponse::success("Social login successful");
-    Response::from_json(&response)
+async fn update_profile(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
+
+    let update_request: UpdateProfileRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let auth_user = match get_user_by_id(&db, &user_id).await {
+        Some(user) => user,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    let name = match update_request.name {
+        Some(name) if name.trim().is_empty() => return Err(ApiError::invalid("name", "Name cannot be empty")),
+        Some(name) => name.trim().to_string(),
+        None => auth_user.name.clone(),
+    };
+
+    // An empty avatar clears it
+    let avatar = match update_request.avatar {
+        Some(avatar) if avatar.trim().is_empty() => None,
+        Some(avatar) => Some(avatar.trim().to_string()),
+        None => auth_user.avatar.clone(),
+    };
+
+    // A new address only takes effect once the link sent to it is followed
+    let new_email = match update_request.email {
+        Some(email) if !email.eq_ignore_ascii_case(&auth_user.email) => {
+            if !is_valid_email(&email) {
+                return Err(ApiError::invalid("email", "Invalid email format"));
+            }
+            if user_exists(&db, &email).await {
+                return Err(ApiError::Conflict("User with this email already exists".to_string()));
+            }
+            Some(email)
+        }
+        _ => None,
+    };
+
+    let now = Utc::now().to_rfc3339();
+    let stmt = db.prepare("UPDATE users SET name = ?1, avatar = ?2, updated_at = ?3 WHERE id = ?4");
+    let result = stmt.bind(&[
+        name.clone().into(),
+        avatar.clone().map(Into::into).unwrap_or(JsValue::NULL),
+        now.clone().into(),
+        user_id.clone().into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to update profile".to_string()));
+    }
+
+    if let Some(new_email) = &new_email {
+        let verification_token = create_email_verification_token(&db, &user_id, new_email).await;
+        send_verification_email(&ctx.env, new_email, &name, &verification_token).await;
+    }
+
+    let user = User {
+        id: auth_user.id,
+        email: auth_user.email,
+        name,
+        avatar,
+        created_at: auth_user.created_at,
+        updated_at: now,
+        email_verified: auth_user.email_verified,
+        is_active: auth_user.is_active,
+    };
+
+    let response = ApiResponse::success(user);
+    Ok(Response::from_json(&response)?)
 }
 
-async fn get_user_sessions(_req: Request, _ctx: RouteContext<()>) -> Result<Response> {
-    let sessions: Vec<Session> = vec![];
+async fn get_user_sessions(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let current = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let sessions: Vec<SessionSummary> = get_active_sessions(&db, &current.user_id).await
+        .into_iter()
+        .map(|session| SessionSummary {
+            is_current: session.id == current.session_id,
+            device: session.user_agent.as_deref().map(summarize_user_agent),
+            ip_address: session.ip_address.as_deref().map(mask_ip_address),
+            id: session.id,
+            created_at: session.created_at,
+            last_accessed: session.last_accessed,
+            expires_at: session.expires_at,
+        })
+        .collect();
+
     let response = ApiResponse::success(sessions);
-    Response::from_json(&response)
+    Ok(Response::from_json(&response)?)
+}
+
+async fn revoke_all_sessions(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let current = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+    revoke_other_sessions(&db, &current.user_id, &current.session_id).await;
+    audit::record_auth_event(&db, &req, &current.user_id, AuthEventType::OtherSessionsRevoked, None).await;
+
+    let response = ApiResponse::success("All other sessions revoked successfully");
+    Ok(Response::from_json(&response)?)
 }
 
-async fn revoke_all_sessions(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
+async fn revoke_session(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let current = ctx.data.session()?;
+
+    let session_id = match ctx.param("id") {
+        Some(id) => id.to_string(),
+        None => return Err(ApiError::BadRequest("Session ID required".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    // Scoped to the caller so one user can't probe or revoke another's sessions
+    let stmt = db.prepare("UPDATE sessions SET is_active = 0 WHERE id = ?1 AND user_id = ?2 AND is_active = 1");
+    let result = stmt.bind(&[session_id.clone().into(), current.user_id.clone().into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) => {}
+        Ok(_) => return Err(ApiError::NotFound("Session not found".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to revoke session".to_string())),
     }
 
-    let response = ApiResponse::success("All sessions revoked successfully");
-    Response::from_json(&response)
+    audit::record_auth_event(&db, &req, &current.user_id, AuthEventType::SessionRevoked, Some(&session_id)).await;
+
+    let mut response = Response::from_json(&ApiResponse::success("Session revoked successfully"))?;
+
+    // Revoking the session this request came from is a logout
+    if session_id == current.session_id {
+        clear_auth_cookies(&mut response)?;
+    }
+
+    Ok(response)
 }
 
 // Helper functions for D1 database operations
 
 async fn user_exists(db: &D1Database, email: &str) -> bool {
-    let stmt = db.prepare("SELECT COUNT(*) as count FROM users WHERE email = ?1");
-    
-    let stmt = match stmt.bind(&vec![email.into()]) {
-        Ok(stmt) => stmt,
-        Err(_) => return false,
-    };
-    
-    match stmt.first::<serde_json::Value>(None).await {
-        Ok(Some(result)) => result["count"].as_u64().unwrap_or(0) > 0,
-        _ => false,
-    }
-}
-
-async fn get_user_by_email(db: &D1Database, email: &str) -> Option<AuthUser> {
-    let stmt = db.prepare("SELECT * FROM users WHERE email = ?1");
-    let stmt = stmt.bind(&vec![email.into()]).ok()?;
-    let result = stmt.first::<serde_json::Value>(None).await.ok()??;
-        
-    Some(AuthUser {
-        id: result["id"].as_str()?.to_string(),
-        email: result["email"].as_str()?.to_string(),
-        password_hash: result["password_hash"].as_str()?.to_string(),
-        name: result["name"].as_str()?.to_string(),
-        avatar: result["avatar"].as_str().map(|s| s.to_string()),
-        created_at: result["created_at"].as_str()?.to_string(),
-        updated_at: result["updated_at"].as_str()?.to_string(),
-        email_verified: result["email_verified"].as_i64()? == 1,
-        is_active: result["is_active"].as_i64()? == 1,
-        last_login: result["last_login"].as_str().map(|s| s.to_string()),
-        failed_login_attempts: result["failed_login_attempts"].as_u64()? as u32,
-        locked_until: result["locked_until"].as_str().map(|s| s.to_string()),
-    })
+    D1Store::new(db).user_exists(email).await.unwrap_or(false)
 }
 
-async fn get_user_by_id(db: &D1Database, user_id: &str) -> Option<AuthUser> {
-    let stmt = db.prepare("SELECT * FROM users WHERE id = ?1");
-    let stmt = stmt.bind(&vec![user_id.into()]).ok()?;
-    let result = stmt.first::<serde_json::Value>(None).await.ok()??;
-        
-    Some(AuthUser {
-        id: result["id"].as_str()?.to_string(),
-        email: result["email"].as_str()?.to_string(),
-        password_hash: result["password_hash"].as_str()?.to_string(),
-        name: result["name"].as_str()?.to_string(),
-        avatar: result["avatar"].as_str().map(|s| s.to_string()),
-        created_at: result["created_at"].as_str()?.to_string(),
-        updated_at: result["updated_at"].as_str()?.to_string(),
-        email_verified: result["email_verified"].as_i64()? == 1,
-        is_active: result["is_active"].as_i64()? == 1,
-        last_login: result["last_login"].as_str().map(|s| s.to_string()),
-        failed_login_attempts: result["failed_login_attempts"].as_u64()? as u32,
-        locked_until: result["locked_until"].as_str().map(|s| s.to_string()),
-    })
+pub(crate) async fn get_user_by_email(db: &D1Database, email: &str) -> Option<AuthUser> {
+    D1Store::new(db).user_by_email(email).await.ok()?
+}
+
+pub(crate) async fn get_user_by_id(db: &D1Database, user_id: &str) -> Option<AuthUser> {
+    D1Store::new(db).user_by_id(user_id).await.ok()?
 }
 
+// Only counted now: repeated failures are throttled by the login rate limits,
+// which unlike a lockout can't be triggered by a stranger against someone else
 async fn increment_failed_login_attempts(db: &D1Database, user_id: &str) {
-    // Get current failed attempts
-    let stmt = db.prepare("SELECT failed_login_attempts FROM users WHERE id = ?1");
-    if let Ok(stmt) = stmt.bind(&vec![user_id.into()]) {
-        if let Ok(Some(result)) = stmt.first::<serde_json::Value>(None).await {
-            let attempts = result["failed_login_attempts"].as_u64().unwrap_or(0) + 1;
-            
-            if attempts >= 5 {
-                // Lock account for 15 minutes
-                let lock_until = (Utc::now() + chrono::Duration::minutes(15)).to_rfc3339();
-                let stmt = db.prepare("UPDATE users SET failed_login_attempts = ?1, locked_until = ?2 WHERE id = ?3");
-                if let Ok(stmt) = stmt.bind(&vec![
-                    (attempts as f64).into(),
-                    lock_until.into(),
-                    user_id.into(),
-                ]) {
-                    let _ = stmt.run().await;
-                }
-            } else {
-                let stmt = db.prepare("UPDATE users SET failed_login_attempts = ?1 WHERE id = ?2");
-                if let Ok(stmt) = stmt.bind(&vec![
-                    (attempts as f64).into(),
-                    user_id.into(),
-                ]) {
-                    let _ = stmt.run().await;
-                }
-            }
-        }
+    let stmt = db.prepare("UPDATE users SET failed_login_attempts = failed_login_attempts + 1 WHERE id = ?1");
+    if let Ok(stmt) = stmt.bind(&[user_id.into()]) {
+        let _ = stmt.run().await;
+    }
+}
+
+// Only replaces the hash that was verified, so a password change that lands
+// in the meantime is never overwritten
+async fn update_password_hash(db: &D1Database, user_id: &str, old_hash: &str, new_hash: &str) {
+    let stmt = db.prepare("UPDATE users SET password_hash = ?1 WHERE id = ?2 AND password_hash = ?3");
+    if let Ok(stmt) = stmt.bind(&[new_hash.into(), user_id.into(), old_hash.into()]) {
+        let _ = stmt.run().await;
     }
 }
 
 async fn reset_failed_login_attempts(db: &D1Database, user_id: &str) {
     let stmt = db.prepare("UPDATE users SET failed_login_attempts = 0, locked_until = NULL, last_login = ?1 WHERE id = ?2");
-    if let Ok(stmt) = stmt.bind(&vec![
+    if let Ok(stmt) = stmt.bind(&[
         Utc::now().to_rfc3339().into(),
         user_id.into(),
     ]) {
@@ -454,37 +831,215 @@ async fn reset_failed_login_attempts(db: &D1Database, user_id: &str) {
     }
 }
 
-async fn create_session(db: &D1Database, session_id: &str, user_id: &str, token: &str, expires_at: &str, created_at: &str) {
+/// Creates a session for a user who has just proven who they are and returns
+/// the `AuthResponse` plus auth cookies. Every sign-in method ends here.
+pub(crate) async fn start_session(req: &Request, ctx: &RouteContext<RequestContext>, db: &D1Database, auth_user: AuthUser) -> ApiResult<Response> {
+    let session_id = Uuid::new_v4().to_string();
+
+    let (access_token, access_expires_at) = match issue_access_token(ctx, &auth_user, &session_id) {
+        Ok(issued) => issued,
+        Err(_) => return Err(ApiError::Internal("Failed to generate token".to_string())),
+    };
+
+    let now = Utc::now();
+    let session_expires_at = (now + chrono::Duration::days(REFRESH_TOKEN_TTL_DAYS)).to_rfc3339();
+
+    if !create_session(db, req, &session_id, &auth_user.id, &access_token, &session_expires_at, &now.to_rfc3339()).await {
+        return Err(ApiError::Internal("Failed to create session".to_string()));
+    }
+
+    let refresh_token = generate_secure_token();
+    if !store_refresh_token(db, &session_id, &auth_user.id, &refresh_token, &session_expires_at).await {
+        return Err(ApiError::Internal("Failed to create session".to_string()));
+    }
+
+    audit::record_login(ctx, db, req, &auth_user).await;
+
+    auth_success_response(auth_user, access_token, access_expires_at, refresh_token)
+}
+
+fn issue_access_token(ctx: &RouteContext<RequestContext>, auth_user: &AuthUser, session_id: &str) -> Result<(String, String)> {
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::minutes(ACCESS_TOKEN_TTL_MINUTES);
+    let claims = Claims {
+        sub: auth_user.id.clone(),
+        email: auth_user.email.clone(),
+        jti: session_id.to_string(),
+        exp: expires_at.timestamp() as usize,
+        iat: now.timestamp() as usize,
+    };
+
+    let keys = KeySet::from_env(&ctx.env)
+        .map_err(|e| Error::RustError(e.to_string()))?;
+    let token = keys.sign(&claims, now)
+        .map_err(|e| Error::RustError(format!("Failed to encode token: {}", e)))?;
+
+    Ok((token, expires_at.to_rfc3339()))
+}
+
+fn auth_success_response(auth_user: AuthUser, access_token: String, expires_at: String, refresh_token: String) -> ApiResult<Response> {
+    // Signing in doesn't cancel a pending deletion; the app offers
+    // `POST /api/auth/account/restore` when this is set
+    let deletion_scheduled_for = auth_user.deletion_scheduled_for.clone();
+    let user = User {
+        id: auth_user.id,
+        email: auth_user.email,
+        name: auth_user.name,
+        avatar: auth_user.avatar,
+        created_at: auth_user.created_at,
+        updated_at: auth_user.updated_at,
+        email_verified: auth_user.email_verified,
+        is_active: auth_user.is_active,
+    };
+
+    let response = AuthResponse {
+        success: true,
+        user: Some(user),
+        token: Some(access_token.clone()),
+        refresh_token: Some(refresh_token.clone()),
+        expires_at: Some(expires_at),
+        error: None,
+        deletion_scheduled_for,
+    };
+
+    let mut response = Response::from_json(&response)?;
+
+    // Set secure httpOnly cookies; the refresh token is only ever sent to the refresh endpoint
+    let headers = response.headers_mut();
+    headers.append("Set-Cookie", &format!(
+        "auth_token={}; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age={}",
+        access_token, ACCESS_TOKEN_TTL_MINUTES * 60
+    ))?;
+    headers.append("Set-Cookie", &format!(
+        "refresh_token={}; HttpOnly; Secure; SameSite=Strict; Path=/api/auth; Max-Age={}",
+        refresh_token, REFRESH_TOKEN_TTL_DAYS * 24 * 60 * 60
+    ))?;
+
+    Ok(response)
+}
+
+fn clear_auth_cookies(response: &mut Response) -> Result<()> {
+    let headers = response.headers_mut();
+    headers.append("Set-Cookie", "auth_token=; HttpOnly; Secure; SameSite=Strict; Path=/; Max-Age=0")?;
+    headers.append("Set-Cookie", "refresh_token=; HttpOnly; Secure; SameSite=Strict; Path=/api/auth; Max-Age=0")?;
+    Ok(())
+}
+
+async fn create_session(db: &D1Database, req: &Request, session_id: &str, user_id: &str, token: &str, expires_at: &str, created_at: &str) -> bool {
+    let session = Session {
+        id: session_id.to_string(),
+        user_id: user_id.to_string(),
+        token: hash_token(token),
+        expires_at: expires_at.to_string(),
+        created_at: created_at.to_string(),
+        last_accessed: created_at.to_string(),
+        user_agent: user_agent(req),
+        ip_address: client_ip(req),
+        is_active: true,
+    };
+
+    D1Store::new(db).create_session(&session).await.is_ok()
+}
+
+async fn get_active_sessions(db: &D1Database, user_id: &str) -> Vec<Session> {
+    D1Store::new(db).active_sessions(user_id, Utc::now()).await.unwrap_or_default()
+}
+
+async fn is_session_active(db: &D1Database, session_id: &str, user_id: &str) -> bool {
+    let store = D1Store::new(db);
+    let now = Utc::now();
+
+    let session = match store.active_session(session_id, user_id, now).await {
+        Ok(Some(session)) => session,
+        _ => return false,
+    };
+
+    let stale = chrono::DateTime::parse_from_rfc3339(&session.last_accessed).ok()
+        .is_none_or(|last_accessed| last_accessed + chrono::Duration::minutes(SESSION_TOUCH_INTERVAL_MINUTES) < now);
+    if stale {
+        let _ = store.touch_session(session_id, &now.to_rfc3339()).await;
+    }
+
+    true
+}
+
+async fn extend_session(db: &D1Database, session_id: &str, access_token: &str, expires_at: &str, now: &str) {
+    let stmt = db.prepare("UPDATE sessions SET token = ?1, expires_at = ?2, last_accessed = ?3 WHERE id = ?4");
+    if let Ok(stmt) = stmt.bind(&[
+        hash_token(access_token).into(),
+        expires_at.into(),
+        now.into(),
+        session_id.into(),
+    ]) {
+        let _ = stmt.run().await;
+    }
+}
+
+async fn revoke_session_by_id(db: &D1Database, session_id: &str, user_id: &str) {
+    let _ = D1Store::new(db).revoke_session(session_id, user_id).await;
+}
+
+async fn store_refresh_token(db: &D1Database, session_id: &str, user_id: &str, token: &str, expires_at: &str) -> bool {
     let stmt = db.prepare("
-        INSERT INTO sessions (id, user_id, token, expires_at, created_at, last_accessed, is_active)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6, 1)
+        INSERT INTO refresh_tokens (id, session_id, user_id, token_hash, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
     ");
-    if let Ok(stmt) = stmt.bind(&vec![
+    let stmt = stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
         session_id.into(),
         user_id.into(),
-        token.into(),
+        hash_token(token).into(),
         expires_at.into(),
-        created_at.into(),
-        created_at.into(),
-    ]) {
-        let _ = stmt.run().await;
+        Utc::now().to_rfc3339().into(),
+    ]);
+
+    match stmt {
+        Ok(stmt) => stmt.run().await.is_ok(),
+        Err(_) => false,
     }
 }
 
-async fn create_email_verification_token(db: &D1Database, user_id: &str, email: &str) -> String {
-    let token = Uuid::new_v4().to_string();
+async fn get_refresh_token(db: &D1Database, token: &str) -> Option<RefreshToken> {
+    D1Store::new(db)
+        .query_first_as("SELECT * FROM refresh_tokens WHERE token_hash = ?1", &[hash_token(token).into()])
+        .await
+        .ok()?
+}
+
+async fn mark_refresh_token_used(db: &D1Database, token_id: &str) -> bool {
+    let stmt = db.prepare("UPDATE refresh_tokens SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL");
+    let stmt = match stmt.bind(&[Utc::now().to_rfc3339().into(), token_id.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
+
+pub(crate) async fn create_email_verification_token(db: &D1Database, user_id: &str, email: &str) -> String {
+    let token = generate_secure_token();
     let verification_id = Uuid::new_v4().to_string();
-    let expires_at = (Utc::now() + chrono::Duration::hours(24)).to_rfc3339();
+    let expires_at = (Utc::now() + chrono::Duration::hours(EMAIL_VERIFICATION_TTL_HOURS)).to_rfc3339();
     let created_at = Utc::now().to_rfc3339();
-    
+
+    // Only the most recent link is valid, so an older one can't switch the
+    // account back to an address from an abandoned email change
+    let stmt = db.prepare("DELETE FROM email_verifications WHERE user_id = ?1");
+    if let Ok(stmt) = stmt.bind(&[user_id.into()]) {
+        let _ = stmt.run().await;
+    }
+
     let stmt = db.prepare("
         INSERT INTO email_verifications (id, user_id, token, email, expires_at, created_at)
         VALUES (?1, ?2, ?3, ?4, ?5, ?6)
     ");
-    if let Ok(stmt) = stmt.bind(&vec![
+    if let Ok(stmt) = stmt.bind(&[
         verification_id.into(),
         user_id.into(),
-        token.clone().into(),
+        hash_token(&token).into(),
         email.into(),
         expires_at.into(),
         created_at.into(),
@@ -495,9 +1050,71 @@ async fn create_email_verification_token(db: &D1Database, user_id: &str, email:
     token
 }
 
+/// Stores a sign-in link for `user_id` and returns its token.
+async fn create_magic_link(db: &D1Database, user_id: &str, email_address: &str, ip_address: Option<&str>) -> Option<String> {
+    let token = generate_secure_token();
+    let now = Utc::now();
+    let expires_at = (now + chrono::Duration::minutes(MAGIC_LINK_TTL_MINUTES)).to_rfc3339();
+    let created_at = now.to_rfc3339();
+
+    // A new link supersedes any older unused ones
+    let stmt = db.prepare("UPDATE magic_links SET used_at = ?1 WHERE user_id = ?2 AND used_at IS NULL");
+    if let Ok(stmt) = stmt.bind(&[created_at.clone().into(), user_id.into()]) {
+        let _ = stmt.run().await;
+    }
+
+    let stmt = db.prepare("
+        INSERT INTO magic_links (id, user_id, email, token, ip_address, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+    ");
+    let stmt = stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
+        user_id.into(),
+        email_address.into(),
+        hash_token(&token).into(),
+        ip_address.map(JsValue::from).unwrap_or(JsValue::NULL),
+        expires_at.into(),
+        created_at.into(),
+    ]).ok()?;
+
+    stmt.run().await.ok()?;
+    Some(token)
+}
+
+async fn get_magic_link_by_token(db: &D1Database, token: &str) -> Option<MagicLink> {
+    D1Store::new(db)
+        .query_first_as("SELECT * FROM magic_links WHERE token = ?1 AND used_at IS NULL", &[hash_token(token).into()])
+        .await
+        .ok()?
+}
+
+async fn mark_magic_link_used(db: &D1Database, link_id: &str) -> bool {
+    let stmt = db.prepare("UPDATE magic_links SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL");
+    let stmt = match stmt.bind(&[Utc::now().to_rfc3339().into(), link_id.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
+
+async fn revoke_other_sessions(db: &D1Database, user_id: &str, current_session_id: &str) {
+    let stmt = db.prepare("UPDATE sessions SET is_active = 0 WHERE user_id = ?1 AND id != ?2 AND is_active = 1");
+    if let Ok(stmt) = stmt.bind(&[user_id.into(), current_session_id.into()]) {
+        let _ = stmt.run().await;
+    }
+}
+
+async fn revoke_user_sessions(db: &D1Database, user_id: &str) {
+    let _ = D1Store::new(db).revoke_user_sessions(user_id).await;
+}
+
 // Utility functions
 
-fn is_valid_email(email: &str) -> bool {
+pub(crate) fn is_valid_email(email: &str) -> bool {
     email.contains('@') && email.contains('.')
 }
 
@@ -509,21 +1126,68 @@ fn is_strong_password(password: &str) -> bool {
         && password.chars().any(|c| !c.is_alphanumeric())
 }
 
-async fn send_verification_email(_email: &str, _token: &str) {
-    // Mock email sending - in production, use an email service
+pub(crate) async fn send_verification_email(env: &Env, email: &str, name: &str, token: &str) {
+    let template = EmailTemplate::Verification {
+        name: name.to_string(),
+        link: email::app_link(env, &format!("/auth/verify-email?token={}", token)),
+    };
+    email::send_email(env, email, template).await;
+}
+
+async fn send_password_reset_email(env: &Env, email: &str, name: &str, token: &str) {
+    let template = EmailTemplate::PasswordReset {
+        name: name.to_string(),
+        link: email::app_link(env, &format!("/auth/reset-password?token={}", token)),
+    };
+    email::send_email(env, email, template).await;
 }
 
-fn get_jwt_secret(ctx: &RouteContext<()>) -> Result<String> {
-    ctx.env.var("JWT_SECRET")
-        .map_err(|_| "JWT_SECRET environment variable not set".into())
-        .map(|secret| secret.to_string())
+async fn send_magic_link_email(env: &Env, email: &str, name: &str, token: &str) {
+    let template = EmailTemplate::MagicLink {
+        name: name.to_string(),
+        link: email::app_link(env, &format!("/auth/magic-link?token={}", token)),
+    };
+    email::send_email(env, email, template).await;
+}
+
+fn get_unverified_login_policy(ctx: &RouteContext<RequestContext>) -> UnverifiedLoginPolicy {
+    // Anyone can type an address at an allowed domain, so it only counts once proven
+    if SignupPolicy::from_env(&ctx.env).requires_verified_email() {
+        return UnverifiedLoginPolicy::Block;
+    }
+
+    let policy = ctx.env.var("UNVERIFIED_LOGIN_POLICY")
+        .map(|policy| policy.to_string())
+        .unwrap_or_default();
+
+    match policy.trim().to_lowercase().as_str() {
+        "block" => UnverifiedLoginPolicy::Block,
+        "grace" => {
+            let hours = ctx.env.var("UNVERIFIED_GRACE_HOURS")
+                .ok()
+                .and_then(|hours| hours.to_string().parse().ok())
+                .unwrap_or(DEFAULT_UNVERIFIED_GRACE_HOURS);
+            UnverifiedLoginPolicy::Grace(hours)
+        }
+        _ => UnverifiedLoginPolicy::Allow,
+    }
+}
+
+pub(crate) fn unverified_login_allowed(ctx: &RouteContext<RequestContext>, created_at: &str) -> bool {
+    match get_unverified_login_policy(ctx) {
+        UnverifiedLoginPolicy::Allow => true,
+        UnverifiedLoginPolicy::Block => false,
+        UnverifiedLoginPolicy::Grace(hours) => chrono::DateTime::parse_from_rfc3339(created_at)
+            .map(|created_at| created_at + chrono::Duration::hours(hours) > Utc::now())
+            .unwrap_or(false),
+    }
 }
 
 fn extract_token(req: &Request) -> Option<String> {
     // Try to get token from Authorization header first
     if let Ok(Some(auth_header)) = req.headers().get("Authorization") {
-        if auth_header.starts_with("Bearer ") {
-            return Some(auth_header[7..].to_string());
+        if let Some(token) = auth_header.strip_prefix("Bearer ") {
+            return Some(token.to_string());
         }
     }
     
@@ -531,8 +1195,8 @@ fn extract_token(req: &Request) -> Option<String> {
     if let Ok(Some(cookie_header)) = req.headers().get("Cookie") {
         for cookie in cookie_header.split(';') {
             let cookie = cookie.trim();
-            if cookie.starts_with("auth_token=") {
-                return Some(cookie[11..].to_string());
+            if let Some(token) = cookie.strip_prefix("auth_token=") {
+                return Some(token.to_string());
             }
         }
     }
@@ -540,121 +1204,109 @@ fn extract_token(req: &Request) -> Option<String> {
     None
 }
 
-pub fn get_user_id_from_token(req: &Request, ctx: &RouteContext<()>) -> Option<String> {
+fn extract_refresh_token(req: &Request) -> Option<String> {
+    if let Ok(Some(cookie_header)) = req.headers().get("Cookie") {
+        for cookie in cookie_header.split(';') {
+            if let Some(token) = cookie.trim().strip_prefix("refresh_token=") {
+                if !token.is_empty() {
+                    return Some(token.to_string());
+                }
+            }
+        }
+    }
+
+    None
+}
+
+pub(crate) fn user_agent(req: &Request) -> Option<String> {
+    req.headers().get("User-Agent").ok().flatten()
+}
+
+pub(crate) fn client_ip(req: &Request) -> Option<String> {
+    // Set by Cloudflare on every request that reaches the Worker
+    req.headers().get("CF-Connecting-IP").ok().flatten()
+}
+
+fn decode_claims(req: &Request, env: &Env) -> Option<Claims> {
     let token = extract_token(req)?;
     
-    let secret = match get_jwt_secret(ctx) {
-        Ok(secret) => secret,
+    let keys = match KeySet::from_env(env) {
+        Ok(keys) => keys,
         Err(e) => {
-            console_log!("Error getting JWT secret: {}", e);
+            console_log!("Error loading JWT signing keys: {}", e);
             return None;
         }
     };
-    let decoding_key = DecodingKey::from_secret(secret.as_ref());
-    let validation = Validation::default();
-    
-    match decode::<Claims>(&token, &decoding_key, &validation) {
-        Ok(token_data) => Some(token_data.claims.sub),
-        Err(_) => None, // Invalid or expired token
-    }
-}
 
-// CSRF Protection Functions
+    // Invalid, expired or signed by an unknown/retired key
+    keys.verify::<Claims>(&token, Utc::now()).ok()
+}
 
-pub async fn get_csrf_token(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // For CSRF tokens, we need a user to be authenticated
-    let user_id = match get_user_id_from_token(&req, &ctx) {
-        Some(id) => id,
-        None => return Response::error("Unauthorized", 401),
+/// Publishes the public halves of the current signing keys so other services
+/// can verify Nivaro access tokens.
+pub async fn handle_jwks(_req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let keys = match KeySet::from_env(&ctx.env) {
+        Ok(keys) => keys,
+        Err(e) => {
+            console_log!("Error loading JWT signing keys: {}", e);
+            return Err(ApiError::Unavailable("Signing keys unavailable".to_string()));
+        }
     };
 
-    let db = ctx.env.d1("DB")?;
-
-    // Try to get existing valid CSRF token
-    if let Some(existing_token) = get_valid_csrf_token(&db, &user_id).await {
-        let response = serde_json::json!({
-            "token": existing_token,
-            "expires_at": chrono::Utc::now().checked_add_signed(chrono::Duration::hours(1))
-                .unwrap_or(chrono::Utc::now()).to_rfc3339()
-        });
-        return Response::from_json(&response);
-    }
+    let mut response = Response::from_json(&keys.jwks(Utc::now()))?;
+    // Short enough that a newly published key is picked up well before it signs
+    response.headers_mut().set("Cache-Control", "public, max-age=300")?;
+    Ok(response)
+}
 
-    // Generate new CSRF token
-    let csrf_token = generate_csrf_token();
-    let token_id = Uuid::new_v4().to_string();
-    let now = Utc::now().to_rfc3339();
-    let expires_at = (Utc::now() + chrono::Duration::hours(1)).to_rfc3339();
+/// Validates the request's token and checks that its session has not been
+/// revoked or expired server-side.
+pub async fn authenticate_request(req: &Request, env: &Env) -> Option<AuthenticatedSession> {
+    let claims = decode_claims(req, env)?;
+    let db = env.d1("DB").ok()?;
 
-    // Store token in database
-    let result = store_csrf_token(&db, &token_id, &user_id, &csrf_token, &expires_at, &now).await;
-    
-    if !result {
-        return Response::error("Failed to generate CSRF token", 500);
+    if !is_session_active(&db, &claims.jti, &claims.sub).await {
+        return None;
     }
 
-    let response = serde_json::json!({
-        "token": csrf_token,
-        "expires_at": expires_at
-    });
-
-    Response::from_json(&response)
+    Some(AuthenticatedSession {
+        user_id: claims.sub,
+        session_id: claims.jti,
+    })
 }
 
-async fn get_valid_csrf_token(db: &D1Database, user_id: &str) -> Option<String> {
-    let now = Utc::now().to_rfc3339();
-    let stmt = db.prepare("SELECT token FROM csrf_tokens WHERE user_id = ?1 AND expires_at > ?2 ORDER BY created_at DESC LIMIT 1");
-    
-    if let Ok(stmt) = stmt.bind(&vec![user_id.into(), now.into()]) {
-        if let Ok(Some(result)) = stmt.first::<serde_json::Value>(None).await {
-            if let Some(token) = result["token"].as_str() {
-                return Some(token.to_string());
-            }
+// CSRF Protection Functions
+
+pub async fn get_csrf_token(_req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    // Tokens are bound to the caller's session, so only a signed-in user gets one
+    let session = ctx.data.session()?;
+
+    let key = match CsrfKey::from_env(&ctx.env) {
+        Some(key) => key,
+        None => {
+            console_log!("CSRF_SECRET is missing or shorter than 32 bytes");
+            return Err(ApiError::Internal("Failed to generate CSRF token".to_string()));
         }
-    }
-    None
-}
+    };
 
-fn generate_csrf_token() -> String {
-    Uuid::new_v4().to_string()
-}
+    let now = Utc::now();
+    let response = serde_json::json!({
+        "token": key.issue(&session.session_id, now.timestamp()),
+        "expires_at": (now + chrono::Duration::seconds(csrf::TOKEN_TTL_SECONDS)).to_rfc3339()
+    });
 
-async fn store_csrf_token(db: &D1Database, token_id: &str, user_id: &str, token: &str, expires_at: &str, created_at: &str) -> bool {
-    // Clean up expired tokens first
-    cleanup_expired_csrf_tokens(db, user_id).await;
-    
-    let stmt = db.prepare("
-        INSERT INTO csrf_tokens (id, user_id, token, expires_at, created_at)
-        VALUES (?1, ?2, ?3, ?4, ?5)
-    ");
-    
-    if let Ok(stmt) = stmt.bind(&vec![
-        token_id.into(),
-        user_id.into(),
-        token.into(),
-        expires_at.into(),
-        created_at.into(),
-    ]) {
-        stmt.run().await.is_ok()
-    } else {
-        false
-    }
+    Ok(Response::from_json(&response)?)
 }
 
-async fn cleanup_expired_csrf_tokens(db: &D1Database, user_id: &str) {
-    let now = Utc::now().to_rfc3339();
-    let stmt = db.prepare("DELETE FROM csrf_tokens WHERE user_id = ?1 AND expires_at <= ?2");
-    if let Ok(stmt) = stmt.bind(&vec![user_id.into(), now.into()]) {
-        let _ = stmt.run().await;
+/// Checks the request's origin and that its `X-CSRF-Token` header was issued
+/// for `session_id`.
+pub fn verify_csrf_token(req: &Request, env: &Env, session_id: &str) -> Result<bool> {
+    let sec_fetch_site = req.headers().get("Sec-Fetch-Site")?;
+    let origin = req.headers().get("Origin")?;
+    let cors = CorsPolicy::from_env(env);
+    if !csrf::origin_allowed(sec_fetch_site.as_deref(), origin.as_deref(), |origin| cors.allows(origin)) {
+        return Ok(false);
     }
-}
-
-pub async fn verify_csrf_token(req: &Request, ctx: &RouteContext<()>) -> Result<bool> {
-    // Get user ID from the request
-    let user_id = match get_user_id_from_token(req, ctx) {
-        Some(id) => id,
-        None => return Ok(false), // Not authenticated
-    };
 
     // Get CSRF token from header
     let csrf_token = match req.headers().get("X-CSRF-Token") {
@@ -662,22 +1314,10 @@ pub async fn verify_csrf_token(req: &Request, ctx: &RouteContext<()>) -> Result<
         _ => return Ok(false), // No CSRF token provided
     };
 
-    let db = ctx.env.d1("DB")?;
-    
-    // Verify token exists and is valid
-    let now = Utc::now().to_rfc3339();
-    let stmt = db.prepare("SELECT COUNT(*) as count FROM csrf_tokens WHERE user_id = ?1 AND token = ?2 AND expires_at > ?3");
-    
-    if let Ok(stmt) = stmt.bind(&vec![
-        user_id.into(),
-        csrf_token.into(),
-        now.into(),
-    ]) {
-        if let Ok(Some(result)) = stmt.first::<serde_json::Value>(None).await {
-            let count = result["count"].as_u64().unwrap_or(0);
-            return Ok(count > 0);
-        }
-    }
-    
-    Ok(false)
-}
\ No newline at end of file
+    let key = match CsrfKey::from_env(env) {
+        Some(key) => key,
+        None => return Ok(false),
+    };
+
+    Ok(key.verify(session_id, &csrf_token, Utc::now().timestamp()))
+}
@@ -1,10 +1,13 @@
+use crate::error::{read_json, ApiError, ApiResult};
 use crate::models::*;
-use crate::handlers::auth::verify_csrf_token;
+use crate::membership::{self, MembershipError};
+use crate::middleware::RequestContext;
+use crate::store::{D1Store, Store};
 use chrono::Utc;
 use uuid::Uuid;
 use worker::*;
 
-pub async fn handle_clubs(req: Request, ctx: RouteContext<()>) -> Result<Response> {
+pub async fn handle_clubs(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
     let method = req.method();
     let url = req.url()?;
     let path = url.path();
@@ -15,203 +18,135 @@ pub async fn handle_clubs(req: Request, ctx: RouteContext<()>) -> Result<Respons
             if path.contains("/clubs/") {
                 // Extract club_id from path manually
                 let segments: Vec<&str> = path.split('/').collect();
-                if let Some(club_id) = segments.get(segments.len() - 1) {
+                if let Some(club_id) = segments.last() {
                     if !club_id.is_empty() && *club_id != "clubs" {
-                        return get_club_authenticated(club_id, req, ctx).await;
+                        return get_club(club_id, ctx).await;
                     }
                 }
             }
-            get_clubs_authenticated(req, ctx).await
+            get_clubs(ctx).await
+        }
+        Method::Post if path.ends_with("/transfer") => {
+            transfer_club_ownership(req, ctx).await
         }
         Method::Post => {
             // Create new club - requires CSRF protection
             create_club(req, ctx).await
         }
-        _ => Response::error("Method not allowed", 405),
-    }
-}
-
-async fn get_clubs_authenticated(req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Require authentication for viewing clubs
-    if get_user_id_from_token(&req, &ctx).is_none() {
-        return Response::error("Unauthorized", 401);
+        Method::Put if path.ends_with("/security") => {
+            update_club_security(req, ctx).await
+        }
+        _ => Err(ApiError::MethodNotAllowed),
     }
-    
-    get_clubs(ctx).await
 }
 
-async fn get_clubs(ctx: RouteContext<()>) -> Result<Response> {
+async fn get_clubs(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
     let db = match ctx.env.d1("DB") {
         Ok(db) => db,
-        Err(_) => return Response::error("Database connection failed", 500),
-    };
-
-    // Query all clubs from database
-    let stmt = db.prepare("SELECT id, name, description, avatar, created_at, updated_at, owner_id FROM clubs ORDER BY created_at DESC");
-    let results = match stmt.all().await {
-        Ok(results) => results.results::<serde_json::Value>().ok().unwrap_or_default(),
-        Err(_) => return Response::error("Failed to fetch clubs", 500),
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
     };
 
-    let clubs: Vec<Club> = results
-        .into_iter()
-        .filter_map(|row| {
-            Some(Club {
-                id: row["id"].as_str()?.to_string(),
-                name: row["name"].as_str()?.to_string(),
-                description: row["description"].as_str().map(|s| s.to_string()),
-                avatar: row["avatar"].as_str().map(|s| s.to_string()),
-                created_at: row["created_at"].as_str()?.to_string(),
-                updated_at: row["updated_at"].as_str()?.to_string(),
-                owner_id: row["owner_id"].as_str()?.to_string(),
-            })
-        })
-        .collect();
-
-    let response = ApiResponse {
-        success: true,
-        data: Some(clubs),
-        error: None,
+    let clubs = match D1Store::new(&db).clubs().await {
+        Ok(clubs) => clubs,
+        Err(_) => return Err(ApiError::Internal("Failed to fetch clubs".to_string())),
     };
 
-    Response::from_json(&response)
-}
+    let response = ApiResponse::success(clubs);
 
-async fn get_club_authenticated(club_id: &str, req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Require authentication for viewing club details
-    if get_user_id_from_token(&req, &ctx).is_none() {
-        return Response::error("Unauthorized", 401);
-    }
-    
-    get_club(club_id, ctx).await
+    Ok(Response::from_json(&response)?)
 }
 
-async fn get_club(club_id: &str, ctx: RouteContext<()>) -> Result<Response> {
+async fn get_club(club_id: &str, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
     let db = match ctx.env.d1("DB") {
         Ok(db) => db,
-        Err(_) => return Response::error("Database connection failed", 500),
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
     };
 
-    // Query specific club from database
-    let stmt = db.prepare("SELECT id, name, description, avatar, created_at, updated_at, owner_id FROM clubs WHERE id = ?1");
-    let stmt = match stmt.bind(&vec![club_id.into()]) {
-        Ok(stmt) => stmt,
-        Err(_) => return Response::error("Failed to prepare query", 500),
-    };
-
-    match stmt.first::<serde_json::Value>(None).await {
-        Ok(Some(row)) => {
-            let club = Club {
-                id: row["id"].as_str().unwrap_or("").to_string(),
-                name: row["name"].as_str().unwrap_or("").to_string(),
-                description: row["description"].as_str().map(|s| s.to_string()),
-                avatar: row["avatar"].as_str().map(|s| s.to_string()),
-                created_at: row["created_at"].as_str().unwrap_or("").to_string(),
-                updated_at: row["updated_at"].as_str().unwrap_or("").to_string(),
-                owner_id: row["owner_id"].as_str().unwrap_or("").to_string(),
-            };
-
-            let response = ApiResponse {
-                success: true,
-                data: Some(club),
-                error: None,
-            };
-
-            Response::from_json(&response)
-        }
-        Ok(None) => {
-            let response: ApiResponse<Club> = ApiResponse {
-                success: false,
-                data: None,
-                error: Some("Club not found".to_string()),
-            };
-            Ok(Response::from_json(&response)?.with_status(404))
-        }
-        Err(_) => Response::error("Failed to fetch club", 500),
+    match D1Store::new(&db).club(club_id).await {
+        Ok(Some(club)) => club_response(club),
+        Ok(None) => Err(ApiError::NotFound("Club not found".to_string())),
+        Err(_) => Err(ApiError::Internal("Failed to fetch club".to_string())),
     }
 }
 
-async fn create_club(mut req: Request, ctx: RouteContext<()>) -> Result<Response> {
-    // Verify CSRF token for this state-changing operation
-    if !verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-        return Response::error("CSRF token validation failed", 403);
-    }
+fn club_response(club: Club) -> ApiResult<Response> {
+    let response = ApiResponse::success(club);
 
-    // Get user ID from token
-    let user_id = match get_user_id_from_token(&req, &ctx) {
-        Some(id) => id,
-        None => return Response::error("Unauthorized", 401),
-    };
+    Ok(Response::from_json(&response)?)
+}
 
-    let create_request: CreateClubRequest = match req.json().await {
-        Ok(req) => req,
-        Err(_) => return Response::error("Invalid request body", 400),
-    };
+async fn create_club(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
+
+    let create_request: CreateClubRequest = read_json(&mut req).await?;
 
     let db = match ctx.env.d1("DB") {
         Ok(db) => db,
-        Err(_) => return Response::error("Database connection failed", 500),
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
     };
 
-    // Create new club in database
-    let club_id = Uuid::new_v4().to_string();
+    // Create new club in database, with the creator as its first admin
     let now = Utc::now().to_rfc3339();
-
-    let stmt = db.prepare("
-        INSERT INTO clubs (id, name, description, created_at, updated_at, owner_id)
-        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
-    ");
-    
-    let stmt = match stmt.bind(&vec![
-        club_id.clone().into(),
-        create_request.name.clone().into(),
-        create_request.description.clone().into(),
-        now.clone().into(),
-        now.clone().into(),
-        user_id.clone().into(),
-    ]) {
-        Ok(stmt) => stmt,
-        Err(_) => return Response::error("Failed to prepare club insert", 500),
-    };
-    
-    if stmt.run().await.is_err() {
-        return Response::error("Failed to create club", 500);
-    }
-
-    // Also add the creator as an admin member
-    let member_id = Uuid::new_v4().to_string();
-    let member_stmt = db.prepare("
-        INSERT INTO members (id, user_id, club_id, role, joined_at)
-        VALUES (?1, ?2, ?3, 'admin', ?4)
-    ");
-    
-    if let Ok(stmt) = member_stmt.bind(&vec![
-        member_id.into(),
-        user_id.clone().into(),
-        club_id.clone().into(),
-        now.clone().into(),
-    ]) {
-        let _ = stmt.run().await;
-    }
-
     let club = Club {
-        id: club_id,
+        id: Uuid::new_v4().to_string(),
         name: create_request.name,
         description: Some(create_request.description),
         avatar: None,
         created_at: now.clone(),
         updated_at: now,
-        owner_id: user_id, // Use user_id here (after cloning above)
+        owner_id: user_id,
+        require_admin_mfa: false,
     };
 
-    let response = ApiResponse {
-        success: true,
-        data: Some(club),
-        error: None,
-    };
+    if D1Store::new(&db).create_club(&club).await.is_err() {
+        return Err(ApiError::Internal("Failed to create club".to_string()));
+    }
+
+    let response = ApiResponse::success(club);
 
     Ok(Response::from_json(&response)?.with_status(201))
 }
 
-// Import the helper function from auth module
-use crate::handlers::auth::get_user_id_from_token;
+async fn update_club_security(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
+
+    let club_id = ctx.data.club()?.club_id.clone();
+
+    let security_request: UpdateClubSecurityRequest = read_json(&mut req).await?;
+
+    let db = match ctx.env.d1("DB") {
+        Ok(db) => db,
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
+    };
+
+    let store = D1Store::new(&db);
+    match membership::set_admin_mfa_requirement(&store, &club_id, &user_id, security_request.require_admin_mfa, Utc::now()).await {
+        Ok(club) => club_response(club),
+        Err(MembershipError::NotOwner) => Err(ApiError::Forbidden("Only the club owner can change security settings".to_string())),
+        // Don't let the owner lock themselves out of their own club
+        Err(MembershipError::MfaRequired) => Err(ApiError::Conflict("Enable two-factor authentication on your account first".to_string())),
+        Err(error) => Err(error.into()),
+    }
+}
+
+async fn transfer_club_ownership(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let user_id = ctx.data.auth()?.user_id.clone();
+
+    let club_id = ctx.data.club()?.club_id.clone();
+
+    let transfer_request: TransferClubOwnershipRequest = read_json(&mut req).await?;
+
+    let db = match ctx.env.d1("DB") {
+        Ok(db) => db,
+        Err(_) => return Err(ApiError::Internal("Database connection failed".to_string())),
+    };
+
+    let store = D1Store::new(&db);
+    match membership::transfer_ownership(&store, &club_id, &user_id, &transfer_request.new_owner_id, Utc::now()).await {
+        Ok(club) => club_response(club),
+        Err(MembershipError::NotOwner) => Err(ApiError::Forbidden("Only the club owner can transfer ownership".to_string())),
+        Err(MembershipError::NotMember) => Err(ApiError::BadRequest("The new owner must be a member of the club".to_string())),
+        Err(MembershipError::MfaRequired) => Err(ApiError::Conflict("The new owner must enable two-factor authentication first".to_string())),
+        Err(error) => Err(error.into()),
+    }
+}
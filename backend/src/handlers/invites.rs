@@ -0,0 +1,210 @@
+use crate::error::{read_json, ApiError, ApiResult};
+use crate::models::*;
+use crate::email::{self, EmailTemplate};
+use crate::handlers::auth::{generate_secure_token, get_user_by_id, hash_token, is_valid_email};
+use crate::middleware::RequestContext;
+use crate::store::{D1Store, Database};
+use chrono::Utc;
+use uuid::Uuid;
+use worker::*;
+use worker::wasm_bindgen::JsValue;
+
+const DEFAULT_INVITE_TTL_DAYS: i64 = 14;
+const MAX_INVITE_TTL_DAYS: i64 = 90;
+const MAX_LISTED_INVITES: i64 = 200;
+
+pub async fn handle_invites(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Get, "/api/admin/invites") => list_platform_invites(ctx).await,
+        (Method::Post, "/api/admin/invites") => create_platform_invite(req, ctx).await,
+        (Method::Delete, path) if path.starts_with("/api/admin/invites/") => revoke_platform_invite(ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+/// Platform admins are the verified accounts whose address is listed in the
+/// comma-separated `PLATFORM_ADMIN_EMAILS` var.
+fn is_platform_admin(env: &Env, user: &AuthUser) -> bool {
+    let admins = env.var("PLATFORM_ADMIN_EMAILS").map(|v| v.to_string()).unwrap_or_default();
+    user.email_verified
+        && user.is_active
+        && admins.split(',').any(|email| email.trim().eq_ignore_ascii_case(&user.email))
+}
+
+async fn list_platform_invites(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    match get_user_by_id(&db, &session.user_id).await {
+        Some(user) if is_platform_admin(&ctx.env, &user) => {}
+        _ => return Err(ApiError::Forbidden("Only platform admins can manage invites".to_string())),
+    }
+
+    let invites: Vec<PlatformInvite> = D1Store::new(&db)
+        .query_as("SELECT * FROM platform_invites ORDER BY created_at DESC LIMIT ?1", &[MAX_LISTED_INVITES.into()])
+        .await?;
+    let invites: Vec<PlatformInviteSummary> = invites.into_iter().map(PlatformInviteSummary::from).collect();
+
+    let response = ApiResponse::success(invites);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn create_platform_invite(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let create_request: CreatePlatformInviteRequest = read_json(&mut req).await?;
+
+    let email = create_request.email
+        .map(|email| email.trim().to_lowercase())
+        .filter(|email| !email.is_empty());
+    if matches!(&email, Some(email) if !is_valid_email(email)) {
+        return Err(ApiError::invalid("email", "Invalid email format"));
+    }
+
+    let ttl_days = create_request.expires_in_days.unwrap_or(DEFAULT_INVITE_TTL_DAYS);
+    if !(1..=MAX_INVITE_TTL_DAYS).contains(&ttl_days) {
+        return Err(ApiError::invalid("expires_in_days", "Invites must expire within 1 to 90 days"));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    let admin = match get_user_by_id(&db, &session.user_id).await {
+        Some(user) if is_platform_admin(&ctx.env, &user) => user,
+        _ => return Err(ApiError::Forbidden("Only platform admins can manage invites".to_string())),
+    };
+
+    let token = generate_secure_token();
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::days(ttl_days);
+    let invite = PlatformInvite {
+        id: Uuid::new_v4().to_string(),
+        token_hash: hash_token(&token),
+        email,
+        created_by: admin.id,
+        expires_at: expires_at.to_rfc3339(),
+        created_at: now.to_rfc3339(),
+        used_at: None,
+        used_by: None,
+    };
+
+    if !store_platform_invite(&db, &invite).await {
+        return Err(ApiError::Internal("Failed to create invite".to_string()));
+    }
+
+    let link = email::app_link(&ctx.env, &format!("/auth/signup?invite={}", token));
+
+    if let Some(recipient) = &invite.email {
+        let template = EmailTemplate::PlatformInvite {
+            inviter_name: admin.name,
+            link: link.clone(),
+            expires_at: expires_at.format("%Y-%m-%d").to_string(),
+        };
+        email::send_email(&ctx.env, recipient, template).await;
+    }
+
+    // The raw token is only ever returned here
+    let response = ApiResponse::success(CreatePlatformInviteResponse {
+        token,
+        link,
+        invite: PlatformInviteSummary::from(invite),
+    });
+    Ok(Response::from_json(&response)?.with_status(201))
+}
+
+async fn revoke_platform_invite(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let invite_id = match ctx.param("id") {
+        Some(id) => id.to_string(),
+        None => return Err(ApiError::BadRequest("Invite ID required".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    match get_user_by_id(&db, &session.user_id).await {
+        Some(user) if is_platform_admin(&ctx.env, &user) => {}
+        _ => return Err(ApiError::Forbidden("Only platform admins can manage invites".to_string())),
+    }
+
+    // Used invites are kept as a record of who let the account in
+    let stmt = db.prepare("DELETE FROM platform_invites WHERE id = ?1 AND used_at IS NULL");
+    let result = stmt.bind(&[invite_id.into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) => {}
+        Ok(_) => return Err(ApiError::NotFound("Invite not found or already used".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to revoke invite".to_string())),
+    }
+
+    let response = ApiResponse::success("Invite revoked successfully");
+    Ok(Response::from_json(&response)?)
+}
+
+/// Marks an unused, unexpired invite as redeemed by `user_id` for `email`.
+/// Single use: of two signups racing with the same token, only one gets `true`.
+pub(crate) async fn redeem_platform_invite(db: &D1Database, token: &str, email: &str, user_id: &str) -> bool {
+    let now = Utc::now().to_rfc3339();
+    let stmt = db.prepare("
+        UPDATE platform_invites SET used_at = ?1, used_by = ?2
+        WHERE token_hash = ?3 AND used_at IS NULL AND expires_at > ?1
+          AND (email IS NULL OR email = lower(?4))
+    ");
+    let stmt = match stmt.bind(&[now.into(), user_id.into(), hash_token(token).into(), email.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
+
+/// Puts back an invite redeemed by a signup that then failed.
+pub(crate) async fn release_platform_invite(db: &D1Database, user_id: &str) {
+    let stmt = db.prepare("UPDATE platform_invites SET used_at = NULL, used_by = NULL WHERE used_by = ?1");
+    if let Ok(stmt) = stmt.bind(&[user_id.into()]) {
+        let _ = stmt.run().await;
+    }
+}
+
+// Helper functions for D1 database operations
+
+async fn store_platform_invite(db: &D1Database, invite: &PlatformInvite) -> bool {
+    let stmt = db.prepare("
+        INSERT INTO platform_invites (id, token_hash, email, created_by, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ");
+    let stmt = match stmt.bind(&[
+        invite.id.clone().into(),
+        invite.token_hash.clone().into(),
+        invite.email.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        invite.created_by.clone().into(),
+        invite.expires_at.clone().into(),
+        invite.created_at.clone().into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    stmt.run().await.is_ok()
+}
+
+impl From<PlatformInvite> for PlatformInviteSummary {
+    fn from(invite: PlatformInvite) -> Self {
+        PlatformInviteSummary {
+            id: invite.id,
+            email: invite.email,
+            created_by: invite.created_by,
+            expires_at: invite.expires_at,
+            created_at: invite.created_at,
+            used_at: invite.used_at,
+            used_by: invite.used_by,
+        }
+    }
+}
@@ -0,0 +1,371 @@
+use crate::error::{read_json, ApiError, ApiResult};
+use crate::models::*;
+use crate::handlers::auth::{
+    generate_secure_token, get_user_by_id, hash_token, start_session,
+};
+use crate::middleware::RequestContext;
+use crate::rate_limit::{self, D1CounterStore};
+use crate::second_factor::{self, SecondFactor, Verification};
+use crate::store::{D1Store, Database, Store};
+use crate::totp;
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+use worker::*;
+
+const RECOVERY_CODE_COUNT: usize = 10;
+
+// A pending login must be completed quickly and can't be brute-forced.
+const MFA_CHALLENGE_TTL_MINUTES: i64 = 5;
+const MAX_MFA_CHALLENGE_ATTEMPTS: i64 = 5;
+
+// Carries the challenge token for sign-ins that end in a redirect (social
+// login), so it never appears in a URL
+const MFA_CHALLENGE_COOKIE: &str = "mfa_challenge";
+
+pub async fn handle_mfa(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Post, "/api/auth/mfa/setup") => setup_mfa(ctx).await,
+        (Method::Post, "/api/auth/mfa/confirm") => confirm_mfa(req, ctx).await,
+        (Method::Post, "/api/auth/mfa/verify") => verify_mfa_challenge(req, ctx).await,
+        (Method::Post, "/api/auth/mfa/recovery-codes") => regenerate_recovery_codes(req, ctx).await,
+        (Method::Delete, "/api/auth/mfa") => disable_mfa(req, ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+async fn setup_mfa(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let auth_user = match get_user_by_id(&db, &session.user_id).await {
+        Some(user) => user,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    if user_has_mfa(&db, &auth_user.id).await {
+        return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string()));
+    }
+
+    let secret = totp::generate_secret();
+    let now = Utc::now().to_rfc3339();
+
+    // Starting setup again replaces any secret that was never confirmed
+    let stmt = db.prepare("
+        INSERT INTO user_mfa (user_id, secret, enabled, created_at)
+        VALUES (?1, ?2, 0, ?3)
+        ON CONFLICT(user_id) DO UPDATE SET secret = excluded.secret, created_at = excluded.created_at, enabled = 0, confirmed_at = NULL, last_used_step = NULL
+    ");
+    let result = stmt.bind(&[
+        auth_user.id.clone().into(),
+        secret.clone().into(),
+        now.into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to start two-factor setup".to_string()));
+    }
+
+    let response = ApiResponse::success(MfaSetupResponse {
+        otpauth_uri: totp::otpauth_uri(&secret, &auth_user.email),
+        secret,
+    });
+    Ok(Response::from_json(&response)?)
+}
+
+async fn confirm_mfa(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let confirm_request: MfaCodeRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let mfa = match get_user_mfa(&db, &session.user_id).await {
+        Some(mfa) if !mfa.enabled => mfa,
+        Some(_) => return Err(ApiError::Conflict("Two-factor authentication is already enabled".to_string())),
+        None => return Err(ApiError::BadRequest("Two-factor setup has not been started".to_string())),
+    };
+
+    let step = match totp::verify(&mfa.secret, &confirm_request.code, mfa.last_used_step, Utc::now()) {
+        Some(step) => step,
+        None => return Err(ApiError::BadRequest("Invalid authentication code".to_string())),
+    };
+
+    let stmt = db.prepare("UPDATE user_mfa SET enabled = 1, confirmed_at = ?1, last_used_step = ?2 WHERE user_id = ?3");
+    let result = stmt.bind(&[
+        Utc::now().to_rfc3339().into(),
+        (step as f64).into(),
+        session.user_id.clone().into(),
+    ])?.run().await;
+
+    if result.is_err() {
+        return Err(ApiError::Internal("Failed to enable two-factor authentication".to_string()));
+    }
+
+    let recovery_codes = match replace_recovery_codes(&db, &session.user_id).await {
+        Some(codes) => codes,
+        None => return Err(ApiError::Internal("Failed to generate recovery codes".to_string())),
+    };
+
+    let response = ApiResponse::success(MfaRecoveryCodesResponse { recovery_codes });
+    Ok(Response::from_json(&response)?)
+}
+
+async fn verify_mfa_challenge(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let verify_request: MfaVerifyRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let mfa_token = match verify_request.mfa_token.filter(|token| !token.is_empty()).or_else(|| challenge_cookie(&req)) {
+        Some(mfa_token) => mfa_token,
+        None => return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string())),
+    };
+
+    let challenge = match get_mfa_challenge(&db, &mfa_token).await {
+        Some(challenge) => challenge,
+        None => return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string())),
+    };
+
+    let expired = chrono::DateTime::parse_from_rfc3339(&challenge.expires_at)
+        .map(|expires_at| expires_at < Utc::now())
+        .unwrap_or(true);
+    if expired {
+        return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string()));
+    }
+
+    // Claim an attempt before checking the code. The claim only succeeds while
+    // attempts are left, so parallel guesses can't get past the limit
+    if !record_challenge_attempt(&db, &challenge.id).await {
+        return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string()));
+    }
+
+    let factor = match (&verify_request.code, &verify_request.recovery_code) {
+        (Some(code), _) => SecondFactor::Code(code),
+        (None, Some(recovery_code)) => SecondFactor::RecoveryCode(recovery_code),
+        (None, None) => return Err(ApiError::BadRequest("Authentication code or recovery code is required".to_string())),
+    };
+
+    match second_factor::verify(&D1Store::new(&db), &D1CounterStore::new(&db), &challenge.user_id, factor, Utc::now()).await {
+        Verification::Verified => {}
+        Verification::Rejected => return Err(ApiError::Unauthorized("Invalid authentication code".to_string())),
+        Verification::Limited { retry_after } => return Err(rate_limit::too_many_requests(retry_after)),
+    }
+
+    // Challenges are single-use; only the request that consumed it gets a session
+    if !consume_mfa_challenge(&db, &challenge.id).await {
+        return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string()));
+    }
+
+    let auth_user = match get_user_by_id(&db, &challenge.user_id).await {
+        Some(user) if user.is_active => user,
+        _ => return Err(ApiError::Unauthorized("Invalid or expired two-factor challenge".to_string())),
+    };
+
+    let mut response = start_session(&req, &ctx, &db, auth_user).await?;
+    response.headers_mut().append("Set-Cookie", &format!("{}=; HttpOnly; Secure; SameSite=Lax; Path=/api/auth/mfa; Max-Age=0", MFA_CHALLENGE_COOKIE))?;
+    Ok(response)
+}
+
+async fn regenerate_recovery_codes(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let code_request: MfaCodeRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    if !verify_current_code(&D1Store::new(&db), &session.user_id, &code_request.code, Utc::now()).await {
+        return Err(ApiError::BadRequest("Invalid authentication code".to_string()));
+    }
+
+    let recovery_codes = match replace_recovery_codes(&db, &session.user_id).await {
+        Some(codes) => codes,
+        None => return Err(ApiError::Internal("Failed to generate recovery codes".to_string())),
+    };
+
+    let response = ApiResponse::success(MfaRecoveryCodesResponse { recovery_codes });
+    Ok(Response::from_json(&response)?)
+}
+
+async fn disable_mfa(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let code_request: MfaCodeRequest = read_json(&mut req).await?;
+
+    let db = ctx.env.d1("DB")?;
+
+    if !verify_current_code(&D1Store::new(&db), &session.user_id, &code_request.code, Utc::now()).await {
+        return Err(ApiError::BadRequest("Invalid authentication code".to_string()));
+    }
+
+    if admin_of_mfa_required_club(&db, &session.user_id).await {
+        return Err(ApiError::Conflict("Two-factor authentication is required for admins of one of your clubs".to_string()));
+    }
+
+    let stmt = db.prepare("DELETE FROM user_mfa WHERE user_id = ?1");
+    stmt.bind(&[session.user_id.clone().into()])?.run().await?;
+    let stmt = db.prepare("DELETE FROM mfa_recovery_codes WHERE user_id = ?1");
+    stmt.bind(&[session.user_id.into()])?.run().await?;
+
+    let response = ApiResponse::success("Two-factor authentication disabled");
+    Ok(Response::from_json(&response)?)
+}
+
+// Shared with login and the club handlers
+
+/// Whether the user has completed two-factor enrollment.
+pub async fn user_has_mfa(db: &D1Database, user_id: &str) -> bool {
+    matches!(get_user_mfa(db, user_id).await, Some(mfa) if mfa.enabled)
+}
+
+/// Issues the pending-MFA challenge `login` returns in place of a session.
+pub async fn start_mfa_challenge(db: &D1Database, user_id: &str) -> ApiResult<Response> {
+    let (mfa_token, expires_at) = match create_mfa_challenge(db, user_id).await {
+        Some(challenge) => challenge,
+        None => return Err(ApiError::Internal("Failed to start two-factor challenge".to_string())),
+    };
+
+    let response = MfaChallengeResponse {
+        success: true,
+        mfa_required: true,
+        mfa_token,
+        expires_at,
+    };
+    Ok(Response::from_json(&response)?)
+}
+
+/// A `Set-Cookie` value holding a challenge token for `verify_mfa_challenge`
+/// to pick up when the request body has none.
+pub fn mfa_challenge_cookie(mfa_token: &str) -> String {
+    format!(
+        "{}={}; HttpOnly; Secure; SameSite=Lax; Path=/api/auth/mfa; Max-Age={}",
+        MFA_CHALLENGE_COOKIE, mfa_token, MFA_CHALLENGE_TTL_MINUTES * 60
+    )
+}
+
+fn challenge_cookie(req: &Request) -> Option<String> {
+    let cookie_header = req.headers().get("Cookie").ok()??;
+    cookie_header.split(';')
+        .find_map(|cookie| cookie.trim().strip_prefix(&format!("{}=", MFA_CHALLENGE_COOKIE)).map(|s| s.to_string()))
+        .filter(|token| !token.is_empty())
+}
+
+/// Stores a new challenge for `user_id`, returning the raw token and its expiry.
+pub async fn create_mfa_challenge(db: &D1Database, user_id: &str) -> Option<(String, String)> {
+    let token = generate_secure_token();
+    let now = Utc::now();
+    let expires_at = (now + chrono::Duration::minutes(MFA_CHALLENGE_TTL_MINUTES)).to_rfc3339();
+
+    let stmt = db.prepare("
+        INSERT INTO mfa_challenges (id, user_id, token_hash, expires_at, created_at, attempts)
+        VALUES (?1, ?2, ?3, ?4, ?5, 0)
+    ");
+    stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
+        user_id.into(),
+        hash_token(&token).into(),
+        expires_at.clone().into(),
+        now.to_rfc3339().into(),
+    ]).ok()?.run().await.ok()?;
+
+    Some((token, expires_at))
+}
+
+/// Whether the user is an admin of any club that requires admins to use 2FA.
+pub async fn admin_of_mfa_required_club(db: &D1Database, user_id: &str) -> bool {
+    let stmt = db.prepare("
+        SELECT COUNT(*) as count
+        FROM members m
+        INNER JOIN clubs c ON m.club_id = c.id
+        WHERE m.user_id = ?1 AND m.role = 'admin' AND c.require_admin_mfa = 1
+    ");
+
+    let stmt = match stmt.bind(&[user_id.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return true,
+    };
+
+    match stmt.first::<serde_json::Value>(None).await {
+        Ok(Some(result)) => result["count"].as_u64().unwrap_or(0) > 0,
+        _ => true,
+    }
+}
+
+// Helper functions for D1 database operations
+
+async fn get_user_mfa(db: &D1Database, user_id: &str) -> Option<UserMfa> {
+    D1Store::new(db).user_mfa(user_id).await.ok()?
+}
+
+/// Whether `code` is the user's current authentication code. Accepting it
+/// uses up its time step.
+pub(crate) async fn verify_current_code<S: Store>(store: &S, user_id: &str, code: &str, now: DateTime<Utc>) -> bool {
+    match store.user_mfa(user_id).await {
+        Ok(Some(mfa)) if mfa.enabled => match totp::verify(&mfa.secret, code, mfa.last_used_step, now) {
+            Some(step) => store.record_totp_step(user_id, step).await.unwrap_or(false),
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+async fn replace_recovery_codes(db: &D1Database, user_id: &str) -> Option<Vec<String>> {
+    let stmt = db.prepare("DELETE FROM mfa_recovery_codes WHERE user_id = ?1");
+    stmt.bind(&[user_id.into()]).ok()?.run().await.ok()?;
+
+    let now = Utc::now().to_rfc3339();
+    let mut codes = Vec::with_capacity(RECOVERY_CODE_COUNT);
+    for _ in 0..RECOVERY_CODE_COUNT {
+        let code = totp::generate_recovery_code();
+        let stmt = db.prepare("
+            INSERT INTO mfa_recovery_codes (id, user_id, code_hash, created_at)
+            VALUES (?1, ?2, ?3, ?4)
+        ");
+        stmt.bind(&[
+            Uuid::new_v4().to_string().into(),
+            user_id.into(),
+            hash_token(&totp::normalize_recovery_code(&code)).into(),
+            now.clone().into(),
+        ]).ok()?.run().await.ok()?;
+        codes.push(code);
+    }
+
+    Some(codes)
+}
+
+async fn get_mfa_challenge(db: &D1Database, token: &str) -> Option<MfaChallenge> {
+    D1Store::new(db)
+        .query_first_as("SELECT * FROM mfa_challenges WHERE token_hash = ?1 AND used_at IS NULL", &[hash_token(token).into()])
+        .await
+        .ok()?
+}
+
+/// Uses up one of the challenge's attempts; false once they are all gone.
+async fn record_challenge_attempt(db: &D1Database, challenge_id: &str) -> bool {
+    let stmt = db.prepare("UPDATE mfa_challenges SET attempts = attempts + 1 WHERE id = ?1 AND attempts < ?2");
+    let stmt = match stmt.bind(&[challenge_id.into(), (MAX_MFA_CHALLENGE_ATTEMPTS as f64).into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
+
+async fn consume_mfa_challenge(db: &D1Database, challenge_id: &str) -> bool {
+    let stmt = db.prepare("UPDATE mfa_challenges SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL");
+    let stmt = match stmt.bind(&[Utc::now().to_rfc3339().into(), challenge_id.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
@@ -1,4 +1,11 @@
 pub mod auth;
+pub mod account;
+pub mod audit;
+pub mod invites;
+pub mod mfa;
+pub mod passkeys;
+pub mod social;
+pub mod tokens;
 pub mod clubs;
 pub mod members;
 pub mod events;
@@ -6,6 +13,13 @@ pub mod announcements;
 pub mod projects;
 
 pub use auth::*;
+pub use account::*;
+pub use audit::*;
+pub use invites::*;
+pub use mfa::*;
+pub use passkeys::*;
+pub use social::*;
+pub use tokens::*;
 pub use clubs::*;
 pub use members::*;
 pub use events::*;
@@ -0,0 +1,406 @@
+use crate::error::{read_json, ApiError, ApiResult};
+use crate::models::*;
+use crate::handlers::auth::{
+    get_user_by_id, start_session, unverified_login_allowed,
+};
+use crate::middleware::RequestContext;
+use crate::webauthn::{self, RelyingParty, WebAuthnError};
+use crate::store::{D1Store, Database};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::Utc;
+use uuid::Uuid;
+use worker::wasm_bindgen::JsValue;
+use worker::*;
+
+// Browsers show their own UI for the whole ceremony, so give the user time
+const WEBAUTHN_CHALLENGE_TTL_MINUTES: i64 = 5;
+const WEBAUTHN_TIMEOUT_MS: u32 = 300_000;
+
+const CEREMONY_REGISTRATION: &str = "registration";
+const CEREMONY_AUTHENTICATION: &str = "authentication";
+
+const MAX_PASSKEY_NAME_LENGTH: usize = 64;
+
+pub async fn handle_passkeys(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Post, "/api/auth/passkeys/register/options") => registration_options(ctx).await,
+        (Method::Post, "/api/auth/passkeys/register") => register_passkey(req, ctx).await,
+        (Method::Post, "/api/auth/passkeys/login/options") => authentication_options(req, ctx).await,
+        (Method::Post, "/api/auth/passkeys/login") => passkey_login(req, ctx).await,
+        (Method::Get, "/api/auth/passkeys") => list_passkeys(ctx).await,
+        (Method::Delete, path) if path.starts_with("/api/auth/passkeys/") => delete_passkey(ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+async fn registration_options(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let auth_user = match get_user_by_id(&db, &session.user_id).await {
+        Some(user) => user,
+        None => return Err(ApiError::NotFound("User not found".to_string())),
+    };
+
+    let challenge = match create_challenge(&db, Some(&auth_user.id), CEREMONY_REGISTRATION).await {
+        Some(challenge) => challenge,
+        None => return Err(ApiError::Internal("Failed to start passkey registration".to_string())),
+    };
+
+    let rp = RelyingParty::from_env(&ctx.env);
+
+    // Stop the browser from registering the same authenticator twice
+    let exclude_credentials: Vec<serde_json::Value> = get_user_passkeys(&db, &auth_user.id).await
+        .into_iter()
+        .map(|passkey| serde_json::json!({ "type": "public-key", "id": passkey.credential_id }))
+        .collect();
+
+    let pub_key_cred_params: Vec<serde_json::Value> = webauthn::SUPPORTED_ALGORITHMS
+        .iter()
+        .map(|alg| serde_json::json!({ "type": "public-key", "alg": alg }))
+        .collect();
+
+    let options = serde_json::json!({
+        "challenge": challenge,
+        "rp": { "id": rp.id, "name": rp.name },
+        "user": {
+            "id": URL_SAFE_NO_PAD.encode(auth_user.id.as_bytes()),
+            "name": auth_user.email,
+            "displayName": auth_user.name,
+        },
+        "pubKeyCredParams": pub_key_cred_params,
+        "timeout": WEBAUTHN_TIMEOUT_MS,
+        "attestation": "none",
+        "excludeCredentials": exclude_credentials,
+        "authenticatorSelection": {
+            "residentKey": "preferred",
+            "userVerification": "required",
+        },
+    });
+
+    let response = ApiResponse::success(options);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn register_passkey(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let register_request: RegisterPasskeyRequest = read_json(&mut req).await?;
+
+    let (client_data_json, attestation_object) = match (
+        URL_SAFE_NO_PAD.decode(&register_request.credential.response.client_data_json),
+        URL_SAFE_NO_PAD.decode(&register_request.credential.response.attestation_object),
+    ) {
+        (Ok(client_data_json), Ok(attestation_object)) => (client_data_json, attestation_object),
+        _ => return Err(ApiError::BadRequest("Invalid credential encoding".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    let challenge = match consume_challenge(&db, &client_data_json, CEREMONY_REGISTRATION).await {
+        Some(challenge) if challenge.user_id.as_deref() == Some(session.user_id.as_str()) => challenge,
+        _ => return Err(ApiError::BadRequest("Invalid or expired passkey challenge".to_string())),
+    };
+
+    let rp = RelyingParty::from_env(&ctx.env);
+    let credential = match webauthn::verify_registration(&rp, &challenge.challenge, &client_data_json, &attestation_object) {
+        Ok(credential) => credential,
+        Err(e) => return Err(ApiError::BadRequest(e.to_string())),
+    };
+
+    let name = register_request.name
+        .map(|name| name.trim().chars().take(MAX_PASSKEY_NAME_LENGTH).collect::<String>())
+        .filter(|name| !name.is_empty())
+        .unwrap_or_else(|| "Passkey".to_string());
+    let transports = register_request.credential.response.transports
+        .filter(|transports| !transports.is_empty())
+        .map(|transports| transports.join(","));
+
+    let passkey = Passkey {
+        id: Uuid::new_v4().to_string(),
+        user_id: session.user_id,
+        credential_id: URL_SAFE_NO_PAD.encode(&credential.credential_id),
+        public_key: URL_SAFE_NO_PAD.encode(&credential.public_key),
+        algorithm: credential.algorithm,
+        sign_count: credential.sign_count,
+        name,
+        transports,
+        created_at: Utc::now().to_rfc3339(),
+        last_used_at: None,
+    };
+
+    if !store_passkey(&db, &passkey).await {
+        // The unique credential_id is the only constraint a valid insert can hit
+        return Err(ApiError::Conflict("This passkey is already registered".to_string()));
+    }
+
+    let response = ApiResponse::success(PasskeySummary {
+        id: passkey.id,
+        name: passkey.name,
+        created_at: passkey.created_at,
+        last_used_at: None,
+    });
+    Ok(Response::from_json(&response)?.with_status(201))
+}
+
+async fn authentication_options(_req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let db = ctx.env.d1("DB")?;
+
+    let challenge = match create_challenge(&db, None, CEREMONY_AUTHENTICATION).await {
+        Some(challenge) => challenge,
+        None => return Err(ApiError::Internal("Failed to start passkey sign-in".to_string())),
+    };
+
+    let rp = RelyingParty::from_env(&ctx.env);
+
+    // No allowCredentials: passkeys are discoverable, and listing them for an
+    // email address would reveal whether that account exists
+    let options = serde_json::json!({
+        "challenge": challenge,
+        "rpId": rp.id,
+        "timeout": WEBAUTHN_TIMEOUT_MS,
+        "userVerification": "required",
+    });
+
+    let response = ApiResponse::success(options);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn passkey_login(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let login_request: PasskeyLoginRequest = read_json(&mut req).await?;
+
+    let assertion = &login_request.credential.response;
+    let (client_data_json, authenticator_data, signature) = match (
+        URL_SAFE_NO_PAD.decode(&assertion.client_data_json),
+        URL_SAFE_NO_PAD.decode(&assertion.authenticator_data),
+        URL_SAFE_NO_PAD.decode(&assertion.signature),
+    ) {
+        (Ok(client_data_json), Ok(authenticator_data), Ok(signature)) => (client_data_json, authenticator_data, signature),
+        _ => return Err(ApiError::BadRequest("Invalid credential encoding".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    let challenge = match consume_challenge(&db, &client_data_json, CEREMONY_AUTHENTICATION).await {
+        Some(challenge) => challenge,
+        None => return passkey_login_failed(),
+    };
+
+    let passkey = match get_passkey_by_credential_id(&db, &login_request.credential.id).await {
+        Some(passkey) => passkey,
+        None => return passkey_login_failed(),
+    };
+
+    // When the authenticator reports whose credential this is, it must agree with our record
+    if let Some(user_handle) = &assertion.user_handle {
+        if URL_SAFE_NO_PAD.decode(user_handle).ok().as_deref() != Some(passkey.user_id.as_bytes()) {
+            return passkey_login_failed();
+        }
+    }
+
+    let public_key = match URL_SAFE_NO_PAD.decode(&passkey.public_key) {
+        Ok(public_key) => public_key,
+        Err(_) => return Err(ApiError::Internal("Stored passkey is corrupt".to_string())),
+    };
+
+    let rp = RelyingParty::from_env(&ctx.env);
+    let verified = match webauthn::verify_authentication(
+        &rp,
+        &challenge.challenge,
+        &public_key,
+        passkey.sign_count,
+        &client_data_json,
+        &authenticator_data,
+        &signature,
+    ) {
+        Ok(verified) => verified,
+        Err(WebAuthnError::SignCountRegression) => {
+            console_log!("Passkey {} reported a lower signature counter; possible cloned authenticator", passkey.id);
+            return passkey_login_failed();
+        }
+        Err(_) => return passkey_login_failed(),
+    };
+
+    // Conditional on the stored counter so two racing assertions can't both pass
+    if !record_passkey_use(&db, &passkey, verified.sign_count).await {
+        return passkey_login_failed();
+    }
+
+    let auth_user = match get_user_by_id(&db, &passkey.user_id).await {
+        Some(user) if user.is_active => user,
+        _ => return passkey_login_failed(),
+    };
+
+    if !auth_user.email_verified && !unverified_login_allowed(&ctx, &auth_user.created_at) {
+        return Err(ApiError::Forbidden("Email address has not been verified".to_string()));
+    }
+
+    start_session(&req, &ctx, &db, auth_user).await
+}
+
+async fn list_passkeys(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let passkeys: Vec<PasskeySummary> = get_user_passkeys(&db, &session.user_id).await
+        .into_iter()
+        .map(|passkey| PasskeySummary {
+            id: passkey.id,
+            name: passkey.name,
+            created_at: passkey.created_at,
+            last_used_at: passkey.last_used_at,
+        })
+        .collect();
+
+    let response = ApiResponse::success(passkeys);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn delete_passkey(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let passkey_id = match ctx.param("id") {
+        Some(id) => id.to_string(),
+        None => return Err(ApiError::BadRequest("Passkey ID required".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    let stmt = db.prepare("DELETE FROM passkeys WHERE id = ?1 AND user_id = ?2");
+    let result = stmt.bind(&[passkey_id.into(), session.user_id.into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) => {}
+        Ok(_) => return Err(ApiError::NotFound("Passkey not found".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to remove passkey".to_string())),
+    }
+
+    let response = ApiResponse::success("Passkey removed successfully");
+    Ok(Response::from_json(&response)?)
+}
+
+fn passkey_login_failed() -> ApiResult<Response> {
+    Err(ApiError::Unauthorized("Passkey sign-in failed".to_string()))
+}
+
+// Helper functions for D1 database operations
+
+async fn create_challenge(db: &D1Database, user_id: Option<&str>, ceremony: &str) -> Option<String> {
+    let challenge = webauthn::generate_challenge();
+    let now = Utc::now();
+    let expires_at = now + chrono::Duration::minutes(WEBAUTHN_CHALLENGE_TTL_MINUTES);
+
+    // Sign-in options are unauthenticated, so expired rows are swept here
+    // rather than left to accumulate
+    let stmt = db.prepare("DELETE FROM webauthn_challenges WHERE expires_at < ?1");
+    if let Ok(stmt) = stmt.bind(&[now.to_rfc3339().into()]) {
+        let _ = stmt.run().await;
+    }
+
+    let stmt = db.prepare("
+        INSERT INTO webauthn_challenges (id, user_id, challenge, ceremony, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ");
+    stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
+        user_id.map(JsValue::from).unwrap_or(JsValue::NULL),
+        challenge.clone().into(),
+        ceremony.into(),
+        expires_at.to_rfc3339().into(),
+        now.to_rfc3339().into(),
+    ]).ok()?.run().await.ok()?;
+
+    Some(challenge)
+}
+
+/// Finds the challenge echoed in `client_data_json` and deletes it so it can
+/// only be answered once. Returns `None` if it is unknown, already used,
+/// expired, or was issued for a different ceremony.
+async fn consume_challenge(db: &D1Database, client_data_json: &[u8], ceremony: &str) -> Option<WebAuthnChallenge> {
+    let challenge = webauthn::client_data_challenge(client_data_json).ok()?;
+
+    let challenge: WebAuthnChallenge = D1Store::new(db)
+        .query_first_as(
+            "SELECT * FROM webauthn_challenges WHERE challenge = ?1 AND ceremony = ?2",
+            &[challenge.into(), ceremony.into()],
+        )
+        .await
+        .ok()??;
+
+    let stmt = db.prepare("DELETE FROM webauthn_challenges WHERE id = ?1");
+    let result = stmt.bind(&[challenge.id.clone().into()]).ok()?.run().await.ok()?;
+    if !matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) {
+        return None;
+    }
+
+    let expires_at = chrono::DateTime::parse_from_rfc3339(&challenge.expires_at).ok()?;
+    if expires_at < Utc::now() {
+        return None;
+    }
+
+    Some(challenge)
+}
+
+async fn store_passkey(db: &D1Database, passkey: &Passkey) -> bool {
+    let stmt = db.prepare("
+        INSERT INTO passkeys (id, user_id, credential_id, public_key, algorithm, sign_count, name, transports, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+    ");
+    let stmt = match stmt.bind(&[
+        passkey.id.clone().into(),
+        passkey.user_id.clone().into(),
+        passkey.credential_id.clone().into(),
+        passkey.public_key.clone().into(),
+        (passkey.algorithm as f64).into(),
+        (passkey.sign_count as f64).into(),
+        passkey.name.clone().into(),
+        passkey.transports.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        passkey.created_at.clone().into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    stmt.run().await.is_ok()
+}
+
+async fn record_passkey_use(db: &D1Database, passkey: &Passkey, sign_count: u32) -> bool {
+    let stmt = db.prepare("
+        UPDATE passkeys SET sign_count = ?1, last_used_at = ?2
+        WHERE id = ?3 AND sign_count = ?4
+    ");
+    let stmt = match stmt.bind(&[
+        (sign_count as f64).into(),
+        Utc::now().to_rfc3339().into(),
+        passkey.id.clone().into(),
+        (passkey.sign_count as f64).into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.run().await {
+        Ok(result) => matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)),
+        Err(_) => false,
+    }
+}
+
+async fn get_passkey_by_credential_id(db: &D1Database, credential_id: &str) -> Option<Passkey> {
+    D1Store::new(db)
+        .query_first_as("SELECT * FROM passkeys WHERE credential_id = ?1", &[credential_id.into()])
+        .await
+        .ok()?
+}
+
+async fn get_user_passkeys(db: &D1Database, user_id: &str) -> Vec<Passkey> {
+    D1Store::new(db)
+        .query_as("SELECT * FROM passkeys WHERE user_id = ?1 ORDER BY created_at DESC", &[user_id.into()])
+        .await
+        .unwrap_or_default()
+}
@@ -0,0 +1,580 @@
+use crate::error::{ApiError, ApiResult};
+use crate::models::*;
+use crate::email;
+use crate::handlers::auth::{
+    create_email_verification_token, generate_secure_token, get_user_by_email,
+    get_user_by_id, hash_token, send_verification_email, start_session, unverified_login_allowed,
+};
+use crate::middleware::RequestContext;
+use crate::handlers::mfa;
+use crate::oauth::{self, OAuthError, ProviderConfig, ProviderIdentity, TokenResponse};
+use crate::store::{D1Store, Database};
+use crate::signup::{SignupDecision, SignupMode, SignupPolicy};
+use chrono::Utc;
+use jsonwebtoken::jwk::JwkSet;
+use uuid::Uuid;
+use worker::wasm_bindgen::JsValue;
+use worker::*;
+
+// The user has this long to finish at the provider before the state expires
+const OAUTH_STATE_TTL_MINUTES: i64 = 10;
+
+const MODE_LOGIN: &str = "login";
+const MODE_LINK: &str = "link";
+
+// Binds the callback to the browser that started the flow, so a victim can't
+// be signed in to an attacker's account with a forged callback URL
+const OAUTH_STATE_COOKIE: &str = "oauth_state";
+
+pub async fn handle_social(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Get, "/api/auth/oauth/accounts") => list_linked_accounts(ctx).await,
+        (Method::Post, path) if path.ends_with("/authorize") => begin_oauth(req, ctx, MODE_LOGIN).await,
+        (Method::Post, path) if path.ends_with("/link") => begin_oauth(req, ctx, MODE_LINK).await,
+        (Method::Get, path) if path.ends_with("/callback") => oauth_callback(req, ctx).await,
+        (Method::Delete, _) => unlink_provider(ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+async fn begin_oauth(mut req: Request, ctx: RouteContext<RequestContext>, mode: &str) -> ApiResult<Response> {
+    // Linking attaches a login method to the signed-in account, so unlike
+    // signing in its route is private
+    let user_id = if mode == MODE_LINK {
+        Some(ctx.data.session()?.user_id)
+    } else {
+        None
+    };
+
+    let provider = match ctx.param("provider").and_then(|slug| SocialProvider::from_slug(slug)) {
+        Some(provider) => provider,
+        None => return Err(ApiError::NotFound("Unknown provider".to_string())),
+    };
+
+    let config = match ProviderConfig::from_env(&ctx.env, provider) {
+        Ok(config) => config,
+        Err(_) => return Err(ApiError::Unavailable("This sign-in provider is not configured".to_string())),
+    };
+
+    let start_request: OAuthStartRequest = req.json().await.unwrap_or_default();
+    let redirect_to = start_request.redirect_to.filter(|path| is_safe_redirect(path));
+
+    let db = ctx.env.d1("DB")?;
+
+    let state = generate_secure_token();
+    let code_verifier = generate_secure_token();
+    let nonce = generate_secure_token();
+
+    let oauth_state = OAuthState {
+        id: Uuid::new_v4().to_string(),
+        provider: config.provider.slug().to_string(),
+        mode: mode.to_string(),
+        user_id,
+        code_verifier,
+        nonce,
+        redirect_to,
+        expires_at: (Utc::now() + chrono::Duration::minutes(OAUTH_STATE_TTL_MINUTES)).to_rfc3339(),
+        created_at: Utc::now().to_rfc3339(),
+    };
+
+    if !store_oauth_state(&db, &oauth_state, &state).await {
+        return Err(ApiError::Internal("Failed to start sign-in".to_string()));
+    }
+
+    let authorization_url = config.authorization_url(
+        &callback_url(&req, &config)?,
+        &state,
+        &oauth_state.code_verifier,
+        &oauth_state.nonce,
+    );
+
+    let mut response = Response::from_json(&ApiResponse::success(OAuthStartResponse { authorization_url }))?;
+    response.headers_mut().append("Set-Cookie", &format!(
+        "{}={}; HttpOnly; Secure; SameSite=Lax; Path=/api/auth/oauth; Max-Age={}",
+        OAUTH_STATE_COOKIE, state, OAUTH_STATE_TTL_MINUTES * 60
+    ))?;
+
+    Ok(response)
+}
+
+async fn oauth_callback(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let url = req.url()?;
+    let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).map(|(_, value)| value.to_string());
+
+    let db = ctx.env.d1("DB")?;
+
+    // The state is consumed before anything else so a callback can only be processed once
+    let state = match (param("state"), state_cookie(&req)) {
+        (Some(state), Some(cookie)) if state == cookie => consume_oauth_state(&db, &state).await,
+        _ => None,
+    };
+    let state = match state {
+        Some(state) if Some(state.provider.as_str()) == ctx.param("provider").map(|p| p.as_str()) => state,
+        _ => return redirect_with_error(&ctx, "/auth/login", OAuthError::InvalidState),
+    };
+
+    let failure_path = if state.mode == MODE_LINK { "/profile" } else { "/auth/login" };
+
+    if param("error").is_some() {
+        return redirect_with_error(&ctx, failure_path, OAuthError::AccessDenied);
+    }
+    let code = match param("code") {
+        Some(code) => code,
+        None => return redirect_with_error(&ctx, failure_path, OAuthError::InvalidState),
+    };
+
+    let provider = match SocialProvider::from_slug(&state.provider) {
+        Some(provider) => provider,
+        None => return redirect_with_error(&ctx, failure_path, OAuthError::InvalidState),
+    };
+    let config = match ProviderConfig::from_env(&ctx.env, provider) {
+        Ok(config) => config,
+        Err(e) => return redirect_with_error(&ctx, failure_path, e),
+    };
+
+    let identity = match fetch_identity(&config, &callback_url(&req, &config)?, &code, &state).await {
+        Ok(identity) => identity,
+        Err(e) => return redirect_with_error(&ctx, failure_path, e),
+    };
+
+    match state.mode.as_str() {
+        MODE_LINK => complete_link(&ctx, &db, &config, &identity, &state).await,
+        _ => complete_login(&req, &ctx, &db, &config, identity, &state).await,
+    }
+}
+
+async fn complete_login(
+    req: &Request,
+    ctx: &RouteContext<RequestContext>,
+    db: &D1Database,
+    config: &ProviderConfig,
+    identity: ProviderIdentity,
+    state: &OAuthState,
+) -> ApiResult<Response> {
+    let provider = config.provider.slug();
+
+    let auth_user = match get_social_account(db, provider, &identity.provider_id).await {
+        Some(account) => {
+            update_social_account(db, &account.id, &identity).await;
+            get_user_by_id(db, &account.user_id).await
+        }
+        None => {
+            // Never attach a provider to an existing account by email alone: the
+            // owner has to sign in and link it explicitly
+            if get_user_by_email(db, &identity.email).await.is_some() {
+                return redirect_with_error(ctx, "/auth/login", "account_exists");
+            }
+            // There is nowhere to present an invite here, and a restricted
+            // domain only counts when the provider has verified the address
+            let policy = SignupPolicy::from_env(&ctx.env);
+            let allowed = match policy.decide(&identity.email, false) {
+                SignupDecision::Allowed => policy.mode == SignupMode::Open || identity.email_verified,
+                _ => false,
+            };
+            if !allowed {
+                return redirect_with_error(ctx, "/auth/signup", "signup_restricted");
+            }
+            create_social_user(ctx, db, provider, &identity).await
+        }
+    };
+
+    let auth_user = match auth_user {
+        Some(user) if user.is_active => user,
+        _ => return redirect_with_error(ctx, "/auth/login", "account_unavailable"),
+    };
+
+    if !auth_user.email_verified && !unverified_login_allowed(ctx, &auth_user.created_at) {
+        return redirect_with_error(ctx, "/auth/login", "email_not_verified");
+    }
+
+    // The provider doesn't stand in for our own second factor
+    if mfa::user_has_mfa(db, &auth_user.id).await {
+        return match mfa::create_mfa_challenge(db, &auth_user.id).await {
+            Some((mfa_token, _)) => {
+                let mut response = redirect_to_app(ctx, "/auth/login", &[("mfa", "required")])?;
+                response.headers_mut().append("Set-Cookie", &mfa::mfa_challenge_cookie(&mfa_token))?;
+                Ok(response)
+            }
+            None => redirect_with_error(ctx, "/auth/login", "mfa_unavailable"),
+        };
+    }
+
+    // This is a top-level navigation, so failures go back to the app rather than as JSON
+    let mut response = match start_session(req, ctx, db, auth_user).await {
+        Ok(response) => response,
+        Err(_) => return redirect_with_error(ctx, "/auth/login", "session_failed"),
+    };
+
+    // Keep the session cookies start_session set, but send the browser back to the app
+    let location = email::app_link(&ctx.env, state.redirect_to.as_deref().unwrap_or("/"));
+    response.headers_mut().set("Location", &location)?;
+    response.headers_mut().append("Set-Cookie", &clear_state_cookie())?;
+    Ok(response.with_status(302))
+}
+
+async fn complete_link(
+    ctx: &RouteContext<RequestContext>,
+    db: &D1Database,
+    config: &ProviderConfig,
+    identity: &ProviderIdentity,
+    state: &OAuthState,
+) -> ApiResult<Response> {
+    let provider = config.provider.slug();
+    let return_path = state.redirect_to.as_deref().unwrap_or("/profile");
+
+    let user_id = match &state.user_id {
+        Some(user_id) => user_id,
+        None => return redirect_with_error(ctx, return_path, OAuthError::InvalidState),
+    };
+
+    if let Some(account) = get_social_account(db, provider, &identity.provider_id).await {
+        if &account.user_id != user_id {
+            return redirect_with_error(ctx, return_path, "already_linked");
+        }
+        update_social_account(db, &account.id, identity).await;
+        return redirect_to_app(ctx, return_path, &[("linked", provider)]);
+    }
+
+    if get_user_social_accounts(db, user_id).await.iter().any(|account| account.provider.slug() == provider) {
+        return redirect_with_error(ctx, return_path, "provider_already_linked");
+    }
+
+    if !insert_social_account(db, user_id, provider, identity).await {
+        return redirect_with_error(ctx, return_path, "link_failed");
+    }
+
+    redirect_to_app(ctx, return_path, &[("linked", provider)])
+}
+
+async fn list_linked_accounts(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let accounts: Vec<LinkedAccountSummary> = get_user_social_accounts(&db, &session.user_id).await
+        .into_iter()
+        .map(|account| LinkedAccountSummary {
+            provider: account.provider.slug().to_string(),
+            email: account.email,
+            name: account.name,
+            avatar: account.avatar,
+            created_at: account.created_at,
+        })
+        .collect();
+
+    let response = ApiResponse::success(accounts);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn unlink_provider(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let provider = match ctx.param("provider").and_then(|slug| SocialProvider::from_slug(slug)) {
+        Some(provider) => provider,
+        None => return Err(ApiError::NotFound("Unknown provider".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    // Don't strand the account without any way to sign in
+    if !has_other_login_method(&db, &session.user_id, provider.slug()).await {
+        return Err(ApiError::Conflict("Set a password or add a passkey before unlinking your last sign-in method".to_string()));
+    }
+
+    let stmt = db.prepare("DELETE FROM social_accounts WHERE user_id = ?1 AND provider = ?2");
+    let result = stmt.bind(&[session.user_id.into(), provider.slug().into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes.unwrap_or(0) > 0) => {}
+        Ok(_) => return Err(ApiError::NotFound("Provider is not linked".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to unlink provider".to_string())),
+    }
+
+    let response = ApiResponse::success("Provider unlinked successfully");
+    Ok(Response::from_json(&response)?)
+}
+
+// Provider calls
+
+async fn fetch_identity(
+    config: &ProviderConfig,
+    redirect_uri: &str,
+    code: &str,
+    state: &OAuthState,
+) -> std::result::Result<ProviderIdentity, OAuthError> {
+    let body = config.token_request_body(code, redirect_uri, &state.code_verifier);
+    let tokens: TokenResponse = fetch_json(
+        &config.token_url,
+        Method::Post,
+        &[("Content-Type", "application/x-www-form-urlencoded")],
+        Some(body),
+    )
+    .await
+    .and_then(|value| serde_json::from_value(value).ok())
+    .ok_or(OAuthError::TokenExchangeFailed)?;
+
+    // OIDC providers are trusted through the signed ID token, not the userinfo endpoint
+    if let Some(jwks_url) = &config.jwks_url {
+        let id_token = tokens.id_token.ok_or(OAuthError::InvalidIdToken)?;
+        let jwks: JwkSet = fetch_json(jwks_url, Method::Get, &[], None)
+            .await
+            .and_then(|value| serde_json::from_value(value).ok())
+            .ok_or(OAuthError::InvalidIdToken)?;
+        return oauth::verify_id_token(config, &jwks, &id_token, &state.nonce);
+    }
+
+    let authorization = format!("Bearer {}", tokens.access_token);
+    let headers = [("Authorization", authorization.as_str())];
+
+    let userinfo_url = config.userinfo_url.as_deref().ok_or(OAuthError::NotConfigured)?;
+    let user = fetch_json(userinfo_url, Method::Get, &headers, None)
+        .await
+        .ok_or(OAuthError::ProfileUnavailable)?;
+
+    match config.provider {
+        SocialProvider::GitHub => {
+            let emails_url = config.emails_url.as_deref().ok_or(OAuthError::NotConfigured)?;
+            let emails = fetch_json(emails_url, Method::Get, &headers, None)
+                .await
+                .ok_or(OAuthError::MissingVerifiedEmail)?;
+            oauth::github_identity(&user, &emails)
+        }
+        _ => oauth::discord_identity(&user),
+    }
+}
+
+async fn fetch_json(url: &str, method: Method, headers: &[(&str, &str)], body: Option<String>) -> Option<serde_json::Value> {
+    let request_headers = Headers::new();
+    request_headers.set("Accept", "application/json").ok()?;
+    // GitHub's API rejects requests without a User-Agent
+    request_headers.set("User-Agent", "Nivaro").ok()?;
+    for (name, value) in headers {
+        request_headers.set(name, value).ok()?;
+    }
+
+    let mut init = RequestInit::new();
+    init.with_method(method)
+        .with_headers(request_headers)
+        .with_body(body.map(JsValue::from));
+
+    let request = Request::new_with_init(url, &init).ok()?;
+    let mut response = Fetch::Request(request).send().await.ok()?;
+    if !(200..300).contains(&response.status_code()) {
+        console_log!("OAuth provider request to {} failed with status {}", url, response.status_code());
+        return None;
+    }
+
+    response.json().await.ok()
+}
+
+// Helper functions for D1 database operations
+
+async fn store_oauth_state(db: &D1Database, oauth_state: &OAuthState, state: &str) -> bool {
+    // Abandoned flows are swept whenever a new one starts
+    let stmt = db.prepare("DELETE FROM oauth_states WHERE expires_at < ?1");
+    if let Ok(stmt) = stmt.bind(&[Utc::now().to_rfc3339().into()]) {
+        let _ = stmt.run().await;
+    }
+
+    let stmt = db.prepare("
+        INSERT INTO oauth_states (id, state_hash, provider, mode, user_id, code_verifier, nonce, redirect_to, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+    ");
+    let stmt = match stmt.bind(&[
+        oauth_state.id.clone().into(),
+        hash_token(state).into(),
+        oauth_state.provider.clone().into(),
+        oauth_state.mode.clone().into(),
+        oauth_state.user_id.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        oauth_state.code_verifier.clone().into(),
+        oauth_state.nonce.clone().into(),
+        oauth_state.redirect_to.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        oauth_state.expires_at.clone().into(),
+        oauth_state.created_at.clone().into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    stmt.run().await.is_ok()
+}
+
+async fn consume_oauth_state(db: &D1Database, state: &str) -> Option<OAuthState> {
+    let oauth_state: OAuthState = D1Store::new(db)
+        .query_first_as("SELECT * FROM oauth_states WHERE state_hash = ?1", &[hash_token(state).into()])
+        .await
+        .ok()??;
+
+    let stmt = db.prepare("DELETE FROM oauth_states WHERE id = ?1");
+    let result = stmt.bind(&[oauth_state.id.clone().into()]).ok()?.run().await.ok()?;
+    if !matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) {
+        return None;
+    }
+
+    let expires_at = chrono::DateTime::parse_from_rfc3339(&oauth_state.expires_at).ok()?;
+    if expires_at < Utc::now() {
+        return None;
+    }
+
+    Some(oauth_state)
+}
+
+async fn get_social_account(db: &D1Database, provider: &str, provider_id: &str) -> Option<SocialAccount> {
+    D1Store::new(db)
+        .query_first_as(
+            "SELECT * FROM social_accounts WHERE provider = ?1 AND provider_id = ?2",
+            &[provider.into(), provider_id.into()],
+        )
+        .await
+        .ok()?
+}
+
+async fn get_user_social_accounts(db: &D1Database, user_id: &str) -> Vec<SocialAccount> {
+    D1Store::new(db)
+        .query_as("SELECT * FROM social_accounts WHERE user_id = ?1 ORDER BY created_at ASC", &[user_id.into()])
+        .await
+        .unwrap_or_default()
+}
+
+async fn insert_social_account(db: &D1Database, user_id: &str, provider: &str, identity: &ProviderIdentity) -> bool {
+    let now = Utc::now().to_rfc3339();
+
+    // Provider tokens are only needed during the callback and are not retained
+    let stmt = db.prepare("
+        INSERT INTO social_accounts (id, user_id, provider, provider_id, email, name, avatar, access_token, created_at, updated_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, '', ?8, ?9)
+    ");
+    let stmt = match stmt.bind(&[
+        Uuid::new_v4().to_string().into(),
+        user_id.into(),
+        provider.into(),
+        identity.provider_id.clone().into(),
+        identity.email.clone().into(),
+        identity.name.clone().into(),
+        identity.avatar.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        now.clone().into(),
+        now.into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    stmt.run().await.is_ok()
+}
+
+async fn update_social_account(db: &D1Database, account_id: &str, identity: &ProviderIdentity) {
+    let stmt = db.prepare("UPDATE social_accounts SET email = ?1, name = ?2, avatar = ?3, updated_at = ?4 WHERE id = ?5");
+    if let Ok(stmt) = stmt.bind(&[
+        identity.email.clone().into(),
+        identity.name.clone().into(),
+        identity.avatar.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        Utc::now().to_rfc3339().into(),
+        account_id.into(),
+    ]) {
+        let _ = stmt.run().await;
+    }
+}
+
+/// Creates a password-less user for a first-time social sign-in and links the
+/// provider account to it.
+async fn create_social_user(
+    ctx: &RouteContext<RequestContext>,
+    db: &D1Database,
+    provider: &str,
+    identity: &ProviderIdentity,
+) -> Option<AuthUser> {
+    let user_id = Uuid::new_v4().to_string();
+    let now = Utc::now().to_rfc3339();
+
+    // An empty password hash never verifies; the user can set one through password reset
+    let stmt = db.prepare("
+        INSERT INTO users (id, email, password_hash, name, avatar, created_at, updated_at, email_verified, is_active, failed_login_attempts)
+        VALUES (?1, ?2, '', ?3, ?4, ?5, ?6, ?7, 1, 0)
+    ");
+    stmt.bind(&[
+        user_id.clone().into(),
+        identity.email.clone().into(),
+        identity.name.clone().into(),
+        identity.avatar.clone().map(JsValue::from).unwrap_or(JsValue::NULL),
+        now.clone().into(),
+        now.into(),
+        (if identity.email_verified { 1 } else { 0 }).into(),
+    ]).ok()?.run().await.ok()?;
+
+    if !insert_social_account(db, &user_id, provider, identity).await {
+        return None;
+    }
+
+    if !identity.email_verified {
+        let token = create_email_verification_token(db, &user_id, &identity.email).await;
+        send_verification_email(&ctx.env, &identity.email, &identity.name, &token).await;
+    }
+
+    get_user_by_id(db, &user_id).await
+}
+
+async fn has_other_login_method(db: &D1Database, user_id: &str, provider: &str) -> bool {
+    let stmt = db.prepare("
+        SELECT
+            (SELECT password_hash FROM users WHERE id = ?1) as password_hash,
+            (SELECT COUNT(*) FROM social_accounts WHERE user_id = ?1 AND provider != ?2) as social_count,
+            (SELECT COUNT(*) FROM passkeys WHERE user_id = ?1) as passkey_count
+    ");
+    let stmt = match stmt.bind(&[user_id.into(), provider.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    match stmt.first::<serde_json::Value>(None).await {
+        Ok(Some(result)) => {
+            !result["password_hash"].as_str().unwrap_or("").is_empty()
+                || result["social_count"].as_u64().unwrap_or(0) > 0
+                || result["passkey_count"].as_u64().unwrap_or(0) > 0
+        }
+        _ => false,
+    }
+}
+
+// Utility functions
+
+fn callback_url(req: &Request, config: &ProviderConfig) -> Result<String> {
+    let origin = req.url()?.origin().ascii_serialization();
+    Ok(format!("{}/api/auth/oauth/{}/callback", origin, config.provider.slug()))
+}
+
+fn state_cookie(req: &Request) -> Option<String> {
+    let cookie_header = req.headers().get("Cookie").ok()??;
+    cookie_header.split(';')
+        .find_map(|cookie| cookie.trim().strip_prefix(&format!("{}=", OAUTH_STATE_COOKIE)).map(|s| s.to_string()))
+        .filter(|state| !state.is_empty())
+}
+
+fn clear_state_cookie() -> String {
+    format!("{}=; HttpOnly; Secure; SameSite=Lax; Path=/api/auth/oauth; Max-Age=0", OAUTH_STATE_COOKIE)
+}
+
+/// Only same-app paths, so the flow can't be used as an open redirect.
+fn is_safe_redirect(path: &str) -> bool {
+    path.starts_with('/') && !path.starts_with("//") && !path.contains('\\')
+}
+
+fn redirect_to_app(ctx: &RouteContext<RequestContext>, path: &str, params: &[(&str, &str)]) -> ApiResult<Response> {
+    let mut location = email::app_link(&ctx.env, path);
+    if !params.is_empty() {
+        location.push(if location.contains('?') { '&' } else { '?' });
+        location.push_str(&oauth::form_encode(params));
+    }
+
+    let mut response = Response::empty()?.with_status(302);
+    response.headers_mut().set("Location", &location)?;
+    response.headers_mut().append("Set-Cookie", &clear_state_cookie())?;
+    Ok(response)
+}
+
+fn redirect_with_error(ctx: &RouteContext<RequestContext>, path: &str, error: impl ToString) -> ApiResult<Response> {
+    redirect_to_app(ctx, path, &[("error", &error.to_string())])
+}
@@ -0,0 +1,258 @@
+use crate::error::{read_json, ApiError, ApiResult};
+use crate::models::*;
+use crate::handlers::auth::{generate_secure_token, hash_token};
+use crate::middleware::{self, RequestContext};
+use crate::store::{D1Store, Database};
+use chrono::Utc;
+use uuid::Uuid;
+use worker::*;
+
+/// Every token starts with this so it can be told apart from a session JWT
+/// (and spotted by secret scanners) without a database lookup.
+pub const ACCESS_TOKEN_PREFIX: &str = "nvr_pat_";
+
+/// Scopes a personal access token can be granted. Each covers one API area;
+/// `:read` allows GET requests and `:write` everything else.
+pub const ACCESS_TOKEN_SCOPES: [&str; 15] = [
+    "profile:read",
+    "clubs:read",
+    "clubs:write",
+    "members:read",
+    "members:write",
+    "events:read",
+    "events:write",
+    "announcements:read",
+    "announcements:write",
+    "projects:read",
+    "projects:write",
+    "meetings:read",
+    "meetings:write",
+    "forum:read",
+    "forum:write",
+];
+
+const DEFAULT_ACCESS_TOKEN_TTL_DAYS: i64 = 30;
+const MAX_ACCESS_TOKEN_TTL_DAYS: i64 = 365;
+const MAX_ACCESS_TOKENS_PER_USER: u64 = 25;
+const MAX_ACCESS_TOKEN_NAME_LENGTH: usize = 64;
+// Matches the session touch interval so scripted traffic doesn't write on every call
+const LAST_USED_TOUCH_INTERVAL_MINUTES: i64 = 5;
+
+pub async fn handle_tokens(req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let method = req.method();
+    let url = req.url()?;
+    let path = url.path();
+
+    match (method, path) {
+        (Method::Get, "/api/auth/tokens") => list_access_tokens(ctx).await,
+        (Method::Post, "/api/auth/tokens") => create_access_token(req, ctx).await,
+        (Method::Delete, path) if path.starts_with("/api/auth/tokens/") => revoke_access_token(ctx).await,
+        _ => Err(ApiError::NotFound("Not found".to_string()))
+    }
+}
+
+// Managing tokens needs a browser session: a token can't mint or revoke tokens
+
+async fn list_access_tokens(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let db = ctx.env.d1("DB")?;
+
+    let tokens: Vec<AccessTokenSummary> = get_user_access_tokens(&db, &session.user_id).await
+        .into_iter()
+        .map(AccessTokenSummary::from)
+        .collect();
+
+    let response = ApiResponse::success(tokens);
+    Ok(Response::from_json(&response)?)
+}
+
+async fn create_access_token(mut req: Request, ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let create_request: CreateAccessTokenRequest = read_json(&mut req).await?;
+
+    let name = create_request.name.trim();
+    if name.is_empty() || name.chars().count() > MAX_ACCESS_TOKEN_NAME_LENGTH {
+        return Err(ApiError::invalid("name", "Token name must be between 1 and 64 characters"));
+    }
+
+    if create_request.scopes.is_empty() {
+        return Err(ApiError::invalid("scopes", "At least one scope is required"));
+    }
+    if let Some(scope) = create_request.scopes.iter().find(|scope| !ACCESS_TOKEN_SCOPES.contains(&scope.as_str())) {
+        return Err(ApiError::invalid("scopes", &format!("Unknown scope: {}", scope)));
+    }
+
+    let ttl_days = create_request.expires_in_days.unwrap_or(DEFAULT_ACCESS_TOKEN_TTL_DAYS);
+    if !(1..=MAX_ACCESS_TOKEN_TTL_DAYS).contains(&ttl_days) {
+        return Err(ApiError::invalid("expires_in_days", "Tokens must expire within 1 to 365 days"));
+    }
+
+    let db = ctx.env.d1("DB")?;
+
+    if count_user_access_tokens(&db, &session.user_id).await >= MAX_ACCESS_TOKENS_PER_USER {
+        return Err(ApiError::Conflict("Too many access tokens; revoke an unused one first".to_string()));
+    }
+
+    let mut scopes = create_request.scopes.clone();
+    scopes.sort();
+    scopes.dedup();
+
+    let token = format!("{}{}", ACCESS_TOKEN_PREFIX, generate_secure_token());
+    let now = Utc::now();
+    let access_token = PersonalAccessToken {
+        id: Uuid::new_v4().to_string(),
+        user_id: session.user_id,
+        name: name.to_string(),
+        token_hash: hash_token(&token),
+        // Enough to recognise the token in a list without making it usable
+        token_prefix: token.chars().take(ACCESS_TOKEN_PREFIX.len() + 4).collect(),
+        scopes,
+        expires_at: (now + chrono::Duration::days(ttl_days)).to_rfc3339(),
+        created_at: now.to_rfc3339(),
+        last_used_at: None,
+    };
+
+    if !store_access_token(&db, &access_token).await {
+        return Err(ApiError::Internal("Failed to create access token".to_string()));
+    }
+
+    // The raw token is only ever returned here
+    let response = ApiResponse::success(CreateAccessTokenResponse {
+        token,
+        access_token: AccessTokenSummary::from(access_token),
+    });
+    Ok(Response::from_json(&response)?.with_status(201))
+}
+
+async fn revoke_access_token(ctx: RouteContext<RequestContext>) -> ApiResult<Response> {
+    let session = ctx.data.session()?;
+
+    let token_id = match ctx.param("id") {
+        Some(id) => id.to_string(),
+        None => return Err(ApiError::BadRequest("Token ID required".to_string())),
+    };
+
+    let db = ctx.env.d1("DB")?;
+
+    let stmt = db.prepare("DELETE FROM personal_access_tokens WHERE id = ?1 AND user_id = ?2");
+    let result = stmt.bind(&[token_id.into(), session.user_id.into()])?.run().await;
+
+    match result {
+        Ok(result) if matches!(result.meta(), Ok(Some(meta)) if meta.changes == Some(1)) => {}
+        Ok(_) => return Err(ApiError::NotFound("Access token not found".to_string())),
+        Err(_) => return Err(ApiError::Internal("Failed to revoke access token".to_string())),
+    }
+
+    let response = ApiResponse::success("Access token revoked successfully");
+    Ok(Response::from_json(&response)?)
+}
+
+/// Returns the personal access token from an `Authorization: Bearer` header,
+/// if that is how the request is authenticated.
+pub fn bearer_access_token(req: &Request) -> Option<String> {
+    let header = req.headers().get("Authorization").ok()??;
+    let token = header.strip_prefix("Bearer ")?;
+    token.starts_with(ACCESS_TOKEN_PREFIX).then(|| token.to_string())
+}
+
+/// Resolves a personal access token, provided it is unexpired, its owner is
+/// active and it was granted the scope this request needs.
+pub async fn authenticate_access_token(req: &Request, env: &Env, token: &str) -> Option<PersonalAccessToken> {
+    let url = req.url().ok()?;
+    let required_scope = middleware::required_scope(&req.method(), url.path())?;
+
+    let db = env.d1("DB").ok()?;
+    let access_token = get_access_token_by_hash(&db, &hash_token(token)).await?;
+
+    let expires_at = chrono::DateTime::parse_from_rfc3339(&access_token.expires_at).ok()?;
+    if expires_at < Utc::now() || !access_token.scopes.contains(&required_scope) {
+        return None;
+    }
+
+    touch_access_token(&db, &access_token.id).await;
+    Some(access_token)
+}
+
+// Helper functions for D1 database operations
+
+async fn store_access_token(db: &D1Database, access_token: &PersonalAccessToken) -> bool {
+    let stmt = db.prepare("
+        INSERT INTO personal_access_tokens (id, user_id, name, token_hash, token_prefix, scopes, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+    ");
+    let stmt = match stmt.bind(&[
+        access_token.id.clone().into(),
+        access_token.user_id.clone().into(),
+        access_token.name.clone().into(),
+        access_token.token_hash.clone().into(),
+        access_token.token_prefix.clone().into(),
+        access_token.scopes.join(" ").into(),
+        access_token.expires_at.clone().into(),
+        access_token.created_at.clone().into(),
+    ]) {
+        Ok(stmt) => stmt,
+        Err(_) => return false,
+    };
+
+    stmt.run().await.is_ok()
+}
+
+async fn get_access_token_by_hash(db: &D1Database, token_hash: &str) -> Option<PersonalAccessToken> {
+    D1Store::new(db)
+        .query_first_as("
+            SELECT t.* FROM personal_access_tokens t
+            INNER JOIN users u ON t.user_id = u.id
+            WHERE t.token_hash = ?1 AND u.is_active = 1
+        ", &[token_hash.into()])
+        .await
+        .ok()?
+}
+
+async fn get_user_access_tokens(db: &D1Database, user_id: &str) -> Vec<PersonalAccessToken> {
+    D1Store::new(db)
+        .query_as("SELECT * FROM personal_access_tokens WHERE user_id = ?1 ORDER BY created_at DESC", &[user_id.into()])
+        .await
+        .unwrap_or_default()
+}
+
+async fn count_user_access_tokens(db: &D1Database, user_id: &str) -> u64 {
+    let stmt = db.prepare("SELECT COUNT(*) as count FROM personal_access_tokens WHERE user_id = ?1");
+    let stmt = match stmt.bind(&[user_id.into()]) {
+        Ok(stmt) => stmt,
+        Err(_) => return u64::MAX,
+    };
+
+    match stmt.first::<serde_json::Value>(None).await {
+        Ok(Some(result)) => result["count"].as_u64().unwrap_or(0),
+        _ => u64::MAX,
+    }
+}
+
+async fn touch_access_token(db: &D1Database, token_id: &str) {
+    let now = Utc::now();
+    let threshold = (now - chrono::Duration::minutes(LAST_USED_TOUCH_INTERVAL_MINUTES)).to_rfc3339();
+
+    let stmt = db.prepare("
+        UPDATE personal_access_tokens SET last_used_at = ?1
+        WHERE id = ?2 AND (last_used_at IS NULL OR last_used_at < ?3)
+    ");
+    if let Ok(stmt) = stmt.bind(&[now.to_rfc3339().into(), token_id.into(), threshold.into()]) {
+        let _ = stmt.run().await;
+    }
+}
+
+impl From<PersonalAccessToken> for AccessTokenSummary {
+    fn from(access_token: PersonalAccessToken) -> Self {
+        AccessTokenSummary {
+            id: access_token.id,
+            name: access_token.name,
+            token_prefix: access_token.token_prefix,
+            scopes: access_token.scopes,
+            expires_at: access_token.expires_at,
+            created_at: access_token.created_at,
+            last_used_at: access_token.last_used_at,
+        }
+    }
+}
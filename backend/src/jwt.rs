@@ -0,0 +1,291 @@
+//! Signing keys for Nivaro's own access tokens.
+//!
+//! Keys come from the `JWT_SIGNING_KEYS` secret, a JSON array of
+//! `{ "kid", "alg", "private_key", "public_key", "retired_at" }` entries with
+//! PKCS#8 / SPKI PEM key material. The first entry that isn't retired signs new
+//! tokens; any other live entry is only published, so a replacement key can be
+//! announced in the JWKS before it starts signing. A retired key keeps
+//! verifying (and stays published) for `JWT_KEY_GRACE_MINUTES` after its
+//! `retired_at`, then is ignored.
+//!
+//! Every token names Nivaro as its issuer (`JWT_ISSUER`) and the API as its
+//! audience (`JWT_AUDIENCE`), so a service verifying through the JWKS can tell
+//! Nivaro's access tokens from anything else signed with the same keys.
+//!
+//! Parsing the keys is too slow to repeat on every request, so `from_env`
+//! keeps the parsed set for as long as the configuration stays the same.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Utc};
+use jsonwebtoken::{decode, decode_header, encode, Algorithm, DecodingKey, EncodingKey, Header, Validation};
+use rsa::traits::PublicKeyParts;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use worker::Env;
+
+/// Default grace period; must outlast the longest-lived token signed by a key.
+pub const DEFAULT_KEY_GRACE_MINUTES: i64 = 60;
+pub const DEFAULT_ISSUER: &str = "nivaro";
+pub const DEFAULT_AUDIENCE: &str = "nivaro-api";
+
+/// The configuration a cached `KeySet` was parsed from.
+#[derive(PartialEq)]
+struct KeySetSource {
+    json: String,
+    grace_minutes: i64,
+    issuer: String,
+    audience: String,
+}
+
+thread_local! {
+    // Workers run one isolate per thread, so this lives as long as the isolate
+    static CACHED_KEYS: RefCell<Option<(KeySetSource, Rc<KeySet>)>> = const { RefCell::new(None) };
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    NotConfigured,
+    InvalidKeySet(String),
+    NoSigningKey,
+    SigningFailed,
+    UnknownKey,
+    InvalidToken,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::NotConfigured => f.write_str("JWT_SIGNING_KEYS is not set"),
+            KeyError::InvalidKeySet(reason) => write!(f, "JWT_SIGNING_KEYS is invalid: {}", reason),
+            KeyError::NoSigningKey => f.write_str("no signing key is active"),
+            KeyError::SigningFailed => f.write_str("failed to sign token"),
+            KeyError::UnknownKey => f.write_str("token was not signed by a current key"),
+            KeyError::InvalidToken => f.write_str("token is invalid or expired"),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct KeyConfig {
+    kid: String,
+    alg: String,
+    private_key: Option<String>,
+    public_key: Option<String>,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+struct SigningKey {
+    kid: String,
+    algorithm: Algorithm,
+    encoding_key: Option<EncodingKey>,
+    decoding_key: DecodingKey,
+    jwk: serde_json::Value,
+    retired_at: Option<DateTime<Utc>>,
+}
+
+impl SigningKey {
+    fn from_config(config: KeyConfig) -> Result<Self, KeyError> {
+        let invalid = |reason: &str| KeyError::InvalidKeySet(format!("key {}: {}", config.kid, reason));
+
+        if config.kid.is_empty() {
+            return Err(KeyError::InvalidKeySet("every key needs a kid".to_string()));
+        }
+
+        let (algorithm, jwk) = match config.alg.as_str() {
+            "EdDSA" => (Algorithm::EdDSA, ed25519_jwk(&config).ok_or_else(|| invalid("unreadable Ed25519 key"))?),
+            "RS256" => (Algorithm::RS256, rsa_jwk(&config).ok_or_else(|| invalid("unreadable RSA key"))?),
+            _ => return Err(invalid("alg must be EdDSA or RS256")),
+        };
+
+        let decoding_key = match algorithm {
+            Algorithm::EdDSA => DecodingKey::from_ed_components(jwk["x"].as_str().unwrap_or_default()),
+            _ => DecodingKey::from_rsa_components(jwk["n"].as_str().unwrap_or_default(), jwk["e"].as_str().unwrap_or_default()),
+        }
+        .map_err(|_| invalid("unreadable public key"))?;
+
+        let encoding_key = match &config.private_key {
+            Some(pem) => Some(match algorithm {
+                Algorithm::EdDSA => EncodingKey::from_ed_pem(pem.as_bytes()),
+                _ => EncodingKey::from_rsa_pem(pem.as_bytes()),
+            }
+            .map_err(|_| invalid("unreadable private key"))?),
+            None => None,
+        };
+
+        let mut jwk = jwk;
+        jwk["kid"] = config.kid.clone().into();
+        jwk["alg"] = config.alg.clone().into();
+        jwk["use"] = "sig".into();
+
+        Ok(SigningKey {
+            kid: config.kid,
+            algorithm,
+            encoding_key,
+            decoding_key,
+            jwk,
+            retired_at: config.retired_at,
+        })
+    }
+}
+
+/// Public half of an Ed25519 key as a JWK, read from whichever PEM is configured.
+fn ed25519_jwk(config: &KeyConfig) -> Option<serde_json::Value> {
+    use ed25519_dalek::pkcs8::{DecodePrivateKey, DecodePublicKey};
+
+    let public_key = match (&config.private_key, &config.public_key) {
+        (Some(pem), _) => ed25519_dalek::SigningKey::from_pkcs8_pem(pem).ok()?.verifying_key(),
+        (None, Some(pem)) => ed25519_dalek::VerifyingKey::from_public_key_pem(pem).ok()?,
+        (None, None) => return None,
+    };
+
+    Some(serde_json::json!({
+        "kty": "OKP",
+        "crv": "Ed25519",
+        "x": URL_SAFE_NO_PAD.encode(public_key.as_bytes()),
+    }))
+}
+
+/// Public half of an RSA key as a JWK, read from whichever PEM is configured.
+fn rsa_jwk(config: &KeyConfig) -> Option<serde_json::Value> {
+    use rsa::pkcs8::{DecodePrivateKey, DecodePublicKey};
+
+    let public_key = match (&config.private_key, &config.public_key) {
+        (Some(pem), _) => rsa::RsaPrivateKey::from_pkcs8_pem(pem).ok()?.to_public_key(),
+        (None, Some(pem)) => rsa::RsaPublicKey::from_public_key_pem(pem).ok()?,
+        (None, None) => return None,
+    };
+
+    Some(serde_json::json!({
+        "kty": "RSA",
+        "n": URL_SAFE_NO_PAD.encode(public_key.n().to_bytes_be()),
+        "e": URL_SAFE_NO_PAD.encode(public_key.e().to_bytes_be()),
+    }))
+}
+
+pub struct KeySet {
+    keys: Vec<SigningKey>,
+    grace: chrono::Duration,
+    issuer: String,
+    audience: String,
+}
+
+impl KeySet {
+    /// The set configured by `JWT_SIGNING_KEYS`, `JWT_KEY_GRACE_MINUTES`,
+    /// `JWT_ISSUER` and `JWT_AUDIENCE`, parsed once and reused until they change.
+    pub fn from_env(env: &Env) -> Result<Rc<Self>, KeyError> {
+        let var = |name: &str| env.var(name).ok().map(|v| v.to_string()).filter(|v| !v.is_empty());
+        let source = KeySetSource {
+            json: env.secret("JWT_SIGNING_KEYS")
+                .map(|v| v.to_string())
+                .map_err(|_| KeyError::NotConfigured)?,
+            grace_minutes: var("JWT_KEY_GRACE_MINUTES")
+                .and_then(|v| v.parse().ok())
+                .unwrap_or(DEFAULT_KEY_GRACE_MINUTES),
+            issuer: var("JWT_ISSUER").unwrap_or_else(|| DEFAULT_ISSUER.to_string()),
+            audience: var("JWT_AUDIENCE").unwrap_or_else(|| DEFAULT_AUDIENCE.to_string()),
+        };
+
+        let cached = CACHED_KEYS.with_borrow(|cached| match cached {
+            Some((cached_source, keys)) if *cached_source == source => Some(keys.clone()),
+            _ => None,
+        });
+        if let Some(keys) = cached {
+            return Ok(keys);
+        }
+
+        let keys = Rc::new(
+            KeySet::from_json(&source.json, chrono::Duration::minutes(source.grace_minutes))?
+                .with_audience(&source.issuer, &source.audience),
+        );
+        CACHED_KEYS.set(Some((source, keys.clone())));
+        Ok(keys)
+    }
+
+    /// Parses a key set that issues tokens as `DEFAULT_ISSUER` for
+    /// `DEFAULT_AUDIENCE`.
+    pub fn from_json(json: &str, grace: chrono::Duration) -> Result<Self, KeyError> {
+        let configs: Vec<KeyConfig> = serde_json::from_str(json)
+            .map_err(|e| KeyError::InvalidKeySet(e.to_string()))?;
+
+        let keys = configs.into_iter()
+            .map(SigningKey::from_config)
+            .collect::<Result<Vec<_>, _>>()?;
+
+        for (i, key) in keys.iter().enumerate() {
+            if keys[..i].iter().any(|other| other.kid == key.kid) {
+                return Err(KeyError::InvalidKeySet(format!("duplicate kid {}", key.kid)));
+            }
+        }
+
+        Ok(KeySet {
+            keys,
+            grace,
+            issuer: DEFAULT_ISSUER.to_string(),
+            audience: DEFAULT_AUDIENCE.to_string(),
+        })
+    }
+
+    /// Sets the `iss` and `aud` that tokens are signed with and must carry.
+    pub fn with_audience(mut self, issuer: &str, audience: &str) -> Self {
+        self.issuer = issuer.to_string();
+        self.audience = audience.to_string();
+        self
+    }
+
+    /// Keys that may still verify tokens at `now`.
+    fn live_keys(&self, now: DateTime<Utc>) -> impl Iterator<Item = &SigningKey> {
+        self.keys.iter().filter(move |key| match key.retired_at {
+            Some(retired_at) => now < retired_at + self.grace,
+            None => true,
+        })
+    }
+
+    /// Signs `claims` with the active key, naming it in the header's `kid` and
+    /// adding the set's `iss` and `aud`.
+    pub fn sign<T: Serialize>(&self, claims: &T, now: DateTime<Utc>) -> Result<String, KeyError> {
+        let key = self.keys.iter()
+            .find(|key| key.retired_at.is_none_or(|retired_at| retired_at > now))
+            .ok_or(KeyError::NoSigningKey)?;
+        let encoding_key = key.encoding_key.as_ref().ok_or(KeyError::NoSigningKey)?;
+
+        let mut header = Header::new(key.algorithm);
+        header.kid = Some(key.kid.clone());
+
+        let mut claims = serde_json::to_value(claims).map_err(|_| KeyError::SigningFailed)?;
+        let Some(fields) = claims.as_object_mut() else {
+            return Err(KeyError::SigningFailed);
+        };
+        fields.insert("iss".to_string(), self.issuer.clone().into());
+        fields.insert("aud".to_string(), self.audience.clone().into());
+
+        encode(&header, &claims, encoding_key).map_err(|_| KeyError::SigningFailed)
+    }
+
+    /// Verifies a token against the key its `kid` names. Only that key's own
+    /// algorithm is accepted, whatever the header claims, and the token must
+    /// carry the set's `iss` and `aud`.
+    pub fn verify<T: DeserializeOwned>(&self, token: &str, now: DateTime<Utc>) -> Result<T, KeyError> {
+        let header = decode_header(token).map_err(|_| KeyError::InvalidToken)?;
+        let kid = header.kid.ok_or(KeyError::UnknownKey)?;
+        let key = self.live_keys(now)
+            .find(|key| key.kid == kid)
+            .ok_or(KeyError::UnknownKey)?;
+
+        let mut validation = Validation::new(key.algorithm);
+        validation.set_issuer(&[&self.issuer]);
+        validation.set_audience(&[&self.audience]);
+        validation.set_required_spec_claims(&["exp", "iss", "aud"]);
+        decode::<T>(token, &key.decoding_key, &validation)
+            .map(|data| data.claims)
+            .map_err(|_| KeyError::InvalidToken)
+    }
+
+    /// The JWKS document for `/.well-known/jwks.json`: every live public key.
+    pub fn jwks(&self, now: DateTime<Utc>) -> serde_json::Value {
+        let keys: Vec<&serde_json::Value> = self.live_keys(now).map(|key| &key.jwk).collect();
+        serde_json::json!({ "keys": keys })
+    }
+}
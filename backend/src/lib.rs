@@ -1,294 +1,268 @@
 use worker::*;
 
+pub mod account;
+pub mod cors;
+pub mod csrf;
+pub mod devices;
+pub mod email;
+pub mod email_verification;
+pub mod error;
 mod forum;
 mod handlers;
+pub mod jwt;
 mod meetings;
+pub mod membership;
+pub mod middleware;
+pub mod migrations;
 pub mod models;
+pub mod oauth;
+pub mod password;
+pub mod password_reset;
+pub mod rate_limit;
+pub mod second_factor;
+pub mod signup;
+pub mod store;
+pub mod token;
+pub mod totp;
+pub mod webauthn;
 
+use chrono::Utc;
+use cors::CorsPolicy;
+use error::{read_json, ApiError, ApiResult};
 use handlers::*;
+use rate_limit::{D1CounterStore, Decision};
 use meetings::*;
+use models::ApiResponse;
+use std::future::Future;
+use std::rc::Rc;
 
-fn get_allowed_origin(origin: Option<&str>) -> String {
-    match origin {
-        Some("http://localhost:3000") => "http://localhost:3000".to_string(),
-        Some("http://localhost:3001") => "http://localhost:3001".to_string(),
-        Some("http://192.168.1.245:3000") => "http://192.168.1.245:3000".to_string(),
-        Some("http://192.168.1.245:3001") => "http://192.168.1.245:3001".to_string(),
-        Some(other) if other.starts_with("http://localhost:") || other.starts_with("http://192.168.") => {
-            other.to_string() // Allow any localhost or local network origin
-        },
-        _ => "http://localhost:3000".to_string(), // Default fallback
-    }
+// Handlers report failures as `ApiError`s, which become JSON error responses here
+async fn respond(handler: impl Future<Output = ApiResult<Response>>) -> Result<Response> {
+    handler.await.or_else(ApiError::into_response)
+}
+
+fn meeting_not_found() -> ApiError {
+    ApiError::NotFound("Meeting not found".to_string())
 }
 
-fn handle_cors_preflight_with_origin(origin: Option<&str>) -> Result<Response> {
-    let mut headers = worker::Headers::new();
-    let allowed_origin = get_allowed_origin(origin);
-    headers.set("Access-Control-Allow-Origin", &allowed_origin)?;
-    headers.set("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS")?;
-    headers.set("Access-Control-Allow-Headers", "Content-Type, Authorization, X-CSRF-Token")?;
-    headers.set("Access-Control-Allow-Credentials", "true")?;
-    headers.set("Access-Control-Max-Age", "86400")?;
-    
+fn handle_cors_preflight(cors: &CorsPolicy, origin: Option<&str>) -> Result<Response> {
+    // Without CORS headers the browser never sends the real request
+    let Some(cors_headers) = cors.preflight_headers(origin) else {
+        return ApiError::Forbidden("Origin not allowed".to_string()).into_response();
+    };
+
+    let headers = worker::Headers::new();
+    for (name, value) in cors_headers {
+        headers.set(name, &value)?;
+    }
+    headers.set("Vary", "Origin")?;
+
     Ok(Response::empty()?
         .with_status(200)
         .with_headers(headers))
 }
 
-fn add_cors_headers_with_origin(mut response: Response, origin: Option<&str>) -> Result<Response> {
+fn add_cors_headers(mut response: Response, cors: &CorsPolicy, origin: Option<&str>) -> Result<Response> {
     let headers = response.headers_mut();
-    let allowed_origin = get_allowed_origin(origin);
-    headers.set("Access-Control-Allow-Origin", &allowed_origin)?;
-    headers.set("Access-Control-Allow-Credentials", "true")?;
-    headers.set("Access-Control-Allow-Methods", "GET, POST, PUT, DELETE, OPTIONS")?;
-    headers.set("Access-Control-Allow-Headers", "Content-Type, Authorization, X-CSRF-Token")?;
+    // Responses differ by origin, so caches must not share them across origins
+    headers.append("Vary", "Origin")?;
+    if let Some(cors_headers) = cors.response_headers(origin) {
+        for (name, value) in cors_headers {
+            headers.set(name, &value)?;
+        }
+    }
     Ok(response)
 }
 
 #[event(fetch)]
-async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, ctx: Context) -> Result<Response> {
     console_error_panic_hook::set_once();
 
     // Extract origin header before router consumes the request
     let origin = req.headers().get("Origin").ok().flatten();
-    
+    let cors = CorsPolicy::from_env(&env);
+
     // Handle preflight CORS requests
     if req.method() == Method::Options {
-        return handle_cors_preflight_with_origin(origin.as_deref());
+        return handle_cors_preflight(&cors, origin.as_deref());
+    }
+
+    // Client-keyed rate limits run before routing so no POST route can miss them
+    let client_ip = req.headers().get("CF-Connecting-IP").ok().flatten().unwrap_or_else(|| "unknown".to_string());
+    let limits = rate_limit::request_limits(&req.method(), &req.path(), &client_ip);
+    if !limits.is_empty() {
+        let db = env.d1("DB")?;
+        if let Decision::Limited { retry_after } = rate_limit::check_all(&D1CounterStore::new(&db), &limits, Utc::now().timestamp()).await {
+            return rate_limit::too_many_requests(retry_after)
+                .into_response()
+                .and_then(|response| add_cors_headers(response, &cors, origin.as_deref()));
+        }
     }
 
-    let router = Router::new();
+    // Authentication, CSRF and club membership, for every route that isn't public
+    let mut context = match middleware::authorize(&req, &env).await {
+        Ok(context) => context,
+        Err(error) => {
+            return error
+                .into_response()
+                .and_then(|response| add_cors_headers(response, &cors, origin.as_deref()));
+        }
+    };
+
+    context.worker = Some(Rc::new(ctx));
+    let router = Router::with_data(context);
 
     router
         .get("/", |_, _| {
             Response::ok("Nivaro API - Club Management Platform")
         })
+        // Public keys for verifying access tokens
+        .get_async("/.well-known/jwks.json", |req, ctx| respond(handle_jwks(req, ctx)))
         // CSRF token endpoint
-        .get_async("/api/csrf-token", |req, ctx| async move {
-            get_csrf_token(req, ctx).await
-        })
+        .get_async("/api/csrf-token", |req, ctx| respond(get_csrf_token(req, ctx)))
         // Auth endpoints
-        .post_async("/api/auth/signup", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/login", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/logout", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/forgot-password", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/reset-password", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/change-password", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/verify-email", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .get_async("/api/auth/me", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .put_async("/api/auth/profile", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .delete_async("/api/auth/account", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .post_async("/api/auth/social", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .get_async("/api/auth/sessions", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
-        .delete_async("/api/auth/sessions", |req, ctx| async move {
-            handle_auth(req, ctx).await
-        })
+        .post_async("/api/auth/signup", |req, ctx| respond(handle_auth(req, ctx)))
+        .get_async("/api/auth/signup-policy", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/login", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/logout", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/refresh", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/forgot-password", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/reset-password", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/change-password", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/verify-email", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/resend-verification", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/magic-link", |req, ctx| respond(handle_auth(req, ctx)))
+        .post_async("/api/auth/magic-link/verify", |req, ctx| respond(handle_auth(req, ctx)))
+        .get_async("/api/auth/me", |req, ctx| respond(handle_auth(req, ctx)))
+        .put_async("/api/auth/profile", |req, ctx| respond(handle_auth(req, ctx)))
+        .delete_async("/api/auth/account", |req, ctx| respond(handle_account(req, ctx)))
+        .post_async("/api/auth/account/restore", |req, ctx| respond(handle_account(req, ctx)))
+        .get_async("/api/auth/export", |req, ctx| respond(handle_account(req, ctx)))
+        .get_async("/api/auth/events", |req, ctx| respond(handle_audit(req, ctx)))
+        .get_async("/api/auth/sessions", |req, ctx| respond(handle_auth(req, ctx)))
+        .delete_async("/api/auth/sessions", |req, ctx| respond(handle_auth(req, ctx)))
+        .delete_async("/api/auth/sessions/:id", |req, ctx| respond(handle_auth(req, ctx)))
+        // Two-factor endpoints
+        .post_async("/api/auth/mfa/setup", |req, ctx| respond(handle_mfa(req, ctx)))
+        .post_async("/api/auth/mfa/confirm", |req, ctx| respond(handle_mfa(req, ctx)))
+        .post_async("/api/auth/mfa/verify", |req, ctx| respond(handle_mfa(req, ctx)))
+        .post_async("/api/auth/mfa/recovery-codes", |req, ctx| respond(handle_mfa(req, ctx)))
+        .delete_async("/api/auth/mfa", |req, ctx| respond(handle_mfa(req, ctx)))
+        // Personal access token endpoints
+        .get_async("/api/auth/tokens", |req, ctx| respond(handle_tokens(req, ctx)))
+        .post_async("/api/auth/tokens", |req, ctx| respond(handle_tokens(req, ctx)))
+        .delete_async("/api/auth/tokens/:id", |req, ctx| respond(handle_tokens(req, ctx)))
+        // Platform invite endpoints (platform admins only)
+        .get_async("/api/admin/invites", |req, ctx| respond(handle_invites(req, ctx)))
+        .post_async("/api/admin/invites", |req, ctx| respond(handle_invites(req, ctx)))
+        .delete_async("/api/admin/invites/:id", |req, ctx| respond(handle_invites(req, ctx)))
+        // Passkey endpoints
+        .post_async("/api/auth/passkeys/register/options", |req, ctx| respond(handle_passkeys(req, ctx)))
+        .post_async("/api/auth/passkeys/register", |req, ctx| respond(handle_passkeys(req, ctx)))
+        .post_async("/api/auth/passkeys/login/options", |req, ctx| respond(handle_passkeys(req, ctx)))
+        .post_async("/api/auth/passkeys/login", |req, ctx| respond(handle_passkeys(req, ctx)))
+        .get_async("/api/auth/passkeys", |req, ctx| respond(handle_passkeys(req, ctx)))
+        .delete_async("/api/auth/passkeys/:id", |req, ctx| respond(handle_passkeys(req, ctx)))
+        // Social sign-in endpoints
+        .get_async("/api/auth/oauth/accounts", |req, ctx| respond(handle_social(req, ctx)))
+        .post_async("/api/auth/oauth/:provider/authorize", |req, ctx| respond(handle_social(req, ctx)))
+        .post_async("/api/auth/oauth/:provider/link", |req, ctx| respond(handle_social(req, ctx)))
+        .get_async("/api/auth/oauth/:provider/callback", |req, ctx| respond(handle_social(req, ctx)))
+        .delete_async("/api/auth/oauth/:provider", |req, ctx| respond(handle_social(req, ctx)))
         // Club endpoints
-        .get_async("/api/clubs", |req, ctx| async move {
-            handle_clubs(req, ctx).await
-        })
-        .get_async("/api/clubs/:id", |req, ctx| async move {
-            handle_clubs(req, ctx).await
-        })
-        .post_async("/api/clubs", |req, ctx| async move {
-            handle_clubs(req, ctx).await
-        })
-        .get_async("/api/clubs/:club_id/members", |req, ctx| async move {
-            handle_members(req, ctx).await
-        })
-        .post_async("/api/members/join", |req, ctx| async move {
-            handle_members(req, ctx).await
-        })
+        .get_async("/api/clubs", |req, ctx| respond(handle_clubs(req, ctx)))
+        .get_async("/api/clubs/:id", |req, ctx| respond(handle_clubs(req, ctx)))
+        .post_async("/api/clubs", |req, ctx| respond(handle_clubs(req, ctx)))
+        .put_async("/api/clubs/:club_id/security", |req, ctx| respond(handle_clubs(req, ctx)))
+        .post_async("/api/clubs/:club_id/transfer", |req, ctx| respond(handle_clubs(req, ctx)))
+        .get_async("/api/clubs/:club_id/members", |req, ctx| respond(handle_members(req, ctx)))
+        .post_async("/api/members/join", |req, ctx| respond(handle_members(req, ctx)))
         // Event endpoints
-        .get_async("/api/clubs/:club_id/events", |req, ctx| async move {
-            handle_events(req, ctx).await
-        })
-        .post_async("/api/events", |req, ctx| async move {
-            handle_events(req, ctx).await
-        })
+        .get_async("/api/clubs/:club_id/events", |req, ctx| respond(handle_events(req, ctx)))
+        .post_async("/api/events", |req, ctx| respond(handle_events(req, ctx)))
         // Announcement endpoints
-        .get_async("/api/clubs/:club_id/announcements", |req, ctx| async move {
-            handle_announcements(req, ctx).await
-        })
-        .post_async("/api/announcements", |req, ctx| async move {
-            handle_announcements(req, ctx).await
-        })
+        .get_async("/api/clubs/:club_id/announcements", |req, ctx| respond(handle_announcements(req, ctx)))
+        .post_async("/api/announcements", |req, ctx| respond(handle_announcements(req, ctx)))
         // Project endpoints
-        .get_async("/api/clubs/:club_id/projects", |req, ctx| async move {
-            handle_projects(req, ctx).await
-        })
-        .post_async("/api/projects", |req, ctx| async move {
-            handle_projects(req, ctx).await
-        })
+        .get_async("/api/clubs/:club_id/projects", |req, ctx| respond(handle_projects(req, ctx)))
+        .post_async("/api/projects", |req, ctx| respond(handle_projects(req, ctx)))
         // Meeting endpoints
-        .get_async("/api/meetings", |req, ctx| async move {
-            // Require authentication for viewing meetings
-            if crate::handlers::auth::get_user_id_from_token(&req, &ctx).is_none() {
-                return Response::error("Unauthorized", 401);
-            }
-            
+        .get_async("/api/meetings", |_, _| respond(async move {
             let meetings = get_meetings().await;
-            Response::from_json(&meetings)
-        })
-        .get_async("/api/meetings/:id", |req, ctx| async move {
-            // Require authentication for viewing meeting details
-            if crate::handlers::auth::get_user_id_from_token(&req, &ctx).is_none() {
-                return Response::error("Unauthorized", 401);
-            }
-            
-            if let Some(id) = ctx.param("id") {
-                if let Some(meeting) = get_meeting(id).await {
-                    return Response::from_json(&meeting);
-                }
-            }
-            Response::error("Meeting not found", 404)
-        })
-        .post_async("/api/meetings", |mut req, ctx| async move {
-            // CSRF protection for meeting creation
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            match req.json::<CreateMeetingRequest>().await {
-                Ok(meeting_data) => {
-                    let meeting = create_meeting(meeting_data).await;
-                    Response::from_json(&meeting)
-                }
-                Err(_) => Response::error("Invalid request body", 400),
-            }
-        })
-        .put_async("/api/meetings/:id", |mut req, ctx| async move {
-            // CSRF protection for meeting updates
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            if let Some(id) = ctx.param("id") {
-                match req.json::<UpdateMeetingRequest>().await {
-                    Ok(updates) => {
-                        if let Some(meeting) = update_meeting(id, updates).await {
-                            return Response::from_json(&meeting);
-                        }
-                    }
-                    Err(_) => return Response::error("Invalid request body", 400),
-                }
-            }
-            Response::error("Meeting not found", 404)
-        })
-        .delete_async("/api/meetings/:id", |req, ctx| async move {
-            // CSRF protection for meeting deletion
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
+            Ok(Response::from_json(&meetings)?)
+        }))
+        .get_async("/api/meetings/:id", |_, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(meeting_not_found)?;
+            let meeting = get_meeting(id).await.ok_or_else(meeting_not_found)?;
+            Ok(Response::from_json(&meeting)?)
+        }))
+        .post_async("/api/meetings", |mut req, _| respond(async move {
+            let meeting_data: CreateMeetingRequest = read_json(&mut req).await?;
+            let meeting = create_meeting(meeting_data).await;
+            Ok(Response::from_json(&meeting)?)
+        }))
+        .put_async("/api/meetings/:id", |mut req, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(meeting_not_found)?;
+            let updates: UpdateMeetingRequest = read_json(&mut req).await?;
+            let meeting = update_meeting(id, updates).await.ok_or_else(meeting_not_found)?;
+            Ok(Response::from_json(&meeting)?)
+        }))
+        .delete_async("/api/meetings/:id", |_, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(meeting_not_found)?;
+            if !delete_meeting(id).await {
+                return Err(meeting_not_found());
             }
-            
-            if let Some(id) = ctx.param("id") {
-                if delete_meeting(id).await {
-                    return Response::ok("Meeting deleted");
-                }
-            }
-            Response::error("Meeting not found", 404)
-        })
+            Ok(Response::from_json(&ApiResponse::success("Meeting deleted"))?)
+        }))
         // RSVP endpoints
-        .get_async("/api/meetings/:id/rsvps", |_, ctx| async move {
-            if let Some(id) = ctx.param("id") {
-                let rsvps = get_rsvps(id).await;
-                return Response::from_json(&rsvps);
-            }
-            Response::error("Meeting not found", 404)
-        })
-        .post_async("/api/meetings/:id/rsvps", |mut req, ctx| async move {
-            // CSRF protection for RSVP creation
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            if let Some(id) = ctx.param("id") {
-                match req.json::<CreateRSVPRequest>().await {
-                    Ok(rsvp_data) => {
-                        let rsvp = create_rsvp(id, rsvp_data).await;
-                        return Response::from_json(&rsvp);
-                    }
-                    Err(_) => return Response::error("Invalid request body", 400),
-                }
-            }
-            Response::error("Meeting not found", 404)
-        })
+        .get_async("/api/meetings/:id/rsvps", |_, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(meeting_not_found)?;
+            let rsvps = get_rsvps(id).await;
+            Ok(Response::from_json(&rsvps)?)
+        }))
+        .post_async("/api/meetings/:id/rsvps", |mut req, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(meeting_not_found)?;
+            let rsvp_data: CreateRSVPRequest = read_json(&mut req).await?;
+            let rsvp = create_rsvp(id, rsvp_data).await;
+            Ok(Response::from_json(&rsvp)?)
+        }))
         // Forum endpoints
-        .get_async("/api/forum/questions", |req, ctx| async move {
-            // Require authentication for viewing forum questions
-            if crate::handlers::auth::get_user_id_from_token(&req, &ctx).is_none() {
-                return Response::error("Unauthorized", 401);
-            }
-            
-            forum::get_questions().await
-        })
-        .post_async("/api/forum/questions", |req, ctx| async move {
-            // CSRF protection for question creation
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            forum::create_question(req).await
-        })
-        .put_async("/api/forum/questions/:id/claim", |req, ctx| async move {
-            // CSRF protection for question claiming
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            if let Some(id) = ctx.param("id") {
-                forum::claim_question(id, req).await
-            } else {
-                Response::error("Invalid question ID", 400)
-            }
-        })
-        .put_async("/api/forum/questions/:id/resolve", |req, ctx| async move {
-            // CSRF protection for question resolution
-            if !crate::handlers::auth::verify_csrf_token(&req, &ctx).await.unwrap_or(false) {
-                return Response::error("CSRF token validation failed", 403);
-            }
-            
-            if let Some(id) = ctx.param("id") {
-                forum::resolve_question(id).await
-            } else {
-                Response::error("Invalid question ID", 400)
-            }
-        })
-        .get_async("/api/forum/tags", |req, ctx| async move {
-            // Require authentication for viewing forum tags
-            if crate::handlers::auth::get_user_id_from_token(&req, &ctx).is_none() {
-                return Response::error("Unauthorized", 401);
-            }
-            
-            forum::get_tags().await
-        })
+        .get_async("/api/forum/questions", |_, _| respond(forum::get_questions()))
+        .post_async("/api/forum/questions", |req, _| respond(forum::create_question(req)))
+        .put_async("/api/forum/questions/:id/claim", |req, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(|| ApiError::BadRequest("Invalid question ID".to_string()))?;
+            forum::claim_question(id, req).await
+        }))
+        .put_async("/api/forum/questions/:id/resolve", |_, ctx| respond(async move {
+            let id = ctx.param("id").ok_or_else(|| ApiError::BadRequest("Invalid question ID".to_string()))?;
+            forum::resolve_question(id).await
+        }))
+        .get_async("/api/forum/tags", |_, _| respond(forum::get_tags()))
         .run(req, env)
         .await
         .and_then(|response| {
-            add_cors_headers_with_origin(response, origin.as_deref())
+            add_cors_headers(response, &cors, origin.as_deref())
         })
 }
+
+// Must match the crons in wrangler.toml: hourly upkeep, and every minute
+// working through the email queue
+const MAINTENANCE_CRON: &str = "0 * * * *";
+
+#[event(scheduled)]
+async fn scheduled(event: ScheduledEvent, env: Env, _ctx: ScheduleContext) {
+    console_error_panic_hook::set_once();
+
+    if event.cron() != MAINTENANCE_CRON {
+        email::send_queued(&env).await;
+        return;
+    }
+
+    match env.d1("DB") {
+        Ok(db) => {
+            process_due_account_deletions(&db).await;
+            prune_auth_events(&db).await;
+        }
+        Err(e) => console_log!("Scheduled run could not open the database: {}", e),
+    }
+}
@@ -28,7 +28,7 @@ pub struct Meeting {
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-pub struct RSVP {
+pub struct Rsvp {
     pub id: String,
     #[serde(rename = "meetingId")]
     pub meeting_id: String,
@@ -139,9 +139,9 @@ fn get_mock_meetings() -> Vec<Meeting> {
     ]
 }
 
-fn get_mock_rsvps() -> Vec<RSVP> {
+fn get_mock_rsvps() -> Vec<Rsvp> {
     vec![
-        RSVP {
+        Rsvp {
             id: "1".to_string(),
             meeting_id: "1".to_string(),
             user_id: "user1".to_string(),
@@ -150,7 +150,7 @@ fn get_mock_rsvps() -> Vec<RSVP> {
             status: "attending".to_string(),
             rsvp_date: "2024-01-12T10:00:00Z".to_string(),
         },
-        RSVP {
+        Rsvp {
             id: "2".to_string(),
             meeting_id: "1".to_string(),
             user_id: "user2".to_string(),
@@ -239,18 +239,18 @@ pub async fn delete_meeting(id: &str) -> bool {
     get_meeting(id).await.is_some()
 }
 
-pub async fn get_rsvps(meeting_id: &str) -> Vec<RSVP> {
+pub async fn get_rsvps(meeting_id: &str) -> Vec<Rsvp> {
     get_mock_rsvps()
         .into_iter()
         .filter(|r| r.meeting_id == meeting_id)
         .collect()
 }
 
-pub async fn create_rsvp(meeting_id: &str, req: CreateRSVPRequest) -> RSVP {
+pub async fn create_rsvp(meeting_id: &str, req: CreateRSVPRequest) -> Rsvp {
     let now = chrono::Utc::now().to_rfc3339();
     let id = Uuid::new_v4().to_string();
 
-    RSVP {
+    Rsvp {
         id,
         meeting_id: meeting_id.to_string(),
         user_id: "current-user".to_string(), // In a real app, this would come from auth
@@ -0,0 +1,153 @@
+//! Who may do what in a club.
+//!
+//! Every member can see a club and add events and projects to it. Admins can
+//! also post announcements, but only with two-factor enabled when the club
+//! requires it of its admins. Only the owner can change that requirement or
+//! hand the club to another member. Joining takes a single-use invite code.
+//!
+//! These checks run against any `Store`, so they are exercised natively
+//! against SQLite as well as on D1.
+
+use crate::models::*;
+use crate::store::{Store, StoreError};
+use chrono::{DateTime, Utc};
+use uuid::Uuid;
+
+#[derive(Debug)]
+pub enum MembershipError {
+    ClubNotFound,
+    NotMember,
+    NotAdmin,
+    NotOwner,
+    /// The club requires two-factor for its admins and the user hasn't
+    /// enabled it.
+    MfaRequired,
+    InvalidInviteCode,
+    InviteCodeUsed,
+    InviteCodeExpired,
+    AlreadyMember,
+    AlreadyOwner,
+    Store(StoreError),
+}
+
+impl From<StoreError> for MembershipError {
+    fn from(error: StoreError) -> Self {
+        MembershipError::Store(error)
+    }
+}
+
+/// The user's membership of the club.
+pub async fn require_member<S: Store>(store: &S, club_id: &str, user_id: &str) -> Result<Member, MembershipError> {
+    store.member(club_id, user_id).await?.ok_or(MembershipError::NotMember)
+}
+
+/// The user's membership, if they are an admin who meets the club's
+/// two-factor policy.
+pub async fn require_admin<S: Store>(store: &S, club_id: &str, user_id: &str) -> Result<Member, MembershipError> {
+    let member = require_member(store, club_id, user_id).await?;
+    if !matches!(member.role, MemberRole::Admin) {
+        return Err(MembershipError::NotAdmin);
+    }
+
+    let club = store.club(club_id).await?.ok_or(MembershipError::ClubNotFound)?;
+    if club.require_admin_mfa && !store.user_has_mfa(user_id).await? {
+        return Err(MembershipError::MfaRequired);
+    }
+
+    Ok(member)
+}
+
+/// The club, if the user owns it.
+pub async fn require_owner<S: Store>(store: &S, club_id: &str, user_id: &str) -> Result<Club, MembershipError> {
+    let club = store.club(club_id).await?.ok_or(MembershipError::ClubNotFound)?;
+    if club.owner_id != user_id {
+        return Err(MembershipError::NotOwner);
+    }
+    Ok(club)
+}
+
+/// Redeems `invite_code` and adds the user to its club as a plain member.
+pub async fn join_club<S: Store>(store: &S, user_id: &str, invite_code: &str, now: DateTime<Utc>) -> Result<Member, MembershipError> {
+    let code = invite_code.trim().to_uppercase();
+    let invite = store.invite_code(&code).await?.ok_or(MembershipError::InvalidInviteCode)?;
+
+    if invite.used_by.is_some() {
+        return Err(MembershipError::InviteCodeUsed);
+    }
+    let now = now.to_rfc3339();
+    if invite.expires_at < now {
+        return Err(MembershipError::InviteCodeExpired);
+    }
+    if store.member(&invite.club_id, user_id).await?.is_some() {
+        return Err(MembershipError::AlreadyMember);
+    }
+
+    let user = store.user_by_id(user_id).await?
+        .ok_or_else(|| StoreError(format!("user {} not found", user_id)))?;
+
+    // Redeemed before the insert so two joins can't share one code
+    if !store.redeem_invite_code(&code, user_id, &now).await? {
+        return Err(MembershipError::InviteCodeUsed);
+    }
+
+    let member = Member {
+        id: Uuid::new_v4().to_string(),
+        user_id: user_id.to_string(),
+        club_id: invite.club_id,
+        role: MemberRole::Member,
+        joined_at: now,
+        user: User {
+            id: user.id,
+            email: user.email,
+            name: user.name,
+            avatar: user.avatar,
+            created_at: user.created_at,
+            updated_at: user.updated_at,
+            email_verified: user.email_verified,
+            is_active: user.is_active,
+        },
+    };
+
+    if let Err(error) = store.add_member(&member).await {
+        let _ = store.release_invite_code(&code, user_id).await;
+        return Err(error.into());
+    }
+
+    Ok(member)
+}
+
+/// Turns the club's admin two-factor requirement on or off. Owners can't turn
+/// it on without two-factor themselves, or they'd lock themselves out.
+pub async fn set_admin_mfa_requirement<S: Store>(store: &S, club_id: &str, user_id: &str, required: bool, now: DateTime<Utc>) -> Result<Club, MembershipError> {
+    require_owner(store, club_id, user_id).await?;
+
+    if required && !store.user_has_mfa(user_id).await? {
+        return Err(MembershipError::MfaRequired);
+    }
+
+    store.set_require_admin_mfa(club_id, required, &now.to_rfc3339()).await?;
+    store.club(club_id).await?.ok_or(MembershipError::ClubNotFound)
+}
+
+/// Hands the club from its owner to another active member, who becomes an
+/// admin. Owners are admins, so the club's two-factor policy applies to the
+/// new owner too.
+pub async fn transfer_ownership<S: Store>(store: &S, club_id: &str, user_id: &str, new_owner_id: &str, now: DateTime<Utc>) -> Result<Club, MembershipError> {
+    let club = require_owner(store, club_id, user_id).await?;
+
+    if new_owner_id == user_id {
+        return Err(MembershipError::AlreadyOwner);
+    }
+
+    match store.member(club_id, new_owner_id).await? {
+        Some(member) if member.user.is_active => {}
+        _ => return Err(MembershipError::NotMember),
+    }
+
+    if club.require_admin_mfa && !store.user_has_mfa(new_owner_id).await? {
+        return Err(MembershipError::MfaRequired);
+    }
+
+    store.transfer_club(club_id, user_id, new_owner_id, &now.to_rfc3339()).await?;
+    store.club(club_id).await?.ok_or(MembershipError::ClubNotFound)
+}
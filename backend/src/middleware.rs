@@ -0,0 +1,270 @@
+//! Who is calling, checked once per request before the router runs.
+//!
+//! Every route needs a signed-in caller unless it is listed in
+//! `PUBLIC_ROUTES`, so a new route is private until someone decides
+//! otherwise. For private routes the middleware resolves the caller into an
+//! `AuthContext`, checks the CSRF token on unsafe methods and, for routes
+//! under `/api/clubs/:club_id/`, loads the caller's role in that club.
+//! Handlers read the result from `ctx.data`.
+
+use crate::error::{ApiError, ApiResult};
+use crate::handlers::auth::{authenticate_request, verify_csrf_token, AuthenticatedSession};
+use crate::handlers::tokens;
+use crate::membership;
+use crate::models::MemberRole;
+use crate::store::{D1Store, Store};
+use std::future::Future;
+use std::rc::Rc;
+use worker::{wasm_bindgen_futures, Context, Env, Method, Request};
+
+/// Routes anyone may call: the ways of signing in and the steps that lead
+/// up to it, plus the public signing keys.
+const PUBLIC_ROUTES: &[(Method, &str)] = &[
+    (Method::Get, "/"),
+    (Method::Get, "/.well-known/jwks.json"),
+    (Method::Post, "/api/auth/signup"),
+    (Method::Get, "/api/auth/signup-policy"),
+    (Method::Post, "/api/auth/login"),
+    (Method::Post, "/api/auth/logout"),
+    (Method::Post, "/api/auth/refresh"),
+    (Method::Post, "/api/auth/forgot-password"),
+    (Method::Post, "/api/auth/reset-password"),
+    (Method::Post, "/api/auth/verify-email"),
+    (Method::Post, "/api/auth/resend-verification"),
+    (Method::Post, "/api/auth/magic-link"),
+    (Method::Post, "/api/auth/magic-link/verify"),
+    (Method::Post, "/api/auth/mfa/verify"),
+    (Method::Post, "/api/auth/passkeys/login/options"),
+    (Method::Post, "/api/auth/passkeys/login"),
+    (Method::Post, "/api/auth/oauth/:provider/authorize"),
+    (Method::Get, "/api/auth/oauth/:provider/callback"),
+];
+
+/// The caller behind a request.
+#[derive(Clone, Debug)]
+pub struct AuthContext {
+    pub user_id: String,
+    /// The sign-in session, unless the caller used a personal access token.
+    pub session_id: Option<String>,
+    /// What a personal access token was granted. `None` for sessions, which
+    /// aren't limited.
+    pub scopes: Option<Vec<String>>,
+}
+
+impl AuthContext {
+    pub fn has_scope(&self, scope: &str) -> bool {
+        match &self.scopes {
+            Some(scopes) => scopes.iter().any(|granted| granted == scope),
+            None => true,
+        }
+    }
+}
+
+/// The caller's membership of the club a route is about.
+#[derive(Clone)]
+pub struct ClubMember {
+    pub club_id: String,
+    pub role: MemberRole,
+}
+
+impl ClubMember {
+    pub fn is_admin(&self) -> bool {
+        matches!(self.role, MemberRole::Admin)
+    }
+}
+
+/// What the middleware established about a request, handed to the router
+/// as its data. Public routes get neither `auth` nor `club` filled in.
+#[derive(Default)]
+pub struct RequestContext {
+    pub auth: Option<AuthContext>,
+    pub club: Option<ClubMember>,
+    /// The invocation's context, set by `fetch` once the checks have passed.
+    pub worker: Option<Rc<Context>>,
+}
+
+impl RequestContext {
+    /// Runs `task` after the response has gone out, for work such as email
+    /// that the caller shouldn't wait for.
+    pub fn wait_until<F: Future<Output = ()> + 'static>(&self, task: F) {
+        match &self.worker {
+            Some(worker) => worker.wait_until(task),
+            // Only without an invocation, where nothing would keep it alive anyway
+            None => wasm_bindgen_futures::spawn_local(task),
+        }
+    }
+
+    pub fn auth(&self) -> ApiResult<&AuthContext> {
+        self.auth.as_ref().ok_or_else(|| ApiError::Unauthorized("Unauthorized".to_string()))
+    }
+
+    /// The caller's session. Personal access tokens don't have one, so
+    /// handlers that manage the account itself refuse them through this.
+    pub fn session(&self) -> ApiResult<AuthenticatedSession> {
+        let auth = self.auth()?;
+        match &auth.session_id {
+            Some(session_id) => Ok(AuthenticatedSession {
+                user_id: auth.user_id.clone(),
+                session_id: session_id.clone(),
+            }),
+            None => Err(ApiError::Unauthorized("Unauthorized".to_string())),
+        }
+    }
+
+    /// The caller's membership of the route's `:club_id`.
+    pub fn club(&self) -> ApiResult<&ClubMember> {
+        // Only reachable if a handler asks for a club on a route without one
+        self.club.as_ref().ok_or_else(|| ApiError::Internal("route has no :club_id".to_string()))
+    }
+}
+
+/// How a route is protected.
+#[derive(Debug, PartialEq)]
+pub enum Access<'a> {
+    Public,
+    /// Needs a signed-in caller.
+    Private,
+    /// Needs a signed-in caller who is a member of this club.
+    Club(&'a str),
+}
+
+pub fn access<'a>(method: &Method, path: &'a str) -> Access<'a> {
+    if PUBLIC_ROUTES.iter().any(|(public_method, pattern)| public_method == method && route_matches(pattern, path)) {
+        return Access::Public;
+    }
+
+    // `/api/clubs/:club_id/...`, but not the club itself, which any signed-in
+    // user may look up
+    let club_id = path
+        .strip_prefix("/api/clubs/")
+        .and_then(|rest| rest.split_once('/'))
+        .map(|(club_id, _)| club_id)
+        .filter(|club_id| !club_id.is_empty());
+    match club_id {
+        Some(club_id) => Access::Club(club_id),
+        None => Access::Private,
+    }
+}
+
+/// Whether `path` fits `pattern`, where a `:name` segment matches any one
+/// non-empty segment.
+pub fn route_matches(pattern: &str, path: &str) -> bool {
+    let mut path_segments = path.split('/');
+    for pattern_segment in pattern.split('/') {
+        match path_segments.next() {
+            Some(segment) if pattern_segment.starts_with(':') && !segment.is_empty() => {}
+            Some(segment) if segment == pattern_segment => {}
+            _ => return false,
+        }
+    }
+    path_segments.next().is_none()
+}
+
+/// The scope a personal access token needs for a request, derived from the
+/// API area it targets. Anything not listed here (account management, tokens
+/// themselves, CSRF, a club's security settings and ownership) can't be
+/// reached with a personal access token at all.
+pub fn required_scope(method: &Method, path: &str) -> Option<String> {
+    let area = if path == "/api/auth/me" {
+        return (*method == Method::Get).then(|| "profile:read".to_string());
+    } else if let Some(rest) = path.strip_prefix("/api/clubs") {
+        // Nested club resources use their own scope
+        match rest.split('/').nth(2) {
+            // Turning off the admin two-factor rule or giving the club away
+            // needs the owner in person
+            Some("security") | Some("transfer") => return None,
+            Some("members") => "members",
+            Some("events") => "events",
+            Some("announcements") => "announcements",
+            Some("projects") => "projects",
+            _ => "clubs",
+        }
+    } else if path.starts_with("/api/members") {
+        "members"
+    } else if path.starts_with("/api/events") {
+        "events"
+    } else if path.starts_with("/api/announcements") {
+        "announcements"
+    } else if path.starts_with("/api/projects") {
+        "projects"
+    } else if path.starts_with("/api/meetings") {
+        "meetings"
+    } else if path.starts_with("/api/forum") {
+        "forum"
+    } else {
+        return None;
+    };
+
+    let access = if *method == Method::Get { "read" } else { "write" };
+    Some(format!("{}:{}", area, access))
+}
+
+/// Methods that change something, and so need a CSRF token.
+pub fn is_unsafe(method: &Method) -> bool {
+    !matches!(method, Method::Get | Method::Head | Method::Options)
+}
+
+/// Resolves the credentials a request carries, if they are valid.
+pub async fn authenticate(req: &Request, env: &Env) -> Option<AuthContext> {
+    // A request presenting an access token is judged on that token alone,
+    // never on whatever cookies happen to accompany it
+    if let Some(token) = tokens::bearer_access_token(req) {
+        let access_token = tokens::authenticate_access_token(req, env, &token).await?;
+        return Some(AuthContext {
+            user_id: access_token.user_id,
+            session_id: None,
+            scopes: Some(access_token.scopes),
+        });
+    }
+
+    let session = authenticate_request(req, env).await?;
+    Some(AuthContext {
+        user_id: session.user_id,
+        session_id: Some(session.session_id),
+        scopes: None,
+    })
+}
+
+/// Runs the checks the route needs and returns what they established, or
+/// the error to answer with instead of routing the request.
+pub async fn authorize(req: &Request, env: &Env) -> ApiResult<RequestContext> {
+    let method = req.method();
+    let path = req.path();
+
+    let club_id = match access(&method, &path) {
+        Access::Public => return Ok(RequestContext::default()),
+        Access::Private => None,
+        Access::Club(club_id) => Some(club_id),
+    };
+
+    let auth = authenticate(req, env)
+        .await
+        .ok_or_else(|| ApiError::Unauthorized("Unauthorized".to_string()))?;
+
+    // Access tokens are never sent automatically by a browser, so there is no
+    // forged request to defend against
+    if let Some(session_id) = &auth.session_id {
+        if is_unsafe(&method) && !verify_csrf_token(req, env, session_id).unwrap_or(false) {
+            return Err(ApiError::CsrfFailed);
+        }
+    }
+
+    let club = match club_id {
+        Some(club_id) => {
+            let db = env.d1("DB")?;
+            Some(club_member(&D1Store::new(&db), club_id, &auth.user_id).await?)
+        }
+        None => None,
+    };
+
+    Ok(RequestContext { auth: Some(auth), club, worker: None })
+}
+
+/// The user's role in the club, or a 403 if they aren't a member.
+pub async fn club_member<S: Store>(store: &S, club_id: &str, user_id: &str) -> ApiResult<ClubMember> {
+    let member = membership::require_member(store, club_id, user_id).await?;
+    Ok(ClubMember {
+        club_id: member.club_id,
+        role: member.role,
+    })
+}
@@ -0,0 +1,265 @@
+//! Versioned schema migrations.
+//!
+//! Migrations are numbered and applied in order, once each. SQL migrations
+//! live in `migrations/NNNN_name.sql` and are registered in `all()`; data
+//! migrations that need more than SQL are Rust functions registered in the
+//! same list. The `schema_migrations` table records what has been applied
+//! along with a checksum, and the runner refuses to go on if an applied
+//! migration has since been edited, renamed or removed.
+//!
+//! The runner works against any `MigrationTarget`: SQLite for tests and
+//! local databases, and D1 through wrangler (see `src/bin/migrate.rs`).
+
+use crate::store::{inline_params, Database, DecodeError, FromRow, Row, StoreError, StoreResult};
+use chrono::{DateTime, Utc};
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+/// The migrations, oldest first. Versions only ever grow; an applied
+/// migration is never edited, a later one corrects it.
+pub fn all<D: MigrationTarget>() -> Vec<Migration<D>> {
+    vec![
+        Migration::sql(1, "initial_schema", include_str!("../migrations/0001_initial_schema.sql")),
+        Migration::sql(2, "password_reset_requests_index", include_str!("../migrations/0002_password_reset_requests_index.sql")),
+        Migration::sql(3, "email_outbox", include_str!("../migrations/0003_email_outbox.sql")),
+        Migration::sql(4, "refresh_tokens", include_str!("../migrations/0004_refresh_tokens.sql")),
+        Migration::sql(5, "two_factor", include_str!("../migrations/0005_two_factor.sql")),
+        Migration::sql(6, "passkeys", include_str!("../migrations/0006_passkeys.sql")),
+        Migration::sql(7, "oauth_states", include_str!("../migrations/0007_oauth_states.sql")),
+        Migration::sql(8, "magic_links", include_str!("../migrations/0008_magic_links.sql")),
+        Migration::sql(9, "personal_access_tokens", include_str!("../migrations/0009_personal_access_tokens.sql")),
+        Migration::sql(10, "rate_limit_counters", include_str!("../migrations/0010_rate_limit_counters.sql")),
+        Migration::sql(11, "account_deletion", include_str!("../migrations/0011_account_deletion.sql")),
+        Migration::sql(12, "auth_events", include_str!("../migrations/0012_auth_events.sql")),
+        Migration::sql(13, "drop_csrf_tokens", include_str!("../migrations/0013_drop_csrf_tokens.sql")),
+        Migration::sql(14, "platform_invites", include_str!("../migrations/0014_platform_invites.sql")),
+        Migration::sql(15, "email_queue", include_str!("../migrations/0015_email_queue.sql")),
+    ]
+}
+
+/// A database the runner can migrate: `Database` plus running a whole SQL
+/// script in one go.
+#[allow(async_fn_in_trait)]
+pub trait MigrationTarget: Database {
+    /// Runs every statement in `sql`, atomically where the backend allows.
+    async fn execute_script(&self, sql: &str) -> StoreResult<()>;
+}
+
+pub type MigrationFuture<'a> = Pin<Box<dyn Future<Output = StoreResult<()>> + 'a>>;
+
+/// A data migration written in Rust. It isn't applied atomically with its
+/// record in `schema_migrations`, so it should be safe to run again.
+pub type DataMigration<D> = for<'a> fn(&'a D) -> MigrationFuture<'a>;
+
+pub enum Step<D> {
+    Sql(&'static str),
+    Rust(DataMigration<D>),
+}
+
+pub struct Migration<D> {
+    pub version: u32,
+    pub name: &'static str,
+    pub step: Step<D>,
+}
+
+impl<D: MigrationTarget> Migration<D> {
+    pub fn sql(version: u32, name: &'static str, sql: &'static str) -> Self {
+        Migration { version, name, step: Step::Sql(sql) }
+    }
+
+    pub fn rust(version: u32, name: &'static str, run: DataMigration<D>) -> Self {
+        Migration { version, name, step: Step::Rust(run) }
+    }
+
+    /// SHA-256 of the SQL, ignoring line endings so a checkout with CRLFs
+    /// matches. Rust can't be hashed, so a data migration is only checked by
+    /// name.
+    pub fn checksum(&self) -> String {
+        let content = match &self.step {
+            Step::Sql(sql) => sql.replace("\r\n", "\n"),
+            Step::Rust(_) => format!("rust:{}", self.name),
+        };
+        format!("{:x}", Sha256::digest(content.as_bytes()))
+    }
+
+    async fn apply(&self, db: &D, now: DateTime<Utc>) -> StoreResult<()> {
+        let record = [
+            (self.version as i64).into(),
+            self.name.into(),
+            self.checksum().into(),
+            now.to_rfc3339().into(),
+        ];
+        match &self.step {
+            // The record goes in the same script, so the migration and its
+            // record are applied together
+            Step::Sql(sql) => {
+                let record = inline_params(RECORD_MIGRATION, &record)?;
+                db.execute_script(&format!("{}\n{};\n", sql, record)).await
+            }
+            Step::Rust(run) => {
+                run(db).await?;
+                db.execute(RECORD_MIGRATION, &record).await.map(|_| ())
+            }
+        }
+    }
+}
+
+const CREATE_MIGRATIONS_TABLE: &str = "
+    CREATE TABLE IF NOT EXISTS schema_migrations (
+        version INTEGER PRIMARY KEY,
+        name TEXT NOT NULL,
+        checksum TEXT NOT NULL,
+        applied_at TEXT NOT NULL
+    )
+";
+
+const RECORD_MIGRATION: &str =
+    "INSERT INTO schema_migrations (version, name, checksum, applied_at) VALUES (?1, ?2, ?3, ?4)";
+
+/// A row of `schema_migrations`.
+pub struct AppliedMigration {
+    pub version: u32,
+    pub name: String,
+    pub checksum: String,
+    pub applied_at: String,
+}
+
+impl FromRow for AppliedMigration {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(AppliedMigration {
+            version: row.get("version")?,
+            name: row.get("name")?,
+            checksum: row.get("checksum")?,
+            applied_at: row.get("applied_at")?,
+        })
+    }
+}
+
+pub struct MigrationStatus {
+    pub version: u32,
+    pub name: &'static str,
+    /// When it was applied, or `None` while pending.
+    pub applied_at: Option<String>,
+}
+
+#[derive(Debug)]
+pub enum MigrationError {
+    /// Versions in the list aren't strictly increasing.
+    InvalidOrder { version: u32 },
+    /// An applied migration's name or contents have changed since.
+    Modified { version: u32, name: String },
+    /// The database has a migration this build doesn't know, so it was
+    /// migrated by newer code.
+    Unknown { version: u32, name: String },
+    /// A pending migration is older than one already applied, usually from
+    /// merging branches that both added migrations.
+    OutOfOrder { version: u32, latest: u32 },
+    Failed { version: u32, name: &'static str, error: StoreError },
+    Store(StoreError),
+}
+
+impl From<StoreError> for MigrationError {
+    fn from(error: StoreError) -> Self {
+        MigrationError::Store(error)
+    }
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::InvalidOrder { version } => {
+                write!(f, "migration {:04} is out of sequence in the migration list", version)
+            }
+            MigrationError::Modified { version, name } => {
+                write!(f, "migration {:04} ({}) has changed since it was applied", version, name)
+            }
+            MigrationError::Unknown { version, name } => {
+                write!(f, "the database has migration {:04} ({}), which this build doesn't know", version, name)
+            }
+            MigrationError::OutOfOrder { version, latest } => {
+                write!(f, "migration {:04} is pending but {:04} is already applied; renumber it", version, latest)
+            }
+            MigrationError::Failed { version, name, error } => {
+                write!(f, "migration {:04} ({}) failed: {}", version, name, error)
+            }
+            MigrationError::Store(error) => error.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+/// Every migration, applied or pending. Fails if an applied one no longer
+/// matches its definition.
+pub async fn status<D: MigrationTarget>(db: &D, migrations: &[Migration<D>]) -> Result<Vec<MigrationStatus>, MigrationError> {
+    let applied = verify(db, migrations).await?;
+
+    Ok(migrations
+        .iter()
+        .map(|migration| MigrationStatus {
+            version: migration.version,
+            name: migration.name,
+            applied_at: applied
+                .iter()
+                .find(|applied| applied.version == migration.version)
+                .map(|applied| applied.applied_at.clone()),
+        })
+        .collect())
+}
+
+/// Applies the pending migrations in order and returns their versions. Stops
+/// at the first failure; the migrations before it stay applied.
+pub async fn migrate<D: MigrationTarget>(db: &D, migrations: &[Migration<D>], now: DateTime<Utc>) -> Result<Vec<u32>, MigrationError> {
+    let applied = verify(db, migrations).await?;
+    let latest = applied.iter().map(|applied| applied.version).max().unwrap_or(0);
+
+    let pending: Vec<&Migration<D>> = migrations
+        .iter()
+        .filter(|migration| !applied.iter().any(|applied| applied.version == migration.version))
+        .collect();
+    if let Some(stale) = pending.iter().find(|migration| migration.version < latest) {
+        return Err(MigrationError::OutOfOrder { version: stale.version, latest });
+    }
+
+    let mut versions = Vec::new();
+    for migration in pending {
+        migration.apply(db, now).await.map_err(|error| MigrationError::Failed {
+            version: migration.version,
+            name: migration.name,
+            error,
+        })?;
+        versions.push(migration.version);
+    }
+    Ok(versions)
+}
+
+async fn verify<D: MigrationTarget>(db: &D, migrations: &[Migration<D>]) -> Result<Vec<AppliedMigration>, MigrationError> {
+    let mut previous = 0;
+    for migration in migrations {
+        if migration.version <= previous {
+            return Err(MigrationError::InvalidOrder { version: migration.version });
+        }
+        previous = migration.version;
+    }
+
+    db.execute(CREATE_MIGRATIONS_TABLE, &[]).await?;
+    let applied: Vec<AppliedMigration> = db
+        .query_as("SELECT version, name, checksum, applied_at FROM schema_migrations ORDER BY version", &[])
+        .await?;
+
+    for record in &applied {
+        let migration = migrations.iter().find(|migration| migration.version == record.version);
+        match migration {
+            None => {
+                return Err(MigrationError::Unknown { version: record.version, name: record.name.clone() });
+            }
+            Some(migration) if migration.name != record.name || migration.checksum() != record.checksum => {
+                return Err(MigrationError::Modified { version: record.version, name: record.name.clone() });
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(applied)
+}
@@ -1,5 +1,7 @@
 use serde::{Deserialize, Serialize};
 
+mod rows;
+
 // User and Authentication models
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct User {
@@ -27,6 +29,7 @@ pub struct AuthUser {
     pub last_login: Option<String>,
     pub failed_login_attempts: u32,
     pub locked_until: Option<String>,
+    pub deletion_scheduled_for: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -42,6 +45,75 @@ pub struct Session {
     pub is_active: bool,
 }
 
+#[derive(Serialize, Deserialize, Clone)]
+pub struct RefreshToken {
+    pub id: String,
+    pub session_id: String,
+    pub user_id: String,
+    pub token_hash: String,
+    pub expires_at: String,
+    pub created_at: String,
+    pub used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct UserMfa {
+    pub user_id: String,
+    #[serde(skip_serializing)]
+    pub secret: String,
+    pub enabled: bool,
+    pub created_at: String,
+    pub confirmed_at: Option<String>,
+    pub last_used_step: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct MfaChallenge {
+    pub id: String,
+    pub user_id: String,
+    pub expires_at: String,
+    pub created_at: String,
+    pub attempts: i64,
+    pub used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct OAuthState {
+    pub id: String,
+    pub provider: String,
+    pub mode: String,
+    pub user_id: Option<String>,
+    pub code_verifier: String,
+    pub nonce: String,
+    pub redirect_to: Option<String>,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Passkey {
+    pub id: String,
+    pub user_id: String,
+    pub credential_id: String,
+    pub public_key: String,
+    pub algorithm: i64,
+    pub sign_count: u32,
+    pub name: String,
+    pub transports: Option<String>,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct WebAuthnChallenge {
+    pub id: String,
+    pub user_id: Option<String>,
+    pub challenge: String,
+    pub ceremony: String,
+    pub expires_at: String,
+    pub created_at: String,
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 pub struct EmailVerification {
     pub id: String,
@@ -63,12 +135,65 @@ pub struct PasswordReset {
 }
 
 #[derive(Serialize, Deserialize, Clone)]
-pub struct CsrfToken {
+pub struct MagicLink {
     pub id: String,
-    pub user_id: String,
+    pub user_id: Option<String>,
+    pub email: String,
     pub token: String,
+    pub ip_address: Option<String>,
+    pub expires_at: String,
+    pub created_at: String,
+    pub used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct PersonalAccessToken {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub token_hash: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
     pub expires_at: String,
     pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PlatformInvite {
+    pub id: String,
+    pub token_hash: String,
+    /// When set, only this address can redeem the invite.
+    pub email: Option<String>,
+    pub created_by: String,
+    pub expires_at: String,
+    pub created_at: String,
+    pub used_at: Option<String>,
+    pub used_by: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthEventType {
+    Login,
+    LoginFailed,
+    Logout,
+    PasswordChanged,
+    PasswordReset,
+    SessionRevoked,
+    OtherSessionsRevoked,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct AuthEvent {
+    pub id: String,
+    pub user_id: String,
+    pub event_type: String,
+    pub detail: Option<String>,
+    pub ip_address: Option<String>,
+    pub user_agent: Option<String>,
+    pub device_hash: String,
+    pub created_at: String,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -105,6 +230,7 @@ pub struct Club {
     pub created_at: String,
     pub updated_at: String,
     pub owner_id: String,
+    pub require_admin_mfa: bool,
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -278,6 +404,8 @@ pub struct SignupRequest {
     pub email: String,
     pub password: String,
     pub name: String,
+    /// Required when signups are invite-only.
+    pub invite_token: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -286,11 +414,104 @@ pub struct LoginRequest {
     pub password: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RefreshTokenRequest {
+    pub refresh_token: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaCodeRequest {
+    pub code: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaVerifyRequest {
+    /// Falls back to the challenge cookie social login sets.
+    pub mfa_token: Option<String>,
+    pub code: Option<String>,
+    pub recovery_code: Option<String>,
+}
+
+// Passkey payloads follow the browser's PublicKeyCredential JSON
+// (camelCase, binary fields base64url-encoded)
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAttestationResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub attestation_object: String,
+    pub transports: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyRegistrationCredential {
+    pub id: String,
+    pub response: PasskeyAttestationResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAssertionResponse {
+    #[serde(rename = "clientDataJSON")]
+    pub client_data_json: String,
+    pub authenticator_data: String,
+    pub signature: String,
+    pub user_handle: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct PasskeyAssertionCredential {
+    pub id: String,
+    pub response: PasskeyAssertionResponse,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegisterPasskeyRequest {
+    pub name: Option<String>,
+    pub credential: PasskeyRegistrationCredential,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PasskeyLoginRequest {
+    pub credential: PasskeyAssertionCredential,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ForgotPasswordRequest {
     pub email: String,
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DeleteAccountRequest {
+    pub password: Option<String>,
+    pub code: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateAccessTokenRequest {
+    pub name: String,
+    pub scopes: Vec<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatePlatformInviteRequest {
+    pub email: Option<String>,
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MagicLinkRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ConsumeMagicLinkRequest {
+    pub token: String,
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct ResetPasswordRequest {
     pub token: String,
@@ -307,6 +528,7 @@ pub struct ChangePasswordRequest {
 pub struct UpdateProfileRequest {
     pub name: Option<String>,
     pub email: Option<String>,
+    pub avatar: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -315,18 +537,18 @@ pub struct VerifyEmailRequest {
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SocialLoginRequest {
-    pub provider: SocialProvider,
-    pub access_token: String,
-    pub profile: SocialUserProfile,
+pub struct ResendVerificationRequest {
+    pub email: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default)]
+pub struct OAuthStartRequest {
+    pub redirect_to: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
-pub struct SocialUserProfile {
-    pub id: String,
-    pub email: String,
-    pub name: String,
-    pub avatar: Option<String>,
+pub struct OAuthStartResponse {
+    pub authorization_url: String,
 }
 
 // Auth API Response types
@@ -335,8 +557,108 @@ pub struct AuthResponse {
     pub success: bool,
     pub user: Option<User>,
     pub token: Option<String>,
+    pub refresh_token: Option<String>,
     pub expires_at: Option<String>,
     pub error: Option<String>,
+    /// When the account is due to be erased, so the app can offer to restore it.
+    pub deletion_scheduled_for: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionSummary {
+    pub id: String,
+    pub device: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+    pub last_accessed: String,
+    pub expires_at: String,
+    pub is_current: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PasskeySummary {
+    pub id: String,
+    pub name: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AuthEventSummary {
+    pub id: String,
+    pub event_type: String,
+    pub detail: Option<String>,
+    pub device: Option<String>,
+    pub ip_address: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccountDeletionResponse {
+    pub scheduled_for: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AccessTokenSummary {
+    pub id: String,
+    pub name: String,
+    pub token_prefix: String,
+    pub scopes: Vec<String>,
+    pub expires_at: String,
+    pub created_at: String,
+    pub last_used_at: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateAccessTokenResponse {
+    pub token: String,
+    pub access_token: AccessTokenSummary,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct PlatformInviteSummary {
+    pub id: String,
+    pub email: Option<String>,
+    pub created_by: String,
+    pub expires_at: String,
+    pub created_at: String,
+    pub used_at: Option<String>,
+    pub used_by: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatePlatformInviteResponse {
+    pub token: String,
+    pub link: String,
+    pub invite: PlatformInviteSummary,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LinkedAccountSummary {
+    pub provider: String,
+    pub email: String,
+    pub name: String,
+    pub avatar: Option<String>,
+    pub created_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaChallengeResponse {
+    pub success: bool,
+    pub mfa_required: bool,
+    pub mfa_token: String,
+    pub expires_at: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaSetupResponse {
+    pub secret: String,
+    pub otpauth_uri: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MfaRecoveryCodesResponse {
+    pub recovery_codes: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -352,6 +674,16 @@ pub struct CreateClubRequest {
     pub description: String,
 }
 
+#[derive(Serialize, Deserialize)]
+pub struct UpdateClubSecurityRequest {
+    pub require_admin_mfa: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TransferClubOwnershipRequest {
+    pub new_owner_id: String,
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct JoinClubRequest {
     pub invite_code: String,
@@ -397,11 +729,24 @@ pub struct CourseWithProgress {
     pub progress: Option<UserProgress>,
 }
 
+/// The envelope every API response is sent in. Failures carry a message in
+/// `error` and a machine-readable `code`, plus per-field `details` when the
+/// request didn't validate.
 #[derive(Serialize, Deserialize)]
 pub struct ApiResponse<T> {
     pub success: bool,
     pub data: Option<T>,
     pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub details: Option<Vec<FieldError>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
 }
 
 impl<T> ApiResponse<T> {
@@ -410,14 +755,18 @@ impl<T> ApiResponse<T> {
             success: true,
             data: Some(data),
             error: None,
+            code: None,
+            details: None,
         }
     }
 
-    pub fn error(error: String) -> Self {
+    pub fn failure(error: String, code: &str, details: Option<Vec<FieldError>>) -> Self {
         Self {
             success: false,
             data: None,
             error: Some(error),
+            code: Some(code.to_string()),
+            details,
         }
     }
 }
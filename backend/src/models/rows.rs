@@ -0,0 +1,391 @@
+//! How models are read from database rows.
+//!
+//! Flags the schema declares as `INTEGER DEFAULT n` without `NOT NULL` are
+//! read as optional and fall back to the same default, so a NULL there means
+//! what the schema says it means rather than failing the row.
+
+use super::*;
+use crate::store::row::{parse_text, DecodeError, FromColumn, FromRow, Row};
+use serde_json::Value;
+
+impl MemberRole {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MemberRole::Admin => "admin",
+            MemberRole::Member => "member",
+        }
+    }
+
+    pub fn parse(role: &str) -> Option<Self> {
+        match role {
+            "admin" => Some(MemberRole::Admin),
+            "member" => Some(MemberRole::Member),
+            _ => None,
+        }
+    }
+}
+
+impl ProjectStatus {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ProjectStatus::Planning => "planning",
+            ProjectStatus::Active => "active",
+            ProjectStatus::Completed => "completed",
+            ProjectStatus::OnHold => "on-hold",
+        }
+    }
+
+    pub fn parse(status: &str) -> Option<Self> {
+        match status {
+            "planning" => Some(ProjectStatus::Planning),
+            "active" => Some(ProjectStatus::Active),
+            "completed" => Some(ProjectStatus::Completed),
+            "on-hold" => Some(ProjectStatus::OnHold),
+            _ => None,
+        }
+    }
+}
+
+impl FromColumn for MemberRole {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        parse_text(value, MemberRole::parse, &["admin", "member"])
+    }
+}
+
+impl FromColumn for ProjectStatus {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        parse_text(value, ProjectStatus::parse, &["planning", "active", "completed", "on-hold"])
+    }
+}
+
+impl FromColumn for SocialProvider {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        parse_text(value, SocialProvider::from_slug, &["google", "github", "microsoft", "discord"])
+    }
+}
+
+impl FromRow for AuthUser {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(AuthUser {
+            id: row.get("id")?,
+            email: row.get("email")?,
+            password_hash: row.get("password_hash")?,
+            name: row.get("name")?,
+            avatar: row.get("avatar")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+            email_verified: row.get::<Option<bool>>("email_verified")?.unwrap_or(false),
+            is_active: row.get::<Option<bool>>("is_active")?.unwrap_or(true),
+            last_login: row.get("last_login")?,
+            failed_login_attempts: row.get::<Option<u32>>("failed_login_attempts")?.unwrap_or(0),
+            locked_until: row.get("locked_until")?,
+            deletion_scheduled_for: row.get("deletion_scheduled_for")?,
+        })
+    }
+}
+
+impl FromRow for Session {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Session {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            token: row.get("token")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            last_accessed: row.get("last_accessed")?,
+            user_agent: row.get("user_agent")?,
+            ip_address: row.get("ip_address")?,
+            is_active: row.get::<Option<bool>>("is_active")?.unwrap_or(true),
+        })
+    }
+}
+
+impl FromRow for RefreshToken {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(RefreshToken {
+            id: row.get("id")?,
+            session_id: row.get("session_id")?,
+            user_id: row.get("user_id")?,
+            token_hash: row.get("token_hash")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            used_at: row.get("used_at")?,
+        })
+    }
+}
+
+impl FromRow for UserMfa {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(UserMfa {
+            user_id: row.get("user_id")?,
+            secret: row.get("secret")?,
+            enabled: row.get::<Option<bool>>("enabled")?.unwrap_or(false),
+            created_at: row.get("created_at")?,
+            confirmed_at: row.get("confirmed_at")?,
+            last_used_step: row.get("last_used_step")?,
+        })
+    }
+}
+
+impl FromRow for MfaChallenge {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(MfaChallenge {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            attempts: row.get::<Option<i64>>("attempts")?.unwrap_or(0),
+            used_at: row.get("used_at")?,
+        })
+    }
+}
+
+impl FromRow for OAuthState {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(OAuthState {
+            id: row.get("id")?,
+            provider: row.get("provider")?,
+            mode: row.get("mode")?,
+            user_id: row.get("user_id")?,
+            code_verifier: row.get("code_verifier")?,
+            nonce: row.get("nonce")?,
+            redirect_to: row.get("redirect_to")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+impl FromRow for Passkey {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Passkey {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            credential_id: row.get("credential_id")?,
+            public_key: row.get("public_key")?,
+            algorithm: row.get("algorithm")?,
+            sign_count: row.get::<Option<u32>>("sign_count")?.unwrap_or(0),
+            name: row.get("name")?,
+            transports: row.get("transports")?,
+            created_at: row.get("created_at")?,
+            last_used_at: row.get("last_used_at")?,
+        })
+    }
+}
+
+impl FromRow for WebAuthnChallenge {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(WebAuthnChallenge {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            challenge: row.get("challenge")?,
+            ceremony: row.get("ceremony")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+impl FromRow for EmailVerification {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(EmailVerification {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            token: row.get("token")?,
+            email: row.get("email")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+impl FromRow for PasswordReset {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(PasswordReset {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            token: row.get("token")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            used_at: row.get("used_at")?,
+        })
+    }
+}
+
+impl FromRow for MagicLink {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(MagicLink {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            email: row.get("email")?,
+            token: row.get("token")?,
+            ip_address: row.get("ip_address")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            used_at: row.get("used_at")?,
+        })
+    }
+}
+
+impl FromRow for PersonalAccessToken {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(PersonalAccessToken {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            name: row.get("name")?,
+            token_hash: row.get("token_hash")?,
+            token_prefix: row.get("token_prefix")?,
+            // Stored space-separated
+            scopes: row.get::<String>("scopes")?.split_whitespace().map(|s| s.to_string()).collect(),
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            last_used_at: row.get("last_used_at")?,
+        })
+    }
+}
+
+impl FromRow for PlatformInvite {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(PlatformInvite {
+            id: row.get("id")?,
+            token_hash: row.get("token_hash")?,
+            email: row.get("email")?,
+            created_by: row.get("created_by")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            used_at: row.get("used_at")?,
+            used_by: row.get("used_by")?,
+        })
+    }
+}
+
+impl FromRow for AuthEvent {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(AuthEvent {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            event_type: row.get("event_type")?,
+            detail: row.get("detail")?,
+            ip_address: row.get("ip_address")?,
+            user_agent: row.get("user_agent")?,
+            device_hash: row.get("device_hash")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+impl FromRow for SocialAccount {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(SocialAccount {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            provider: row.get("provider")?,
+            provider_id: row.get("provider_id")?,
+            email: row.get("email")?,
+            name: row.get("name")?,
+            avatar: row.get("avatar")?,
+            access_token: row.get("access_token")?,
+            refresh_token: row.get("refresh_token")?,
+            expires_at: row.get("expires_at")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
+
+impl FromRow for Club {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Club {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            description: row.get("description")?,
+            avatar: row.get("avatar")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+            owner_id: row.get("owner_id")?,
+            require_admin_mfa: row.get::<Option<bool>>("require_admin_mfa")?.unwrap_or(false),
+        })
+    }
+}
+
+/// Reads a membership joined with its user, whose timestamps are selected as
+/// `user_created_at` and `user_updated_at` to keep them apart from the
+/// member's own columns.
+impl FromRow for Member {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Member {
+            id: row.get("id")?,
+            user_id: row.get("user_id")?,
+            club_id: row.get("club_id")?,
+            role: row.get("role")?,
+            joined_at: row.get("joined_at")?,
+            user: User {
+                id: row.get("user_id")?,
+                email: row.get("email")?,
+                name: row.get("name")?,
+                avatar: row.get("avatar")?,
+                created_at: row.get("user_created_at")?,
+                updated_at: row.get("user_updated_at")?,
+                email_verified: row.get::<Option<bool>>("email_verified")?.unwrap_or(false),
+                is_active: row.get::<Option<bool>>("is_active")?.unwrap_or(true),
+            },
+        })
+    }
+}
+
+impl FromRow for InviteCode {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(InviteCode {
+            code: row.get("code")?,
+            club_id: row.get("club_id")?,
+            created_by: row.get("created_by")?,
+            expires_at: row.get("expires_at")?,
+            used_by: row.get("used_by")?,
+            used_at: row.get("used_at")?,
+        })
+    }
+}
+
+impl FromRow for Event {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Event {
+            id: row.get("id")?,
+            club_id: row.get("club_id")?,
+            title: row.get("title")?,
+            description: row.get("description")?,
+            date: row.get("date")?,
+            location: row.get("location")?,
+            created_by: row.get("created_by")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+impl FromRow for Announcement {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Announcement {
+            id: row.get("id")?,
+            club_id: row.get("club_id")?,
+            title: row.get("title")?,
+            content: row.get("content")?,
+            created_by: row.get("created_by")?,
+            created_at: row.get("created_at")?,
+            pinned: row.get::<Option<bool>>("pinned")?.unwrap_or(false),
+        })
+    }
+}
+
+impl FromRow for Project {
+    fn from_row(row: &Row) -> Result<Self, DecodeError> {
+        Ok(Project {
+            id: row.get("id")?,
+            club_id: row.get("club_id")?,
+            name: row.get("name")?,
+            description: row.get("description")?,
+            status: row.get("status")?,
+            created_by: row.get("created_by")?,
+            created_at: row.get("created_at")?,
+            updated_at: row.get("updated_at")?,
+        })
+    }
+}
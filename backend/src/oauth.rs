@@ -0,0 +1,361 @@
+//! OAuth 2.0 / OpenID Connect provider configuration and token handling for
+//! social sign-in.
+//!
+//! Every provider endpoint can be overridden from the environment
+//! (`OAUTH_<PROVIDER>_AUTHORIZE_URL`, `_TOKEN_URL`, `_USERINFO_URL`,
+//! `_EMAILS_URL`, `_JWKS_URL`, `_ISSUER`), so the flow can run against a
+//! local mock identity provider.
+
+use crate::models::SocialProvider;
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{decode, decode_header, Algorithm, DecodingKey, Validation};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use worker::{Env, Url};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OAuthError {
+    NotConfigured,
+    InvalidState,
+    AccessDenied,
+    TokenExchangeFailed,
+    InvalidIdToken,
+    ProfileUnavailable,
+    MissingVerifiedEmail,
+}
+
+impl OAuthError {
+    /// Short code passed back to the frontend in the `error` query parameter.
+    pub fn code(&self) -> &'static str {
+        match self {
+            OAuthError::NotConfigured => "provider_not_configured",
+            OAuthError::InvalidState => "invalid_state",
+            OAuthError::AccessDenied => "access_denied",
+            OAuthError::TokenExchangeFailed => "token_exchange_failed",
+            OAuthError::InvalidIdToken => "invalid_id_token",
+            OAuthError::ProfileUnavailable => "profile_unavailable",
+            OAuthError::MissingVerifiedEmail => "missing_verified_email",
+        }
+    }
+}
+
+impl fmt::Display for OAuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl SocialProvider {
+    pub fn from_slug(slug: &str) -> Option<Self> {
+        match slug {
+            "google" => Some(SocialProvider::Google),
+            "github" => Some(SocialProvider::GitHub),
+            "microsoft" => Some(SocialProvider::Microsoft),
+            "discord" => Some(SocialProvider::Discord),
+            _ => None,
+        }
+    }
+
+    /// The identifier used in URLs, environment variable names and `social_accounts.provider`.
+    pub fn slug(&self) -> &'static str {
+        match self {
+            SocialProvider::Google => "google",
+            SocialProvider::GitHub => "github",
+            SocialProvider::Microsoft => "microsoft",
+            SocialProvider::Discord => "discord",
+        }
+    }
+}
+
+/// Endpoints and credentials for one provider. OIDC providers have a
+/// `jwks_url` and `issuer` and are trusted through their ID token; the others
+/// are read through `userinfo_url` with the access token.
+#[derive(Debug, Clone)]
+pub struct ProviderConfig {
+    pub provider: SocialProvider,
+    pub client_id: String,
+    pub client_secret: String,
+    pub authorize_url: String,
+    pub token_url: String,
+    pub userinfo_url: Option<String>,
+    pub emails_url: Option<String>,
+    pub jwks_url: Option<String>,
+    /// May contain `{tenantid}`, which is filled from the token's `tid` claim
+    /// (Microsoft's multi-tenant endpoint issues per-tenant issuers).
+    pub issuer: Option<String>,
+    pub scope: String,
+}
+
+impl ProviderConfig {
+    /// Reads `OAUTH_<PROVIDER>_CLIENT_ID` and the `OAUTH_<PROVIDER>_CLIENT_SECRET`
+    /// secret; endpoints default to the provider's public ones.
+    pub fn from_env(env: &Env, provider: SocialProvider) -> Result<Self, OAuthError> {
+        let prefix = format!("OAUTH_{}", provider.slug().to_uppercase());
+        let var = |name: &str| {
+            env.var(&format!("{}_{}", prefix, name))
+                .map(|v| v.to_string())
+                .ok()
+                .filter(|v| !v.is_empty())
+        };
+
+        let client_id = var("CLIENT_ID").ok_or(OAuthError::NotConfigured)?;
+        let client_secret = env.secret(&format!("{}_CLIENT_SECRET", prefix))
+            .map(|v| v.to_string())
+            .map_err(|_| OAuthError::NotConfigured)?;
+
+        let defaults = ProviderConfig::defaults(provider);
+        Ok(ProviderConfig {
+            client_id,
+            client_secret,
+            authorize_url: var("AUTHORIZE_URL").unwrap_or(defaults.authorize_url),
+            token_url: var("TOKEN_URL").unwrap_or(defaults.token_url),
+            userinfo_url: var("USERINFO_URL").or(defaults.userinfo_url),
+            emails_url: var("EMAILS_URL").or(defaults.emails_url),
+            jwks_url: var("JWKS_URL").or(defaults.jwks_url),
+            issuer: var("ISSUER").or(defaults.issuer),
+            scope: var("SCOPE").unwrap_or(defaults.scope),
+            provider: defaults.provider,
+        })
+    }
+
+    fn defaults(provider: SocialProvider) -> Self {
+        let owned = |s: &str| s.to_string();
+        let (authorize_url, token_url, userinfo_url, emails_url, jwks_url, issuer, scope) = match provider {
+            SocialProvider::Google => (
+                "https://accounts.google.com/o/oauth2/v2/auth",
+                "https://oauth2.googleapis.com/token",
+                None,
+                None,
+                Some("https://www.googleapis.com/oauth2/v3/certs"),
+                Some("https://accounts.google.com"),
+                "openid email profile",
+            ),
+            SocialProvider::Microsoft => (
+                "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+                "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                None,
+                None,
+                Some("https://login.microsoftonline.com/common/discovery/v2.0/keys"),
+                Some("https://login.microsoftonline.com/{tenantid}/v2.0"),
+                "openid email profile",
+            ),
+            SocialProvider::GitHub => (
+                "https://github.com/login/oauth/authorize",
+                "https://github.com/login/oauth/access_token",
+                Some("https://api.github.com/user"),
+                Some("https://api.github.com/user/emails"),
+                None,
+                None,
+                "read:user user:email",
+            ),
+            SocialProvider::Discord => (
+                "https://discord.com/oauth2/authorize",
+                "https://discord.com/api/oauth2/token",
+                Some("https://discord.com/api/users/@me"),
+                None,
+                None,
+                None,
+                "identify email",
+            ),
+        };
+
+        ProviderConfig {
+            provider,
+            client_id: String::new(),
+            client_secret: String::new(),
+            authorize_url: owned(authorize_url),
+            token_url: owned(token_url),
+            userinfo_url: userinfo_url.map(owned),
+            emails_url: emails_url.map(owned),
+            jwks_url: jwks_url.map(owned),
+            issuer: issuer.map(owned),
+            scope: owned(scope),
+        }
+    }
+
+    pub fn is_oidc(&self) -> bool {
+        self.jwks_url.is_some()
+    }
+
+    /// The URL to send the browser to for an authorization-code + PKCE request.
+    pub fn authorization_url(&self, redirect_uri: &str, state: &str, code_verifier: &str, nonce: &str) -> String {
+        let mut params = vec![
+            ("response_type", "code".to_string()),
+            ("client_id", self.client_id.clone()),
+            ("redirect_uri", redirect_uri.to_string()),
+            ("scope", self.scope.clone()),
+            ("state", state.to_string()),
+            ("code_challenge", pkce_challenge(code_verifier)),
+            ("code_challenge_method", "S256".to_string()),
+        ];
+        if self.is_oidc() {
+            params.push(("nonce", nonce.to_string()));
+        }
+
+        match Url::parse_with_params(&self.authorize_url, &params) {
+            Ok(url) => url.to_string(),
+            Err(_) => self.authorize_url.clone(),
+        }
+    }
+
+    /// Form body for exchanging `code` at the token endpoint.
+    pub fn token_request_body(&self, code: &str, redirect_uri: &str, code_verifier: &str) -> String {
+        form_encode(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", redirect_uri),
+            ("client_id", &self.client_id),
+            ("client_secret", &self.client_secret),
+            ("code_verifier", code_verifier),
+        ])
+    }
+}
+
+/// The provider account as we store it, whichever way it was obtained.
+#[derive(Debug, Clone)]
+pub struct ProviderIdentity {
+    pub provider_id: String,
+    pub email: String,
+    pub email_verified: bool,
+    pub name: String,
+    pub avatar: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct TokenResponse {
+    pub access_token: String,
+    pub id_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct IdTokenClaims {
+    iss: String,
+    sub: String,
+    nonce: Option<String>,
+    email: Option<String>,
+    email_verified: Option<bool>,
+    name: Option<String>,
+    picture: Option<String>,
+    preferred_username: Option<String>,
+    tid: Option<String>,
+}
+
+/// RFC 7636 S256 code challenge for `code_verifier`.
+pub fn pkce_challenge(code_verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier.as_bytes()))
+}
+
+/// Verifies an OIDC ID token's signature against the provider's JWKS and
+/// checks issuer, audience, expiry and nonce.
+pub fn verify_id_token(
+    config: &ProviderConfig,
+    jwks: &JwkSet,
+    id_token: &str,
+    expected_nonce: &str,
+) -> Result<ProviderIdentity, OAuthError> {
+    let header = decode_header(id_token).map_err(|_| OAuthError::InvalidIdToken)?;
+
+    // Only asymmetric algorithms: an HMAC-signed token would be "verified" with a public key
+    if !matches!(
+        header.alg,
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 | Algorithm::PS256 | Algorithm::ES256 | Algorithm::ES384 | Algorithm::EdDSA
+    ) {
+        return Err(OAuthError::InvalidIdToken);
+    }
+
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None if jwks.keys.len() == 1 => jwks.keys.first(),
+        None => None,
+    }
+    .ok_or(OAuthError::InvalidIdToken)?;
+    let key = DecodingKey::from_jwk(jwk).map_err(|_| OAuthError::InvalidIdToken)?;
+
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_required_spec_claims(&["exp", "aud", "iss", "sub"]);
+
+    let claims = decode::<IdTokenClaims>(id_token, &key, &validation)
+        .map_err(|_| OAuthError::InvalidIdToken)?
+        .claims;
+
+    let expected_issuer = config.issuer.as_deref().ok_or(OAuthError::NotConfigured)?;
+    let expected_issuer = match (&claims.tid, expected_issuer.contains("{tenantid}")) {
+        (Some(tid), true) => expected_issuer.replace("{tenantid}", tid),
+        (None, true) => return Err(OAuthError::InvalidIdToken),
+        (_, false) => expected_issuer.to_string(),
+    };
+    if claims.iss != expected_issuer {
+        return Err(OAuthError::InvalidIdToken);
+    }
+
+    if claims.nonce.as_deref() != Some(expected_nonce) {
+        return Err(OAuthError::InvalidIdToken);
+    }
+
+    let email = claims.email
+        .or_else(|| claims.preferred_username.filter(|u| u.contains('@')))
+        .ok_or(OAuthError::MissingVerifiedEmail)?;
+
+    // Microsoft doesn't verify the email claim for every account type, so it
+    // is never treated as verified
+    let email_verified = match config.provider {
+        SocialProvider::Microsoft => false,
+        _ => claims.email_verified.unwrap_or(false),
+    };
+
+    Ok(ProviderIdentity {
+        provider_id: claims.sub,
+        name: claims.name.unwrap_or_else(|| email.clone()),
+        email,
+        email_verified,
+        avatar: claims.picture,
+    })
+}
+
+/// Builds the identity from GitHub's `/user` and `/user/emails` responses,
+/// using the primary verified address.
+pub fn github_identity(user: &serde_json::Value, emails: &serde_json::Value) -> Result<ProviderIdentity, OAuthError> {
+    let provider_id = user["id"].as_u64().ok_or(OAuthError::ProfileUnavailable)?.to_string();
+    let login = user["login"].as_str().ok_or(OAuthError::ProfileUnavailable)?;
+
+    let email = emails.as_array()
+        .and_then(|emails| {
+            emails.iter().find(|e| e["primary"].as_bool() == Some(true) && e["verified"].as_bool() == Some(true))
+        })
+        .and_then(|e| e["email"].as_str())
+        .ok_or(OAuthError::MissingVerifiedEmail)?;
+
+    Ok(ProviderIdentity {
+        provider_id,
+        email: email.to_string(),
+        email_verified: true,
+        name: user["name"].as_str().filter(|n| !n.is_empty()).unwrap_or(login).to_string(),
+        avatar: user["avatar_url"].as_str().map(|s| s.to_string()),
+    })
+}
+
+/// Builds the identity from Discord's `/users/@me` response.
+pub fn discord_identity(user: &serde_json::Value) -> Result<ProviderIdentity, OAuthError> {
+    let provider_id = user["id"].as_str().ok_or(OAuthError::ProfileUnavailable)?;
+    let username = user["username"].as_str().ok_or(OAuthError::ProfileUnavailable)?;
+    let email = user["email"].as_str().ok_or(OAuthError::MissingVerifiedEmail)?;
+
+    Ok(ProviderIdentity {
+        provider_id: provider_id.to_string(),
+        email: email.to_string(),
+        email_verified: user["verified"].as_bool().unwrap_or(false),
+        name: user["global_name"].as_str().unwrap_or(username).to_string(),
+        avatar: user["avatar"].as_str()
+            .map(|hash| format!("https://cdn.discordapp.com/avatars/{}/{}.png", provider_id, hash)),
+    })
+}
+
+/// `application/x-www-form-urlencoded` encoding of `pairs`.
+pub fn form_encode(pairs: &[(&str, &str)]) -> String {
+    let mut url = Url::parse("http://localhost/").expect("static URL parses");
+    url.query_pairs_mut().extend_pairs(pairs);
+    url.query().unwrap_or_default().to_string()
+}
@@ -0,0 +1,152 @@
+//! Password hashing.
+//!
+//! New hashes use PBKDF2-HMAC-SHA256 in a PHC-style string,
+//! `$pbkdf2-sha256$i=<iterations>$<salt>$<hash>`, so the scheme and its cost
+//! travel with every hash. Inside the Worker the derivation runs through
+//! WebCrypto rather than wasm, which keeps it well inside the CPU budget.
+//! bcrypt hashes from before the switch still verify, and `needs_rehash`
+//! flags them (and hashes made with fewer iterations than configured) so they can
+//! be replaced after the next successful login.
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine as _};
+use worker::Env;
+
+const PBKDF2_SCHEME: &str = "pbkdf2-sha256";
+const SALT_LENGTH: usize = 16;
+const HASH_LENGTH: usize = 32;
+
+/// Workers' WebCrypto refuses PBKDF2 with more iterations than this.
+pub const MAX_PBKDF2_ITERATIONS: u32 = 100_000;
+const MIN_PBKDF2_ITERATIONS: u32 = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct HashParams {
+    pub iterations: u32,
+}
+
+impl Default for HashParams {
+    fn default() -> Self {
+        HashParams { iterations: MAX_PBKDF2_ITERATIONS }
+    }
+}
+
+impl HashParams {
+    /// Reads `PASSWORD_PBKDF2_ITERATIONS`, clamped to a range WebCrypto accepts
+    /// and that still means something against offline guessing.
+    pub fn from_env(env: &Env) -> Self {
+        env.var("PASSWORD_PBKDF2_ITERATIONS")
+            .ok()
+            .and_then(|v| v.to_string().parse::<u32>().ok())
+            .map(|iterations| HashParams {
+                iterations: iterations.clamp(MIN_PBKDF2_ITERATIONS, MAX_PBKDF2_ITERATIONS),
+            })
+            .unwrap_or_default()
+    }
+}
+
+pub async fn hash_password(password: &str, params: HashParams) -> Option<String> {
+    let mut salt = [0u8; SALT_LENGTH];
+    getrandom::getrandom(&mut salt).ok()?;
+
+    let hash = pbkdf2_sha256(password.as_bytes(), &salt, params.iterations).await?;
+
+    Some(format!(
+        "${}$i={}${}${}",
+        PBKDF2_SCHEME,
+        params.iterations,
+        STANDARD_NO_PAD.encode(salt),
+        STANDARD_NO_PAD.encode(hash)
+    ))
+}
+
+/// Checks `password` against a stored hash in any supported format. Unknown
+/// or empty hashes (e.g. accounts created through social sign-in) never match.
+pub async fn verify_password(password: &str, stored: &str) -> bool {
+    if is_bcrypt_hash(stored) {
+        return bcrypt::verify(password, stored).unwrap_or(false);
+    }
+
+    let Some(parsed) = parse_pbkdf2_hash(stored) else {
+        return false;
+    };
+
+    match pbkdf2_sha256(password.as_bytes(), &parsed.salt, parsed.iterations).await {
+        Some(hash) => constant_time_eq(&hash, &parsed.hash),
+        None => false,
+    }
+}
+
+/// Whether a hash that just verified should be replaced with one made using `params`.
+pub fn needs_rehash(stored: &str, params: HashParams) -> bool {
+    match parse_pbkdf2_hash(stored) {
+        // Never lower: a smaller configured count must not weaken existing hashes
+        Some(parsed) => parsed.iterations < params.iterations,
+        None => is_bcrypt_hash(stored),
+    }
+}
+
+fn is_bcrypt_hash(stored: &str) -> bool {
+    ["$2a$", "$2b$", "$2x$", "$2y$"].iter().any(|prefix| stored.starts_with(prefix))
+}
+
+struct Pbkdf2Hash {
+    iterations: u32,
+    salt: Vec<u8>,
+    hash: Vec<u8>,
+}
+
+fn parse_pbkdf2_hash(stored: &str) -> Option<Pbkdf2Hash> {
+    let mut parts = stored.strip_prefix('$')?.split('$');
+    if parts.next()? != PBKDF2_SCHEME {
+        return None;
+    }
+
+    let iterations = parts.next()?.strip_prefix("i=")?.parse::<u32>().ok()?;
+    let salt = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+    let hash = STANDARD_NO_PAD.decode(parts.next()?).ok()?;
+
+    if parts.next().is_some() || iterations == 0 || hash.len() != HASH_LENGTH {
+        return None;
+    }
+
+    Some(Pbkdf2Hash { iterations, salt, hash })
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+#[cfg(target_arch = "wasm32")]
+async fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> Option<Vec<u8>> {
+    use js_sys::{Array, Uint8Array};
+    use web_sys::{CryptoKey, Pbkdf2Params, WorkerGlobalScope};
+    use worker::wasm_bindgen::{JsCast, JsValue};
+    use worker::wasm_bindgen_futures::JsFuture;
+
+    let subtle = js_sys::global()
+        .unchecked_into::<WorkerGlobalScope>()
+        .crypto()
+        .ok()?
+        .subtle();
+
+    let usages = Array::of1(&JsValue::from_str("deriveBits"));
+    let import = subtle
+        .import_key_with_str("raw", &Uint8Array::from(password), "PBKDF2", false, &usages)
+        .ok()?;
+    let key: CryptoKey = JsFuture::from(import).await.ok()?.unchecked_into();
+
+    let params = Pbkdf2Params::new("PBKDF2", &JsValue::from_str("SHA-256"), iterations, &Uint8Array::from(salt));
+    let derive = subtle
+        .derive_bits_with_object(&params, &key, (HASH_LENGTH * 8) as u32)
+        .ok()?;
+    let bits = JsFuture::from(derive).await.ok()?;
+
+    Some(Uint8Array::new(&bits).to_vec())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+async fn pbkdf2_sha256(password: &[u8], salt: &[u8], iterations: u32) -> Option<Vec<u8>> {
+    let mut hash = vec![0u8; HASH_LENGTH];
+    pbkdf2::pbkdf2_hmac::<sha2::Sha256>(password, salt, iterations, &mut hash);
+    Some(hash)
+}
@@ -0,0 +1,83 @@
+//! Password reset links.
+//!
+//! Only a hash of each token is stored, so a leaked table can't be used to
+//! reset passwords. A token works once, within `PASSWORD_RESET_TTL_MINUTES`,
+//! and only while it is the user's newest one.
+
+use crate::models::PasswordReset;
+use crate::store::{Database, StoreResult};
+use crate::token::{generate_secure_token, hash_token};
+use chrono::{DateTime, Duration, Utc};
+use uuid::Uuid;
+
+pub const PASSWORD_RESET_TTL_MINUTES: i64 = 60;
+
+/// Stores a new reset token for `user_id` and returns it. A new link
+/// supersedes any older unused ones.
+pub async fn create<D: Database>(db: &D, user_id: &str, now: DateTime<Utc>) -> StoreResult<String> {
+    let token = generate_secure_token();
+    let created_at = now.to_rfc3339();
+    let expires_at = (now + Duration::minutes(PASSWORD_RESET_TTL_MINUTES)).to_rfc3339();
+
+    db.batch(vec![
+        ("UPDATE password_resets SET used_at = ?1 WHERE user_id = ?2 AND used_at IS NULL", vec![
+            created_at.as_str().into(),
+            user_id.into(),
+        ]),
+        ("
+            INSERT INTO password_resets (id, user_id, token, expires_at, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+        ", vec![
+            Uuid::new_v4().to_string().into(),
+            user_id.into(),
+            hash_token(&token).into(),
+            expires_at.into(),
+            created_at.as_str().into(),
+        ]),
+    ]).await?;
+    Ok(token)
+}
+
+/// Uses up `token` and returns the user it was issued to, or `None` if it is
+/// unknown, used or expired. The claim is conditional, so two requests racing
+/// with the same token can't both get the user.
+pub async fn redeem<D: Database>(db: &D, token: &str, now: DateTime<Utc>) -> StoreResult<Option<String>> {
+    let reset: Option<PasswordReset> = db
+        .query_first_as("SELECT * FROM password_resets WHERE token = ?1 AND used_at IS NULL", &[hash_token(token).into()])
+        .await?;
+    let Some(reset) = reset else {
+        return Ok(None);
+    };
+
+    let expired = DateTime::parse_from_rfc3339(&reset.expires_at)
+        .map(|expires_at| expires_at < now)
+        .unwrap_or(true);
+    if expired {
+        return Ok(None);
+    }
+
+    let claimed = db.execute(
+        "UPDATE password_resets SET used_at = ?1 WHERE id = ?2 AND used_at IS NULL",
+        &[now.to_rfc3339().into(), reset.id.as_str().into()],
+    ).await?;
+    Ok((claimed == 1).then_some(reset.user_id))
+}
+
+/// Kills every outstanding reset link for the user, e.g. once the password
+/// has changed.
+pub async fn invalidate<D: Database>(db: &D, user_id: &str, now: DateTime<Utc>) -> StoreResult<()> {
+    db.execute(
+        "UPDATE password_resets SET used_at = ?1 WHERE user_id = ?2 AND used_at IS NULL",
+        &[now.to_rfc3339().into(), user_id.into()],
+    ).await?;
+    Ok(())
+}
+
+/// How many reset links the user was sent in the hour before `now`.
+pub async fn count_recent<D: Database>(db: &D, user_id: &str, now: DateTime<Utc>) -> StoreResult<i64> {
+    let since = (now - Duration::hours(1)).to_rfc3339();
+    let row = db
+        .query_first("SELECT COUNT(*) as count FROM password_resets WHERE user_id = ?1 AND created_at > ?2", &[user_id.into(), since.into()])
+        .await?;
+    Ok(row.and_then(|row| row["count"].as_i64()).unwrap_or(0))
+}
@@ -0,0 +1,256 @@
+//! Sliding-window rate limiting.
+//!
+//! Each limit keeps one counter per fixed window; a request is judged against
+//! the current window's count plus the previous window's count weighted by
+//! how much of it still overlaps the sliding window. Counters live in D1 so
+//! every isolate sees the same numbers, and `MemoryCounterStore` stands in for
+//! tests. Subjects (IPs, email addresses) are hashed before they become keys.
+
+use crate::error::ApiError;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use worker::{D1Database, Method};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub name: &'static str,
+    pub limit: u64,
+    pub window_seconds: i64,
+}
+
+pub const SIGNUP_PER_IP: RateLimit = RateLimit { name: "signup-ip", limit: 5, window_seconds: 3600 };
+pub const LOGIN_PER_IP: RateLimit = RateLimit { name: "login-ip", limit: 30, window_seconds: 900 };
+// Tight per account and client. Per account alone only failures count, and
+// devices the owner has signed in from before are exempt, so guessing one
+// password from many addresses is slow without letting a stranger lock the
+// owner out
+pub const LOGIN_PER_ACCOUNT_AND_IP: RateLimit = RateLimit { name: "login-account-ip", limit: 5, window_seconds: 900 };
+pub const LOGIN_FAILURES_PER_ACCOUNT: RateLimit = RateLimit { name: "login-failures-account", limit: 50, window_seconds: 3600 };
+// Failed authentication or recovery codes, across every challenge the account
+// starts. Only someone past the password can spend these, so it can be tight
+pub const MFA_FAILURES_PER_ACCOUNT: RateLimit = RateLimit { name: "mfa-failures-account", limit: 10, window_seconds: 3600 };
+pub const FORGOT_PASSWORD_PER_IP: RateLimit = RateLimit { name: "forgot-password-ip", limit: 5, window_seconds: 3600 };
+pub const FORGOT_PASSWORD_PER_ACCOUNT: RateLimit = RateLimit { name: "forgot-password-account", limit: 5, window_seconds: 3600 };
+pub const MAGIC_LINK_PER_IP: RateLimit = RateLimit { name: "magic-link-ip", limit: 10, window_seconds: 3600 };
+pub const MAGIC_LINK_PER_ACCOUNT: RateLimit = RateLimit { name: "magic-link-account", limit: 3, window_seconds: 3600 };
+// Keyed by user id, not address, and only counted when an email goes out
+pub const VERIFICATION_EMAIL_PER_ACCOUNT: RateLimit = RateLimit { name: "verification-email-account", limit: 3, window_seconds: 3600 };
+pub const CSRF_TOKEN_PER_IP: RateLimit = RateLimit { name: "csrf-token-ip", limit: 60, window_seconds: 60 };
+/// Applied per client and route to every POST without a more specific limit.
+pub const WRITE_PER_IP_AND_ROUTE: RateLimit = RateLimit { name: "write-ip-route", limit: 60, window_seconds: 60 };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Decision {
+    Allowed,
+    Limited { retry_after: i64 },
+}
+
+/// Where window counters are kept.
+// Worker futures aren't Send, so there is nothing to gain from spelling out bounds
+#[allow(async_fn_in_trait)]
+pub trait CounterStore {
+    /// Records one hit in `key`'s window and returns the window's new count.
+    async fn increment(&self, key: &str, window_start: i64, expires_at: i64) -> Option<u64>;
+    /// The count recorded in `key`'s window, zero if there is none.
+    async fn count(&self, key: &str, window_start: i64) -> Option<u64>;
+}
+
+/// Counts a hit against `limit` for `subject` at unix time `now`.
+///
+/// A store failure counts as limited: these limits guard credentials, so they
+/// fail closed like the rest of the auth checks.
+pub async fn check<S: CounterStore>(store: &S, limit: &RateLimit, subject: &str, now: i64) -> Decision {
+    let window = Window::at(limit, now);
+    let key = counter_key(limit, subject);
+    let current = match window.increment(store, &key).await {
+        Some(count) => count,
+        None => return window.limited(),
+    };
+    window.judge(store, limit, &key, current, 0).await
+}
+
+/// Whether one more hit against `limit` for `subject` would be over it, without
+/// counting one. For limits that only count some outcomes, such as failed
+/// sign-ins; the outcomes are counted with `record`.
+pub async fn peek<S: CounterStore>(store: &S, limit: &RateLimit, subject: &str, now: i64) -> Decision {
+    let window = Window::at(limit, now);
+    let key = counter_key(limit, subject);
+    let current = match store.count(&key, window.start).await {
+        Some(count) => count,
+        None => return window.limited(),
+    };
+    window.judge(store, limit, &key, current, 1).await
+}
+
+/// Counts a hit against `limit` for `subject` without judging it.
+pub async fn record<S: CounterStore>(store: &S, limit: &RateLimit, subject: &str, now: i64) {
+    let window = Window::at(limit, now);
+    window.increment(store, &counter_key(limit, subject)).await;
+}
+
+/// The fixed window `now` falls in.
+struct Window {
+    length: i64,
+    start: i64,
+    elapsed: i64,
+}
+
+impl Window {
+    fn at(limit: &RateLimit, now: i64) -> Self {
+        let length = limit.window_seconds;
+        let start = now - now.rem_euclid(length);
+        Window { length, start, elapsed: now - start }
+    }
+
+    // Waiting out the current window is always enough for the estimate to drop
+    fn limited(&self) -> Decision {
+        Decision::Limited { retry_after: (self.length - self.elapsed).max(1) }
+    }
+
+    async fn increment<S: CounterStore>(&self, store: &S, key: &str) -> Option<u64> {
+        // Counters are kept for two windows so the next one can still weigh this one
+        store.increment(key, self.start, self.start + 2 * self.length).await
+    }
+
+    /// Judges the current window's count, plus `pending` hits not yet counted.
+    async fn judge<S: CounterStore>(&self, store: &S, limit: &RateLimit, key: &str, current: u64, pending: u64) -> Decision {
+        let previous = match store.count(key, self.start - self.length).await {
+            Some(count) => count,
+            None => return self.limited(),
+        };
+
+        let overlap = (self.length - self.elapsed) as f64 / self.length as f64;
+        let estimate = previous as f64 * overlap + (current + pending) as f64;
+
+        if estimate > limit.limit as f64 {
+            self.limited()
+        } else {
+            Decision::Allowed
+        }
+    }
+}
+
+/// Checks every `(limit, subject)` pair, counting a hit against each, and
+/// returns the longest wait among those that were exceeded.
+pub async fn check_all<S: CounterStore>(store: &S, checks: &[(RateLimit, String)], now: i64) -> Decision {
+    let mut decision = Decision::Allowed;
+    for (limit, subject) in checks {
+        if let Decision::Limited { retry_after } = check(store, limit, subject, now).await {
+            decision = match decision {
+                Decision::Limited { retry_after: longest } => Decision::Limited { retry_after: longest.max(retry_after) },
+                Decision::Allowed => Decision::Limited { retry_after },
+            };
+        }
+    }
+    decision
+}
+
+/// The client-keyed limits for a request, decided from its method and path
+/// alone so they can run before routing. Account-keyed limits need the request
+/// body and are checked by the handlers themselves.
+pub fn request_limits(method: &Method, path: &str, client_ip: &str) -> Vec<(RateLimit, String)> {
+    let limit = match (method, path) {
+        (Method::Post, "/api/auth/signup") => SIGNUP_PER_IP,
+        (Method::Post, "/api/auth/login") => LOGIN_PER_IP,
+        (Method::Post, "/api/auth/forgot-password") => FORGOT_PASSWORD_PER_IP,
+        (Method::Post, "/api/auth/magic-link") => MAGIC_LINK_PER_IP,
+        (Method::Get, "/api/csrf-token") => CSRF_TOKEN_PER_IP,
+        (Method::Post, path) => {
+            return vec![(WRITE_PER_IP_AND_ROUTE, format!("{} {}", client_ip, path))];
+        }
+        _ => return Vec::new(),
+    };
+
+    vec![(limit, client_ip.to_string())]
+}
+
+/// A 429 telling the client how many seconds to wait.
+pub fn too_many_requests(retry_after: i64) -> ApiError {
+    ApiError::TooManyRequests {
+        message: "Too many requests. Please try again later.".to_string(),
+        retry_after: Some(retry_after),
+    }
+}
+
+fn counter_key(limit: &RateLimit, subject: &str) -> String {
+    format!("{}:{:x}", limit.name, Sha256::digest(subject.to_lowercase().as_bytes()))
+}
+
+pub struct D1CounterStore<'a> {
+    db: &'a D1Database,
+}
+
+impl<'a> D1CounterStore<'a> {
+    pub fn new(db: &'a D1Database) -> Self {
+        D1CounterStore { db }
+    }
+
+    // Roughly one hit in a hundred sweeps out counters nobody will read again
+    async fn maybe_sweep(&self, now: i64) {
+        let mut byte = [0u8; 1];
+        if getrandom::getrandom(&mut byte).is_err() || byte[0] > 2 {
+            return;
+        }
+
+        let stmt = self.db.prepare("DELETE FROM rate_limit_counters WHERE expires_at < ?1");
+        if let Ok(stmt) = stmt.bind(&[(now as f64).into()]) {
+            let _ = stmt.run().await;
+        }
+    }
+}
+
+impl CounterStore for D1CounterStore<'_> {
+    async fn increment(&self, key: &str, window_start: i64, expires_at: i64) -> Option<u64> {
+        let stmt = self.db.prepare("
+            INSERT INTO rate_limit_counters (key, window_start, count, expires_at)
+            VALUES (?1, ?2, 1, ?3)
+            ON CONFLICT (key, window_start) DO UPDATE SET count = count + 1
+            RETURNING count
+        ");
+        let stmt = stmt.bind(&[
+            key.into(),
+            (window_start as f64).into(),
+            (expires_at as f64).into(),
+        ]).ok()?;
+        let result = stmt.first::<serde_json::Value>(None).await.ok()??;
+
+        self.maybe_sweep(window_start).await;
+        result["count"].as_u64()
+    }
+
+    async fn count(&self, key: &str, window_start: i64) -> Option<u64> {
+        let stmt = self.db.prepare("SELECT count FROM rate_limit_counters WHERE key = ?1 AND window_start = ?2");
+        let stmt = stmt.bind(&[key.into(), (window_start as f64).into()]).ok()?;
+
+        match stmt.first::<serde_json::Value>(None).await.ok()? {
+            Some(result) => result["count"].as_u64(),
+            None => Some(0),
+        }
+    }
+}
+
+/// Keeps counters in process memory; for tests and local experiments.
+#[derive(Default)]
+pub struct MemoryCounterStore {
+    counters: RefCell<HashMap<(String, i64), u64>>,
+}
+
+impl MemoryCounterStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl CounterStore for MemoryCounterStore {
+    async fn increment(&self, key: &str, window_start: i64, _expires_at: i64) -> Option<u64> {
+        let mut counters = self.counters.borrow_mut();
+        let count = counters.entry((key.to_string(), window_start)).or_insert(0);
+        *count += 1;
+        Some(*count)
+    }
+
+    async fn count(&self, key: &str, window_start: i64) -> Option<u64> {
+        Some(self.counters.borrow().get(&(key.to_string(), window_start)).copied().unwrap_or(0))
+    }
+}
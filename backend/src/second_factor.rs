@@ -0,0 +1,69 @@
+//! Checking the second factor of a sign-in.
+//!
+//! Each challenge allows only a few guesses, but anyone who knows the password
+//! can start another one, so failed codes are also counted per account against
+//! `rate_limit::MFA_FAILURES_PER_ACCOUNT`, whichever challenge they came from.
+
+use crate::handlers::mfa;
+use crate::rate_limit::{self, CounterStore, Decision};
+use crate::store::{Database, Store};
+use crate::token::hash_token;
+use crate::totp;
+use chrono::{DateTime, Utc};
+
+/// What the user offered as their second factor.
+#[derive(Debug, Clone, Copy)]
+pub enum SecondFactor<'a> {
+    Code(&'a str),
+    RecoveryCode(&'a str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Verified,
+    Rejected,
+    /// The account has used up its failed guesses; nothing was checked.
+    Limited { retry_after: i64 },
+}
+
+/// Checks `factor` for `user_id`, unless the account is out of guesses, and
+/// counts it against the account if it's wrong. A recovery code that matches
+/// is used up.
+pub async fn verify<S: Store, C: CounterStore>(
+    store: &S,
+    counters: &C,
+    user_id: &str,
+    factor: SecondFactor<'_>,
+    now: DateTime<Utc>,
+) -> Verification {
+    let limit = rate_limit::MFA_FAILURES_PER_ACCOUNT;
+    if let Decision::Limited { retry_after } = rate_limit::peek(counters, &limit, user_id, now.timestamp()).await {
+        return Verification::Limited { retry_after };
+    }
+
+    let verified = match factor {
+        SecondFactor::Code(code) => mfa::verify_current_code(store, user_id, code, now).await,
+        SecondFactor::RecoveryCode(code) => consume_recovery_code(store, user_id, code, now).await,
+    };
+
+    if verified {
+        Verification::Verified
+    } else {
+        rate_limit::record(counters, &limit, user_id, now.timestamp()).await;
+        Verification::Rejected
+    }
+}
+
+/// Marks the user's unused recovery code `code` as used, if there is one.
+pub async fn consume_recovery_code<D: Database>(db: &D, user_id: &str, code: &str, now: DateTime<Utc>) -> bool {
+    let changed = db.execute("
+        UPDATE mfa_recovery_codes SET used_at = ?1
+        WHERE user_id = ?2 AND code_hash = ?3 AND used_at IS NULL
+    ", &[
+        now.to_rfc3339().into(),
+        user_id.into(),
+        hash_token(&totp::normalize_recovery_code(code)).into(),
+    ]).await;
+
+    matches!(changed, Ok(1))
+}
@@ -0,0 +1,118 @@
+//! Who may create an account.
+//!
+//! `SIGNUP_MODE` picks one of three platform-wide policies:
+//!
+//! - `open`: anyone can sign up.
+//! - `domain`: only addresses in `SIGNUP_ALLOWED_DOMAINS` (comma-separated,
+//!   e.g. `uni.edu,alumni.uni.edu`) can sign up. Domains match exactly, so
+//!   `uni.edu` doesn't admit `cs.uni.edu` unless that is listed too.
+//! - `invite`: a platform invite is required.
+//!
+//! In `domain` mode an invite also admits an address from another domain, so
+//! admins can let in outside collaborators. Accounts can't sign in in that
+//! mode until their address is verified. An unrecognised mode is treated
+//! as `invite`, the most restrictive one that still lets admins add people.
+
+use worker::Env;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignupMode {
+    Open,
+    Domains(Vec<String>),
+    Invite,
+}
+
+impl SignupMode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SignupMode::Open => "open",
+            SignupMode::Domains(_) => "domain",
+            SignupMode::Invite => "invite",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SignupDecision {
+    Allowed,
+    /// Allowed only by redeeming the invite that came with the request.
+    NeedsInvite,
+    Denied,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignupPolicy {
+    pub mode: SignupMode,
+}
+
+impl SignupPolicy {
+    pub fn parse(mode: &str, allowed_domains: &str) -> Self {
+        let mode = match mode.trim().to_ascii_lowercase().as_str() {
+            "" | "open" => SignupMode::Open,
+            "domain" => SignupMode::Domains(
+                allowed_domains
+                    .split(',')
+                    .map(|domain| domain.trim().trim_start_matches('@').to_ascii_lowercase())
+                    .filter(|domain| !domain.is_empty())
+                    .collect(),
+            ),
+            _ => SignupMode::Invite,
+        };
+
+        SignupPolicy { mode }
+    }
+
+    /// Reads `SIGNUP_MODE` and `SIGNUP_ALLOWED_DOMAINS`; signup is open when
+    /// no mode is set.
+    pub fn from_env(env: &Env) -> Self {
+        let var = |name: &str| env.var(name).map(|v| v.to_string()).unwrap_or_default();
+        Self::parse(&var("SIGNUP_MODE"), &var("SIGNUP_ALLOWED_DOMAINS"))
+    }
+
+    /// Whether `email` may sign up, given whether the request came with an
+    /// invite token. The token itself is checked when it is redeemed.
+    pub fn decide(&self, email: &str, has_invite: bool) -> SignupDecision {
+        let allowed_without_invite = match &self.mode {
+            SignupMode::Open => true,
+            SignupMode::Domains(_) => self.email_domain_allowed(email),
+            SignupMode::Invite => false,
+        };
+
+        if allowed_without_invite {
+            SignupDecision::Allowed
+        } else if has_invite {
+            SignupDecision::NeedsInvite
+        } else {
+            SignupDecision::Denied
+        }
+    }
+
+    /// Whether `email`'s domain is on the allowlist; always false outside
+    /// `domain` mode.
+    pub fn email_domain_allowed(&self, email: &str) -> bool {
+        let SignupMode::Domains(domains) = &self.mode else {
+            return false;
+        };
+        let Some((_, domain)) = email.trim().rsplit_once('@') else {
+            return false;
+        };
+
+        let domain = domain.to_ascii_lowercase();
+        domains.contains(&domain)
+    }
+
+    /// Whether sign-in waits for the email address to be verified, whatever
+    /// `UNVERIFIED_LOGIN_POLICY` says. In `domain` mode the address is what
+    /// let the account in, so it has to be proven before the account is used.
+    pub fn requires_verified_email(&self) -> bool {
+        matches!(self.mode, SignupMode::Domains(_))
+    }
+
+    /// What the signup page needs to know to show the right form.
+    pub fn summary(&self) -> serde_json::Value {
+        match &self.mode {
+            SignupMode::Domains(domains) => serde_json::json!({ "mode": self.mode.as_str(), "allowed_domains": domains }),
+            _ => serde_json::json!({ "mode": self.mode.as_str() }),
+        }
+    }
+}
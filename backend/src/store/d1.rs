@@ -0,0 +1,61 @@
+use super::{Database, SqlValue, Statement, StoreError, StoreResult};
+use serde_json::Value;
+use worker::wasm_bindgen::JsValue;
+use worker::{D1Database, D1PreparedStatement};
+
+/// The Worker's `DB` binding.
+pub struct D1Store<'a> {
+    db: &'a D1Database,
+}
+
+impl<'a> D1Store<'a> {
+    pub fn new(db: &'a D1Database) -> Self {
+        D1Store { db }
+    }
+
+    fn prepare(&self, sql: &str, params: &[SqlValue]) -> StoreResult<D1PreparedStatement> {
+        let params: Vec<JsValue> = params.iter().map(to_js).collect();
+        self.db.prepare(sql).bind(&params).map_err(store_error)
+    }
+}
+
+impl Database for D1Store<'_> {
+    async fn query(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Vec<Value>> {
+        let result = self.prepare(sql, params)?.all().await.map_err(store_error)?;
+        result.results::<Value>().map_err(store_error)
+    }
+
+    async fn execute(&self, sql: &str, params: &[SqlValue]) -> StoreResult<u64> {
+        let result = self.prepare(sql, params)?.run().await.map_err(store_error)?;
+        let changes = result.meta().map_err(store_error)?.and_then(|meta| meta.changes);
+        Ok(changes.unwrap_or(0) as u64)
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> StoreResult<()> {
+        let statements = statements
+            .iter()
+            .map(|(sql, params)| self.prepare(sql, params))
+            .collect::<StoreResult<Vec<_>>>()?;
+
+        // D1 runs a batch as one transaction and rolls it back if a statement fails
+        let results = self.db.batch(statements).await.map_err(store_error)?;
+        match results.iter().find(|result| !result.success()) {
+            Some(failed) => Err(StoreError(failed.error().unwrap_or_else(|| "batch failed".to_string()))),
+            None => Ok(()),
+        }
+    }
+}
+
+// D1 only takes JavaScript numbers, so integers go over as f64
+fn to_js(value: &SqlValue) -> JsValue {
+    match value {
+        SqlValue::Null => JsValue::NULL,
+        SqlValue::Integer(value) => (*value as f64).into(),
+        SqlValue::Real(value) => (*value).into(),
+        SqlValue::Text(value) => value.as_str().into(),
+    }
+}
+
+fn store_error(error: worker::Error) -> StoreError {
+    StoreError(error.to_string())
+}
@@ -0,0 +1,594 @@
+//! Storage behind the handlers.
+//!
+//! `Database` is all a backend has to provide: run a query, run a write, run
+//! several writes atomically. `Store` is the repository built on top of it, so
+//! the SQL for users, sessions, clubs and the rest is written once and runs
+//! unchanged on D1 in the Worker (`D1Store`) and on SQLite in native tests
+//! (`SqliteStore`, built from the migrations). Rows come back as JSON objects,
+//! the shape D1 hands them over in, and `row::FromRow` decodes them into
+//! models. Natively, `WranglerD1` reaches a D1 database through the wrangler
+//! CLI so the migration runner can apply migrations to it.
+
+pub mod d1;
+pub mod row;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sqlite;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod wrangler;
+
+pub use d1::D1Store;
+pub use row::{DecodeError, FromColumn, FromRow, Row};
+#[cfg(not(target_arch = "wasm32"))]
+pub use sqlite::SqliteStore;
+#[cfg(not(target_arch = "wasm32"))]
+pub use wrangler::WranglerD1;
+
+use crate::models::*;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::fmt;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SqlValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+}
+
+impl From<&str> for SqlValue {
+    fn from(value: &str) -> Self {
+        SqlValue::Text(value.to_string())
+    }
+}
+
+impl From<String> for SqlValue {
+    fn from(value: String) -> Self {
+        SqlValue::Text(value)
+    }
+}
+
+impl From<&String> for SqlValue {
+    fn from(value: &String) -> Self {
+        SqlValue::Text(value.clone())
+    }
+}
+
+impl From<i64> for SqlValue {
+    fn from(value: i64) -> Self {
+        SqlValue::Integer(value)
+    }
+}
+
+impl From<f64> for SqlValue {
+    fn from(value: f64) -> Self {
+        SqlValue::Real(value)
+    }
+}
+
+// SQLite has no boolean type; the schema stores flags as 0 or 1
+impl From<bool> for SqlValue {
+    fn from(value: bool) -> Self {
+        SqlValue::Integer(value as i64)
+    }
+}
+
+impl<T: Into<SqlValue>> From<Option<T>> for SqlValue {
+    fn from(value: Option<T>) -> Self {
+        value.map(Into::into).unwrap_or(SqlValue::Null)
+    }
+}
+
+impl SqlValue {
+    /// The value written out as an SQL literal, for statements that can't
+    /// take bound parameters.
+    pub fn to_literal(&self) -> String {
+        match self {
+            SqlValue::Null => "NULL".to_string(),
+            SqlValue::Integer(value) => value.to_string(),
+            // SQLite stores NaN as NULL and reads 9e999 as infinity
+            SqlValue::Real(value) if value.is_nan() => "NULL".to_string(),
+            SqlValue::Real(value) if value.is_infinite() => if *value > 0.0 { "9e999" } else { "-9e999" }.to_string(),
+            SqlValue::Real(value) => format!("{:?}", value),
+            SqlValue::Text(value) => format!("'{}'", value.replace('\'', "''")),
+        }
+    }
+}
+
+/// Writes `params` into `sql` as literals in place of its `?NNN` and `?`
+/// placeholders, leaving string literals, quoted identifiers and comments
+/// alone.
+pub fn inline_params(sql: &str, params: &[SqlValue]) -> StoreResult<String> {
+    let mut output = String::with_capacity(sql.len());
+    let mut chars = sql.chars().peekable();
+    let mut last_index = 0;
+
+    while let Some(c) = chars.next() {
+        output.push(c);
+        match c {
+            '\'' | '"' | '`' | '[' => {
+                let close = if c == '[' { ']' } else { c };
+                for c in chars.by_ref() {
+                    output.push(c);
+                    if c == close {
+                        break;
+                    }
+                }
+            }
+            '-' if chars.peek() == Some(&'-') => {
+                for c in chars.by_ref() {
+                    output.push(c);
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'*') => {
+                let mut previous = ' ';
+                for c in chars.by_ref() {
+                    output.push(c);
+                    if previous == '*' && c == '/' {
+                        break;
+                    }
+                    previous = c;
+                }
+            }
+            '?' => {
+                output.pop();
+                let mut digits = String::new();
+                while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+                    digits.push(digit);
+                }
+                // A bare ? takes the parameter after the last one used, as in SQLite
+                let index = match digits.parse::<usize>() {
+                    Ok(index) => index,
+                    Err(_) => last_index + 1,
+                };
+                let value = index.checked_sub(1).and_then(|i| params.get(i)).ok_or_else(|| {
+                    StoreError(format!("no value for parameter ?{} ({} given)", index, params.len()))
+                })?;
+                output.push_str(&value.to_literal());
+                last_index = index;
+            }
+            _ => {}
+        }
+    }
+    Ok(output)
+}
+
+/// Runs a native backend's future to completion. SQLite and wrangler do
+/// their work synchronously, so it is ready on the first poll.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    use std::task::{Context, Poll, Waker};
+
+    let mut context = Context::from_waker(Waker::noop());
+    match std::pin::pin!(future).poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("native store futures should never be pending"),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoreError(pub String);
+
+impl StoreError {
+    /// Whether a write broke a UNIQUE constraint. SQLite and D1 both report it
+    /// with SQLite's own message.
+    pub fn is_unique_violation(&self) -> bool {
+        self.0.contains("UNIQUE constraint failed")
+    }
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "store error: {}", self.0)
+    }
+}
+
+impl std::error::Error for StoreError {}
+
+impl From<StoreError> for worker::Error {
+    fn from(error: StoreError) -> Self {
+        worker::Error::RustError(error.to_string())
+    }
+}
+
+pub type StoreResult<T> = std::result::Result<T, StoreError>;
+
+/// A statement and its parameters, for `Database::batch`.
+pub type Statement = (&'static str, Vec<SqlValue>);
+
+/// The primitive operations a database backend provides.
+// Worker futures aren't Send, so there is nothing to gain from spelling out bounds
+#[allow(async_fn_in_trait)]
+pub trait Database {
+    /// Runs a query and returns every row as a JSON object keyed by column.
+    async fn query(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Vec<Value>>;
+    /// Runs a write and returns how many rows it changed.
+    async fn execute(&self, sql: &str, params: &[SqlValue]) -> StoreResult<u64>;
+    /// Runs every statement in one transaction: all of them apply or none do.
+    async fn batch(&self, statements: Vec<Statement>) -> StoreResult<()>;
+
+    async fn query_first(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Option<Value>> {
+        Ok(self.query(sql, params).await?.into_iter().next())
+    }
+
+    /// Runs a query and decodes every row; one bad row fails the whole query.
+    async fn query_as<T: FromRow>(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Vec<T>> {
+        self.query(sql, params).await?.iter().map(decode).collect()
+    }
+
+    async fn query_first_as<T: FromRow>(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Option<T>> {
+        self.query_first(sql, params).await?.as_ref().map(decode).transpose()
+    }
+}
+
+/// Typed access to the tables the handlers share. Every method is provided on
+/// top of `Database`, so a backend gets the whole repository for free.
+#[allow(async_fn_in_trait)]
+pub trait Store: Database {
+    // Users
+
+    async fn user_by_id(&self, user_id: &str) -> StoreResult<Option<AuthUser>> {
+        self.query_first_as("SELECT * FROM users WHERE id = ?1", &[user_id.into()]).await
+    }
+
+    async fn user_by_email(&self, email: &str) -> StoreResult<Option<AuthUser>> {
+        self.query_first_as("SELECT * FROM users WHERE email = ?1", &[email.into()]).await
+    }
+
+    async fn user_exists(&self, email: &str) -> StoreResult<bool> {
+        let row = self.query_first("SELECT COUNT(*) as count FROM users WHERE email = ?1", &[email.into()]).await?;
+        Ok(count(row.as_ref()) > 0)
+    }
+
+    async fn create_user(&self, user: &AuthUser) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO users (id, email, password_hash, name, avatar, created_at, updated_at, email_verified, is_active, failed_login_attempts)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)
+        ", &[
+            user.id.as_str().into(),
+            user.email.as_str().into(),
+            user.password_hash.as_str().into(),
+            user.name.as_str().into(),
+            user.avatar.as_deref().into(),
+            user.created_at.as_str().into(),
+            user.updated_at.as_str().into(),
+            user.email_verified.into(),
+            user.is_active.into(),
+            (user.failed_login_attempts as i64).into(),
+        ]).await?;
+        Ok(())
+    }
+
+    /// Whether the user has completed two-factor enrollment.
+    async fn user_has_mfa(&self, user_id: &str) -> StoreResult<bool> {
+        let row = self.query_first("SELECT enabled FROM user_mfa WHERE user_id = ?1", &[user_id.into()]).await?;
+        Ok(row.is_some_and(|row| row["enabled"].as_i64() == Some(1)))
+    }
+
+    async fn user_mfa(&self, user_id: &str) -> StoreResult<Option<UserMfa>> {
+        self.query_first_as("SELECT * FROM user_mfa WHERE user_id = ?1", &[user_id.into()]).await
+    }
+
+    /// Records the step a code was accepted for so the same code can't be
+    /// replayed. Conditional on the step moving forward, so two requests racing
+    /// with one code can't both succeed.
+    async fn record_totp_step(&self, user_id: &str, step: i64) -> StoreResult<bool> {
+        let changed = self.execute("
+            UPDATE user_mfa SET last_used_step = ?1
+            WHERE user_id = ?2 AND (last_used_step IS NULL OR last_used_step < ?1)
+        ", &[step.into(), user_id.into()]).await?;
+        Ok(changed == 1)
+    }
+
+    // Sessions
+
+    /// Stores a session; `session.token` must already be hashed.
+    async fn create_session(&self, session: &Session) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO sessions (id, user_id, token, expires_at, created_at, last_accessed, user_agent, ip_address, is_active)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)
+        ", &[
+            session.id.as_str().into(),
+            session.user_id.as_str().into(),
+            session.token.as_str().into(),
+            session.expires_at.as_str().into(),
+            session.created_at.as_str().into(),
+            session.last_accessed.as_str().into(),
+            session.user_agent.as_deref().into(),
+            session.ip_address.as_deref().into(),
+            session.is_active.into(),
+        ]).await?;
+        Ok(())
+    }
+
+    /// The session, if it belongs to `user_id` and is neither revoked nor
+    /// expired at `now`.
+    async fn active_session(&self, session_id: &str, user_id: &str, now: DateTime<Utc>) -> StoreResult<Option<Session>> {
+        let session: Option<Session> = self.query_first_as("SELECT * FROM sessions WHERE id = ?1", &[session_id.into()]).await?;
+        let session = session.filter(|session| {
+            session.is_active
+                && session.user_id == user_id
+                && DateTime::parse_from_rfc3339(&session.expires_at).is_ok_and(|expires_at| expires_at > now)
+        });
+        Ok(session)
+    }
+
+    /// The user's live sessions, most recently used first.
+    async fn active_sessions(&self, user_id: &str, now: DateTime<Utc>) -> StoreResult<Vec<Session>> {
+        self.query_as("
+            SELECT * FROM sessions
+            WHERE user_id = ?1 AND is_active = 1 AND expires_at > ?2
+            ORDER BY last_accessed DESC
+        ", &[user_id.into(), now.to_rfc3339().into()]).await
+    }
+
+    async fn touch_session(&self, session_id: &str, now: &str) -> StoreResult<()> {
+        self.execute("UPDATE sessions SET last_accessed = ?1 WHERE id = ?2", &[now.into(), session_id.into()]).await?;
+        Ok(())
+    }
+
+    async fn revoke_session(&self, session_id: &str, user_id: &str) -> StoreResult<()> {
+        self.execute("UPDATE sessions SET is_active = 0 WHERE id = ?1 AND user_id = ?2", &[session_id.into(), user_id.into()]).await?;
+        Ok(())
+    }
+
+    async fn revoke_user_sessions(&self, user_id: &str) -> StoreResult<()> {
+        self.execute("UPDATE sessions SET is_active = 0 WHERE user_id = ?1 AND is_active = 1", &[user_id.into()]).await?;
+        Ok(())
+    }
+
+    // Clubs
+
+    async fn clubs(&self) -> StoreResult<Vec<Club>> {
+        self.query_as("
+            SELECT id, name, description, avatar, created_at, updated_at, owner_id, require_admin_mfa
+            FROM clubs ORDER BY created_at DESC
+        ", &[]).await
+    }
+
+    async fn club(&self, club_id: &str) -> StoreResult<Option<Club>> {
+        self.query_first_as("
+            SELECT id, name, description, avatar, created_at, updated_at, owner_id, require_admin_mfa
+            FROM clubs WHERE id = ?1
+        ", &[club_id.into()]).await
+    }
+
+    /// Creates the club with its owner as its first admin.
+    async fn create_club(&self, club: &Club) -> StoreResult<()> {
+        self.batch(vec![
+            ("
+                INSERT INTO clubs (id, name, description, avatar, created_at, updated_at, owner_id, require_admin_mfa)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+            ", vec![
+                club.id.as_str().into(),
+                club.name.as_str().into(),
+                club.description.as_deref().into(),
+                club.avatar.as_deref().into(),
+                club.created_at.as_str().into(),
+                club.updated_at.as_str().into(),
+                club.owner_id.as_str().into(),
+                club.require_admin_mfa.into(),
+            ]),
+            ("
+                INSERT INTO members (id, user_id, club_id, role, joined_at)
+                VALUES (?1, ?2, ?3, 'admin', ?4)
+            ", vec![
+                Uuid::new_v4().to_string().into(),
+                club.owner_id.as_str().into(),
+                club.id.as_str().into(),
+                club.created_at.as_str().into(),
+            ]),
+        ]).await
+    }
+
+    async fn set_require_admin_mfa(&self, club_id: &str, required: bool, now: &str) -> StoreResult<()> {
+        self.execute(
+            "UPDATE clubs SET require_admin_mfa = ?1, updated_at = ?2 WHERE id = ?3",
+            &[required.into(), now.into(), club_id.into()],
+        ).await?;
+        Ok(())
+    }
+
+    /// Hands the club to `new_owner_id`, who is made an admin as well. Nothing
+    /// changes unless `owner_id` still owns the club.
+    async fn transfer_club(&self, club_id: &str, owner_id: &str, new_owner_id: &str, now: &str) -> StoreResult<()> {
+        self.batch(vec![
+            (
+                "UPDATE clubs SET owner_id = ?1, updated_at = ?2 WHERE id = ?3 AND owner_id = ?4",
+                vec![new_owner_id.into(), now.into(), club_id.into(), owner_id.into()],
+            ),
+            (
+                "UPDATE members SET role = 'admin' WHERE club_id = ?1 AND user_id = ?2
+                 AND EXISTS (SELECT 1 FROM clubs WHERE id = ?1 AND owner_id = ?2)",
+                vec![club_id.into(), new_owner_id.into()],
+            ),
+        ]).await
+    }
+
+    // Members
+
+    async fn member(&self, club_id: &str, user_id: &str) -> StoreResult<Option<Member>> {
+        self.query_first_as(&format!("{} WHERE m.club_id = ?1 AND m.user_id = ?2", MEMBER_SELECT), &[club_id.into(), user_id.into()]).await
+    }
+
+    async fn club_members(&self, club_id: &str) -> StoreResult<Vec<Member>> {
+        self.query_as(&format!("{} WHERE m.club_id = ?1 ORDER BY m.joined_at ASC", MEMBER_SELECT), &[club_id.into()]).await
+    }
+
+    async fn add_member(&self, member: &Member) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO members (id, user_id, club_id, role, joined_at)
+            VALUES (?1, ?2, ?3, ?4, ?5)
+        ", &[
+            member.id.as_str().into(),
+            member.user_id.as_str().into(),
+            member.club_id.as_str().into(),
+            member.role.as_str().into(),
+            member.joined_at.as_str().into(),
+        ]).await?;
+        Ok(())
+    }
+
+    // Events
+
+    async fn club_events(&self, club_id: &str) -> StoreResult<Vec<Event>> {
+        self.query_as("
+            SELECT id, club_id, title, description, date, location, created_by, created_at
+            FROM events WHERE club_id = ?1 ORDER BY date ASC
+        ", &[club_id.into()]).await
+    }
+
+    async fn create_event(&self, event: &Event) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO events (id, club_id, title, description, date, location, created_by, created_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ", &[
+            event.id.as_str().into(),
+            event.club_id.as_str().into(),
+            event.title.as_str().into(),
+            event.description.as_str().into(),
+            event.date.as_str().into(),
+            event.location.as_deref().into(),
+            event.created_by.as_str().into(),
+            event.created_at.as_str().into(),
+        ]).await?;
+        Ok(())
+    }
+
+    // Announcements
+
+    /// Pinned announcements first, then newest first.
+    async fn club_announcements(&self, club_id: &str) -> StoreResult<Vec<Announcement>> {
+        self.query_as("
+            SELECT id, club_id, title, content, created_by, created_at, pinned
+            FROM announcements WHERE club_id = ?1 ORDER BY pinned DESC, created_at DESC
+        ", &[club_id.into()]).await
+    }
+
+    async fn create_announcement(&self, announcement: &Announcement) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO announcements (id, club_id, title, content, created_by, created_at, pinned)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ", &[
+            announcement.id.as_str().into(),
+            announcement.club_id.as_str().into(),
+            announcement.title.as_str().into(),
+            announcement.content.as_str().into(),
+            announcement.created_by.as_str().into(),
+            announcement.created_at.as_str().into(),
+            announcement.pinned.into(),
+        ]).await?;
+        Ok(())
+    }
+
+    /// `(email, club name)` for every active member except the author.
+    async fn announcement_recipients(&self, club_id: &str, author_id: &str) -> StoreResult<Vec<(String, String)>> {
+        let rows = self.query("
+            SELECT u.email, c.name as club_name
+            FROM members m
+            INNER JOIN users u ON m.user_id = u.id
+            INNER JOIN clubs c ON m.club_id = c.id
+            WHERE m.club_id = ?1 AND m.user_id != ?2 AND u.is_active = 1
+        ", &[club_id.into(), author_id.into()]).await?;
+
+        rows.iter()
+            .map(|row| {
+                let row = Row::new(row);
+                Ok((row.get("email")?, row.get("club_name")?))
+            })
+            .collect::<Result<_, DecodeError>>()
+            .map_err(|error| StoreError(format!("cannot decode announcement recipient: {}", error)))
+    }
+
+    // Projects
+
+    async fn club_projects(&self, club_id: &str) -> StoreResult<Vec<Project>> {
+        self.query_as("
+            SELECT id, club_id, name, description, status, created_by, created_at, updated_at
+            FROM projects WHERE club_id = ?1 ORDER BY created_at DESC
+        ", &[club_id.into()]).await
+    }
+
+    async fn create_project(&self, project: &Project) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO projects (id, club_id, name, description, status, created_by, created_at, updated_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)
+        ", &[
+            project.id.as_str().into(),
+            project.club_id.as_str().into(),
+            project.name.as_str().into(),
+            project.description.as_str().into(),
+            project.status.as_str().into(),
+            project.created_by.as_str().into(),
+            project.created_at.as_str().into(),
+            project.updated_at.as_str().into(),
+        ]).await?;
+        Ok(())
+    }
+
+    // Club invite codes
+
+    async fn invite_code(&self, code: &str) -> StoreResult<Option<InviteCode>> {
+        self.query_first_as("SELECT * FROM invite_codes WHERE code = ?1", &[code.into()]).await
+    }
+
+    async fn create_invite_code(&self, invite: &InviteCode) -> StoreResult<()> {
+        self.execute("
+            INSERT INTO invite_codes (code, club_id, created_by, expires_at, used_by, used_at)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+        ", &[
+            invite.code.as_str().into(),
+            invite.club_id.as_str().into(),
+            invite.created_by.as_str().into(),
+            invite.expires_at.as_str().into(),
+            invite.used_by.as_deref().into(),
+            invite.used_at.as_deref().into(),
+        ]).await?;
+        Ok(())
+    }
+
+    /// Marks an unused, unexpired code as used by `user_id`. Single use: of two
+    /// joins racing with the same code, only one gets `true`.
+    async fn redeem_invite_code(&self, code: &str, user_id: &str, now: &str) -> StoreResult<bool> {
+        let changes = self.execute("
+            UPDATE invite_codes SET used_by = ?1, used_at = ?2
+            WHERE code = ?3 AND used_by IS NULL AND expires_at > ?2
+        ", &[user_id.into(), now.into(), code.into()]).await?;
+        Ok(changes == 1)
+    }
+
+    /// Puts back a code redeemed by a join that then failed.
+    async fn release_invite_code(&self, code: &str, user_id: &str) -> StoreResult<()> {
+        self.execute(
+            "UPDATE invite_codes SET used_by = NULL, used_at = NULL WHERE code = ?1 AND used_by = ?2",
+            &[code.into(), user_id.into()],
+        ).await?;
+        Ok(())
+    }
+}
+
+impl<D: Database> Store for D {}
+
+const MEMBER_SELECT: &str = "
+    SELECT
+        m.id, m.user_id, m.club_id, m.role, m.joined_at,
+        u.email, u.name, u.avatar, u.created_at as user_created_at,
+        u.updated_at as user_updated_at, u.email_verified, u.is_active
+    FROM members m
+    INNER JOIN users u ON m.user_id = u.id
+";
+
+fn count(row: Option<&Value>) -> u64 {
+    row.and_then(|row| row["count"].as_u64()).unwrap_or(0)
+}
+
+fn decode<T: FromRow>(row: &Value) -> StoreResult<T> {
+    T::decode(row).map_err(|error| {
+        let model = std::any::type_name::<T>().rsplit("::").next().unwrap_or("row");
+        StoreError(format!("cannot decode {}: {}", model, error))
+    })
+}
@@ -0,0 +1,135 @@
+//! Typed decoding of result rows.
+//!
+//! Both backends hand rows over as JSON objects keyed by column name. `Row`
+//! reads one column at a time into anything that implements `FromColumn`, and
+//! a model implements `FromRow` by reading its columns in turn. A column that
+//! is missing, NULL where a value is required, or of the wrong type fails the
+//! whole row with an error naming the column, rather than the row quietly
+//! disappearing or coming back with blanks.
+
+use serde_json::Value;
+use std::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodeError {
+    pub column: String,
+    pub problem: String,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column `{}`: {}", self.column, self.problem)
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub struct Row<'a> {
+    value: &'a Value,
+}
+
+impl<'a> Row<'a> {
+    pub fn new(value: &'a Value) -> Self {
+        Row { value }
+    }
+
+    /// Reads `column`. A column the query didn't select is always an error,
+    /// even when decoding into an `Option`.
+    pub fn get<T: FromColumn>(&self, column: &str) -> Result<T, DecodeError> {
+        let value = self.value.get(column).ok_or_else(|| DecodeError {
+            column: column.to_string(),
+            problem: "not in the result row".to_string(),
+        })?;
+
+        T::from_column(value).map_err(|problem| DecodeError {
+            column: column.to_string(),
+            problem,
+        })
+    }
+}
+
+/// A model that can be read from a result row.
+pub trait FromRow: Sized {
+    fn from_row(row: &Row) -> Result<Self, DecodeError>;
+
+    fn decode(value: &Value) -> Result<Self, DecodeError> {
+        Self::from_row(&Row::new(value))
+    }
+}
+
+/// A value that can be read from a single column. Errors describe the
+/// problem; `Row::get` adds the column name.
+pub trait FromColumn: Sized {
+    fn from_column(value: &Value) -> Result<Self, String>;
+}
+
+impl<T: FromColumn> FromColumn for Option<T> {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::Null => Ok(None),
+            value => T::from_column(value).map(Some),
+        }
+    }
+}
+
+impl FromColumn for String {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        match value {
+            Value::String(value) => Ok(value.clone()),
+            value => Err(unexpected("text", value)),
+        }
+    }
+}
+
+impl FromColumn for i64 {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        // D1 hands every number over as a JavaScript number, so whole floats
+        // count as integers
+        let integer = value.as_i64().or_else(|| {
+            value.as_f64().filter(|n| n.fract() == 0.0 && n.abs() < i64::MAX as f64).map(|n| n as i64)
+        });
+        integer.ok_or_else(|| unexpected("an integer", value))
+    }
+}
+
+impl FromColumn for u32 {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        let integer = i64::from_column(value)?;
+        u32::try_from(integer).map_err(|_| format!("{} is out of range", integer))
+    }
+}
+
+impl FromColumn for f64 {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        value.as_f64().ok_or_else(|| unexpected("a number", value))
+    }
+}
+
+/// SQLite has no boolean type; the schema stores flags as 0 or 1.
+impl FromColumn for bool {
+    fn from_column(value: &Value) -> Result<Self, String> {
+        if let Value::Bool(flag) = value {
+            return Ok(*flag);
+        }
+        match i64::from_column(value) {
+            Ok(0) => Ok(false),
+            Ok(1) => Ok(true),
+            _ => Err(unexpected("0 or 1", value)),
+        }
+    }
+}
+
+/// Reads a text column that holds one of a fixed set of values.
+pub fn parse_text<T>(value: &Value, parse: impl Fn(&str) -> Option<T>, expected: &[&str]) -> Result<T, String> {
+    let text = String::from_column(value)?;
+    parse(&text).ok_or_else(|| format!("expected one of {}, found {:?}", expected.join(", "), text))
+}
+
+fn unexpected(expected: &str, found: &Value) -> String {
+    let found = match found {
+        Value::Null => "NULL".to_string(),
+        Value::String(text) => format!("text {:?}", text),
+        other => other.to_string(),
+    };
+    format!("expected {}, found {}", expected, found)
+}
@@ -0,0 +1,101 @@
+use super::{block_on, Database, SqlValue, Statement, StoreError, StoreResult};
+use crate::migrations::{self, MigrationTarget};
+use chrono::Utc;
+use rusqlite::types::{ToSqlOutput, ValueRef};
+use rusqlite::{params_from_iter, Connection, ToSql};
+use serde_json::{Map, Value};
+use std::cell::RefCell;
+use std::path::Path;
+
+/// An SQLite database for native tests and local experiments. Foreign keys
+/// are enforced, as they are on D1.
+pub struct SqliteStore {
+    conn: RefCell<Connection>,
+}
+
+impl SqliteStore {
+    /// Opens (or creates) the database at `path` as it is, without migrating it.
+    pub fn open(path: impl AsRef<Path>) -> StoreResult<Self> {
+        let conn = Connection::open(path).map_err(store_error)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;").map_err(store_error)?;
+        Ok(SqliteStore { conn: RefCell::new(conn) })
+    }
+
+    /// A fresh in-memory database with every migration applied.
+    pub fn open_in_memory() -> StoreResult<Self> {
+        let store = SqliteStore::open(":memory:")?;
+        block_on(migrations::migrate(&store, &migrations::all(), Utc::now()))
+            .map_err(|error| StoreError(error.to_string()))?;
+        Ok(store)
+    }
+}
+
+impl Database for SqliteStore {
+    async fn query(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Vec<Value>> {
+        let conn = self.conn.borrow();
+        let mut stmt = conn.prepare(sql).map_err(store_error)?;
+        let columns: Vec<String> = stmt.column_names().into_iter().map(String::from).collect();
+
+        let mut rows = stmt.query(params_from_iter(params)).map_err(store_error)?;
+        let mut results = Vec::new();
+        while let Some(row) = rows.next().map_err(store_error)? {
+            let mut object = Map::new();
+            for (index, column) in columns.iter().enumerate() {
+                let value = row.get_ref(index).map_err(store_error)?;
+                object.insert(column.clone(), to_json(value));
+            }
+            results.push(Value::Object(object));
+        }
+        Ok(results)
+    }
+
+    async fn execute(&self, sql: &str, params: &[SqlValue]) -> StoreResult<u64> {
+        let conn = self.conn.borrow();
+        let changes = conn.execute(sql, params_from_iter(params)).map_err(store_error)?;
+        Ok(changes as u64)
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> StoreResult<()> {
+        let mut conn = self.conn.borrow_mut();
+        let tx = conn.transaction().map_err(store_error)?;
+        for (sql, params) in &statements {
+            tx.execute(sql, params_from_iter(params)).map_err(store_error)?;
+        }
+        tx.commit().map_err(store_error)
+    }
+}
+
+impl MigrationTarget for SqliteStore {
+    async fn execute_script(&self, sql: &str) -> StoreResult<()> {
+        let mut conn = self.conn.borrow_mut();
+        let tx = conn.transaction().map_err(store_error)?;
+        tx.execute_batch(sql).map_err(store_error)?;
+        tx.commit().map_err(store_error)
+    }
+}
+
+impl ToSql for SqlValue {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            SqlValue::Null => ToSqlOutput::Borrowed(ValueRef::Null),
+            SqlValue::Integer(value) => ToSqlOutput::Borrowed(ValueRef::Integer(*value)),
+            SqlValue::Real(value) => ToSqlOutput::Borrowed(ValueRef::Real(*value)),
+            SqlValue::Text(value) => ToSqlOutput::Borrowed(ValueRef::Text(value.as_bytes())),
+        })
+    }
+}
+
+// Mirrors what D1 returns: numbers as numbers, text as strings. The schema has
+// no blob columns.
+fn to_json(value: ValueRef<'_>) -> Value {
+    match value {
+        ValueRef::Null | ValueRef::Blob(_) => Value::Null,
+        ValueRef::Integer(value) => value.into(),
+        ValueRef::Real(value) => value.into(),
+        ValueRef::Text(value) => String::from_utf8_lossy(value).into_owned().into(),
+    }
+}
+
+fn store_error(error: rusqlite::Error) -> StoreError {
+    StoreError(error.to_string())
+}
@@ -0,0 +1,82 @@
+use super::{inline_params, Database, SqlValue, Statement, StoreError, StoreResult};
+use crate::migrations::MigrationTarget;
+use serde_json::Value;
+use std::process::Command;
+
+/// A D1 database reached through `wrangler d1 execute`, for running
+/// migrations from the command line. Wrangler can't bind parameters, so they
+/// are written into the SQL as literals. It reads `wrangler.toml` from the
+/// current directory.
+pub struct WranglerD1 {
+    /// Database name or binding, as `wrangler d1 execute` takes it.
+    pub database: String,
+    /// The deployed database rather than the local one `wrangler dev` uses.
+    pub remote: bool,
+    /// A named environment from `wrangler.toml`.
+    pub env: Option<String>,
+}
+
+impl WranglerD1 {
+    /// Runs `sql` and returns the result of each statement in it.
+    fn run(&self, sql: &str) -> StoreResult<Vec<Value>> {
+        let mut command = Command::new("wrangler");
+        command
+            .args(["d1", "execute", &self.database, "--json", "--command", sql])
+            .arg(if self.remote { "--remote" } else { "--local" });
+        if let Some(env) = &self.env {
+            command.args(["--env", env]);
+        }
+
+        let output = command
+            .output()
+            .map_err(|error| StoreError(format!("cannot run wrangler: {}", error)))?;
+        let stdout: Option<Value> = serde_json::from_slice(&output.stdout).ok();
+
+        if !output.status.success() {
+            // With --json, wrangler reports errors on stdout as {"error": {"text": ...}}
+            let message = stdout
+                .as_ref()
+                .and_then(|stdout| stdout["error"]["text"].as_str().map(str::to_string))
+                .unwrap_or_else(|| String::from_utf8_lossy(&output.stderr).trim().to_string());
+            return Err(StoreError(format!("wrangler: {}", message)));
+        }
+
+        match stdout {
+            Some(Value::Array(results)) => Ok(results),
+            _ => Err(StoreError(format!(
+                "unexpected output from wrangler: {}",
+                String::from_utf8_lossy(&output.stdout).trim()
+            ))),
+        }
+    }
+}
+
+impl Database for WranglerD1 {
+    async fn query(&self, sql: &str, params: &[SqlValue]) -> StoreResult<Vec<Value>> {
+        let results = self.run(&inline_params(sql, params)?)?;
+        match results.into_iter().next().map(|mut result| result["results"].take()) {
+            Some(Value::Array(rows)) => Ok(rows),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    async fn execute(&self, sql: &str, params: &[SqlValue]) -> StoreResult<u64> {
+        let results = self.run(&inline_params(sql, params)?)?;
+        Ok(results.first().and_then(|result| result["meta"]["changes"].as_u64()).unwrap_or(0))
+    }
+
+    // Sent as one command, which D1 runs as a single batch
+    async fn batch(&self, statements: Vec<Statement>) -> StoreResult<()> {
+        let script = statements
+            .iter()
+            .map(|(sql, params)| inline_params(sql, params).map(|sql| format!("{};", sql.trim_end())))
+            .collect::<StoreResult<Vec<_>>>()?;
+        self.run(&script.join("\n")).map(|_| ())
+    }
+}
+
+impl MigrationTarget for WranglerD1 {
+    async fn execute_script(&self, sql: &str) -> StoreResult<()> {
+        self.run(sql).map(|_| ())
+    }
+}
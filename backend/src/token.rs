@@ -0,0 +1,17 @@
+//! Random bearer tokens (reset and sign-in links, refresh and access tokens)
+//! and the form they are stored in.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use sha2::{Digest, Sha256};
+
+/// Generates an unguessable URL-safe token from 32 bytes of OS randomness.
+pub fn generate_secure_token() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("failed to read random bytes");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// Hashes a bearer token for storage; tokens are high-entropy so a plain SHA-256 is enough.
+pub fn hash_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
@@ -0,0 +1,99 @@
+//! Time-based one-time passwords (RFC 6238) and recovery codes for
+//! two-factor sign-in.
+//!
+//! The parameters are the ones every authenticator app assumes by default:
+//! HMAC-SHA1, six digits, thirty-second steps. Secrets are stored base32
+//! encoded, as they are shown to the user.
+
+use chrono::{DateTime, Utc};
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use sha1::Sha1;
+
+pub const STEP_SECONDS: i64 = 30;
+pub const DIGITS: u32 = 6;
+const SECRET_BYTES: usize = 20;
+// Accept the previous and next step to tolerate clock drift on the phone.
+const ALLOWED_DRIFT_STEPS: i64 = 1;
+
+const ISSUER: &str = "Nivaro";
+
+pub fn generate_secret() -> String {
+    let mut bytes = [0u8; SECRET_BYTES];
+    getrandom::getrandom(&mut bytes).expect("failed to read random bytes");
+    BASE32_NOPAD.encode(&bytes)
+}
+
+/// The `otpauth://` URI authenticator apps read from the setup QR code.
+pub fn otpauth_uri(secret: &str, email: &str) -> String {
+    format!(
+        "otpauth://totp/{}:{}?secret={}&issuer={}&algorithm=SHA1&digits={}&period={}",
+        percent_encode(ISSUER),
+        percent_encode(email),
+        secret,
+        percent_encode(ISSUER),
+        DIGITS,
+        STEP_SECONDS
+    )
+}
+
+/// Returns the time step `code` is valid for at `now`, if any, skipping
+/// steps at or before `last_used_step`.
+pub fn verify(secret: &str, code: &str, last_used_step: Option<i64>, now: DateTime<Utc>) -> Option<i64> {
+    let code = code.trim();
+    if code.len() != DIGITS as usize || !code.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let key = BASE32_NOPAD.decode(secret.as_bytes()).ok()?;
+    let current_step = now.timestamp() / STEP_SECONDS;
+
+    (current_step - ALLOWED_DRIFT_STEPS..=current_step + ALLOWED_DRIFT_STEPS)
+        .filter(|step| last_used_step.is_none_or(|last| *step > last))
+        .find(|step| constant_time_eq(code_for_counter(&key, *step as u64).as_bytes(), code.as_bytes()))
+}
+
+/// HOTP (RFC 4226) for the given counter, zero-padded to `DIGITS`.
+pub fn code_for_counter(key: &[u8], counter: u64) -> String {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(&counter.to_be_bytes());
+    let digest = mac.finalize().into_bytes();
+
+    let offset = (digest[digest.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        digest[offset] & 0x7f,
+        digest[offset + 1],
+        digest[offset + 2],
+        digest[offset + 3],
+    ]);
+
+    format!("{:0width$}", binary % 10u32.pow(DIGITS), width = DIGITS as usize)
+}
+
+pub fn generate_recovery_code() -> String {
+    let mut bytes = [0u8; 5];
+    getrandom::getrandom(&mut bytes).expect("failed to read random bytes");
+    let encoded = BASE32_NOPAD.encode(&bytes).to_lowercase();
+    format!("{}-{}", &encoded[..4], &encoded[4..])
+}
+
+/// Recovery codes are accepted regardless of case or dashes/spaces.
+pub fn normalize_recovery_code(code: &str) -> String {
+    code.chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+fn percent_encode(value: &str) -> String {
+    value.bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'@' => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
@@ -0,0 +1,374 @@
+//! WebAuthn (passkey) ceremony verification.
+//!
+//! Everything here is pure: handlers look up challenges and stored credentials
+//! in D1 and pass the raw bytes in, which keeps the checks testable against
+//! recorded authenticator output.
+
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use ciborium::Value;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::Cursor;
+use worker::Env;
+
+// Authenticator data flags (WebAuthn §6.1)
+const FLAG_USER_PRESENT: u8 = 0x01;
+const FLAG_USER_VERIFIED: u8 = 0x04;
+const FLAG_ATTESTED_CREDENTIAL_DATA: u8 = 0x40;
+
+// COSE algorithm identifiers we accept, in order of preference
+pub const COSE_ALG_ES256: i64 = -7;
+pub const COSE_ALG_EDDSA: i64 = -8;
+pub const COSE_ALG_RS256: i64 = -257;
+pub const SUPPORTED_ALGORITHMS: [i64; 3] = [COSE_ALG_ES256, COSE_ALG_EDDSA, COSE_ALG_RS256];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WebAuthnError {
+    InvalidClientData,
+    WrongCeremony,
+    ChallengeMismatch,
+    OriginMismatch,
+    RpIdMismatch,
+    UserNotPresent,
+    UserNotVerified,
+    InvalidAuthenticatorData,
+    InvalidAttestation,
+    UnsupportedAlgorithm,
+    InvalidPublicKey,
+    InvalidSignature,
+    SignCountRegression,
+}
+
+impl fmt::Display for WebAuthnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let message = match self {
+            WebAuthnError::InvalidClientData => "Invalid client data",
+            WebAuthnError::WrongCeremony => "Unexpected WebAuthn ceremony type",
+            WebAuthnError::ChallengeMismatch => "Challenge does not match",
+            WebAuthnError::OriginMismatch => "Origin is not allowed",
+            WebAuthnError::RpIdMismatch => "Relying party ID does not match",
+            WebAuthnError::UserNotPresent => "User presence was not confirmed",
+            WebAuthnError::UserNotVerified => "The authenticator did not verify the user",
+            WebAuthnError::InvalidAuthenticatorData => "Invalid authenticator data",
+            WebAuthnError::InvalidAttestation => "Invalid attestation object",
+            WebAuthnError::UnsupportedAlgorithm => "Unsupported credential algorithm",
+            WebAuthnError::InvalidPublicKey => "Invalid credential public key",
+            WebAuthnError::InvalidSignature => "Invalid assertion signature",
+            WebAuthnError::SignCountRegression => "Authenticator signature counter went backwards",
+        };
+        f.write_str(message)
+    }
+}
+
+/// The relying party passkeys are scoped to: `id` is the registrable domain
+/// the browser hashes into authenticator data, `origin` the exact frontend
+/// origin allowed to run ceremonies.
+#[derive(Debug, Clone)]
+pub struct RelyingParty {
+    pub id: String,
+    pub name: String,
+    pub origin: String,
+}
+
+impl RelyingParty {
+    /// Reads `WEBAUTHN_RP_ID`, `WEBAUTHN_RP_NAME` and `WEBAUTHN_ORIGIN`, falling
+    /// back to the host and origin of `APP_URL`.
+    pub fn from_env(env: &Env) -> Self {
+        let var = |name: &str| env.var(name).map(|v| v.to_string()).ok().filter(|v| !v.is_empty());
+
+        let origin = var("WEBAUTHN_ORIGIN")
+            .or_else(|| var("APP_URL"))
+            .unwrap_or_else(|| "http://localhost:3000".to_string())
+            .trim_end_matches('/')
+            .to_string();
+        let id = var("WEBAUTHN_RP_ID").unwrap_or_else(|| host_of(&origin));
+        let name = var("WEBAUTHN_RP_NAME").unwrap_or_else(|| "Nivaro".to_string());
+
+        Self { id, name, origin }
+    }
+}
+
+/// A credential accepted by `verify_registration`, ready to be stored.
+#[derive(Debug, Clone)]
+pub struct RegisteredCredential {
+    pub credential_id: Vec<u8>,
+    /// COSE_Key bytes exactly as the authenticator produced them.
+    pub public_key: Vec<u8>,
+    pub algorithm: i64,
+    pub sign_count: u32,
+}
+
+/// Result of a successful `verify_authentication`.
+#[derive(Debug, Clone)]
+pub struct VerifiedAssertion {
+    pub sign_count: u32,
+}
+
+#[derive(Deserialize)]
+struct CollectedClientData {
+    #[serde(rename = "type")]
+    ceremony: String,
+    challenge: String,
+    origin: String,
+}
+
+struct AuthenticatorData<'a> {
+    rp_id_hash: &'a [u8],
+    flags: u8,
+    sign_count: u32,
+    attested: Option<AttestedCredential>,
+}
+
+struct AttestedCredential {
+    credential_id: Vec<u8>,
+    public_key: Vec<u8>,
+}
+
+/// Generates a random challenge, base64url-encoded the way it appears in clientDataJSON.
+pub fn generate_challenge() -> String {
+    let mut bytes = [0u8; 32];
+    getrandom::getrandom(&mut bytes).expect("failed to read random bytes");
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// The challenge a client echoed back, used to find the pending ceremony
+/// before the response is verified.
+pub fn client_data_challenge(client_data_json: &[u8]) -> Result<String, WebAuthnError> {
+    let client_data: CollectedClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| WebAuthnError::InvalidClientData)?;
+    Ok(client_data.challenge)
+}
+
+/// Verifies a `navigator.credentials.create()` response (WebAuthn §7.1).
+///
+/// Attestation statements are not evaluated: registration options ask for
+/// `attestation: "none"`, so we only trust the credential key itself.
+pub fn verify_registration(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    client_data_json: &[u8],
+    attestation_object: &[u8],
+) -> Result<RegisteredCredential, WebAuthnError> {
+    verify_client_data(rp, "webauthn.create", expected_challenge, client_data_json)?;
+
+    let attestation: BTreeMap<String, Value> =
+        ciborium::from_reader(attestation_object).map_err(|_| WebAuthnError::InvalidAttestation)?;
+    let auth_data = match attestation.get("authData") {
+        Some(Value::Bytes(bytes)) => bytes,
+        _ => return Err(WebAuthnError::InvalidAttestation),
+    };
+
+    let auth_data = parse_authenticator_data(auth_data)?;
+    check_authenticator_data(rp, &auth_data)?;
+
+    let attested = auth_data.attested.ok_or(WebAuthnError::InvalidAuthenticatorData)?;
+    let key = CoseKey::parse(&attested.public_key)?;
+
+    Ok(RegisteredCredential {
+        credential_id: attested.credential_id,
+        public_key: attested.public_key,
+        algorithm: key.algorithm(),
+        sign_count: auth_data.sign_count,
+    })
+}
+
+/// Verifies a `navigator.credentials.get()` response against a stored
+/// credential (WebAuthn §7.2), including the signature counter check.
+pub fn verify_authentication(
+    rp: &RelyingParty,
+    expected_challenge: &str,
+    public_key: &[u8],
+    stored_sign_count: u32,
+    client_data_json: &[u8],
+    authenticator_data: &[u8],
+    signature: &[u8],
+) -> Result<VerifiedAssertion, WebAuthnError> {
+    verify_client_data(rp, "webauthn.get", expected_challenge, client_data_json)?;
+
+    let auth_data = parse_authenticator_data(authenticator_data)?;
+    check_authenticator_data(rp, &auth_data)?;
+
+    let mut signed = authenticator_data.to_vec();
+    signed.extend_from_slice(&Sha256::digest(client_data_json));
+    CoseKey::parse(public_key)?.verify(&signed, signature)?;
+
+    // Authenticators that don't implement a counter always report zero; for
+    // everything else the counter must increase or the key may have been cloned.
+    if (auth_data.sign_count != 0 || stored_sign_count != 0) && auth_data.sign_count <= stored_sign_count {
+        return Err(WebAuthnError::SignCountRegression);
+    }
+
+    Ok(VerifiedAssertion {
+        sign_count: auth_data.sign_count,
+    })
+}
+
+fn verify_client_data(
+    rp: &RelyingParty,
+    ceremony: &str,
+    expected_challenge: &str,
+    client_data_json: &[u8],
+) -> Result<(), WebAuthnError> {
+    let client_data: CollectedClientData =
+        serde_json::from_slice(client_data_json).map_err(|_| WebAuthnError::InvalidClientData)?;
+
+    if client_data.ceremony != ceremony {
+        return Err(WebAuthnError::WrongCeremony);
+    }
+    if client_data.challenge != expected_challenge {
+        return Err(WebAuthnError::ChallengeMismatch);
+    }
+    if client_data.origin != rp.origin {
+        return Err(WebAuthnError::OriginMismatch);
+    }
+
+    Ok(())
+}
+
+fn check_authenticator_data(rp: &RelyingParty, auth_data: &AuthenticatorData) -> Result<(), WebAuthnError> {
+    if auth_data.rp_id_hash != Sha256::digest(rp.id.as_bytes()).as_slice() {
+        return Err(WebAuthnError::RpIdMismatch);
+    }
+    if auth_data.flags & FLAG_USER_PRESENT == 0 {
+        return Err(WebAuthnError::UserNotPresent);
+    }
+    // A passkey stands in for both the password and the second factor, so a
+    // touch alone (a security key without a PIN) is not enough.
+    if auth_data.flags & FLAG_USER_VERIFIED == 0 {
+        return Err(WebAuthnError::UserNotVerified);
+    }
+    Ok(())
+}
+
+fn parse_authenticator_data(data: &[u8]) -> Result<AuthenticatorData<'_>, WebAuthnError> {
+    if data.len() < 37 {
+        return Err(WebAuthnError::InvalidAuthenticatorData);
+    }
+
+    let flags = data[32];
+    let sign_count = u32::from_be_bytes([data[33], data[34], data[35], data[36]]);
+
+    let attested = if flags & FLAG_ATTESTED_CREDENTIAL_DATA != 0 {
+        // aaguid (16) | credentialIdLength (2) | credentialId | credentialPublicKey
+        let rest = &data[37..];
+        if rest.len() < 18 {
+            return Err(WebAuthnError::InvalidAuthenticatorData);
+        }
+        let id_len = u16::from_be_bytes([rest[16], rest[17]]) as usize;
+        let rest = &rest[18..];
+        if rest.len() < id_len {
+            return Err(WebAuthnError::InvalidAuthenticatorData);
+        }
+        let (credential_id, rest) = rest.split_at(id_len);
+
+        // The key is a single CBOR item; anything after it is extension data
+        let mut reader = Cursor::new(rest);
+        ciborium::from_reader::<serde::de::IgnoredAny, _>(&mut reader).map_err(|_| WebAuthnError::InvalidPublicKey)?;
+        let key_len = reader.position() as usize;
+
+        Some(AttestedCredential {
+            credential_id: credential_id.to_vec(),
+            public_key: rest[..key_len].to_vec(),
+        })
+    } else {
+        None
+    };
+
+    Ok(AuthenticatorData {
+        rp_id_hash: &data[..32],
+        flags,
+        sign_count,
+        attested,
+    })
+}
+
+enum CoseKey {
+    Es256(p256::ecdsa::VerifyingKey),
+    EdDsa(ed25519_dalek::VerifyingKey),
+    Rs256(rsa::pkcs1v15::VerifyingKey<Sha256>),
+}
+
+impl CoseKey {
+    fn parse(bytes: &[u8]) -> Result<Self, WebAuthnError> {
+        let map: BTreeMap<i64, Value> = ciborium::from_reader(bytes).map_err(|_| WebAuthnError::InvalidPublicKey)?;
+
+        let int = |label: i64| match map.get(&label) {
+            Some(Value::Integer(value)) => i64::try_from(*value).ok(),
+            _ => None,
+        };
+        let bytes = |label: i64| match map.get(&label) {
+            Some(Value::Bytes(value)) => Some(value.as_slice()),
+            _ => None,
+        };
+
+        // kty (1), alg (3), then key-type specific parameters
+        match (int(1), int(3)) {
+            (Some(2), Some(COSE_ALG_ES256)) => {
+                let (x, y) = match (int(-1), bytes(-2), bytes(-3)) {
+                    (Some(1), Some(x), Some(y)) if x.len() == 32 && y.len() == 32 => (x, y),
+                    _ => return Err(WebAuthnError::InvalidPublicKey),
+                };
+                let mut sec1 = Vec::with_capacity(65);
+                sec1.push(0x04);
+                sec1.extend_from_slice(x);
+                sec1.extend_from_slice(y);
+                p256::ecdsa::VerifyingKey::from_sec1_bytes(&sec1)
+                    .map(CoseKey::Es256)
+                    .map_err(|_| WebAuthnError::InvalidPublicKey)
+            }
+            (Some(1), Some(COSE_ALG_EDDSA)) => {
+                let x: [u8; 32] = match (int(-1), bytes(-2)) {
+                    (Some(6), Some(x)) => x.try_into().map_err(|_| WebAuthnError::InvalidPublicKey)?,
+                    _ => return Err(WebAuthnError::InvalidPublicKey),
+                };
+                ed25519_dalek::VerifyingKey::from_bytes(&x)
+                    .map(CoseKey::EdDsa)
+                    .map_err(|_| WebAuthnError::InvalidPublicKey)
+            }
+            (Some(3), Some(COSE_ALG_RS256)) => {
+                let (n, e) = match (bytes(-1), bytes(-2)) {
+                    (Some(n), Some(e)) => (n, e),
+                    _ => return Err(WebAuthnError::InvalidPublicKey),
+                };
+                let key = rsa::RsaPublicKey::new(rsa::BigUint::from_bytes_be(n), rsa::BigUint::from_bytes_be(e))
+                    .map_err(|_| WebAuthnError::InvalidPublicKey)?;
+                Ok(CoseKey::Rs256(rsa::pkcs1v15::VerifyingKey::new(key)))
+            }
+            _ => Err(WebAuthnError::UnsupportedAlgorithm),
+        }
+    }
+
+    fn algorithm(&self) -> i64 {
+        match self {
+            CoseKey::Es256(_) => COSE_ALG_ES256,
+            CoseKey::EdDsa(_) => COSE_ALG_EDDSA,
+            CoseKey::Rs256(_) => COSE_ALG_RS256,
+        }
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> Result<(), WebAuthnError> {
+        use signature::Verifier;
+
+        let verified = match self {
+            CoseKey::Es256(key) => p256::ecdsa::Signature::from_der(signature)
+                .map(|sig| key.verify(message, &sig).is_ok()),
+            CoseKey::EdDsa(key) => ed25519_dalek::Signature::from_slice(signature)
+                .map(|sig| key.verify(message, &sig).is_ok()),
+            CoseKey::Rs256(key) => rsa::pkcs1v15::Signature::try_from(signature)
+                .map(|sig| key.verify(message, &sig).is_ok()),
+        };
+
+        match verified {
+            Ok(true) => Ok(()),
+            _ => Err(WebAuthnError::InvalidSignature),
+        }
+    }
+}
+
+fn host_of(origin: &str) -> String {
+    let without_scheme = origin.split("://").nth(1).unwrap_or(origin);
+    let authority = without_scheme.split('/').next().unwrap_or(without_scheme);
+    authority.split(':').next().unwrap_or(authority).to_string()
+}
@@ -0,0 +1,199 @@
+//! Confirming an account deletion and carrying it out, run against SQLite.
+
+mod common;
+
+use backend::account::{self, DeletionOutcome};
+use backend::models::{Announcement, DeleteAccountRequest, MemberRole, Session};
+use backend::password::{self, HashParams};
+use backend::store::{Database, SqliteStore, Store};
+use backend::totp;
+use chrono::{DateTime, Duration, Utc};
+use common::*;
+use data_encoding::BASE32_NOPAD;
+
+// The secret `enable_mfa` stores
+const MFA_SECRET: &str = "JBSWY3DPEHPK3PXP";
+
+fn add_session(store: &SqliteStore, id: &str, user_id: &str, created_at: DateTime<Utc>) {
+    let session = Session {
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+        token: format!("{}-token", id),
+        expires_at: (created_at + Duration::days(30)).to_rfc3339(),
+        created_at: created_at.to_rfc3339(),
+        last_accessed: created_at.to_rfc3339(),
+        user_agent: None,
+        ip_address: None,
+        is_active: true,
+    };
+    run(store.create_session(&session)).unwrap();
+}
+
+fn set_password_hash(store: &SqliteStore, user_id: &str, hash: &str) {
+    run(store.execute("UPDATE users SET password_hash = ?1 WHERE id = ?2", &[hash.into(), user_id.into()])).unwrap();
+}
+
+fn schedule_deletion(store: &SqliteStore, user_id: &str, at: DateTime<Utc>) {
+    run(store.execute("UPDATE users SET deletion_scheduled_for = ?1 WHERE id = ?2", &[at.to_rfc3339().into(), user_id.into()])).unwrap();
+}
+
+fn current_code() -> String {
+    let key = BASE32_NOPAD.decode(MFA_SECRET.as_bytes()).unwrap();
+    totp::code_for_counter(&key, (now().timestamp() / totp::STEP_SECONDS) as u64)
+}
+
+fn with_password(password: &str) -> DeleteAccountRequest {
+    DeleteAccountRequest { password: Some(password.to_string()), code: None }
+}
+
+fn with_code(code: &str) -> DeleteAccountRequest {
+    DeleteAccountRequest { password: None, code: Some(code.to_string()) }
+}
+
+fn with_nothing() -> DeleteAccountRequest {
+    DeleteAccountRequest { password: None, code: None }
+}
+
+fn count(store: &SqliteStore, sql: &'static str, user_id: &str) -> i64 {
+    run(store.query_first(sql, &[user_id.into()])).unwrap().unwrap()["count"].as_i64().unwrap()
+}
+
+#[test]
+fn the_password_confirms_a_deletion() {
+    let store = store();
+    let mut alice = add_user(&store, "alice");
+    alice.password_hash = run(password::hash_password("Correct horse 1!", HashParams { iterations: 10_000 })).unwrap();
+    set_password_hash(&store, "alice", &alice.password_hash);
+    add_session(&store, "s1", "alice", now() - Duration::days(3));
+
+    assert!(run(account::reauthenticated(&store, &alice, "s1", &with_password("Correct horse 1!"), now())));
+    assert!(!run(account::reauthenticated(&store, &alice, "s1", &with_password("correct horse 1!"), now())));
+    // An old session alone isn't enough for an account with a password
+    assert!(!run(account::reauthenticated(&store, &alice, "s1", &with_nothing(), now())));
+}
+
+#[test]
+fn a_current_authentication_code_confirms_a_deletion_once() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    enable_mfa(&store, "alice");
+    add_session(&store, "s1", "alice", now() - Duration::days(3));
+
+    assert!(!run(account::reauthenticated(&store, &alice, "s1", &with_code("000000"), now())));
+    assert!(run(account::reauthenticated(&store, &alice, "s1", &with_code(&current_code()), now())));
+    // The same code can't be used again
+    assert!(!run(account::reauthenticated(&store, &alice, "s1", &with_code(&current_code()), now())));
+}
+
+#[test]
+fn a_code_is_refused_without_two_factor() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    add_session(&store, "s1", "alice", now() - Duration::days(3));
+
+    assert!(!run(account::reauthenticated(&store, &alice, "s1", &with_code(&current_code()), now())));
+}
+
+#[test]
+fn accounts_without_credentials_confirm_with_a_fresh_sign_in() {
+    let store = store();
+    let mut alice = add_user(&store, "alice");
+    alice.password_hash = String::new();
+    set_password_hash(&store, "alice", "");
+    add_user(&store, "bob");
+    add_session(&store, "fresh", "alice", now() - Duration::minutes(5));
+    add_session(&store, "stale", "alice", now() - Duration::minutes(account::RECENT_SIGN_IN_MINUTES));
+    add_session(&store, "bobs", "bob", now() - Duration::minutes(1));
+
+    assert!(run(account::reauthenticated(&store, &alice, "fresh", &with_nothing(), now())));
+    assert!(!run(account::reauthenticated(&store, &alice, "stale", &with_nothing(), now())));
+    assert!(!run(account::reauthenticated(&store, &alice, "bobs", &with_nothing(), now())));
+    assert!(!run(account::reauthenticated(&store, &alice, "missing", &with_nothing(), now())));
+}
+
+#[test]
+fn a_fresh_sign_in_isnt_enough_once_two_factor_is_on() {
+    let store = store();
+    let mut alice = add_user(&store, "alice");
+    alice.password_hash = String::new();
+    set_password_hash(&store, "alice", "");
+    enable_mfa(&store, "alice");
+    add_session(&store, "fresh", "alice", now() - Duration::minutes(1));
+
+    assert!(!run(account::reauthenticated(&store, &alice, "fresh", &with_nothing(), now())));
+}
+
+#[test]
+fn owned_clubs_hold_up_a_deletion() {
+    let store = store();
+    add_user(&store, "alice");
+    add_club(&store, "go", "alice");
+    add_club(&store, "chess", "alice");
+    schedule_deletion(&store, "alice", now() - Duration::hours(1));
+
+    assert_eq!(run(account::owned_club_names(&store, "alice")).unwrap(), vec!["chess club", "go club"]);
+
+    let outcomes = run(account::process_due_deletions(&store, now())).unwrap();
+    assert_eq!(outcomes, vec![("alice".to_string(), DeletionOutcome::WaitingOnClubs)]);
+    let alice = run(store.user_by_id("alice")).unwrap().unwrap();
+    assert_eq!(alice.name, "alice");
+    assert!(alice.is_active);
+}
+
+#[test]
+fn deletions_wait_for_the_grace_period() {
+    let store = store();
+    add_user(&store, "alice");
+    schedule_deletion(&store, "alice", now() + Duration::minutes(1));
+
+    assert!(run(account::process_due_deletions(&store, now())).unwrap().is_empty());
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().name, "alice");
+}
+
+#[test]
+fn erasing_removes_credentials_but_keeps_authored_content() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    add_club(&store, "chess", "bob");
+    add_member(&store, "chess", &alice, MemberRole::Member);
+    enable_mfa(&store, "alice");
+    add_session(&store, "s1", "alice", now() - Duration::days(1));
+    add_session(&store, "s2", "bob", now() - Duration::days(1));
+    run(store.create_announcement(&Announcement {
+        id: "a1".to_string(),
+        club_id: "chess".to_string(),
+        title: "Tournament".to_string(),
+        content: "Saturday".to_string(),
+        created_by: "alice".to_string(),
+        created_at: now().to_rfc3339(),
+        pinned: false,
+    })).unwrap();
+    schedule_deletion(&store, "alice", now() - Duration::hours(1));
+
+    let outcomes = run(account::process_due_deletions(&store, now())).unwrap();
+    assert_eq!(outcomes, vec![("alice".to_string(), DeletionOutcome::Erased)]);
+
+    let erased = run(store.user_by_id("alice")).unwrap().unwrap();
+    assert_eq!(erased.name, "Deleted user");
+    assert_eq!(erased.email, "deleted-alice@deleted.invalid");
+    assert_eq!(erased.password_hash, "");
+    assert!(!erased.is_active);
+    assert!(!erased.email_verified);
+    assert!(run(store.user_by_email("alice@example.com")).unwrap().is_none());
+
+    assert_eq!(count(&store, "SELECT COUNT(*) as count FROM members WHERE user_id = ?1", "alice"), 0);
+    assert_eq!(count(&store, "SELECT COUNT(*) as count FROM sessions WHERE user_id = ?1", "alice"), 0);
+    assert_eq!(count(&store, "SELECT COUNT(*) as count FROM user_mfa WHERE user_id = ?1", "alice"), 0);
+    assert_eq!(count(&store, "SELECT COUNT(*) as count FROM users WHERE id = ?1 AND deletion_scheduled_for IS NULL AND deleted_at IS NOT NULL", "alice"), 1);
+
+    // The announcement stays with the club, credited to the anonymized account
+    let announcements = run(store.club_announcements("chess")).unwrap();
+    assert_eq!(announcements.len(), 1);
+    assert_eq!(announcements[0].created_by, "alice");
+
+    // Nobody else is touched
+    assert_eq!(run(store.user_by_id("bob")).unwrap().unwrap().email, bob.email);
+    assert_eq!(count(&store, "SELECT COUNT(*) as count FROM sessions WHERE user_id = ?1", "bob"), 1);
+    assert!(run(account::process_due_deletions(&store, now())).unwrap().is_empty());
+}
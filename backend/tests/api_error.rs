@@ -0,0 +1,108 @@
+//! How handler errors map to statuses, codes and the JSON envelope.
+
+use backend::error::ApiError;
+use backend::membership::MembershipError;
+use backend::models::{ApiResponse, FieldError};
+use backend::store::StoreError;
+use serde_json::json;
+
+#[test]
+fn every_error_has_a_status_and_code() {
+    let cases = [
+        (ApiError::BadRequest("bad".into()), 400, "bad_request"),
+        (ApiError::invalid("email", "Invalid email format"), 422, "validation_failed"),
+        (ApiError::Unauthorized("who".into()), 401, "unauthorized"),
+        (ApiError::Forbidden("no".into()), 403, "forbidden"),
+        (ApiError::CsrfFailed, 403, "csrf_failed"),
+        (ApiError::NotFound("gone".into()), 404, "not_found"),
+        (ApiError::MethodNotAllowed, 405, "method_not_allowed"),
+        (ApiError::Conflict("taken".into()), 409, "conflict"),
+        (ApiError::Locked("locked".into()), 423, "locked"),
+        (ApiError::TooManyRequests { message: "slow".into(), retry_after: Some(30) }, 429, "rate_limited"),
+        (ApiError::Unavailable("later".into()), 503, "unavailable"),
+        (ApiError::Internal("boom".into()), 500, "internal_error"),
+    ];
+    for (error, status, code) in cases {
+        assert_eq!(error.status(), status, "{}", code);
+        assert_eq!(error.code(), code);
+    }
+}
+
+#[test]
+fn errors_are_sent_in_the_api_response_envelope() {
+    let body = serde_json::to_value(ApiError::NotFound("Question not found".into()).body()).unwrap();
+    assert_eq!(
+        body,
+        json!({
+            "success": false,
+            "data": null,
+            "error": "Question not found",
+            "code": "not_found",
+        })
+    );
+
+    // Successes don't grow the new fields
+    let body = serde_json::to_value(ApiResponse::success("ok")).unwrap();
+    assert_eq!(body, json!({ "success": true, "data": "ok", "error": null }));
+}
+
+#[test]
+fn validation_errors_list_each_field() {
+    let error = ApiError::Validation(vec![
+        FieldError { field: "email".into(), message: "Invalid email format".into() },
+        FieldError { field: "name".into(), message: "Name cannot be empty".into() },
+    ]);
+    let body = serde_json::to_value(error.body()).unwrap();
+
+    assert_eq!(body["error"], "Invalid email format");
+    assert_eq!(body["code"], "validation_failed");
+    assert_eq!(
+        body["details"],
+        json!([
+            { "field": "email", "message": "Invalid email format" },
+            { "field": "name", "message": "Name cannot be empty" },
+        ])
+    );
+}
+
+#[test]
+fn internal_details_stay_on_the_server() {
+    let error: ApiError = StoreError("no such table: clubs".to_string()).into();
+    assert_eq!(error.status(), 500);
+    assert_eq!(error.message(), "Internal server error");
+    assert!(!serde_json::to_string(&error.body()).unwrap().contains("clubs"));
+}
+
+#[test]
+fn membership_failures_map_to_client_errors() {
+    let cases = [
+        (MembershipError::ClubNotFound, 404),
+        (MembershipError::NotMember, 403),
+        (MembershipError::NotAdmin, 403),
+        (MembershipError::NotOwner, 403),
+        (MembershipError::MfaRequired, 403),
+        (MembershipError::InvalidInviteCode, 400),
+        (MembershipError::InviteCodeUsed, 400),
+        (MembershipError::InviteCodeExpired, 400),
+        (MembershipError::AlreadyMember, 409),
+        (MembershipError::AlreadyOwner, 400),
+        (MembershipError::Store(StoreError("locked".to_string())), 500),
+    ];
+    for (error, status) in cases {
+        assert_eq!(ApiError::from(error).status(), status);
+    }
+}
+
+#[test]
+fn handlers_can_bail_out_with_question_mark() {
+    fn require_member(error: Option<MembershipError>) -> Result<&'static str, ApiError> {
+        if let Some(error) = error {
+            Err(error)?;
+        }
+        Ok("member")
+    }
+
+    assert_eq!(require_member(None).unwrap(), "member");
+    let error = require_member(Some(MembershipError::NotMember)).unwrap_err();
+    assert_eq!(error.message(), "User is not a member of this club");
+}
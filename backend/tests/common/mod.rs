@@ -0,0 +1,112 @@
+//! Fixtures shared by the SQLite-backed integration tests.
+
+#![allow(dead_code)]
+
+use backend::models::{AuthUser, Club, InviteCode, Member, MemberRole, User};
+use backend::store::{Database, SqliteStore, Store};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+
+/// SQLite calls are synchronous, so a single poll finishes every store call.
+pub fn run<F: Future>(future: F) -> F::Output {
+    let mut context = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("SQLite store futures should never be pending"),
+    }
+}
+
+pub fn now() -> DateTime<Utc> {
+    Utc.with_ymd_and_hms(2026, 3, 1, 12, 0, 0).unwrap()
+}
+
+pub fn store() -> SqliteStore {
+    SqliteStore::open_in_memory().expect("the migrations should apply to SQLite")
+}
+
+pub fn add_user(store: &SqliteStore, id: &str) -> AuthUser {
+    let created_at = (now() - Duration::days(30)).to_rfc3339();
+    let user = AuthUser {
+        id: id.to_string(),
+        email: format!("{}@example.com", id),
+        password_hash: "$argon2id$v=19$m=19456,t=2,p=1$c2FsdA$aGFzaA".to_string(),
+        name: id.to_string(),
+        avatar: None,
+        created_at: created_at.clone(),
+        updated_at: created_at,
+        email_verified: true,
+        is_active: true,
+        last_login: None,
+        failed_login_attempts: 0,
+        locked_until: None,
+        deletion_scheduled_for: None,
+    };
+    run(store.create_user(&user)).unwrap();
+    user
+}
+
+pub fn deactivate(store: &SqliteStore, user_id: &str) {
+    run(store.execute("UPDATE users SET is_active = 0 WHERE id = ?1", &[user_id.into()])).unwrap();
+}
+
+pub fn enable_mfa(store: &SqliteStore, user_id: &str) {
+    run(store.execute("
+        INSERT INTO user_mfa (user_id, secret, enabled, created_at, confirmed_at)
+        VALUES (?1, 'JBSWY3DPEHPK3PXP', 1, ?2, ?2)
+    ", &[user_id.into(), now().to_rfc3339().into()])).unwrap();
+}
+
+pub fn add_club(store: &SqliteStore, id: &str, owner_id: &str) -> Club {
+    let created_at = (now() - Duration::days(7)).to_rfc3339();
+    let club = Club {
+        id: id.to_string(),
+        name: format!("{} club", id),
+        description: None,
+        avatar: None,
+        created_at: created_at.clone(),
+        updated_at: created_at,
+        owner_id: owner_id.to_string(),
+        require_admin_mfa: false,
+    };
+    run(store.create_club(&club)).unwrap();
+    club
+}
+
+pub fn require_admin_mfa(store: &SqliteStore, club_id: &str) {
+    run(store.set_require_admin_mfa(club_id, true, &now().to_rfc3339())).unwrap();
+}
+
+pub fn add_member(store: &SqliteStore, club_id: &str, user: &AuthUser, role: MemberRole) {
+    let member = Member {
+        id: format!("{}-{}", club_id, user.id),
+        user_id: user.id.clone(),
+        club_id: club_id.to_string(),
+        role,
+        joined_at: now().to_rfc3339(),
+        user: User {
+            id: user.id.clone(),
+            email: user.email.clone(),
+            name: user.name.clone(),
+            avatar: None,
+            created_at: user.created_at.clone(),
+            updated_at: user.updated_at.clone(),
+            email_verified: user.email_verified,
+            is_active: user.is_active,
+        },
+    };
+    run(store.add_member(&member)).unwrap();
+}
+
+pub fn add_invite_code(store: &SqliteStore, code: &str, club_id: &str, created_by: &str, expires_at: DateTime<Utc>) {
+    let invite = InviteCode {
+        code: code.to_string(),
+        club_id: club_id.to_string(),
+        created_by: created_by.to_string(),
+        expires_at: expires_at.to_rfc3339(),
+        used_by: None,
+        used_at: None,
+    };
+    run(store.create_invite_code(&invite)).unwrap();
+}
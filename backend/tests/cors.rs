@@ -0,0 +1,121 @@
+//! CORS allowlists as configured for each environment in wrangler.toml.
+
+use backend::cors::{CorsPolicy, ALLOWED_HEADERS, ALLOWED_METHODS};
+
+const STAGING_ORIGINS: &str = "https://nivaro-frontend-staging.pages.dev,https://*.nivaro-frontend-staging.pages.dev";
+const PRODUCTION_ORIGINS: &str = "https://nivaro-frontend.pages.dev";
+
+fn header<'a>(headers: &'a [(&'static str, String)], name: &str) -> Option<&'a str> {
+    headers.iter().find(|(key, _)| *key == name).map(|(_, value)| value.as_str())
+}
+
+#[test]
+fn production_preflight_allows_the_frontend() {
+    let cors = CorsPolicy::parse(PRODUCTION_ORIGINS);
+    let headers = cors.preflight_headers(Some("https://nivaro-frontend.pages.dev")).unwrap();
+
+    assert_eq!(header(&headers, "Access-Control-Allow-Origin"), Some("https://nivaro-frontend.pages.dev"));
+    assert_eq!(header(&headers, "Access-Control-Allow-Credentials"), Some("true"));
+    assert_eq!(header(&headers, "Access-Control-Allow-Methods"), Some(ALLOWED_METHODS));
+    assert_eq!(header(&headers, "Access-Control-Allow-Headers"), Some(ALLOWED_HEADERS));
+    assert_eq!(header(&headers, "Access-Control-Max-Age"), Some("86400"));
+}
+
+#[test]
+fn production_credentialed_response_echoes_the_frontend() {
+    let cors = CorsPolicy::parse(PRODUCTION_ORIGINS);
+    let headers = cors.response_headers(Some("https://nivaro-frontend.pages.dev")).unwrap();
+
+    assert_eq!(header(&headers, "Access-Control-Allow-Origin"), Some("https://nivaro-frontend.pages.dev"));
+    assert_eq!(header(&headers, "Access-Control-Allow-Credentials"), Some("true"));
+    assert_eq!(header(&headers, "Access-Control-Max-Age"), None);
+}
+
+#[test]
+fn production_rejects_local_and_lan_origins() {
+    let cors = CorsPolicy::parse(PRODUCTION_ORIGINS);
+
+    for origin in ["http://localhost:3000", "http://192.168.1.245:3000", "http://nivaro-frontend.pages.dev"] {
+        assert_eq!(cors.preflight_headers(Some(origin)), None, "{}", origin);
+        assert_eq!(cors.response_headers(Some(origin)), None, "{}", origin);
+    }
+}
+
+#[test]
+fn production_does_not_allow_preview_subdomains() {
+    let cors = CorsPolicy::parse(PRODUCTION_ORIGINS);
+
+    assert_eq!(cors.response_headers(Some("https://abc123.nivaro-frontend.pages.dev")), None);
+}
+
+#[test]
+fn staging_preflight_allows_preview_deployments() {
+    let cors = CorsPolicy::parse(STAGING_ORIGINS);
+    let origin = "https://abc123.nivaro-frontend-staging.pages.dev";
+    let headers = cors.preflight_headers(Some(origin)).unwrap();
+
+    assert_eq!(header(&headers, "Access-Control-Allow-Origin"), Some(origin));
+    assert_eq!(header(&headers, "Access-Control-Allow-Credentials"), Some("true"));
+}
+
+#[test]
+fn staging_credentialed_response_allows_the_bare_domain_and_subdomains() {
+    let cors = CorsPolicy::parse(STAGING_ORIGINS);
+
+    for origin in [
+        "https://nivaro-frontend-staging.pages.dev",
+        "https://feature-x.nivaro-frontend-staging.pages.dev",
+        "https://a.b.nivaro-frontend-staging.pages.dev",
+    ] {
+        let headers = cors.response_headers(Some(origin)).unwrap();
+        assert_eq!(header(&headers, "Access-Control-Allow-Origin"), Some(origin));
+    }
+}
+
+#[test]
+fn staging_wildcard_cannot_be_spoofed() {
+    let cors = CorsPolicy::parse(STAGING_ORIGINS);
+
+    for origin in [
+        "https://evilnivaro-frontend-staging.pages.dev",
+        "https://nivaro-frontend-staging.pages.dev.evil.example",
+        "http://abc123.nivaro-frontend-staging.pages.dev",
+        "https://abc123.nivaro-frontend-staging.pages.dev:8443",
+        "https://evil.example/.nivaro-frontend-staging.pages.dev",
+        "https://user@abc123.nivaro-frontend-staging.pages.dev",
+        "null",
+    ] {
+        assert_eq!(cors.response_headers(Some(origin)), None, "{}", origin);
+    }
+}
+
+#[test]
+fn requests_without_an_origin_get_no_cors_headers() {
+    let cors = CorsPolicy::parse(STAGING_ORIGINS);
+
+    assert_eq!(cors.preflight_headers(None), None);
+    assert_eq!(cors.response_headers(None), None);
+}
+
+#[test]
+fn an_empty_allowlist_allows_nothing() {
+    let cors = CorsPolicy::parse("");
+
+    assert!(!cors.allows("http://localhost:3000"));
+}
+
+#[test]
+fn entries_are_trimmed_and_case_insensitive() {
+    let cors = CorsPolicy::parse(" https://Nivaro-Frontend.pages.dev/ , http://localhost:3000");
+
+    assert!(cors.allows("https://nivaro-frontend.pages.dev"));
+    assert!(cors.allows("http://localhost:3000"));
+    assert!(!cors.allows("http://localhost:3001"));
+}
+
+#[test]
+fn malformed_entries_are_ignored() {
+    let cors = CorsPolicy::parse("*,https://*,https://app.*.example.com,https://example.com/app,localhost:3000");
+
+    assert_eq!(cors, CorsPolicy::default());
+}
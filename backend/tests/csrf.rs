@@ -0,0 +1,111 @@
+//! CSRF tokens and the `Sec-Fetch-Site`/`Origin` check in front of them.
+
+use backend::csrf::{origin_allowed, CsrfKey, TOKEN_TTL_SECONDS};
+
+const SECRET: &[u8] = b"0123456789abcdef0123456789abcdef";
+const NOW: i64 = 1_772_366_400;
+
+fn key() -> CsrfKey {
+    CsrfKey::new(SECRET).unwrap()
+}
+
+#[test]
+fn a_token_verifies_for_its_session() {
+    let token = key().issue("session-1", NOW);
+    assert!(key().verify("session-1", &token, NOW));
+    assert!(key().verify("session-1", &token, NOW + 60));
+}
+
+#[test]
+fn a_token_is_bound_to_its_session_and_key() {
+    let token = key().issue("session-1", NOW);
+    assert!(!key().verify("session-2", &token, NOW));
+
+    let other = CsrfKey::new(b"fedcba9876543210fedcba9876543210").unwrap();
+    assert!(!other.verify("session-1", &token, NOW));
+}
+
+#[test]
+fn a_tampered_token_is_rejected() {
+    let token = key().issue("session-1", NOW);
+    let (issued_at, mac) = token.split_once('.').unwrap();
+
+    // Moving the issue time forward to stretch its life
+    assert!(!key().verify("session-1", &format!("{}.{}", NOW + 600, mac), NOW + 600));
+
+    // Flipping one character of the MAC
+    let mut flipped: Vec<char> = mac.chars().collect();
+    flipped[0] = if flipped[0] == 'A' { 'B' } else { 'A' };
+    let flipped: String = flipped.into_iter().collect();
+    assert!(!key().verify("session-1", &format!("{}.{}", issued_at, flipped), NOW));
+
+    // Truncating the MAC
+    assert!(!key().verify("session-1", &format!("{}.{}", issued_at, &mac[..10]), NOW));
+}
+
+#[test]
+fn a_token_expires_after_its_lifetime() {
+    let token = key().issue("session-1", NOW);
+    assert!(key().verify("session-1", &token, NOW + TOKEN_TTL_SECONDS - 1));
+    assert!(!key().verify("session-1", &token, NOW + TOKEN_TTL_SECONDS));
+}
+
+#[test]
+fn a_token_from_the_future_is_only_allowed_within_the_clock_skew() {
+    let token = key().issue("session-1", NOW + 60);
+    assert!(key().verify("session-1", &token, NOW));
+
+    let token = key().issue("session-1", NOW + 61);
+    assert!(!key().verify("session-1", &token, NOW));
+}
+
+#[test]
+fn malformed_tokens_are_rejected() {
+    let mac = key().issue("session-1", NOW).split_once('.').unwrap().1.to_string();
+    for token in [
+        String::new(),
+        ".".to_string(),
+        NOW.to_string(),
+        mac.clone(),
+        format!("soon.{}", mac),
+        format!("{}.", NOW),
+        format!("{}.not base64!", NOW),
+        format!("{}.{}.extra", NOW, mac),
+    ] {
+        assert!(!key().verify("session-1", &token, NOW), "{:?}", token);
+    }
+}
+
+#[test]
+fn short_secrets_are_refused() {
+    assert!(CsrfKey::new(b"").is_none());
+    assert!(CsrfKey::new(&SECRET[..31]).is_none());
+    assert!(CsrfKey::new(SECRET).is_some());
+}
+
+#[test]
+fn same_origin_requests_are_allowed() {
+    let nowhere = |_: &str| false;
+    assert!(origin_allowed(Some("same-origin"), None, nowhere));
+    assert!(origin_allowed(Some("same-origin"), Some("https://nivaro.app"), nowhere));
+}
+
+#[test]
+fn requests_with_an_origin_need_it_allowed() {
+    let frontend = |origin: &str| origin == "https://nivaro.app";
+    for sec_fetch_site in [None, Some("same-site"), Some("cross-site"), Some("none")] {
+        assert!(origin_allowed(sec_fetch_site, Some("https://nivaro.app"), frontend), "{:?}", sec_fetch_site);
+        assert!(!origin_allowed(sec_fetch_site, Some("https://evil.example"), frontend), "{:?}", sec_fetch_site);
+        assert!(!origin_allowed(sec_fetch_site, Some("null"), frontend), "{:?}", sec_fetch_site);
+    }
+}
+
+#[test]
+fn requests_without_an_origin_are_refused_only_when_cross_site() {
+    let anywhere = |_: &str| true;
+    assert!(!origin_allowed(Some("cross-site"), None, anywhere));
+    // curl and other clients that send neither header are left to the token
+    assert!(origin_allowed(None, None, anywhere));
+    assert!(origin_allowed(Some("same-site"), None, anywhere));
+    assert!(origin_allowed(Some("none"), None, anywhere));
+}
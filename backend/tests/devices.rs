@@ -0,0 +1,123 @@
+//! Summarizing browsers and networks, and telling a new sign-in device from
+//! one the account has used before, run against SQLite.
+
+mod common;
+
+use backend::devices::{self, device_hash, mask_ip_address, summarize_user_agent, LoginHistory};
+use backend::store::{Database, SqliteStore};
+use common::*;
+
+const CHROME_ON_MAC: &str = "Mozilla/5.0 (Macintosh; Intel Mac OS X 10_15_7) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
+const FIREFOX_ON_WINDOWS: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64; rv:125.0) Gecko/20100101 Firefox/125.0";
+
+fn record(store: &SqliteStore, user_id: &str, event_type: &str, device_hash: &str) {
+    run(store.execute("
+        INSERT INTO auth_events (id, user_id, event_type, device_hash, created_at)
+        VALUES (lower(hex(randomblob(16))), ?1, ?2, ?3, ?4)
+    ", &[user_id.into(), event_type.into(), device_hash.into(), now().to_rfc3339().into()])).unwrap();
+}
+
+#[test]
+fn user_agents_are_reduced_to_browser_and_os() {
+    for (user_agent, summary) in [
+        (CHROME_ON_MAC, "Chrome on macOS"),
+        (FIREFOX_ON_WINDOWS, "Firefox on Windows"),
+        ("Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 Edg/124.0.2478.51", "Edge on Windows"),
+        ("Mozilla/5.0 (X11; Linux x86_64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36 OPR/109.0.0.0", "Opera on Linux"),
+        ("Mozilla/5.0 (iPhone; CPU iPhone OS 17_4 like Mac OS X) AppleWebKit/605.1.15 (KHTML, like Gecko) Version/17.4 Mobile/15E148 Safari/604.1", "Safari on iOS"),
+        ("Mozilla/5.0 (Linux; Android 14; Pixel 8) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Mobile Safari/537.36", "Chrome on Android"),
+        ("curl/8.7.1", "Unknown browser on unknown OS"),
+        ("", "Unknown browser on unknown OS"),
+    ] {
+        assert_eq!(summarize_user_agent(user_agent), summary, "{}", user_agent);
+    }
+}
+
+#[test]
+fn ipv4_addresses_lose_their_last_octet() {
+    assert_eq!(mask_ip_address("203.0.113.7"), "203.0.113.x");
+    assert_eq!(mask_ip_address("10.0.0.255"), "10.0.0.x");
+    assert_eq!(mask_ip_address("::ffff:203.0.113.7"), "203.0.113.x");
+}
+
+#[test]
+fn ipv6_addresses_keep_their_first_three_groups() {
+    assert_eq!(mask_ip_address("2001:db8:85a3:8d3:1319:8a2e:370:7348"), "2001:db8:85a3::");
+    assert_eq!(mask_ip_address("2001:0db8:85a3:0000:0000:8a2e:0370:7334"), "2001:db8:85a3::");
+    // Compressed forms are expanded before masking
+    assert_eq!(mask_ip_address("2001:db8::1"), "2001:db8::");
+    assert_eq!(mask_ip_address("2001:db8:1::1"), "2001:db8:1::");
+    assert_eq!(mask_ip_address("2001::1"), "2001::");
+    assert_eq!(mask_ip_address("::1"), "::");
+    assert_ne!(mask_ip_address("2001:db8:1::1"), mask_ip_address("2001:db8:2::1"));
+    assert_eq!(mask_ip_address("2001:db8:1::1"), mask_ip_address("2001:db8:1:ffff::2"));
+}
+
+#[test]
+fn anything_else_is_shown_as_it_is() {
+    assert_eq!(mask_ip_address("unknown"), "unknown");
+}
+
+#[test]
+fn the_hash_ignores_browser_updates_and_the_host_part_of_the_address() {
+    let hash = device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7"));
+    assert_eq!(hash, device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7")));
+    assert_eq!(hash, device_hash(Some(&CHROME_ON_MAC.replace("124.0.0.0", "125.0.6422.60")), Some("203.0.113.7")));
+    assert_eq!(hash, device_hash(Some(CHROME_ON_MAC), Some("203.0.113.200")));
+    assert_eq!(hash.len(), 64);
+}
+
+#[test]
+fn the_hash_tells_browsers_and_networks_apart() {
+    let hash = device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7"));
+    assert_ne!(hash, device_hash(Some(FIREFOX_ON_WINDOWS), Some("203.0.113.7")));
+    assert_ne!(hash, device_hash(Some(CHROME_ON_MAC), Some("198.51.100.7")));
+    assert_ne!(hash, device_hash(None, Some("203.0.113.7")));
+    assert_ne!(hash, device_hash(Some(CHROME_ON_MAC), None));
+}
+
+#[test]
+fn compressed_ipv6_networks_are_told_apart() {
+    assert_ne!(device_hash(Some(CHROME_ON_MAC), Some("2001:db8:1::1")), device_hash(Some(CHROME_ON_MAC), Some("2001:db8:2::1")));
+    assert_eq!(device_hash(Some(CHROME_ON_MAC), Some("2001:db8:1::1")), device_hash(Some(CHROME_ON_MAC), Some("2001:db8:1::2")));
+}
+
+#[test]
+fn the_first_sign_in_is_not_reported() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let hash = device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7"));
+    let history = run(devices::login_history(&store, "alice", &hash)).unwrap();
+    assert_eq!(history, LoginHistory { has_logins: false, seen_device: false });
+    assert!(!history.is_new_device());
+}
+
+#[test]
+fn a_sign_in_from_a_known_device_is_not_reported() {
+    let store = store();
+    add_user(&store, "alice");
+    let hash = device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7"));
+    record(&store, "alice", "login", &hash);
+
+    let history = run(devices::login_history(&store, "alice", &hash)).unwrap();
+    assert!(history.seen_device);
+    assert!(!history.is_new_device());
+}
+
+#[test]
+fn a_sign_in_from_another_device_is_reported() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    let known = device_hash(Some(CHROME_ON_MAC), Some("203.0.113.7"));
+    let other = device_hash(Some(FIREFOX_ON_WINDOWS), Some("198.51.100.7"));
+    record(&store, "alice", "login", &known);
+    // Neither someone else's sign-ins nor failed attempts make a device known
+    record(&store, "bob", "login", &other);
+    record(&store, "alice", "login_failed", &other);
+
+    let history = run(devices::login_history(&store, "alice", &other)).unwrap();
+    assert_eq!(history, LoginHistory { has_logins: true, seen_device: false });
+    assert!(history.is_new_device());
+}
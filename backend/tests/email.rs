@@ -0,0 +1,176 @@
+//! Email rendering, and the queue that sends fan-outs after the response,
+//! run against SQLite with a sender that records what it was given.
+
+mod common;
+
+use backend::email::{self, EmailMessage, EmailSender, EmailTemplate};
+use backend::store::Database;
+use chrono::Duration;
+use common::*;
+use std::cell::RefCell;
+
+fn announcement(title: &str, content: &str) -> EmailTemplate {
+    EmailTemplate::Announcement {
+        club_name: "Chess & Go".to_string(),
+        title: title.to_string(),
+        content: content.to_string(),
+        link: "https://nivaro.example/club/chess?tab=news&x=1".to_string(),
+    }
+}
+
+#[test]
+fn every_template_renders_all_three_parts() {
+    let templates = [
+        EmailTemplate::Verification { name: "Ada".into(), link: "https://nivaro.example/verify?t=1".into() },
+        EmailTemplate::PasswordReset { name: "Ada".into(), link: "https://nivaro.example/reset?t=1".into() },
+        EmailTemplate::MagicLink { name: "Ada".into(), link: "https://nivaro.example/magic?t=1".into() },
+        EmailTemplate::NewDeviceLogin {
+            name: "Ada".into(),
+            device: "Firefox on Linux".into(),
+            location: "203.0.113.x".into(),
+            time: "2026-03-01 12:00 UTC".into(),
+            link: "https://nivaro.example/profile".into(),
+        },
+        EmailTemplate::ClubInvite {
+            club_name: "Chess".into(),
+            inviter_name: "Ada".into(),
+            invite_code: "ABC123".into(),
+            link: "https://nivaro.example/join/ABC123".into(),
+        },
+        EmailTemplate::PlatformInvite {
+            inviter_name: "Ada".into(),
+            link: "https://nivaro.example/signup?invite=1".into(),
+            expires_at: "2026-03-15".into(),
+        },
+        announcement("Tournament", "Saturday at 10"),
+    ];
+
+    let mut names: Vec<&str> = templates.iter().map(|template| template.name()).collect();
+    names.sort();
+    names.dedup();
+    assert_eq!(names.len(), templates.len(), "template names must be unique");
+
+    for template in &templates {
+        assert!(!template.subject().is_empty(), "{}", template.name());
+        assert!(template.html_body().starts_with("<!DOCTYPE html>"), "{}", template.name());
+        assert!(template.html_body().ends_with("</body></html>"), "{}", template.name());
+        assert!(!template.text_body().contains('<'), "{}", template.name());
+    }
+}
+
+#[test]
+fn links_appear_in_both_bodies() {
+    let template = EmailTemplate::PasswordReset { name: "Ada".into(), link: "https://nivaro.example/reset?token=abc".into() };
+    assert!(template.text_body().contains("https://nivaro.example/reset?token=abc"));
+    assert!(template.html_body().contains("href=\"https://nivaro.example/reset?token=abc\""));
+}
+
+#[test]
+fn html_bodies_escape_user_supplied_text() {
+    let template = announcement("<script>alert(1)</script>", "Bring \"snacks\" & 'boards'");
+    let html = template.html_body();
+
+    assert!(!html.contains("<script>"));
+    assert!(html.contains("&lt;script&gt;alert(1)&lt;/script&gt;"));
+    assert!(html.contains("Bring &quot;snacks&quot; &amp; &#39;boards&#39;"));
+    assert!(html.contains("<strong>Chess &amp; Go</strong>"));
+    assert!(html.contains("href=\"https://nivaro.example/club/chess?tab=news&amp;x=1\""));
+
+    // An attribute can't be broken out of
+    let template = EmailTemplate::Verification { name: "Ada".into(), link: "\" onclick=\"steal()".into() };
+    assert!(template.html_body().contains("href=\"&quot; onclick=&quot;steal()\""));
+}
+
+#[test]
+fn text_bodies_are_left_as_written() {
+    let template = announcement("Q&A <tonight>", "Line one\nLine two");
+    assert_eq!(template.subject(), "[Chess & Go] Q&A <tonight>");
+    assert!(template.text_body().contains("Q&A <tonight>\n\nLine one\nLine two"));
+    // HTML keeps the line breaks
+    assert!(template.html_body().contains("Line one<br>Line two"));
+}
+
+#[derive(Default)]
+struct RecordingSender {
+    sent: RefCell<Vec<String>>,
+    failing: Vec<String>,
+}
+
+impl EmailSender for RecordingSender {
+    async fn send(&self, message: &EmailMessage) -> worker::Result<()> {
+        if self.failing.contains(&message.to) {
+            return Err(worker::Error::RustError("provider down".to_string()));
+        }
+        self.sent.borrow_mut().push(message.to.clone());
+        Ok(())
+    }
+}
+
+fn queue(store: &impl Database, recipients: &[&str]) {
+    let messages: Vec<EmailMessage> = recipients
+        .iter()
+        .map(|to| EmailMessage::new(to, &announcement("Tournament", "Saturday at 10")))
+        .collect();
+    run(email::enqueue(store, &messages, now())).unwrap();
+}
+
+fn queued(store: &impl Database) -> i64 {
+    run(store.query_first("SELECT COUNT(*) as count FROM email_queue", &[])).unwrap().unwrap()["count"].as_i64().unwrap()
+}
+
+#[test]
+fn queued_mail_goes_out_in_batches() {
+    let store = store();
+    queue(&store, &["a@example.com", "b@example.com", "c@example.com"]);
+    let sender = RecordingSender::default();
+
+    assert_eq!(run(email::deliver_queued(&store, &sender, 2, now())).unwrap(), 2);
+    assert_eq!(queued(&store), 1);
+    assert_eq!(run(email::deliver_queued(&store, &sender, 2, now())).unwrap(), 1);
+    assert_eq!(queued(&store), 0);
+
+    let mut sent = sender.sent.into_inner();
+    sent.sort();
+    assert_eq!(sent, vec!["a@example.com", "b@example.com", "c@example.com"]);
+}
+
+#[test]
+fn queued_messages_keep_their_rendering() {
+    let store = store();
+    queue(&store, &["a@example.com"]);
+
+    let claimed = run(email::claim_queued(&store, 10, now())).unwrap();
+    let expected = EmailMessage::new("a@example.com", &announcement("Tournament", "Saturday at 10"));
+    assert_eq!(claimed.len(), 1);
+    assert_eq!(claimed[0].message.subject, expected.subject);
+    assert_eq!(claimed[0].message.html_body, expected.html_body);
+    assert_eq!(claimed[0].message.text_body, expected.text_body);
+    assert_eq!(claimed[0].message.template, "announcement");
+}
+
+#[test]
+fn claimed_mail_is_not_sent_twice_by_overlapping_runs() {
+    let store = store();
+    queue(&store, &["a@example.com"]);
+
+    assert_eq!(run(email::claim_queued(&store, 10, now())).unwrap().len(), 1);
+    assert!(run(email::claim_queued(&store, 10, now() + Duration::minutes(1))).unwrap().is_empty());
+    // Until the run that claimed it has evidently died
+    assert_eq!(run(email::claim_queued(&store, 10, now() + Duration::minutes(11))).unwrap().len(), 1);
+}
+
+#[test]
+fn failed_mail_is_retried_a_few_times() {
+    let store = store();
+    queue(&store, &["down@example.com"]);
+    let sender = RecordingSender { failing: vec!["down@example.com".to_string()], ..Default::default() };
+
+    let mut time = now();
+    for _ in 0..5 {
+        assert_eq!(run(email::deliver_queued(&store, &sender, 10, time)).unwrap(), 0);
+        time += Duration::minutes(11);
+    }
+    assert!(run(email::claim_queued(&store, 10, time)).unwrap().is_empty());
+    // Kept for someone to look into
+    assert_eq!(queued(&store), 1);
+}
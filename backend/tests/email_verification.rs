@@ -0,0 +1,84 @@
+//! Redeeming email verification links, run against SQLite.
+
+mod common;
+
+use backend::email_verification::{self, Verification};
+use backend::store::{Database, SqliteStore, Store};
+use backend::token::hash_token;
+use chrono::{DateTime, Duration, Utc};
+use common::*;
+
+fn add_link(store: &SqliteStore, token: &str, user_id: &str, email: &str, expires_at: DateTime<Utc>) {
+    run(store.execute("
+        INSERT INTO email_verifications (id, user_id, token, email, expires_at, created_at)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+    ", &[
+        format!("{}-link", token).into(),
+        user_id.into(),
+        hash_token(token).into(),
+        email.into(),
+        expires_at.to_rfc3339().into(),
+        now().to_rfc3339().into(),
+    ])).unwrap();
+}
+
+fn unverify(store: &SqliteStore, user_id: &str) {
+    run(store.execute("UPDATE users SET email_verified = 0 WHERE id = ?1", &[user_id.into()])).unwrap();
+}
+
+fn links(store: &SqliteStore) -> usize {
+    run(store.query("SELECT id FROM email_verifications", &[])).unwrap().len()
+}
+
+#[test]
+fn a_link_verifies_the_address_once() {
+    let store = store();
+    add_user(&store, "alice");
+    unverify(&store, "alice");
+    add_link(&store, "t1", "alice", "alice@example.com", now() + Duration::hours(1));
+
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::Verified);
+    assert!(run(store.user_by_id("alice")).unwrap().unwrap().email_verified);
+    assert_eq!(links(&store), 0);
+
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::InvalidToken);
+}
+
+#[test]
+fn a_link_from_an_email_change_moves_the_account() {
+    let store = store();
+    add_user(&store, "alice");
+    add_link(&store, "t1", "alice", "alice@new.example", now() + Duration::hours(1));
+
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::Verified);
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().email, "alice@new.example");
+}
+
+#[test]
+fn expired_and_unknown_links_are_refused() {
+    let store = store();
+    add_user(&store, "alice");
+    unverify(&store, "alice");
+    add_link(&store, "t1", "alice", "alice@example.com", now() - Duration::seconds(1));
+
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::InvalidToken);
+    assert_eq!(run(email_verification::redeem(&store, "t2", now())).unwrap(), Verification::InvalidToken);
+    assert!(!run(store.user_by_id("alice")).unwrap().unwrap().email_verified);
+}
+
+#[test]
+fn a_link_to_an_address_taken_since_is_kept_for_later() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    add_link(&store, "t1", "alice", "bob@example.com", now() + Duration::hours(1));
+
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::AddressTaken);
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().email, "alice@example.com");
+    assert_eq!(links(&store), 1);
+
+    // Once bob moves on, the same link still works
+    run(store.execute("UPDATE users SET email = 'bob@new.example' WHERE id = 'bob'", &[])).unwrap();
+    assert_eq!(run(email_verification::redeem(&store, "t1", now())).unwrap(), Verification::Verified);
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().email, "bob@example.com");
+}
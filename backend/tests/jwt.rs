@@ -0,0 +1,216 @@
+//! Access token signing and verification with the keys from
+//! `JWT_SIGNING_KEYS`, using the test-only keys in `fixtures/jwt`.
+
+use backend::jwt::{KeyError, KeySet, DEFAULT_AUDIENCE, DEFAULT_ISSUER};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use chrono::{DateTime, Duration, Utc};
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+const ED25519_A: &str = include_str!("fixtures/jwt/ed25519-a.pem");
+const ED25519_A_PUBLIC: &str = include_str!("fixtures/jwt/ed25519-a.pub.pem");
+const ED25519_B: &str = include_str!("fixtures/jwt/ed25519-b.pem");
+const RSA: &str = include_str!("fixtures/jwt/rsa.pem");
+const RSA_PUBLIC: &str = include_str!("fixtures/jwt/rsa.pub.pem");
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Claims {
+    sub: String,
+    exp: i64,
+}
+
+#[derive(Deserialize)]
+struct Registered {
+    iss: String,
+    aud: String,
+}
+
+fn claims(expires_at: DateTime<Utc>) -> Claims {
+    Claims { sub: "alice".to_string(), exp: expires_at.timestamp() }
+}
+
+// Expiry is checked against the real clock, so tokens expire relative to it
+fn fresh() -> Claims {
+    claims(Utc::now() + Duration::minutes(15))
+}
+
+fn keys(entries: serde_json::Value) -> KeySet {
+    KeySet::from_json(&entries.to_string(), Duration::minutes(60)).unwrap()
+}
+
+fn signed_by_a() -> KeySet {
+    keys(json!([{ "kid": "a", "alg": "EdDSA", "private_key": ED25519_A }]))
+}
+
+#[test]
+fn tokens_verify_with_the_key_that_signed_them() {
+    let now = Utc::now();
+    for set in [
+        signed_by_a(),
+        keys(json!([{ "kid": "r", "alg": "RS256", "private_key": RSA }])),
+    ] {
+        let token = set.sign(&fresh(), now).unwrap();
+        assert_eq!(set.verify::<Claims>(&token, now).unwrap().sub, "alice");
+    }
+}
+
+#[test]
+fn tokens_name_the_issuer_and_audience() {
+    let set = signed_by_a();
+    let token = set.sign(&fresh(), Utc::now()).unwrap();
+    let registered: Registered = set.verify(&token, Utc::now()).unwrap();
+    assert_eq!(registered.iss, DEFAULT_ISSUER);
+    assert_eq!(registered.aud, DEFAULT_AUDIENCE);
+
+    let set = signed_by_a().with_audience("https://auth.example", "clubs");
+    let token = set.sign(&fresh(), Utc::now()).unwrap();
+    let registered: Registered = set.verify(&token, Utc::now()).unwrap();
+    assert_eq!(registered.iss, "https://auth.example");
+    assert_eq!(registered.aud, "clubs");
+}
+
+#[test]
+fn tokens_for_another_issuer_or_audience_are_rejected() {
+    let now = Utc::now();
+    let token = signed_by_a().with_audience("other", DEFAULT_AUDIENCE).sign(&fresh(), now).unwrap();
+    assert_eq!(signed_by_a().verify::<Claims>(&token, now), Err(KeyError::InvalidToken));
+
+    let token = signed_by_a().with_audience(DEFAULT_ISSUER, "other").sign(&fresh(), now).unwrap();
+    assert_eq!(signed_by_a().verify::<Claims>(&token, now), Err(KeyError::InvalidToken));
+
+    // Signed with the right key but without iss and aud at all
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("a".to_string());
+    let token = encode(&header, &fresh(), &EncodingKey::from_ed_pem(ED25519_A.as_bytes()).unwrap()).unwrap();
+    assert_eq!(signed_by_a().verify::<Claims>(&token, now), Err(KeyError::InvalidToken));
+}
+
+#[test]
+fn expired_tokens_are_rejected() {
+    let set = signed_by_a();
+    let token = set.sign(&claims(Utc::now() - Duration::minutes(5)), Utc::now()).unwrap();
+    assert_eq!(set.verify::<Claims>(&token, Utc::now()), Err(KeyError::InvalidToken));
+}
+
+#[test]
+fn a_new_key_is_published_before_it_signs() {
+    let now = Utc::now();
+    let set = keys(json!([
+        { "kid": "a", "alg": "EdDSA", "private_key": ED25519_A },
+        { "kid": "b", "alg": "EdDSA", "private_key": ED25519_B },
+    ]));
+
+    let token = set.sign(&fresh(), now).unwrap();
+    let header = jsonwebtoken::decode_header(&token).unwrap();
+    assert_eq!(header.kid.as_deref(), Some("a"));
+
+    let jwks = set.jwks(now);
+    let kids: Vec<&str> = jwks["keys"].as_array().unwrap().iter().map(|key| key["kid"].as_str().unwrap()).collect();
+    assert_eq!(kids, vec!["a", "b"]);
+}
+
+#[test]
+fn a_retired_key_verifies_through_the_grace_period() {
+    let retired_at = Utc::now();
+    let token = signed_by_a().sign(&fresh(), retired_at - Duration::minutes(1)).unwrap();
+
+    let rotated = keys(json!([
+        { "kid": "b", "alg": "EdDSA", "private_key": ED25519_B },
+        { "kid": "a", "alg": "EdDSA", "public_key": ED25519_A_PUBLIC, "retired_at": retired_at.to_rfc3339() },
+    ]));
+
+    let during = retired_at + Duration::minutes(59);
+    assert_eq!(rotated.verify::<Claims>(&token, during).unwrap().sub, "alice");
+    assert_eq!(rotated.jwks(during)["keys"].as_array().unwrap().len(), 2);
+    // New tokens come from the replacement
+    let new_token = rotated.sign(&fresh(), during).unwrap();
+    assert_eq!(jsonwebtoken::decode_header(&new_token).unwrap().kid.as_deref(), Some("b"));
+
+    let after = retired_at + Duration::minutes(61);
+    assert_eq!(rotated.verify::<Claims>(&token, after), Err(KeyError::UnknownKey));
+    assert_eq!(rotated.jwks(after)["keys"].as_array().unwrap().len(), 1);
+}
+
+#[test]
+fn a_set_with_only_retired_keys_cannot_sign() {
+    let set = keys(json!([
+        { "kid": "a", "alg": "EdDSA", "private_key": ED25519_A, "retired_at": "2026-01-01T00:00:00Z" },
+    ]));
+    assert_eq!(set.sign(&fresh(), Utc::now()), Err(KeyError::NoSigningKey));
+
+    let set = keys(json!([{ "kid": "a", "alg": "EdDSA", "public_key": ED25519_A_PUBLIC }]));
+    assert_eq!(set.sign(&fresh(), Utc::now()), Err(KeyError::NoSigningKey));
+}
+
+#[test]
+fn only_the_named_keys_algorithm_is_accepted() {
+    let now = Utc::now();
+    let set = keys(json!([
+        { "kid": "a", "alg": "EdDSA", "private_key": ED25519_A },
+        { "kid": "r", "alg": "RS256", "public_key": RSA_PUBLIC },
+    ]));
+
+    // A valid RS256 signature that claims to come from the Ed25519 key
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("a".to_string());
+    let registered = json!({ "sub": "alice", "exp": fresh().exp, "iss": DEFAULT_ISSUER, "aud": DEFAULT_AUDIENCE });
+    let token = encode(&header, &registered, &EncodingKey::from_rsa_pem(RSA.as_bytes()).unwrap()).unwrap();
+    assert_eq!(set.verify::<Claims>(&token, now), Err(KeyError::InvalidToken));
+
+    // HMAC keyed with the published public key
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some("r".to_string());
+    let token = encode(&header, &registered, &EncodingKey::from_secret(RSA_PUBLIC.as_bytes())).unwrap();
+    assert_eq!(set.verify::<Claims>(&token, now), Err(KeyError::InvalidToken));
+}
+
+#[test]
+fn unsigned_tokens_are_rejected() {
+    let header = URL_SAFE_NO_PAD.encode(json!({ "alg": "none", "kid": "a", "typ": "JWT" }).to_string());
+    let payload = URL_SAFE_NO_PAD.encode(
+        json!({ "sub": "alice", "exp": fresh().exp, "iss": DEFAULT_ISSUER, "aud": DEFAULT_AUDIENCE }).to_string(),
+    );
+    let token = format!("{}.{}.", header, payload);
+    assert!(signed_by_a().verify::<Claims>(&token, Utc::now()).is_err());
+}
+
+#[test]
+fn tokens_must_name_a_known_key() {
+    let now = Utc::now();
+    let registered = json!({ "sub": "alice", "exp": fresh().exp, "iss": DEFAULT_ISSUER, "aud": DEFAULT_AUDIENCE });
+    let encoding_key = EncodingKey::from_ed_pem(ED25519_A.as_bytes()).unwrap();
+
+    let token = encode(&Header::new(Algorithm::EdDSA), &registered, &encoding_key).unwrap();
+    assert_eq!(signed_by_a().verify::<Claims>(&token, now), Err(KeyError::UnknownKey));
+
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("z".to_string());
+    let token = encode(&header, &registered, &encoding_key).unwrap();
+    assert_eq!(signed_by_a().verify::<Claims>(&token, now), Err(KeyError::UnknownKey));
+
+    assert_eq!(signed_by_a().verify::<Claims>("not.a.token", now), Err(KeyError::InvalidToken));
+}
+
+#[test]
+fn broken_key_sets_are_refused() {
+    let grace = Duration::minutes(60);
+    for entries in [
+        json!([{ "kid": "a", "alg": "HS256", "private_key": ED25519_A }]),
+        json!([{ "kid": "", "alg": "EdDSA", "private_key": ED25519_A }]),
+        json!([{ "kid": "a", "alg": "EdDSA", "private_key": "not a key" }]),
+        json!([{ "kid": "a", "alg": "EdDSA", "private_key": RSA }]),
+        json!([{ "kid": "a", "alg": "EdDSA" }]),
+        json!([
+            { "kid": "a", "alg": "EdDSA", "private_key": ED25519_A },
+            { "kid": "a", "alg": "EdDSA", "private_key": ED25519_B },
+        ]),
+    ] {
+        assert!(
+            matches!(KeySet::from_json(&entries.to_string(), grace), Err(KeyError::InvalidKeySet(_))),
+            "{}",
+            entries
+        );
+    }
+    assert!(matches!(KeySet::from_json("{", grace), Err(KeyError::InvalidKeySet(_))));
+}
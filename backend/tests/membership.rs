@@ -0,0 +1,216 @@
+//! Club membership and permission checks, run against SQLite with the
+//! Worker's schema.
+
+mod common;
+
+use backend::membership::{self, MembershipError};
+use backend::models::MemberRole;
+use backend::store::{SqliteStore, Store};
+use chrono::Duration;
+use common::*;
+
+/// alice owns the chess club, bob is a plain member, carol isn't a member.
+fn chess_club() -> SqliteStore {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    add_user(&store, "carol");
+    add_club(&store, "chess", &alice.id);
+    add_member(&store, "chess", &bob, MemberRole::Member);
+    store
+}
+
+#[test]
+fn joining_with_an_invite_code_adds_a_plain_member() {
+    let store = chess_club();
+    add_invite_code(&store, "CHESS1", "chess", "alice", now() + Duration::days(1));
+
+    // Codes are matched case-insensitively
+    let member = run(membership::join_club(&store, "carol", " chess1 ", now())).unwrap();
+    assert_eq!(member.club_id, "chess");
+    assert_eq!(member.user.email, "carol@example.com");
+    assert!(matches!(member.role, MemberRole::Member));
+
+    let stored = run(store.member("chess", "carol")).unwrap().unwrap();
+    assert_eq!(stored.id, member.id);
+    let invite = run(store.invite_code("CHESS1")).unwrap().unwrap();
+    assert_eq!(invite.used_by.as_deref(), Some("carol"));
+}
+
+#[test]
+fn unknown_invite_codes_are_rejected() {
+    let store = chess_club();
+
+    let result = run(membership::join_club(&store, "carol", "NOPE00", now()));
+    assert!(matches!(result, Err(MembershipError::InvalidInviteCode)));
+}
+
+#[test]
+fn invite_codes_only_admit_one_member() {
+    let store = chess_club();
+    add_user(&store, "dave");
+    add_invite_code(&store, "CHESS1", "chess", "alice", now() + Duration::days(1));
+    run(membership::join_club(&store, "carol", "CHESS1", now())).unwrap();
+
+    let result = run(membership::join_club(&store, "dave", "CHESS1", now()));
+    assert!(matches!(result, Err(MembershipError::InviteCodeUsed)));
+    assert!(run(store.member("chess", "dave")).unwrap().is_none());
+}
+
+#[test]
+fn expired_invite_codes_are_rejected() {
+    let store = chess_club();
+    add_invite_code(&store, "STALE1", "chess", "alice", now() - Duration::minutes(1));
+
+    let result = run(membership::join_club(&store, "carol", "STALE1", now()));
+    assert!(matches!(result, Err(MembershipError::InviteCodeExpired)));
+    assert!(run(store.member("chess", "carol")).unwrap().is_none());
+}
+
+#[test]
+fn members_cannot_join_twice_or_burn_a_code() {
+    let store = chess_club();
+    add_invite_code(&store, "CHESS1", "chess", "alice", now() + Duration::days(1));
+
+    let result = run(membership::join_club(&store, "bob", "CHESS1", now()));
+    assert!(matches!(result, Err(MembershipError::AlreadyMember)));
+
+    let invite = run(store.invite_code("CHESS1")).unwrap().unwrap();
+    assert_eq!(invite.used_by, None);
+}
+
+#[test]
+fn only_members_pass_the_member_check() {
+    let store = chess_club();
+
+    assert!(run(membership::require_member(&store, "chess", "alice")).is_ok());
+    assert!(run(membership::require_member(&store, "chess", "bob")).is_ok());
+    assert!(matches!(run(membership::require_member(&store, "chess", "carol")), Err(MembershipError::NotMember)));
+    assert!(matches!(run(membership::require_member(&store, "go", "alice")), Err(MembershipError::NotMember)));
+}
+
+#[test]
+fn only_admins_pass_the_admin_check() {
+    let store = chess_club();
+
+    assert!(run(membership::require_admin(&store, "chess", "alice")).is_ok());
+    assert!(matches!(run(membership::require_admin(&store, "chess", "bob")), Err(MembershipError::NotAdmin)));
+    assert!(matches!(run(membership::require_admin(&store, "chess", "carol")), Err(MembershipError::NotMember)));
+}
+
+#[test]
+fn admins_need_two_factor_when_the_club_requires_it() {
+    let store = chess_club();
+    require_admin_mfa(&store, "chess");
+
+    assert!(matches!(run(membership::require_admin(&store, "chess", "alice")), Err(MembershipError::MfaRequired)));
+
+    enable_mfa(&store, "alice");
+    assert!(run(membership::require_admin(&store, "chess", "alice")).is_ok());
+}
+
+#[test]
+fn only_the_owner_passes_the_owner_check() {
+    let store = chess_club();
+
+    assert_eq!(run(membership::require_owner(&store, "chess", "alice")).unwrap().id, "chess");
+    assert!(matches!(run(membership::require_owner(&store, "chess", "bob")), Err(MembershipError::NotOwner)));
+    assert!(matches!(run(membership::require_owner(&store, "go", "alice")), Err(MembershipError::ClubNotFound)));
+}
+
+#[test]
+fn only_the_owner_can_change_the_admin_mfa_requirement() {
+    let store = chess_club();
+    enable_mfa(&store, "bob");
+
+    let result = run(membership::set_admin_mfa_requirement(&store, "chess", "bob", true, now()));
+    assert!(matches!(result, Err(MembershipError::NotOwner)));
+    assert!(!run(store.club("chess")).unwrap().unwrap().require_admin_mfa);
+}
+
+#[test]
+fn owners_need_two_factor_before_requiring_it() {
+    let store = chess_club();
+
+    let result = run(membership::set_admin_mfa_requirement(&store, "chess", "alice", true, now()));
+    assert!(matches!(result, Err(MembershipError::MfaRequired)));
+
+    enable_mfa(&store, "alice");
+    let club = run(membership::set_admin_mfa_requirement(&store, "chess", "alice", true, now())).unwrap();
+    assert!(club.require_admin_mfa);
+    assert_eq!(club.updated_at, now().to_rfc3339());
+
+    // Turning it off again never needs two-factor
+    let club = run(membership::set_admin_mfa_requirement(&store, "chess", "alice", false, now())).unwrap();
+    assert!(!club.require_admin_mfa);
+}
+
+#[test]
+fn ownership_goes_to_a_member_who_becomes_an_admin() {
+    let store = chess_club();
+
+    let club = run(membership::transfer_ownership(&store, "chess", "alice", "bob", now())).unwrap();
+    assert_eq!(club.owner_id, "bob");
+
+    let bob = run(store.member("chess", "bob")).unwrap().unwrap();
+    assert!(matches!(bob.role, MemberRole::Admin));
+    // The old owner stays on as an admin
+    let alice = run(store.member("chess", "alice")).unwrap().unwrap();
+    assert!(matches!(alice.role, MemberRole::Admin));
+}
+
+#[test]
+fn only_the_owner_can_transfer_ownership() {
+    let store = chess_club();
+
+    let result = run(membership::transfer_ownership(&store, "chess", "bob", "bob", now()));
+    assert!(matches!(result, Err(MembershipError::NotOwner)));
+
+    let result = run(membership::transfer_ownership(&store, "chess", "alice", "alice", now()));
+    assert!(matches!(result, Err(MembershipError::AlreadyOwner)));
+}
+
+#[test]
+fn ownership_only_goes_to_active_members() {
+    let store = chess_club();
+
+    let result = run(membership::transfer_ownership(&store, "chess", "alice", "carol", now()));
+    assert!(matches!(result, Err(MembershipError::NotMember)));
+
+    deactivate(&store, "bob");
+    let result = run(membership::transfer_ownership(&store, "chess", "alice", "bob", now()));
+    assert!(matches!(result, Err(MembershipError::NotMember)));
+
+    assert_eq!(run(store.club("chess")).unwrap().unwrap().owner_id, "alice");
+}
+
+#[test]
+fn new_owners_need_two_factor_when_admins_do() {
+    let store = chess_club();
+    enable_mfa(&store, "alice");
+    require_admin_mfa(&store, "chess");
+
+    let result = run(membership::transfer_ownership(&store, "chess", "alice", "bob", now()));
+    assert!(matches!(result, Err(MembershipError::MfaRequired)));
+    let bob = run(store.member("chess", "bob")).unwrap().unwrap();
+    assert!(matches!(bob.role, MemberRole::Member));
+
+    enable_mfa(&store, "bob");
+    let club = run(membership::transfer_ownership(&store, "chess", "alice", "bob", now())).unwrap();
+    assert_eq!(club.owner_id, "bob");
+}
+
+#[test]
+fn a_stale_transfer_changes_nothing() {
+    let store = chess_club();
+    let carol = run(store.user_by_id("carol")).unwrap().unwrap();
+    add_member(&store, "chess", &carol, MemberRole::Member);
+    run(membership::transfer_ownership(&store, "chess", "alice", "bob", now())).unwrap();
+
+    // alice's earlier view of the club no longer holds, so carol isn't promoted
+    run(store.transfer_club("chess", "alice", "carol", &now().to_rfc3339())).unwrap();
+
+    assert_eq!(run(store.club("chess")).unwrap().unwrap().owner_id, "bob");
+    let carol = run(store.member("chess", "carol")).unwrap().unwrap();
+    assert!(matches!(carol.role, MemberRole::Member));
+}
@@ -0,0 +1,166 @@
+//! Which routes the middleware lets through without signing in, the scope an
+//! access token needs for each, and the club membership guard, run against
+//! SQLite.
+
+mod common;
+
+use backend::error::ApiError;
+use backend::middleware::{self, Access, AuthContext};
+use backend::models::MemberRole;
+use common::*;
+use worker::Method;
+
+#[test]
+fn sign_in_routes_are_public() {
+    for (method, path) in [
+        (Method::Get, "/"),
+        (Method::Get, "/.well-known/jwks.json"),
+        (Method::Post, "/api/auth/login"),
+        (Method::Post, "/api/auth/signup"),
+        (Method::Post, "/api/auth/mfa/verify"),
+        (Method::Post, "/api/auth/passkeys/login"),
+        (Method::Post, "/api/auth/oauth/github/authorize"),
+        (Method::Get, "/api/auth/oauth/github/callback"),
+    ] {
+        assert_eq!(middleware::access(&method, path), Access::Public, "{:?} {}", method, path);
+    }
+}
+
+#[test]
+fn everything_else_needs_a_signed_in_caller() {
+    for (method, path) in [
+        (Method::Get, "/api/auth/me"),
+        (Method::Get, "/api/csrf-token"),
+        (Method::Post, "/api/auth/oauth/github/link"),
+        (Method::Post, "/api/auth/mfa/setup"),
+        (Method::Get, "/api/clubs"),
+        (Method::Get, "/api/clubs/chess"),
+        (Method::Post, "/api/events"),
+        (Method::Get, "/api/meetings/1/rsvps"),
+        (Method::Put, "/api/forum/questions/1/claim"),
+        // Only the listed method of a public route is public
+        (Method::Get, "/api/auth/login"),
+        (Method::Delete, "/api/auth/oauth/github/callback"),
+        // Routes nobody has thought about yet
+        (Method::Get, "/api/reports"),
+        (Method::Post, "/api/auth/login/extra"),
+    ] {
+        assert_eq!(middleware::access(&method, path), Access::Private, "{:?} {}", method, path);
+    }
+}
+
+#[test]
+fn routes_under_a_club_need_its_membership() {
+    assert_eq!(middleware::access(&Method::Get, "/api/clubs/chess/members"), Access::Club("chess"));
+    assert_eq!(middleware::access(&Method::Get, "/api/clubs/chess/events"), Access::Club("chess"));
+    assert_eq!(middleware::access(&Method::Put, "/api/clubs/chess/security"), Access::Club("chess"));
+    assert_eq!(middleware::access(&Method::Get, "/api/clubs//events"), Access::Private);
+}
+
+#[test]
+fn route_patterns_match_whole_segments() {
+    assert!(middleware::route_matches("/api/auth/oauth/:provider/callback", "/api/auth/oauth/google/callback"));
+    assert!(!middleware::route_matches("/api/auth/oauth/:provider/callback", "/api/auth/oauth//callback"));
+    assert!(!middleware::route_matches("/api/auth/oauth/:provider/callback", "/api/auth/oauth/google"));
+    assert!(!middleware::route_matches("/api/auth/login", "/api/auth/login/"));
+    assert!(!middleware::route_matches("/api/auth/login", "/api/auth/logins"));
+}
+
+#[test]
+fn only_safe_methods_skip_csrf() {
+    assert!(!middleware::is_unsafe(&Method::Get));
+    assert!(!middleware::is_unsafe(&Method::Head));
+    assert!(!middleware::is_unsafe(&Method::Options));
+    for method in [Method::Post, Method::Put, Method::Patch, Method::Delete] {
+        assert!(middleware::is_unsafe(&method));
+    }
+}
+
+#[test]
+fn access_tokens_are_limited_to_their_scopes() {
+    let token = AuthContext {
+        user_id: "alice".to_string(),
+        session_id: None,
+        scopes: Some(vec!["events:read".to_string()]),
+    };
+    assert!(token.has_scope("events:read"));
+    assert!(!token.has_scope("events:write"));
+
+    let session = AuthContext {
+        user_id: "alice".to_string(),
+        session_id: Some("session".to_string()),
+        scopes: None,
+    };
+    assert!(session.has_scope("events:write"));
+}
+
+#[test]
+fn each_api_area_has_its_own_scope() {
+    for (method, path, scope) in [
+        (Method::Get, "/api/auth/me", "profile:read"),
+        (Method::Get, "/api/clubs", "clubs:read"),
+        (Method::Post, "/api/clubs", "clubs:write"),
+        (Method::Get, "/api/clubs/chess", "clubs:read"),
+        (Method::Put, "/api/clubs/chess", "clubs:write"),
+        (Method::Delete, "/api/clubs/chess", "clubs:write"),
+        (Method::Get, "/api/clubs/chess/members", "members:read"),
+        (Method::Post, "/api/clubs/chess/members", "members:write"),
+        (Method::Get, "/api/clubs/chess/events", "events:read"),
+        (Method::Post, "/api/clubs/chess/announcements", "announcements:write"),
+        (Method::Get, "/api/clubs/chess/projects", "projects:read"),
+        (Method::Put, "/api/members/1", "members:write"),
+        (Method::Delete, "/api/events/1", "events:write"),
+        (Method::Put, "/api/announcements/1", "announcements:write"),
+        (Method::Get, "/api/projects/1", "projects:read"),
+        (Method::Post, "/api/meetings", "meetings:write"),
+        (Method::Get, "/api/forum/questions", "forum:read"),
+    ] {
+        assert_eq!(middleware::required_scope(&method, path).as_deref(), Some(scope), "{:?} {}", method, path);
+    }
+}
+
+#[test]
+fn account_and_ownership_routes_need_a_session() {
+    for (method, path) in [
+        (Method::Put, "/api/auth/me"),
+        (Method::Post, "/api/auth/logout"),
+        (Method::Get, "/api/auth/sessions"),
+        (Method::Post, "/api/auth/tokens"),
+        (Method::Delete, "/api/auth/account"),
+        (Method::Get, "/api/auth/export"),
+        (Method::Get, "/api/csrf-token"),
+        (Method::Put, "/api/clubs/chess/security"),
+        (Method::Post, "/api/clubs/chess/transfer"),
+        (Method::Get, "/api/admin/invites"),
+    ] {
+        assert_eq!(middleware::required_scope(&method, path), None, "{:?} {}", method, path);
+    }
+}
+
+#[test]
+fn the_club_guard_loads_the_callers_role() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    add_user(&store, "carol");
+    add_club(&store, "chess", &alice.id);
+    add_member(&store, "chess", &bob, MemberRole::Member);
+
+    let owner = run(middleware::club_member(&store, "chess", "alice")).unwrap();
+    assert_eq!(owner.club_id, "chess");
+    assert!(owner.is_admin());
+
+    let member = run(middleware::club_member(&store, "chess", "bob")).unwrap();
+    assert!(!member.is_admin());
+
+    let Err(error) = run(middleware::club_member(&store, "chess", "carol")) else {
+        panic!("carol isn't a member");
+    };
+    assert!(matches!(error, ApiError::Forbidden(_)));
+
+    // A club that doesn't exist looks the same as one the caller isn't in
+    let Err(error) = run(middleware::club_member(&store, "go", "alice")) else {
+        panic!("there is no go club");
+    };
+    assert_eq!(error.status(), 403);
+}
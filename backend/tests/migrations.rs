@@ -0,0 +1,242 @@
+//! The migration runner, run against bare SQLite databases.
+
+mod common;
+
+use backend::migrations::{self, Migration, MigrationError, MigrationFuture, MigrationTarget};
+use backend::store::{inline_params, Database, SqlValue, SqliteStore, Store};
+use common::*;
+
+fn empty() -> SqliteStore {
+    SqliteStore::open(":memory:").unwrap()
+}
+
+fn tables(store: &SqliteStore) -> Vec<String> {
+    let rows = run(store.query("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name", &[])).unwrap();
+    rows.iter().map(|row| row["name"].as_str().unwrap().to_string()).collect()
+}
+
+fn notes() -> Vec<Migration<SqliteStore>> {
+    vec![
+        Migration::sql(1, "create_notes", "CREATE TABLE notes (id INTEGER PRIMARY KEY, body TEXT NOT NULL);"),
+        Migration::sql(2, "add_pinned", "ALTER TABLE notes ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;"),
+    ]
+}
+
+fn pin_short_notes(db: &SqliteStore) -> MigrationFuture<'_> {
+    Box::pin(async move {
+        let rows = db.query("SELECT id, body FROM notes", &[]).await?;
+        for row in rows {
+            if row["body"].as_str().unwrap_or_default().len() < 10 {
+                db.execute("UPDATE notes SET pinned = 1 WHERE id = ?1", &[row["id"].as_i64().into()]).await?;
+            }
+        }
+        Ok(())
+    })
+}
+
+#[test]
+fn every_migration_is_applied_once_in_order() {
+    let store = empty();
+
+    let applied = run(migrations::migrate(&store, &notes(), now())).unwrap();
+    assert_eq!(applied, vec![1, 2]);
+    run(store.execute("INSERT INTO notes (body) VALUES ('hello')", &[])).unwrap();
+
+    let applied = run(migrations::migrate(&store, &notes(), now())).unwrap();
+    assert!(applied.is_empty());
+
+    let status = run(migrations::status(&store, &notes())).unwrap();
+    assert_eq!(status.len(), 2);
+    assert!(status.iter().all(|migration| migration.applied_at == Some(now().to_rfc3339())));
+}
+
+#[test]
+fn only_new_migrations_are_applied() {
+    let store = empty();
+    let mut list = notes();
+    let second = list.pop().unwrap();
+    run(migrations::migrate(&store, &list, now())).unwrap();
+
+    let status = run(migrations::status(&store, &notes())).unwrap();
+    assert!(status[0].applied_at.is_some());
+    assert_eq!(status[1].applied_at, None);
+
+    list.push(second);
+    assert_eq!(run(migrations::migrate(&store, &list, now())).unwrap(), vec![2]);
+}
+
+#[test]
+fn data_migrations_run_in_rust() {
+    let store = empty();
+    run(migrations::migrate(&store, &notes(), now())).unwrap();
+    run(store.execute("INSERT INTO notes (body) VALUES ('short'), ('a much longer note')", &[])).unwrap();
+
+    let mut list = notes();
+    list.push(Migration::rust(3, "pin_short_notes", pin_short_notes));
+    assert_eq!(run(migrations::migrate(&store, &list, now())).unwrap(), vec![3]);
+
+    let pinned = run(store.query("SELECT body FROM notes WHERE pinned = 1", &[])).unwrap();
+    assert_eq!(pinned.len(), 1);
+    assert_eq!(pinned[0]["body"], "short");
+    assert!(run(migrations::status(&store, &list)).unwrap()[2].applied_at.is_some());
+}
+
+#[test]
+fn editing_an_applied_migration_is_refused() {
+    let store = empty();
+    run(migrations::migrate(&store, &notes(), now())).unwrap();
+
+    let mut list = notes();
+    list[1] = Migration::sql(2, "add_pinned", "ALTER TABLE notes ADD COLUMN pinned INTEGER;");
+    let result = run(migrations::migrate(&store, &list, now()));
+    assert!(matches!(result, Err(MigrationError::Modified { version: 2, .. })));
+
+    let mut list = notes();
+    list[1] = Migration::sql(2, "add_flag", "ALTER TABLE notes ADD COLUMN pinned INTEGER NOT NULL DEFAULT 0;");
+    let result = run(migrations::status(&store, &list));
+    assert!(matches!(result, Err(MigrationError::Modified { version: 2, .. })));
+}
+
+#[test]
+fn line_endings_do_not_change_the_checksum() {
+    let unix: Migration<SqliteStore> = Migration::sql(1, "create", "CREATE TABLE a (id INTEGER);\nCREATE TABLE b (id INTEGER);\n");
+    let windows: Migration<SqliteStore> = Migration::sql(1, "create", "CREATE TABLE a (id INTEGER);\r\nCREATE TABLE b (id INTEGER);\r\n");
+    assert_eq!(unix.checksum(), windows.checksum());
+}
+
+#[test]
+fn databases_migrated_by_newer_code_are_refused() {
+    let store = empty();
+    run(migrations::migrate(&store, &notes(), now())).unwrap();
+
+    let mut list = notes();
+    list.pop();
+    let result = run(migrations::migrate(&store, &list, now()));
+    assert!(matches!(result, Err(MigrationError::Unknown { version: 2, .. })));
+}
+
+#[test]
+fn pending_migrations_older_than_applied_ones_are_refused() {
+    let store = empty();
+    let mut list = notes();
+    list.push(Migration::sql(5, "create_tags", "CREATE TABLE tags (id INTEGER PRIMARY KEY);"));
+    list.remove(1);
+    run(migrations::migrate(&store, &list, now())).unwrap();
+
+    let mut list = notes();
+    list.push(Migration::sql(5, "create_tags", "CREATE TABLE tags (id INTEGER PRIMARY KEY);"));
+    let result = run(migrations::migrate(&store, &list, now()));
+    assert!(matches!(result, Err(MigrationError::OutOfOrder { version: 2, latest: 5 })));
+}
+
+#[test]
+fn migrations_must_be_listed_in_order() {
+    let store = empty();
+    let mut list = notes();
+    list.swap(0, 1);
+
+    let result = run(migrations::migrate(&store, &list, now()));
+    assert!(matches!(result, Err(MigrationError::InvalidOrder { version: 1 })));
+    assert!(!tables(&store).contains(&"notes".to_string()));
+}
+
+#[test]
+fn a_failing_migration_is_rolled_back_and_not_recorded() {
+    let store = empty();
+    let mut list = notes();
+    list.push(Migration::sql(3, "broken", "CREATE TABLE tags (id INTEGER PRIMARY KEY);\nINSERT INTO missing VALUES (1);"));
+
+    let result = run(migrations::migrate(&store, &list, now()));
+    assert!(matches!(result, Err(MigrationError::Failed { version: 3, .. })));
+
+    assert!(!tables(&store).contains(&"tags".to_string()));
+    // The migrations before it stay applied
+    let status = run(migrations::status(&store, &list)).unwrap();
+    assert!(status[1].applied_at.is_some());
+    assert_eq!(status[2].applied_at, None);
+}
+
+#[test]
+fn the_registered_migrations_build_the_schema() {
+    let store = empty();
+    let applied = run(migrations::migrate(&store, &migrations::all(), now())).unwrap();
+    assert_eq!(applied.len(), migrations::all::<SqliteStore>().len());
+
+    let tables = tables(&store);
+    for table in ["users", "sessions", "clubs", "members", "schema_migrations"] {
+        assert!(tables.contains(&table.to_string()), "missing table {}", table);
+    }
+
+    let alice = add_user(&store, "alice");
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().email, alice.email);
+}
+
+#[test]
+fn every_migration_file_is_registered() {
+    let directory = concat!(env!("CARGO_MANIFEST_DIR"), "/migrations");
+    let mut files: Vec<String> = std::fs::read_dir(directory)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".sql"))
+        .collect();
+    files.sort();
+
+    let registered: Vec<String> = migrations::all::<SqliteStore>()
+        .iter()
+        .filter(|migration| matches!(migration.step, migrations::Step::Sql(_)))
+        .map(|migration| format!("{:04}_{}.sql", migration.version, migration.name))
+        .collect();
+    assert_eq!(files, registered);
+}
+
+#[test]
+fn parameters_are_inlined_as_literals() {
+    let sql = inline_params(
+        "INSERT INTO notes (id, body, score, note) VALUES (?1, ?2, ?3, ?)",
+        &[7.into(), "it's".into(), 1.5.into(), SqlValue::Null],
+    )
+    .unwrap();
+    assert_eq!(sql, "INSERT INTO notes (id, body, score, note) VALUES (7, 'it''s', 1.5, NULL)");
+
+    // Question marks in strings and comments aren't placeholders
+    let sql = inline_params("SELECT '?1', \"a?\" -- why?\nFROM t WHERE id = ?1", &["x".into()]).unwrap();
+    assert_eq!(sql, "SELECT '?1', \"a?\" -- why?\nFROM t WHERE id = 'x'");
+
+    assert!(inline_params("SELECT ?2", &["x".into()]).is_err());
+}
+
+#[test]
+fn databases_set_up_from_the_old_schema_file_are_brought_up_to_date() {
+    // Before migrations, databases were created by running schema.sql by hand,
+    // which is exactly what 0001 holds
+    let store = empty();
+    run(store.execute_script(include_str!("../migrations/0001_initial_schema.sql"))).unwrap();
+    run(store.execute("
+        INSERT INTO users (id, email, password_hash, name, created_at, updated_at)
+        VALUES ('alice', 'alice@example.com', 'hash', 'Alice', '2025-01-01T00:00:00+00:00', '2025-01-01T00:00:00+00:00')
+    ", &[])).unwrap();
+    run(store.execute("
+        INSERT INTO clubs (id, name, created_at, updated_at, owner_id)
+        VALUES ('chess', 'Chess club', '2025-01-01T00:00:00+00:00', '2025-01-01T00:00:00+00:00', 'alice')
+    ", &[])).unwrap();
+
+    let applied = run(migrations::migrate(&store, &migrations::all(), now())).unwrap();
+    assert_eq!(applied.len(), migrations::all::<SqliteStore>().len());
+
+    let alice = run(store.user_by_id("alice")).unwrap().unwrap();
+    assert_eq!(alice.email, "alice@example.com");
+    let club = run(store.club("chess")).unwrap().unwrap();
+    assert!(!club.require_admin_mfa);
+
+    // The columns added since are there for the code that reads them
+    let row = run(store.query_first("SELECT deletion_scheduled_for, deleted_at FROM users WHERE id = 'alice'", &[]))
+        .unwrap()
+        .unwrap();
+    assert!(row["deletion_scheduled_for"].is_null());
+    assert!(row["deleted_at"].is_null());
+    let tables = tables(&store);
+    assert!(!tables.contains(&"csrf_tokens".to_string()));
+    for table in ["refresh_tokens", "user_mfa", "passkeys", "auth_events", "platform_invites"] {
+        assert!(tables.contains(&table.to_string()), "missing table {}", table);
+    }
+}
@@ -0,0 +1,231 @@
+//! Social sign-in: PKCE, ID token checks and reading GitHub and Discord
+//! profiles. ID tokens are signed with the test keys in `fixtures/jwt`,
+//! standing in for the identity provider.
+
+use backend::jwt::KeySet;
+use backend::models::SocialProvider;
+use backend::oauth::{self, OAuthError, ProviderConfig};
+use chrono::{Duration, Utc};
+use jsonwebtoken::jwk::JwkSet;
+use jsonwebtoken::{encode, Algorithm, EncodingKey, Header};
+use serde_json::{json, Value};
+
+const RSA: &str = include_str!("fixtures/jwt/rsa.pem");
+const ED25519: &str = include_str!("fixtures/jwt/ed25519-a.pem");
+
+const CLIENT_ID: &str = "nivaro-client";
+const NONCE: &str = "n-0S6_WzA2Mj";
+
+fn config(provider: SocialProvider, issuer: &str) -> ProviderConfig {
+    ProviderConfig {
+        provider,
+        client_id: CLIENT_ID.to_string(),
+        client_secret: "secret".to_string(),
+        authorize_url: "https://idp.example/authorize".to_string(),
+        token_url: "https://idp.example/token".to_string(),
+        userinfo_url: None,
+        emails_url: None,
+        jwks_url: Some("https://idp.example/keys".to_string()),
+        issuer: Some(issuer.to_string()),
+        scope: "openid email profile".to_string(),
+    }
+}
+
+fn google() -> ProviderConfig {
+    config(SocialProvider::Google, "https://accounts.google.com")
+}
+
+fn microsoft() -> ProviderConfig {
+    config(SocialProvider::Microsoft, "https://login.microsoftonline.com/{tenantid}/v2.0")
+}
+
+// The provider's published keys, in the same JWK form Nivaro publishes its own
+fn jwks() -> JwkSet {
+    let keys = json!([{ "kid": "idp-1", "alg": "RS256", "private_key": RSA }]).to_string();
+    let keys = KeySet::from_json(&keys, Duration::minutes(60)).unwrap();
+    serde_json::from_value(keys.jwks(Utc::now())).unwrap()
+}
+
+fn claims() -> Value {
+    json!({
+        "iss": "https://accounts.google.com",
+        "aud": CLIENT_ID,
+        "sub": "1234567890",
+        "exp": (Utc::now() + Duration::minutes(10)).timestamp(),
+        "nonce": NONCE,
+        "email": "alice@example.com",
+        "email_verified": true,
+        "name": "Alice",
+        "picture": "https://example.com/alice.png",
+    })
+}
+
+fn sign_with(claims: &Value, kid: Option<&str>) -> String {
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = kid.map(|kid| kid.to_string());
+    encode(&header, claims, &EncodingKey::from_rsa_pem(RSA.as_bytes()).unwrap()).unwrap()
+}
+
+fn sign(claims: &Value) -> String {
+    sign_with(claims, Some("idp-1"))
+}
+
+fn with(changes: Value) -> Value {
+    let mut claims = claims();
+    for (key, value) in changes.as_object().unwrap() {
+        claims[key] = value.clone();
+    }
+    claims
+}
+
+#[test]
+fn the_pkce_challenge_matches_rfc_7636() {
+    // Appendix B
+    assert_eq!(
+        oauth::pkce_challenge("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk"),
+        "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    );
+}
+
+#[test]
+fn the_authorization_url_carries_the_challenge_and_nonce() {
+    let url = google().authorization_url("https://api.example/callback", "state-1", "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk", NONCE);
+    assert!(url.starts_with("https://idp.example/authorize?response_type=code&client_id=nivaro-client&"));
+    assert!(url.contains("&code_challenge=E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM&code_challenge_method=S256"));
+    assert!(url.contains(&format!("&nonce={}", NONCE)));
+    assert!(url.contains("&state=state-1&"));
+
+    let mut github = google();
+    github.jwks_url = None;
+    assert!(!github.authorization_url("https://api.example/callback", "state-1", "verifier", NONCE).contains("nonce="));
+}
+
+#[test]
+fn a_valid_id_token_gives_the_identity() {
+    let identity = oauth::verify_id_token(&google(), &jwks(), &sign(&claims()), NONCE).unwrap();
+    assert_eq!(identity.provider_id, "1234567890");
+    assert_eq!(identity.email, "alice@example.com");
+    assert!(identity.email_verified);
+    assert_eq!(identity.name, "Alice");
+    assert_eq!(identity.avatar.as_deref(), Some("https://example.com/alice.png"));
+
+    // Without a kid the only published key is used
+    assert!(oauth::verify_id_token(&google(), &jwks(), &sign_with(&claims(), None), NONCE).is_ok());
+}
+
+#[test]
+fn id_tokens_for_someone_else_are_rejected() {
+    for claims in [
+        with(json!({ "iss": "https://accounts.example" })),
+        with(json!({ "aud": "another-client" })),
+        with(json!({ "nonce": "replayed" })),
+        with(json!({ "exp": (Utc::now() - Duration::minutes(5)).timestamp() })),
+    ] {
+        assert_eq!(
+            oauth::verify_id_token(&google(), &jwks(), &sign(&claims), NONCE).unwrap_err(),
+            OAuthError::InvalidIdToken,
+            "{}",
+            claims
+        );
+    }
+
+    let mut claims = claims();
+    claims.as_object_mut().unwrap().remove("nonce");
+    assert_eq!(oauth::verify_id_token(&google(), &jwks(), &sign(&claims), NONCE).unwrap_err(), OAuthError::InvalidIdToken);
+}
+
+#[test]
+fn id_tokens_signed_with_an_unknown_key_are_rejected() {
+    assert_eq!(
+        oauth::verify_id_token(&google(), &jwks(), &sign_with(&claims(), Some("idp-2")), NONCE).unwrap_err(),
+        OAuthError::InvalidIdToken
+    );
+
+    // Right kid, wrong key
+    let mut header = Header::new(Algorithm::EdDSA);
+    header.kid = Some("idp-1".to_string());
+    let token = encode(&header, &claims(), &EncodingKey::from_ed_pem(ED25519.as_bytes()).unwrap()).unwrap();
+    assert_eq!(oauth::verify_id_token(&google(), &jwks(), &token, NONCE).unwrap_err(), OAuthError::InvalidIdToken);
+
+    // HMAC under something an attacker could know
+    let mut header = Header::new(Algorithm::HS256);
+    header.kid = Some("idp-1".to_string());
+    let token = encode(&header, &claims(), &EncodingKey::from_secret(CLIENT_ID.as_bytes())).unwrap();
+    assert_eq!(oauth::verify_id_token(&google(), &jwks(), &token, NONCE).unwrap_err(), OAuthError::InvalidIdToken);
+}
+
+#[test]
+fn microsoft_issuers_are_checked_per_tenant() {
+    let tenant = "9188040d-6c67-4c5b-b112-36a304b66dad";
+    let claims = with(json!({
+        "iss": format!("https://login.microsoftonline.com/{}/v2.0", tenant),
+        "tid": tenant,
+    }));
+    let identity = oauth::verify_id_token(&microsoft(), &jwks(), &sign(&claims), NONCE).unwrap();
+    // Microsoft's email claim isn't verified for every account type
+    assert!(!identity.email_verified);
+
+    let other_tenant = with(json!({
+        "iss": format!("https://login.microsoftonline.com/{}/v2.0", tenant),
+        "tid": "72f988bf-86f1-41af-91ab-2d7cd011db47",
+    }));
+    assert_eq!(oauth::verify_id_token(&microsoft(), &jwks(), &sign(&other_tenant), NONCE).unwrap_err(), OAuthError::InvalidIdToken);
+
+    let no_tenant = with(json!({ "iss": "https://login.microsoftonline.com/{tenantid}/v2.0" }));
+    assert_eq!(oauth::verify_id_token(&microsoft(), &jwks(), &sign(&no_tenant), NONCE).unwrap_err(), OAuthError::InvalidIdToken);
+}
+
+#[test]
+fn github_uses_the_primary_verified_address() {
+    let user = json!({ "id": 583231, "login": "octocat", "name": "", "avatar_url": "https://github.com/octocat.png" });
+    let emails = json!([
+        { "email": "old@example.com", "primary": false, "verified": true },
+        { "email": "octocat@example.com", "primary": true, "verified": true },
+    ]);
+    let identity = oauth::github_identity(&user, &emails).unwrap();
+    assert_eq!(identity.provider_id, "583231");
+    assert_eq!(identity.email, "octocat@example.com");
+    assert!(identity.email_verified);
+    assert_eq!(identity.name, "octocat");
+
+    for emails in [
+        json!([
+            { "email": "octocat@example.com", "primary": true, "verified": false },
+            { "email": "old@example.com", "primary": false, "verified": true },
+        ]),
+        json!([]),
+        json!({ "message": "Requires authentication" }),
+    ] {
+        assert_eq!(oauth::github_identity(&user, &emails).unwrap_err(), OAuthError::MissingVerifiedEmail);
+    }
+
+    assert_eq!(oauth::github_identity(&json!({ "login": "octocat" }), &json!([])).unwrap_err(), OAuthError::ProfileUnavailable);
+}
+
+#[test]
+fn discord_reports_whether_the_address_is_verified() {
+    let user = json!({
+        "id": "80351110224678912",
+        "username": "nelly",
+        "global_name": "Nelly",
+        "email": "nelly@example.com",
+        "verified": true,
+        "avatar": "8342729096ea3675442027381ff50dfe",
+    });
+    let identity = oauth::discord_identity(&user).unwrap();
+    assert_eq!(identity.email, "nelly@example.com");
+    assert!(identity.email_verified);
+    assert_eq!(identity.name, "Nelly");
+    assert_eq!(
+        identity.avatar.as_deref(),
+        Some("https://cdn.discordapp.com/avatars/80351110224678912/8342729096ea3675442027381ff50dfe.png")
+    );
+
+    let mut unverified = user.clone();
+    unverified["verified"] = json!(false);
+    assert!(!oauth::discord_identity(&unverified).unwrap().email_verified);
+
+    let mut no_email = user.clone();
+    no_email.as_object_mut().unwrap().remove("email");
+    assert_eq!(oauth::discord_identity(&no_email).unwrap_err(), OAuthError::MissingVerifiedEmail);
+}
@@ -0,0 +1,74 @@
+//! Password hashing with the native PBKDF2 fallback, and bcrypt hashes from
+//! before the switch.
+
+mod common;
+
+use backend::password::{self, HashParams};
+use common::run;
+
+fn params(iterations: u32) -> HashParams {
+    HashParams { iterations }
+}
+
+#[test]
+fn hashes_verify_their_own_password() {
+    let hash = run(password::hash_password("Correct horse 1!", params(10_000))).unwrap();
+    assert!(hash.starts_with("$pbkdf2-sha256$i=10000$"));
+
+    assert!(run(password::verify_password("Correct horse 1!", &hash)));
+    assert!(!run(password::verify_password("correct horse 1!", &hash)));
+    assert!(!run(password::verify_password("", &hash)));
+}
+
+#[test]
+fn every_hash_gets_its_own_salt() {
+    let first = run(password::hash_password("Correct horse 1!", params(10_000))).unwrap();
+    let second = run(password::hash_password("Correct horse 1!", params(10_000))).unwrap();
+    assert_ne!(first, second);
+}
+
+#[test]
+fn stored_hashes_follow_pbkdf2_sha256() {
+    // PBKDF2-HMAC-SHA256("password", "salt", 1), a widely published vector
+    let stored = "$pbkdf2-sha256$i=1$c2FsdA$Eg+2z/z4syxD5yJSVsT4N6hlSMkszDVICAWYfLcL4Xs";
+    assert!(run(password::verify_password("password", stored)));
+    assert!(!run(password::verify_password("passwore", stored)));
+}
+
+#[test]
+fn legacy_bcrypt_hashes_still_verify() {
+    let stored = bcrypt::hash("Correct horse 1!", 4).unwrap();
+    assert!(run(password::verify_password("Correct horse 1!", &stored)));
+    assert!(!run(password::verify_password("Wrong horse 1!", &stored)));
+}
+
+#[test]
+fn malformed_or_empty_hashes_never_match() {
+    for stored in [
+        "",
+        "plaintext",
+        "$pbkdf2-sha256$i=0$c2FsdA$Eg+2z/z4syxD5yJSVsT4N6hlSMkszDVICAWYfLcL4Xs",
+        "$pbkdf2-sha256$i=1$c2FsdA$c2hvcnQ",
+        "$pbkdf2-sha1$i=1$c2FsdA$Eg+2z/z4syxD5yJSVsT4N6hlSMkszDVICAWYfLcL4Xs",
+        "$pbkdf2-sha256$i=1$c2FsdA$Eg+2z/z4syxD5yJSVsT4N6hlSMkszDVICAWYfLcL4Xs$extra",
+    ] {
+        assert!(!run(password::verify_password("password", stored)), "{:?}", stored);
+    }
+}
+
+#[test]
+fn only_weaker_hashes_are_rehashed() {
+    let hash = run(password::hash_password("Correct horse 1!", params(20_000))).unwrap();
+
+    assert!(password::needs_rehash(&hash, params(30_000)));
+    assert!(!password::needs_rehash(&hash, params(20_000)));
+    // Lowering the configured count never weakens hashes already stored
+    assert!(!password::needs_rehash(&hash, params(10_000)));
+}
+
+#[test]
+fn bcrypt_hashes_are_always_rehashed() {
+    let stored = bcrypt::hash("Correct horse 1!", 4).unwrap();
+    assert!(password::needs_rehash(&stored, HashParams::default()));
+    assert!(!password::needs_rehash("", HashParams::default()));
+}
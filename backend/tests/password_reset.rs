@@ -0,0 +1,98 @@
+//! Password reset tokens, run against SQLite.
+
+mod common;
+
+use backend::password_reset::{self, PASSWORD_RESET_TTL_MINUTES};
+use backend::store::Database;
+use backend::token::hash_token;
+use chrono::Duration;
+use common::*;
+
+#[test]
+fn a_token_resets_the_password_of_its_user() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    assert_eq!(run(password_reset::redeem(&store, &token, now())).unwrap().as_deref(), Some("alice"));
+}
+
+#[test]
+fn only_the_hash_is_stored() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    let row = run(store.query_first("SELECT token FROM password_resets WHERE user_id = 'alice'", &[])).unwrap().unwrap();
+    assert_eq!(row["token"].as_str(), Some(hash_token(&token).as_str()));
+}
+
+#[test]
+fn a_token_works_once() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    assert!(run(password_reset::redeem(&store, &token, now())).unwrap().is_some());
+    assert!(run(password_reset::redeem(&store, &token, now())).unwrap().is_none());
+}
+
+#[test]
+fn a_token_expires() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    let expiry = now() + Duration::minutes(PASSWORD_RESET_TTL_MINUTES);
+    assert!(run(password_reset::redeem(&store, &token, expiry + Duration::seconds(1))).unwrap().is_none());
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    assert!(run(password_reset::redeem(&store, &token, expiry)).unwrap().is_some());
+}
+
+#[test]
+fn a_new_token_supersedes_the_old_one() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let old = run(password_reset::create(&store, "alice", now())).unwrap();
+    let new = run(password_reset::create(&store, "alice", now() + Duration::minutes(1))).unwrap();
+    assert!(run(password_reset::redeem(&store, &old, now())).unwrap().is_none());
+    assert!(run(password_reset::redeem(&store, &new, now())).unwrap().is_some());
+}
+
+#[test]
+fn changing_the_password_kills_outstanding_tokens() {
+    let store = store();
+    add_user(&store, "alice");
+
+    let token = run(password_reset::create(&store, "alice", now())).unwrap();
+    run(password_reset::invalidate(&store, "alice", now())).unwrap();
+    assert!(run(password_reset::redeem(&store, &token, now())).unwrap().is_none());
+}
+
+#[test]
+fn unknown_tokens_are_refused() {
+    let store = store();
+    add_user(&store, "alice");
+    run(password_reset::create(&store, "alice", now())).unwrap();
+
+    assert!(run(password_reset::redeem(&store, "not-a-token", now())).unwrap().is_none());
+    assert!(run(password_reset::redeem(&store, "", now())).unwrap().is_none());
+}
+
+#[test]
+fn recent_links_are_counted_per_user_for_an_hour() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+
+    run(password_reset::create(&store, "alice", now() - Duration::minutes(90))).unwrap();
+    run(password_reset::create(&store, "alice", now() - Duration::minutes(30))).unwrap();
+    run(password_reset::create(&store, "alice", now() - Duration::minutes(10))).unwrap();
+    run(password_reset::create(&store, "bob", now())).unwrap();
+
+    assert_eq!(run(password_reset::count_recent(&store, "alice", now())).unwrap(), 2);
+    assert_eq!(run(password_reset::count_recent(&store, "bob", now())).unwrap(), 1);
+}
@@ -0,0 +1,174 @@
+//! Sliding-window rate limiting against the in-memory counter store.
+
+use backend::rate_limit::{self, CounterStore, Decision, MemoryCounterStore, RateLimit};
+use std::future::Future;
+use std::pin::pin;
+use std::task::{Context, Poll, Waker};
+use worker::Method;
+
+const LIMIT: RateLimit = RateLimit { name: "test", limit: 3, window_seconds: 60 };
+// A multiple of every window below, so offsets read as seconds into a window
+const T0: i64 = 1_800_000_000;
+
+/// The memory store never suspends, so a single poll finishes every call.
+fn run<F: Future>(future: F) -> F::Output {
+    let mut context = Context::from_waker(Waker::noop());
+    match pin!(future).poll(&mut context) {
+        Poll::Ready(output) => output,
+        Poll::Pending => panic!("memory store futures should never be pending"),
+    }
+}
+
+fn hit(store: &MemoryCounterStore, subject: &str, now: i64) -> Decision {
+    run(rate_limit::check(store, &LIMIT, subject, now))
+}
+
+#[test]
+fn allows_requests_up_to_the_limit() {
+    let store = MemoryCounterStore::new();
+
+    for _ in 0..3 {
+        assert_eq!(hit(&store, "203.0.113.7", T0 + 10), Decision::Allowed);
+    }
+}
+
+#[test]
+fn limits_past_the_limit_until_the_window_ends() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..3 {
+        hit(&store, "203.0.113.7", T0 + 10);
+    }
+
+    assert_eq!(hit(&store, "203.0.113.7", T0 + 10), Decision::Limited { retry_after: 50 });
+}
+
+#[test]
+fn subjects_are_counted_separately() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..4 {
+        hit(&store, "203.0.113.7", T0);
+    }
+
+    assert_eq!(hit(&store, "198.51.100.1", T0), Decision::Allowed);
+}
+
+#[test]
+fn subjects_ignore_case() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..3 {
+        hit(&store, "Someone@Example.com", T0);
+    }
+
+    assert!(matches!(hit(&store, "someone@example.com", T0), Decision::Limited { .. }));
+}
+
+#[test]
+fn previous_window_weighs_in_while_it_overlaps() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..3 {
+        hit(&store, "203.0.113.7", T0 + 59);
+    }
+
+    // Just after the boundary nearly all of the previous window still counts
+    assert!(matches!(hit(&store, "203.0.113.7", T0 + 61), Decision::Limited { .. }));
+}
+
+#[test]
+fn previous_window_fades_out() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..3 {
+        hit(&store, "203.0.113.7", T0);
+    }
+
+    // Two thirds through the next window only a third of the old hits count
+    assert_eq!(hit(&store, "203.0.113.7", T0 + 100), Decision::Allowed);
+}
+
+#[test]
+fn check_all_reports_the_longest_wait() {
+    let store = MemoryCounterStore::new();
+    let short = RateLimit { name: "short", limit: 1, window_seconds: 10 };
+    let long = RateLimit { name: "long", limit: 1, window_seconds: 600 };
+    let checks = [(short, "client".to_string()), (long, "client".to_string())];
+
+    assert_eq!(run(rate_limit::check_all(&store, &checks, T0 * 10)), Decision::Allowed);
+    assert_eq!(
+        run(rate_limit::check_all(&store, &checks, T0 * 10)),
+        Decision::Limited { retry_after: 600 }
+    );
+}
+
+#[test]
+fn peeking_does_not_count() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..10 {
+        assert_eq!(run(rate_limit::peek(&store, &LIMIT, "someone@example.com", T0)), Decision::Allowed);
+    }
+}
+
+#[test]
+fn recorded_hits_are_judged_by_peek() {
+    let store = MemoryCounterStore::new();
+    for _ in 0..2 {
+        run(rate_limit::record(&store, &LIMIT, "someone@example.com", T0 + 10));
+    }
+    // The third is still within the limit
+    assert_eq!(run(rate_limit::peek(&store, &LIMIT, "someone@example.com", T0 + 10)), Decision::Allowed);
+
+    run(rate_limit::record(&store, &LIMIT, "someone@example.com", T0 + 10));
+    assert_eq!(
+        run(rate_limit::peek(&store, &LIMIT, "someone@example.com", T0 + 10)),
+        Decision::Limited { retry_after: 50 }
+    );
+    assert_eq!(run(rate_limit::peek(&store, &LIMIT, "other@example.com", T0 + 10)), Decision::Allowed);
+}
+
+#[test]
+fn store_failures_fail_closed() {
+    struct BrokenStore;
+
+    impl CounterStore for BrokenStore {
+        async fn increment(&self, _key: &str, _window_start: i64, _expires_at: i64) -> Option<u64> {
+            None
+        }
+
+        async fn count(&self, _key: &str, _window_start: i64) -> Option<u64> {
+            None
+        }
+    }
+
+    assert!(matches!(
+        run(rate_limit::check(&BrokenStore, &LIMIT, "203.0.113.7", T0)),
+        Decision::Limited { .. }
+    ));
+    assert!(matches!(
+        run(rate_limit::peek(&BrokenStore, &LIMIT, "203.0.113.7", T0)),
+        Decision::Limited { .. }
+    ));
+}
+
+#[test]
+fn auth_routes_get_their_own_client_limits() {
+    let limits = rate_limit::request_limits(&Method::Post, "/api/auth/login", "203.0.113.7");
+    assert_eq!(limits, vec![(rate_limit::LOGIN_PER_IP, "203.0.113.7".to_string())]);
+
+    let limits = rate_limit::request_limits(&Method::Post, "/api/auth/magic-link", "203.0.113.7");
+    assert_eq!(limits, vec![(rate_limit::MAGIC_LINK_PER_IP, "203.0.113.7".to_string())]);
+
+    let limits = rate_limit::request_limits(&Method::Get, "/api/csrf-token", "203.0.113.7");
+    assert_eq!(limits, vec![(rate_limit::CSRF_TOKEN_PER_IP, "203.0.113.7".to_string())]);
+}
+
+#[test]
+fn other_posts_are_limited_per_route() {
+    let clubs = rate_limit::request_limits(&Method::Post, "/api/clubs", "203.0.113.7");
+    let meetings = rate_limit::request_limits(&Method::Post, "/api/meetings", "203.0.113.7");
+
+    assert_eq!(clubs[0].0, rate_limit::WRITE_PER_IP_AND_ROUTE);
+    assert_ne!(clubs[0].1, meetings[0].1);
+}
+
+#[test]
+fn reads_are_not_limited() {
+    assert!(rate_limit::request_limits(&Method::Get, "/api/clubs", "203.0.113.7").is_empty());
+}
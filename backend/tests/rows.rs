@@ -0,0 +1,164 @@
+//! Decoding result rows into models, both from hand-written rows shaped like
+//! D1's and from SQLite.
+
+mod common;
+
+use backend::models::{AuthUser, Club, Member, MemberRole, Project, ProjectStatus, UserMfa};
+use backend::store::{Database, DecodeError, FromRow, Store};
+use common::*;
+use serde_json::json;
+
+fn user_row() -> serde_json::Value {
+    json!({
+        "id": "alice",
+        "email": "alice@example.com",
+        "password_hash": "hash",
+        "name": "Alice",
+        "avatar": null,
+        "created_at": "2026-02-01T12:00:00+00:00",
+        "updated_at": "2026-02-01T12:00:00+00:00",
+        "email_verified": 1,
+        "is_active": 1,
+        "last_login": "2026-02-28T09:30:00+00:00",
+        "failed_login_attempts": 2,
+        "locked_until": null,
+        "deletion_scheduled_for": null,
+    })
+}
+
+fn decode_error(row: &serde_json::Value) -> DecodeError {
+    match AuthUser::decode(row) {
+        Ok(_) => panic!("row should not decode"),
+        Err(error) => error,
+    }
+}
+
+#[test]
+fn integer_flags_decode_as_booleans() {
+    let user = AuthUser::decode(&user_row()).unwrap();
+    assert!(user.email_verified);
+    assert!(user.is_active);
+    assert_eq!(user.failed_login_attempts, 2);
+
+    // D1 hands numbers over as JavaScript numbers
+    let mut row = user_row();
+    row["email_verified"] = json!(0.0);
+    row["failed_login_attempts"] = json!(3.0);
+    let user = AuthUser::decode(&row).unwrap();
+    assert!(!user.email_verified);
+    assert_eq!(user.failed_login_attempts, 3);
+}
+
+#[test]
+fn flags_outside_zero_and_one_are_rejected() {
+    let mut row = user_row();
+    row["is_active"] = json!(2);
+
+    let error = decode_error(&row);
+    assert_eq!(error.column, "is_active");
+    assert_eq!(error.to_string(), "column `is_active`: expected 0 or 1, found 2");
+}
+
+#[test]
+fn nullable_columns_decode_as_options() {
+    let user = AuthUser::decode(&user_row()).unwrap();
+    assert_eq!(user.avatar, None);
+    assert_eq!(user.last_login.as_deref(), Some("2026-02-28T09:30:00+00:00"));
+    assert_eq!(user.locked_until, None);
+    assert_eq!(user.deletion_scheduled_for, None);
+}
+
+#[test]
+fn null_flags_fall_back_to_the_schema_default() {
+    let row = json!({
+        "user_id": "alice",
+        "secret": "JBSWY3DPEHPK3PXP",
+        "enabled": null,
+        "created_at": "2026-02-01T12:00:00+00:00",
+        "confirmed_at": null,
+        "last_used_step": null,
+    });
+
+    let mfa = UserMfa::decode(&row).unwrap();
+    assert!(!mfa.enabled);
+    assert_eq!(mfa.last_used_step, None);
+}
+
+#[test]
+fn null_in_a_required_column_names_the_column() {
+    let mut row = user_row();
+    row["email"] = json!(null);
+
+    let error = decode_error(&row);
+    assert_eq!(error.to_string(), "column `email`: expected text, found NULL");
+}
+
+#[test]
+fn columns_missing_from_the_query_are_errors() {
+    let mut row = user_row();
+    row.as_object_mut().unwrap().remove("avatar");
+
+    // Even optional columns have to be selected
+    let error = decode_error(&row);
+    assert_eq!(error.to_string(), "column `avatar`: not in the result row");
+}
+
+#[test]
+fn wrongly_typed_columns_are_rejected() {
+    let mut row = user_row();
+    row["name"] = json!(42);
+
+    let error = decode_error(&row);
+    assert_eq!(error.to_string(), "column `name`: expected text, found 42");
+}
+
+#[test]
+fn roles_and_statuses_decode_from_their_text_form() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    add_club(&store, "chess", &alice.id);
+    run(store.execute("
+        INSERT INTO projects (id, club_id, name, description, status, created_by, created_at, updated_at)
+        VALUES ('p1', 'chess', 'Openings', 'Study group', 'on-hold', 'alice', ?1, ?1)
+    ", &[now().to_rfc3339().into()])).unwrap();
+
+    let member: Member = run(store.member("chess", "alice")).unwrap().unwrap();
+    assert!(matches!(member.role, MemberRole::Admin));
+
+    let projects: Vec<Project> = run(store.club_projects("chess")).unwrap();
+    assert!(matches!(projects[0].status, ProjectStatus::OnHold));
+}
+
+#[test]
+fn unknown_roles_fail_the_query_instead_of_dropping_the_row() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    add_club(&store, "chess", &alice.id);
+    add_member(&store, "chess", &bob, MemberRole::Member);
+
+    // Simulate a row written by a newer deploy that knows more roles
+    run(store.execute("PRAGMA ignore_check_constraints = ON", &[])).unwrap();
+    run(store.execute("UPDATE members SET role = 'moderator' WHERE user_id = 'bob'", &[])).unwrap();
+
+    let Err(error) = run(store.club_members("chess")) else {
+        panic!("the bad row should fail the query");
+    };
+    assert_eq!(
+        error.to_string(),
+        "store error: cannot decode Member: column `role`: expected one of admin, member, found \"moderator\"",
+    );
+}
+
+#[test]
+fn clubs_read_through_the_typed_query_helpers() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    add_club(&store, "chess", &alice.id);
+
+    let club: Option<Club> = run(store.query_first_as("SELECT * FROM clubs WHERE id = ?1", &["chess".into()])).unwrap();
+    assert_eq!(club.unwrap().owner_id, "alice");
+
+    let clubs: Vec<Club> = run(store.query_as("SELECT * FROM clubs WHERE id = ?1", &["go".into()])).unwrap();
+    assert!(clubs.is_empty());
+}
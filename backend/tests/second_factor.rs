@@ -0,0 +1,107 @@
+//! Second-factor checks and their per-account failure budget, run against
+//! SQLite with in-memory counters.
+
+mod common;
+
+use backend::rate_limit::{MemoryCounterStore, MFA_FAILURES_PER_ACCOUNT};
+use backend::second_factor::{self, SecondFactor, Verification};
+use backend::store::{Database, SqliteStore};
+use backend::token::hash_token;
+use backend::totp;
+use chrono::Duration;
+use common::*;
+use data_encoding::BASE32_NOPAD;
+
+// The secret `enable_mfa` stores
+const MFA_SECRET: &str = "JBSWY3DPEHPK3PXP";
+const RECOVERY_CODE: &str = "abcde-fghij";
+
+fn current_code() -> String {
+    let key = BASE32_NOPAD.decode(MFA_SECRET.as_bytes()).unwrap();
+    totp::code_for_counter(&key, (now().timestamp() / totp::STEP_SECONDS) as u64)
+}
+
+fn add_recovery_code(store: &SqliteStore, user_id: &str, code: &str) {
+    run(store.execute(
+        "INSERT INTO mfa_recovery_codes (id, user_id, code_hash, created_at) VALUES (?1, ?2, ?3, ?4)",
+        &[
+            format!("{}-{}", user_id, code).into(),
+            user_id.into(),
+            hash_token(&totp::normalize_recovery_code(code)).into(),
+            now().to_rfc3339().into(),
+        ],
+    ))
+    .unwrap();
+}
+
+fn verify(store: &SqliteStore, counters: &MemoryCounterStore, user_id: &str, factor: SecondFactor) -> Verification {
+    run(second_factor::verify(store, counters, user_id, factor, now()))
+}
+
+#[test]
+fn the_current_code_verifies() {
+    let store = store();
+    let counters = MemoryCounterStore::new();
+    add_user(&store, "alice");
+    enable_mfa(&store, "alice");
+
+    assert_eq!(verify(&store, &counters, "alice", SecondFactor::Code(&current_code())), Verification::Verified);
+    assert_eq!(verify(&store, &counters, "alice", SecondFactor::Code("000000")), Verification::Rejected);
+}
+
+#[test]
+fn a_recovery_code_works_once() {
+    let store = store();
+    let counters = MemoryCounterStore::new();
+    add_user(&store, "alice");
+    enable_mfa(&store, "alice");
+    add_recovery_code(&store, "alice", RECOVERY_CODE);
+
+    assert_eq!(verify(&store, &counters, "alice", SecondFactor::RecoveryCode("ABCDE-FGHIJ")), Verification::Verified);
+    assert_eq!(verify(&store, &counters, "alice", SecondFactor::RecoveryCode(RECOVERY_CODE)), Verification::Rejected);
+}
+
+#[test]
+fn failed_guesses_across_challenges_throttle_the_account() {
+    let store = store();
+    let counters = MemoryCounterStore::new();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    enable_mfa(&store, "alice");
+    enable_mfa(&store, "bob");
+    add_recovery_code(&store, "alice", RECOVERY_CODE);
+
+    // Five guesses per challenge, each batch as if from a fresh sign-in
+    let mut guesses = 0;
+    while guesses < MFA_FAILURES_PER_ACCOUNT.limit {
+        for _ in 0..5 {
+            let factor = if guesses % 2 == 0 { SecondFactor::Code("000000") } else { SecondFactor::RecoveryCode("wrong-guess") };
+            assert_eq!(verify(&store, &counters, "alice", factor), Verification::Rejected);
+            guesses += 1;
+        }
+    }
+
+    // Even the right code isn't checked now, and isn't used up
+    assert!(matches!(verify(&store, &counters, "alice", SecondFactor::Code(&current_code())), Verification::Limited { .. }));
+    assert!(matches!(verify(&store, &counters, "alice", SecondFactor::RecoveryCode(RECOVERY_CODE)), Verification::Limited { .. }));
+    let unused = run(store.query_first("SELECT COUNT(*) as count FROM mfa_recovery_codes WHERE used_at IS NULL", &[])).unwrap().unwrap();
+    assert_eq!(unused["count"], 1);
+
+    assert_eq!(verify(&store, &counters, "bob", SecondFactor::Code(&current_code())), Verification::Verified);
+}
+
+#[test]
+fn the_budget_comes_back_after_the_window() {
+    let store = store();
+    let counters = MemoryCounterStore::new();
+    add_user(&store, "alice");
+    enable_mfa(&store, "alice");
+    for _ in 0..MFA_FAILURES_PER_ACCOUNT.limit {
+        verify(&store, &counters, "alice", SecondFactor::Code("000000"));
+    }
+
+    let later = now() + Duration::seconds(2 * MFA_FAILURES_PER_ACCOUNT.window_seconds);
+    let key = BASE32_NOPAD.decode(MFA_SECRET.as_bytes()).unwrap();
+    let code = totp::code_for_counter(&key, (later.timestamp() / totp::STEP_SECONDS) as u64);
+    assert_eq!(run(second_factor::verify(&store, &counters, "alice", SecondFactor::Code(&code), later)), Verification::Verified);
+}
@@ -0,0 +1,65 @@
+//! Who the signup policy lets in.
+
+use backend::signup::{SignupDecision, SignupMode, SignupPolicy};
+
+#[test]
+fn modes_are_parsed_leniently() {
+    assert_eq!(SignupPolicy::parse("", "").mode, SignupMode::Open);
+    assert_eq!(SignupPolicy::parse(" Open ", "").mode, SignupMode::Open);
+    assert_eq!(SignupPolicy::parse("invite", "uni.edu").mode, SignupMode::Invite);
+    assert_eq!(
+        SignupPolicy::parse("DOMAIN", " uni.edu, @Alumni.Uni.edu ,, ").mode,
+        SignupMode::Domains(vec!["uni.edu".to_string(), "alumni.uni.edu".to_string()])
+    );
+}
+
+#[test]
+fn unknown_modes_fall_back_to_invite_only() {
+    assert_eq!(SignupPolicy::parse("closed", "").mode, SignupMode::Invite);
+    assert_eq!(SignupPolicy::parse("domains", "uni.edu").mode, SignupMode::Invite);
+}
+
+#[test]
+fn open_signup_admits_anyone() {
+    let policy = SignupPolicy::parse("open", "");
+    assert_eq!(policy.decide("anyone@example.com", false), SignupDecision::Allowed);
+    assert_eq!(policy.decide("anyone@example.com", true), SignupDecision::Allowed);
+}
+
+#[test]
+fn invite_signup_needs_an_invite() {
+    let policy = SignupPolicy::parse("invite", "");
+    assert_eq!(policy.decide("student@uni.edu", false), SignupDecision::Denied);
+    assert_eq!(policy.decide("student@uni.edu", true), SignupDecision::NeedsInvite);
+}
+
+#[test]
+fn domain_signup_admits_listed_domains_or_invitees() {
+    let policy = SignupPolicy::parse("domain", "uni.edu");
+    assert_eq!(policy.decide("student@uni.edu", false), SignupDecision::Allowed);
+    assert_eq!(policy.decide("student@uni.edu", true), SignupDecision::Allowed);
+    assert_eq!(policy.decide("guest@example.com", false), SignupDecision::Denied);
+    assert_eq!(policy.decide("guest@example.com", true), SignupDecision::NeedsInvite);
+}
+
+#[test]
+fn domains_match_exactly() {
+    let policy = SignupPolicy::parse("domain", "uni.edu");
+    assert!(policy.email_domain_allowed("student@uni.edu"));
+    assert!(policy.email_domain_allowed(" Student@UNI.EDU "));
+    assert!(!policy.email_domain_allowed("student@cs.uni.edu"));
+    assert!(!policy.email_domain_allowed("student@uni.edu.evil.com"));
+    assert!(!policy.email_domain_allowed("student@notuni.edu"));
+    assert!(!policy.email_domain_allowed("uni.edu"));
+    // The last @ is the one that counts
+    assert!(!policy.email_domain_allowed("student@uni.edu@evil.com"));
+
+    assert!(!SignupPolicy::parse("open", "uni.edu").email_domain_allowed("student@uni.edu"));
+}
+
+#[test]
+fn domain_signup_waits_for_a_verified_address() {
+    assert!(SignupPolicy::parse("domain", "uni.edu").requires_verified_email());
+    assert!(!SignupPolicy::parse("open", "").requires_verified_email());
+    assert!(!SignupPolicy::parse("invite", "").requires_verified_email());
+}
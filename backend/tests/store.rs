@@ -0,0 +1,348 @@
+//! The repository queries, run against SQLite with the Worker's schema.
+
+mod common;
+
+use backend::models::{Announcement, Event, MemberRole, Project, ProjectStatus, Session};
+use backend::store::{Database, Store, StoreError};
+use chrono::Duration;
+use common::*;
+
+fn session(id: &str, user_id: &str, expires_in: Duration, last_accessed: Duration) -> Session {
+    Session {
+        id: id.to_string(),
+        user_id: user_id.to_string(),
+        token: format!("hash-of-{}", id),
+        expires_at: (now() + expires_in).to_rfc3339(),
+        created_at: (now() - Duration::days(1)).to_rfc3339(),
+        last_accessed: (now() - last_accessed).to_rfc3339(),
+        user_agent: Some("Mozilla/5.0".to_string()),
+        ip_address: None,
+        is_active: true,
+    }
+}
+
+#[test]
+fn users_round_trip() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+
+    let found = run(store.user_by_email("alice@example.com")).unwrap().unwrap();
+    assert_eq!(found.id, alice.id);
+    assert_eq!(found.password_hash, alice.password_hash);
+    assert!(found.email_verified && found.is_active);
+    assert_eq!(found.failed_login_attempts, 0);
+
+    assert!(run(store.user_by_id("alice")).unwrap().is_some());
+    assert!(run(store.user_by_id("bob")).unwrap().is_none());
+    assert!(run(store.user_exists("alice@example.com")).unwrap());
+    assert!(!run(store.user_exists("bob@example.com")).unwrap());
+}
+
+#[test]
+fn users_carry_their_scheduled_deletion() {
+    let store = store();
+    add_user(&store, "alice");
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().deletion_scheduled_for, None);
+
+    let due = (now() + Duration::days(30)).to_rfc3339();
+    run(store.execute("UPDATE users SET deletion_scheduled_for = ?1 WHERE id = 'alice'", &[due.as_str().into()])).unwrap();
+    assert_eq!(run(store.user_by_id("alice")).unwrap().unwrap().deletion_scheduled_for, Some(due));
+}
+
+#[test]
+fn emails_are_unique() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+
+    let twin = backend::models::AuthUser { id: "twin".to_string(), ..alice };
+    let error = run(store.create_user(&twin)).unwrap_err();
+    assert!(error.is_unique_violation());
+    assert!(!StoreError("no such table: users".to_string()).is_unique_violation());
+}
+
+#[test]
+fn user_has_mfa_only_once_enrolled() {
+    let store = store();
+    add_user(&store, "alice");
+    assert!(!run(store.user_has_mfa("alice")).unwrap());
+
+    enable_mfa(&store, "alice");
+    assert!(run(store.user_has_mfa("alice")).unwrap());
+}
+
+#[test]
+fn active_session_belongs_to_its_user_and_is_live() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    run(store.create_session(&session("s1", "alice", Duration::days(1), Duration::minutes(1)))).unwrap();
+
+    let found = run(store.active_session("s1", "alice", now())).unwrap().unwrap();
+    assert_eq!(found.token, "hash-of-s1");
+    assert_eq!(found.user_agent.as_deref(), Some("Mozilla/5.0"));
+    assert_eq!(found.ip_address, None);
+
+    assert!(run(store.active_session("s1", "bob", now())).unwrap().is_none());
+    assert!(run(store.active_session("missing", "alice", now())).unwrap().is_none());
+}
+
+#[test]
+fn expired_and_revoked_sessions_are_not_active() {
+    let store = store();
+    add_user(&store, "alice");
+    run(store.create_session(&session("expired", "alice", -Duration::seconds(1), Duration::days(1)))).unwrap();
+    run(store.create_session(&session("revoked", "alice", Duration::days(1), Duration::minutes(1)))).unwrap();
+
+    run(store.revoke_session("revoked", "alice")).unwrap();
+
+    assert!(run(store.active_session("expired", "alice", now())).unwrap().is_none());
+    assert!(run(store.active_session("revoked", "alice", now())).unwrap().is_none());
+}
+
+#[test]
+fn sessions_can_only_be_revoked_by_their_owner() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    run(store.create_session(&session("s1", "alice", Duration::days(1), Duration::minutes(1)))).unwrap();
+
+    run(store.revoke_session("s1", "bob")).unwrap();
+
+    assert!(run(store.active_session("s1", "alice", now())).unwrap().is_some());
+}
+
+#[test]
+fn active_sessions_lists_live_sessions_most_recent_first() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    run(store.create_session(&session("older", "alice", Duration::days(1), Duration::hours(2)))).unwrap();
+    run(store.create_session(&session("newer", "alice", Duration::days(1), Duration::minutes(5)))).unwrap();
+    run(store.create_session(&session("expired", "alice", -Duration::hours(1), Duration::minutes(1)))).unwrap();
+    run(store.create_session(&session("bobs", "bob", Duration::days(1), Duration::minutes(1)))).unwrap();
+
+    let ids: Vec<String> = run(store.active_sessions("alice", now())).unwrap().into_iter().map(|s| s.id).collect();
+    assert_eq!(ids, ["newer", "older"]);
+
+    run(store.revoke_user_sessions("alice")).unwrap();
+    assert!(run(store.active_sessions("alice", now())).unwrap().is_empty());
+    assert_eq!(run(store.active_sessions("bob", now())).unwrap().len(), 1);
+}
+
+#[test]
+fn touching_a_session_updates_last_accessed() {
+    let store = store();
+    add_user(&store, "alice");
+    run(store.create_session(&session("s1", "alice", Duration::days(1), Duration::hours(1)))).unwrap();
+
+    run(store.touch_session("s1", &now().to_rfc3339())).unwrap();
+
+    let found = run(store.active_session("s1", "alice", now())).unwrap().unwrap();
+    assert_eq!(found.last_accessed, now().to_rfc3339());
+}
+
+#[test]
+fn creating_a_club_makes_the_owner_an_admin() {
+    let store = store();
+    add_user(&store, "alice");
+    add_club(&store, "chess", "alice");
+
+    let club = run(store.club("chess")).unwrap().unwrap();
+    assert_eq!(club.owner_id, "alice");
+    assert!(!club.require_admin_mfa);
+
+    let members = run(store.club_members("chess")).unwrap();
+    assert_eq!(members.len(), 1);
+    assert_eq!(members[0].user.email, "alice@example.com");
+    assert!(matches!(members[0].role, MemberRole::Admin));
+}
+
+#[test]
+fn a_failed_club_insert_leaves_nothing_behind() {
+    let store = store();
+
+    // The owner doesn't exist, so the membership insert violates its foreign key
+    let club = backend::models::Club {
+        id: "orphan".to_string(),
+        name: "Orphan".to_string(),
+        description: None,
+        avatar: None,
+        created_at: now().to_rfc3339(),
+        updated_at: now().to_rfc3339(),
+        owner_id: "nobody".to_string(),
+        require_admin_mfa: false,
+    };
+
+    assert!(matches!(run(store.create_club(&club)), Err(StoreError(_))));
+    assert!(run(store.club("orphan")).unwrap().is_none());
+    assert!(run(store.clubs()).unwrap().is_empty());
+}
+
+#[test]
+fn members_are_listed_in_join_order_with_their_profile() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    add_club(&store, "chess", &alice.id);
+    add_member(&store, "chess", &bob, MemberRole::Member);
+
+    let members = run(store.club_members("chess")).unwrap();
+    let ids: Vec<&str> = members.iter().map(|m| m.user_id.as_str()).collect();
+    assert_eq!(ids, ["alice", "bob"]);
+    assert!(matches!(members[1].role, MemberRole::Member));
+    assert_eq!(members[1].user.name, "bob");
+
+    assert!(run(store.member("chess", "bob")).unwrap().is_some());
+    assert!(run(store.member("chess", "carol")).unwrap().is_none());
+}
+
+#[test]
+fn a_user_can_only_be_a_member_once() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    add_club(&store, "chess", &alice.id);
+
+    let again = backend::models::Member {
+        id: "another".to_string(),
+        ..run(store.member("chess", "alice")).unwrap().unwrap()
+    };
+    assert!(run(store.add_member(&again)).is_err());
+}
+
+#[test]
+fn events_are_listed_by_date() {
+    let store = store();
+    add_user(&store, "alice");
+    add_club(&store, "chess", "alice");
+
+    for (id, date, location) in [("late", "2026-05-01T18:00:00Z", None), ("early", "2026-04-01T18:00:00Z", Some("Hall B"))] {
+        let event = Event {
+            id: id.to_string(),
+            club_id: "chess".to_string(),
+            title: id.to_string(),
+            description: "Weekly games".to_string(),
+            date: date.to_string(),
+            location: location.map(str::to_string),
+            created_by: "alice".to_string(),
+            created_at: now().to_rfc3339(),
+        };
+        run(store.create_event(&event)).unwrap();
+    }
+
+    let events = run(store.club_events("chess")).unwrap();
+    let ids: Vec<&str> = events.iter().map(|e| e.id.as_str()).collect();
+    assert_eq!(ids, ["early", "late"]);
+    assert_eq!(events[0].location.as_deref(), Some("Hall B"));
+    assert_eq!(events[1].location, None);
+}
+
+#[test]
+fn pinned_announcements_come_first() {
+    let store = store();
+    add_user(&store, "alice");
+    add_club(&store, "chess", "alice");
+
+    for (id, minutes_ago, pinned) in [("old-pinned", 60, true), ("new", 1, false), ("old", 30, false)] {
+        let announcement = Announcement {
+            id: id.to_string(),
+            club_id: "chess".to_string(),
+            title: id.to_string(),
+            content: "Bring a board".to_string(),
+            created_by: "alice".to_string(),
+            created_at: (now() - Duration::minutes(minutes_ago)).to_rfc3339(),
+            pinned,
+        };
+        run(store.create_announcement(&announcement)).unwrap();
+    }
+
+    let announcements = run(store.club_announcements("chess")).unwrap();
+    let ids: Vec<&str> = announcements.iter().map(|a| a.id.as_str()).collect();
+    assert_eq!(ids, ["old-pinned", "new", "old"]);
+    assert!(announcements[0].pinned);
+}
+
+#[test]
+fn announcements_reach_other_active_members() {
+    let store = store();
+    let alice = add_user(&store, "alice");
+    let bob = add_user(&store, "bob");
+    let carol = add_user(&store, "carol");
+    add_user(&store, "dave");
+    add_club(&store, "chess", &alice.id);
+    add_member(&store, "chess", &bob, MemberRole::Member);
+    add_member(&store, "chess", &carol, MemberRole::Admin);
+    deactivate(&store, "carol");
+
+    let recipients = run(store.announcement_recipients("chess", "alice")).unwrap();
+    assert_eq!(recipients, [("bob@example.com".to_string(), "chess club".to_string())]);
+}
+
+#[test]
+fn project_statuses_round_trip() {
+    let store = store();
+    add_user(&store, "alice");
+    add_club(&store, "chess", "alice");
+
+    let project = Project {
+        id: "ladder".to_string(),
+        club_id: "chess".to_string(),
+        name: "Club ladder".to_string(),
+        description: "Ranked games all term".to_string(),
+        status: ProjectStatus::OnHold,
+        created_by: "alice".to_string(),
+        created_at: now().to_rfc3339(),
+        updated_at: now().to_rfc3339(),
+    };
+    run(store.create_project(&project)).unwrap();
+
+    let projects = run(store.club_projects("chess")).unwrap();
+    assert_eq!(projects.len(), 1);
+    assert!(matches!(projects[0].status, ProjectStatus::OnHold));
+}
+
+#[test]
+fn invite_codes_are_single_use() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    add_user(&store, "carol");
+    add_club(&store, "chess", "alice");
+    add_invite_code(&store, "CHESS1", "chess", "alice", now() + Duration::days(1));
+
+    let at = now().to_rfc3339();
+    assert!(run(store.redeem_invite_code("CHESS1", "bob", &at)).unwrap());
+    assert!(!run(store.redeem_invite_code("CHESS1", "carol", &at)).unwrap());
+
+    let invite = run(store.invite_code("CHESS1")).unwrap().unwrap();
+    assert_eq!(invite.used_by.as_deref(), Some("bob"));
+    assert_eq!(invite.used_at.as_deref(), Some(at.as_str()));
+}
+
+#[test]
+fn expired_invite_codes_cannot_be_redeemed() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    add_club(&store, "chess", "alice");
+    add_invite_code(&store, "STALE1", "chess", "alice", now() - Duration::seconds(1));
+
+    assert!(!run(store.redeem_invite_code("STALE1", "bob", &now().to_rfc3339())).unwrap());
+}
+
+#[test]
+fn released_invite_codes_can_be_redeemed_again() {
+    let store = store();
+    add_user(&store, "alice");
+    add_user(&store, "bob");
+    add_club(&store, "chess", "alice");
+    add_invite_code(&store, "CHESS1", "chess", "alice", now() + Duration::days(1));
+    let at = now().to_rfc3339();
+    run(store.redeem_invite_code("CHESS1", "bob", &at)).unwrap();
+
+    // Only the user who redeemed it can put it back
+    run(store.release_invite_code("CHESS1", "alice")).unwrap();
+    assert!(!run(store.redeem_invite_code("CHESS1", "bob", &at)).unwrap());
+
+    run(store.release_invite_code("CHESS1", "bob")).unwrap();
+    assert!(run(store.redeem_invite_code("CHESS1", "bob", &at)).unwrap());
+}
@@ -0,0 +1,19 @@
+//! Bearer tokens and their stored hashes.
+
+use backend::token::{generate_secure_token, hash_token};
+
+#[test]
+fn tokens_are_stored_as_their_sha256() {
+    assert_eq!(hash_token(""), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+    assert_eq!(hash_token("abc"), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+    assert_ne!(hash_token("abc"), hash_token("abd"));
+}
+
+#[test]
+fn tokens_are_url_safe_and_unique() {
+    let token = generate_secure_token();
+    // 32 bytes of base64 without padding
+    assert_eq!(token.len(), 43);
+    assert!(token.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'));
+    assert_ne!(token, generate_secure_token());
+}
@@ -0,0 +1,96 @@
+//! TOTP codes against the RFC 6238 test vectors, and recovery codes.
+
+use backend::totp;
+use chrono::{DateTime, TimeZone, Utc};
+
+// The RFC's SHA-1 key, "12345678901234567890", base32 encoded
+const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+const RFC_KEY: &[u8] = b"12345678901234567890";
+
+// The RFC lists eight digits; six-digit codes are the last six of those
+const RFC_VECTORS: &[(i64, &str)] = &[
+    (59, "287082"),
+    (1_111_111_109, "081804"),
+    (1_111_111_111, "050471"),
+    (1_234_567_890, "005924"),
+    (2_000_000_000, "279037"),
+    (20_000_000_000, "353130"),
+];
+
+fn at(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(seconds, 0).unwrap()
+}
+
+#[test]
+fn codes_match_the_rfc_vectors() {
+    for (time, code) in RFC_VECTORS {
+        let counter = (time / totp::STEP_SECONDS) as u64;
+        assert_eq!(totp::code_for_counter(RFC_KEY, counter), *code, "T = {}", time);
+    }
+}
+
+#[test]
+fn codes_verify_at_their_time() {
+    for (time, code) in RFC_VECTORS {
+        assert_eq!(totp::verify(RFC_SECRET, code, None, at(*time)), Some(time / totp::STEP_SECONDS));
+    }
+    // Surrounding whitespace from copy and paste is fine
+    assert!(totp::verify(RFC_SECRET, " 287082\n", None, at(59)).is_some());
+}
+
+#[test]
+fn one_step_of_clock_drift_is_tolerated() {
+    let step = 1_234_567_890 / totp::STEP_SECONDS;
+    let code = "005924";
+    let step_start = step * totp::STEP_SECONDS;
+
+    assert_eq!(totp::verify(RFC_SECRET, code, None, at(step_start - totp::STEP_SECONDS)), Some(step));
+    assert_eq!(totp::verify(RFC_SECRET, code, None, at(step_start + totp::STEP_SECONDS)), Some(step));
+    assert_eq!(totp::verify(RFC_SECRET, code, None, at(step_start + 2 * totp::STEP_SECONDS)), None);
+    assert_eq!(totp::verify(RFC_SECRET, code, None, at(step_start - 2 * totp::STEP_SECONDS)), None);
+}
+
+#[test]
+fn a_used_step_cannot_be_replayed() {
+    let step = 1_234_567_890 / totp::STEP_SECONDS;
+    assert_eq!(totp::verify(RFC_SECRET, "005924", Some(step - 1), at(1_234_567_890)), Some(step));
+    assert_eq!(totp::verify(RFC_SECRET, "005924", Some(step), at(1_234_567_890)), None);
+    assert_eq!(totp::verify(RFC_SECRET, "005924", Some(step + 1), at(1_234_567_890)), None);
+}
+
+#[test]
+fn malformed_codes_are_rejected() {
+    for code in ["", "28708", "2870821", "28708a", "287 082", "-87082"] {
+        assert_eq!(totp::verify(RFC_SECRET, code, None, at(59)), None, "{:?}", code);
+    }
+    assert_eq!(totp::verify("not base32!", "287082", None, at(59)), None);
+}
+
+#[test]
+fn new_secrets_are_base32_and_distinct() {
+    let secret = totp::generate_secret();
+    assert_eq!(secret.len(), 32);
+    assert!(secret.chars().all(|c| c.is_ascii_uppercase() || ('2'..='7').contains(&c)));
+    assert_ne!(secret, totp::generate_secret());
+}
+
+#[test]
+fn the_setup_uri_names_the_issuer_and_account() {
+    let uri = totp::otpauth_uri(RFC_SECRET, "ada+mfa@example.com");
+    assert_eq!(
+        uri,
+        format!("otpauth://totp/Nivaro:ada%2Bmfa@example.com?secret={}&issuer=Nivaro&algorithm=SHA1&digits=6&period=30", RFC_SECRET)
+    );
+}
+
+#[test]
+fn recovery_codes_ignore_case_and_separators() {
+    assert_eq!(totp::normalize_recovery_code("ABCD-EFGH"), "abcdefgh");
+    assert_eq!(totp::normalize_recovery_code(" abcd efgh "), "abcdefgh");
+    assert_eq!(totp::normalize_recovery_code("abcd_efgh\n"), "abcdefgh");
+
+    let code = totp::generate_recovery_code();
+    assert_eq!(code.len(), 9);
+    assert_eq!(&code[4..5], "-");
+    assert_eq!(totp::normalize_recovery_code(&code.to_uppercase()), totp::normalize_recovery_code(&code));
+}
@@ -0,0 +1,234 @@
+//! Passkey ceremony checks against recorded authenticator output.
+//!
+//! Each fixture holds one registration and one follow-up assertion for the
+//! same credential, captured for rp id `localhost` and origin
+//! `http://localhost:3000`. The presence-only fixture comes from a security
+//! key without a PIN, so it also carries the credential public key.
+
+use backend::webauthn::{self, RelyingParty, WebAuthnError, COSE_ALG_EDDSA, COSE_ALG_ES256};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine as _};
+use serde_json::Value;
+
+const ES256_FIXTURE: &str = include_str!("fixtures/webauthn/es256.json");
+const ED25519_FIXTURE: &str = include_str!("fixtures/webauthn/ed25519.json");
+const PRESENCE_ONLY_FIXTURE: &str = include_str!("fixtures/webauthn/es256-presence-only.json");
+
+struct Fixture(Value);
+
+impl Fixture {
+    fn load(json: &str) -> Self {
+        Fixture(serde_json::from_str(json).expect("fixture is valid JSON"))
+    }
+
+    fn rp(&self) -> RelyingParty {
+        RelyingParty {
+            id: self.0["rp_id"].as_str().unwrap().to_string(),
+            name: "Nivaro".to_string(),
+            origin: self.0["origin"].as_str().unwrap().to_string(),
+        }
+    }
+
+    fn text(&self, ceremony: &str, field: &str) -> String {
+        self.0[ceremony][field].as_str().unwrap().to_string()
+    }
+
+    fn bytes(&self, ceremony: &str, field: &str) -> Vec<u8> {
+        URL_SAFE_NO_PAD.decode(self.text(ceremony, field)).unwrap()
+    }
+
+    fn register(&self, rp: &RelyingParty) -> Result<webauthn::RegisteredCredential, WebAuthnError> {
+        webauthn::verify_registration(
+            rp,
+            &self.text("registration", "challenge"),
+            &self.bytes("registration", "client_data_json"),
+            &self.bytes("registration", "attestation_object"),
+        )
+    }
+
+    fn authenticate(&self, public_key: &[u8], stored_sign_count: u32) -> Result<webauthn::VerifiedAssertion, WebAuthnError> {
+        webauthn::verify_authentication(
+            &self.rp(),
+            &self.text("authentication", "challenge"),
+            public_key,
+            stored_sign_count,
+            &self.bytes("authentication", "client_data_json"),
+            &self.bytes("authentication", "authenticator_data"),
+            &self.bytes("authentication", "signature"),
+        )
+    }
+}
+
+#[test]
+fn registers_es256_credential_with_none_attestation() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+
+    assert_eq!(credential.credential_id, fixture.bytes("registration", "credential_id"));
+    assert_eq!(credential.algorithm, COSE_ALG_ES256);
+    assert_eq!(credential.sign_count, 0);
+}
+
+#[test]
+fn registers_ed25519_credential_with_packed_attestation() {
+    let fixture = Fixture::load(ED25519_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+
+    assert_eq!(credential.credential_id, fixture.bytes("registration", "credential_id"));
+    assert_eq!(credential.algorithm, COSE_ALG_EDDSA);
+}
+
+#[test]
+fn registration_stops_the_key_at_extension_data() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let expected = fixture.register(&fixture.rp()).unwrap();
+
+    // The same attestation with {"credProtect": 1} after the key and the ED flag set
+    let attestation: ciborium::Value = ciborium::from_reader(fixture.bytes("registration", "attestation_object").as_slice()).unwrap();
+    let mut entries = attestation.into_map().unwrap();
+    for (key, value) in entries.iter_mut() {
+        if key.as_text() == Some("authData") {
+            let auth_data = value.as_bytes_mut().unwrap();
+            auth_data[32] |= 0x80;
+            auth_data.extend_from_slice(&[0xa1, 0x6b]);
+            auth_data.extend_from_slice(b"credProtect");
+            auth_data.push(0x01);
+        }
+    }
+    let mut attestation_object = Vec::new();
+    ciborium::into_writer(&ciborium::Value::Map(entries), &mut attestation_object).unwrap();
+
+    let credential = webauthn::verify_registration(
+        &fixture.rp(),
+        &fixture.text("registration", "challenge"),
+        &fixture.bytes("registration", "client_data_json"),
+        &attestation_object,
+    )
+    .unwrap();
+    assert_eq!(credential.public_key, expected.public_key);
+    fixture.authenticate(&credential.public_key, credential.sign_count).unwrap();
+}
+
+#[test]
+fn registration_rejects_wrong_challenge() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let result = webauthn::verify_registration(
+        &fixture.rp(),
+        &fixture.text("authentication", "challenge"),
+        &fixture.bytes("registration", "client_data_json"),
+        &fixture.bytes("registration", "attestation_object"),
+    );
+
+    assert_eq!(result.unwrap_err(), WebAuthnError::ChallengeMismatch);
+}
+
+#[test]
+fn registration_rejects_other_origin() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let rp = RelyingParty { origin: "https://evil.example".to_string(), ..fixture.rp() };
+
+    assert_eq!(fixture.register(&rp).unwrap_err(), WebAuthnError::OriginMismatch);
+}
+
+#[test]
+fn registration_rejects_other_rp_id() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let rp = RelyingParty { id: "nivaro.example".to_string(), ..fixture.rp() };
+
+    assert_eq!(fixture.register(&rp).unwrap_err(), WebAuthnError::RpIdMismatch);
+}
+
+#[test]
+fn registration_rejects_assertion_client_data() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let result = webauthn::verify_registration(
+        &fixture.rp(),
+        &fixture.text("authentication", "challenge"),
+        &fixture.bytes("authentication", "client_data_json"),
+        &fixture.bytes("registration", "attestation_object"),
+    );
+
+    assert_eq!(result.unwrap_err(), WebAuthnError::WrongCeremony);
+}
+
+#[test]
+fn registration_rejects_key_without_user_verification() {
+    let fixture = Fixture::load(PRESENCE_ONLY_FIXTURE);
+
+    assert_eq!(fixture.register(&fixture.rp()).unwrap_err(), WebAuthnError::UserNotVerified);
+}
+
+#[test]
+fn client_data_challenge_reads_echoed_challenge() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let challenge = webauthn::client_data_challenge(&fixture.bytes("authentication", "client_data_json")).unwrap();
+
+    assert_eq!(challenge, fixture.text("authentication", "challenge"));
+}
+
+#[test]
+fn authenticates_es256_assertion_and_advances_counter() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+    let assertion = fixture.authenticate(&credential.public_key, credential.sign_count).unwrap();
+
+    assert_eq!(assertion.sign_count, 5);
+}
+
+#[test]
+fn authenticates_counterless_ed25519_assertion() {
+    let fixture = Fixture::load(ED25519_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+    let assertion = fixture.authenticate(&credential.public_key, credential.sign_count).unwrap();
+
+    assert_eq!(assertion.sign_count, 0);
+}
+
+#[test]
+fn authentication_rejects_replayed_counter() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+
+    assert_eq!(
+        fixture.authenticate(&credential.public_key, 5).unwrap_err(),
+        WebAuthnError::SignCountRegression
+    );
+}
+
+#[test]
+fn authentication_rejects_signature_from_other_key() {
+    let es256 = Fixture::load(ES256_FIXTURE);
+    let ed25519 = Fixture::load(ED25519_FIXTURE);
+    let other_key = ed25519.register(&ed25519.rp()).unwrap().public_key;
+
+    assert_eq!(es256.authenticate(&other_key, 0).unwrap_err(), WebAuthnError::InvalidSignature);
+}
+
+#[test]
+fn authentication_rejects_tampered_authenticator_data() {
+    let fixture = Fixture::load(ES256_FIXTURE);
+    let credential = fixture.register(&fixture.rp()).unwrap();
+
+    let mut authenticator_data = fixture.bytes("authentication", "authenticator_data");
+    let last = authenticator_data.len() - 1;
+    authenticator_data[last] += 1;
+
+    let result = webauthn::verify_authentication(
+        &fixture.rp(),
+        &fixture.text("authentication", "challenge"),
+        &credential.public_key,
+        credential.sign_count,
+        &fixture.bytes("authentication", "client_data_json"),
+        &authenticator_data,
+        &fixture.bytes("authentication", "signature"),
+    );
+
+    assert_eq!(result.unwrap_err(), WebAuthnError::InvalidSignature);
+}
+
+#[test]
+fn authentication_rejects_assertion_without_user_verification() {
+    let fixture = Fixture::load(PRESENCE_ONLY_FIXTURE);
+    let public_key = fixture.bytes("registration", "public_key");
+
+    assert_eq!(fixture.authenticate(&public_key, 0).unwrap_err(), WebAuthnError::UserNotVerified);
+}
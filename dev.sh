@@ -77,16 +77,10 @@ echo -e "${BLUE}Building backend...${NC}"
 cd backend
 cargo build
 
-# Initialize database if empty
-echo -e "${BLUE}Checking database...${NC}"
-TABLE_COUNT=$(wrangler d1 execute DB --local --command "SELECT COUNT(*) as count FROM sqlite_master WHERE type='table';" 2>/dev/null | grep -o '"count":[0-9]*' | grep -o '[0-9]*' || echo "0")
-if [ "$TABLE_COUNT" -eq "0" ] || [ -z "$TABLE_COUNT" ]; then
-    echo -e "${YELLOW}Initializing database schema...${NC}"
-    wrangler d1 execute DB --local --file=schema.sql
-    echo -e "${GREEN}✅ Database initialized${NC}"
-else
-    echo -e "${GREEN}✅ Database already initialized${NC}"
-fi
+# Apply pending database migrations
+echo -e "${BLUE}Migrating database...${NC}"
+cargo run --quiet --bin migrate -- up --d1 DB
+echo -e "${GREEN}✅ Database is up to date${NC}"
 cd ..
 
 # Build frontend statically